@@ -6,18 +6,18 @@
 use criterion::{criterion_group, criterion_main, profiler::Profiler, BatchSize, Criterion};
 use firewood::{
     db::{BatchOp, Db, DbConfig},
-    merkle::{Merkle, TrieHash, TRIE_HASH_LEN},
+    merkle::{Merkle, Node, TrieHash, TRIE_HASH_LEN},
     storage::WalConfig,
 };
 use pprof::ProfilerGuard;
 use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
 use shale::{
     cached::PlainMem,
-    compact::{CompactHeader, CompactSpace},
+    compact::{CompactHeader, CompactSpace, CompactSpaceHeader},
     disk_address::DiskAddress,
     CachedStore, ObjCache, Storable, StoredView,
 };
-use std::{fs::File, iter::repeat_with, ops::Deref, os::raw::c_int, path::Path};
+use std::{fs::File, iter::repeat_with, num::NonZeroUsize, ops::Deref, os::raw::c_int, path::Path};
 
 const ZERO_HASH: TrieHash = TrieHash([0u8; TRIE_HASH_LEN]);
 
@@ -73,8 +73,47 @@ fn bench_trie_hash(criterion: &mut Criterion) {
         });
 }
 
+const TEST_MEM_SIZE: u64 = 20_000_000;
+
+fn new_merkle() -> (Merkle<CompactSpace<Node, PlainMem>>, DiskAddress) {
+    const RESERVED: usize = 0x1000;
+    let merkle_payload_header = DiskAddress::from(0);
+
+    // the free lists start out empty, rather than at the null address
+    let mut header_mem = PlainMem::new(2 * CompactHeader::MSIZE, 9);
+    header_mem.write(
+        merkle_payload_header.into(),
+        &shale::to_dehydrated(&CompactSpaceHeader::new(
+            NonZeroUsize::new(RESERVED).unwrap(),
+            NonZeroUsize::new(RESERVED).unwrap(),
+        ))
+        .unwrap(),
+    );
+    let merkle_payload_header_ref =
+        StoredView::ptr_to_obj(&header_mem, merkle_payload_header, CompactHeader::MSIZE).unwrap();
+
+    let store = CompactSpace::new(
+        PlainMem::new(TEST_MEM_SIZE, 0).into(),
+        PlainMem::new(TEST_MEM_SIZE, 1).into(),
+        merkle_payload_header_ref,
+        ObjCache::new(1 << 20),
+        4096,
+        4096,
+    )
+    .unwrap();
+
+    let merkle = Merkle::new(Box::new(store));
+    let root = merkle.init_root().unwrap();
+    (merkle, root)
+}
+
+fn random_keys(rng: &mut StdRng, len: usize, n: usize) -> Vec<Vec<u8>> {
+    repeat_with(|| rng.sample_iter(&Alphanumeric).take(len).collect())
+        .take(n)
+        .collect()
+}
+
 fn bench_merkle<const N: usize>(criterion: &mut Criterion) {
-    const TEST_MEM_SIZE: u64 = 20_000_000;
     const KEY_LEN: usize = 4;
     let mut rng = StdRng::seed_from_u64(1234);
 
@@ -84,38 +123,8 @@ fn bench_merkle<const N: usize>(criterion: &mut Criterion) {
         .bench_function("insert", |b| {
             b.iter_batched(
                 || {
-                    let merkle_payload_header = DiskAddress::from(0);
-
-                    let merkle_payload_header_ref = StoredView::ptr_to_obj(
-                        &PlainMem::new(2 * CompactHeader::MSIZE, 9),
-                        merkle_payload_header,
-                        CompactHeader::MSIZE,
-                    )
-                    .unwrap();
-
-                    let store = CompactSpace::new(
-                        PlainMem::new(TEST_MEM_SIZE, 0).into(),
-                        PlainMem::new(TEST_MEM_SIZE, 1).into(),
-                        merkle_payload_header_ref,
-                        ObjCache::new(1 << 20),
-                        4096,
-                        4096,
-                    )
-                    .unwrap();
-
-                    let merkle = Merkle::new(Box::new(store));
-                    let root = merkle.init_root().unwrap();
-
-                    let keys: Vec<Vec<u8>> = repeat_with(|| {
-                        (&mut rng)
-                            .sample_iter(&Alphanumeric)
-                            .take(KEY_LEN)
-                            .collect()
-                    })
-                    .take(N)
-                    .collect();
-
-                    (merkle, root, keys)
+                    let (merkle, root) = new_merkle();
+                    (merkle, root, random_keys(&mut rng, KEY_LEN, N))
                 },
                 |(mut merkle, root, keys)| {
                     keys.into_iter()
@@ -126,6 +135,107 @@ fn bench_merkle<const N: usize>(criterion: &mut Criterion) {
         });
 }
 
+// a batch of N changes to a trie of N keys, applied one by one and in one pass, then hashed
+fn bench_batch<const N: usize>(criterion: &mut Criterion) {
+    const KEY_LEN: usize = 4;
+    let mut rng = StdRng::seed_from_u64(1234);
+    let mut setup = || {
+        let (mut merkle, root) = new_merkle();
+        let keys = random_keys(&mut rng, KEY_LEN, N);
+        merkle
+            .apply_batch(keys.iter().map(|key| (key, Some(vec![b'v']))), root)
+            .unwrap();
+        merkle.root_hash(root).unwrap();
+        // half of the batch changes keys that are in the trie
+        let batch: Vec<_> = random_keys(&mut rng, KEY_LEN, N / 2)
+            .into_iter()
+            .chain(keys.into_iter().take(N / 2))
+            .enumerate()
+            .map(|(i, key)| (key, (i % 4 != 0).then(|| vec![b'w'])))
+            .collect();
+        (merkle, root, batch)
+    };
+
+    let mut group = criterion.benchmark_group("Batch");
+    group.sample_size(30);
+    group.bench_function("one by one", |b| {
+        b.iter_batched(
+            &mut setup,
+            |(mut merkle, root, batch)| {
+                for (key, value) in batch {
+                    match value {
+                        Some(value) => merkle.insert(key, value, root).unwrap(),
+                        None => {
+                            merkle.remove(key, root).unwrap();
+                        }
+                    }
+                }
+                merkle.root_hash(root).unwrap();
+                // dropped outside of the measurement
+                merkle
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("apply_batch", |b| {
+        b.iter_batched(
+            &mut setup,
+            |(mut merkle, root, batch)| {
+                merkle.apply_batch(batch, root).unwrap();
+                merkle.root_hash(root).unwrap();
+                merkle
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+// a new trie of N sorted keys, made by inserting them and by building it bottom-up, then hashed
+fn bench_build<const N: usize>(criterion: &mut Criterion) {
+    const KEY_LEN: usize = 4;
+    let mut rng = StdRng::seed_from_u64(1234);
+    let mut setup = || {
+        let mut keys = random_keys(&mut rng, KEY_LEN, N);
+        keys.sort();
+        keys.dedup();
+        let (merkle, root) = new_merkle();
+        (merkle, root, keys)
+    };
+
+    let mut group = criterion.benchmark_group("Build");
+    group.sample_size(30);
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            &mut setup,
+            |(mut merkle, root, keys)| {
+                for key in keys {
+                    merkle.insert(key, vec![b'v'], root).unwrap();
+                }
+                merkle.root_hash(root).unwrap();
+                // dropped outside of the measurement
+                merkle
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("trie_builder", |b| {
+        b.iter_batched(
+            &mut setup,
+            |(mut merkle, _, keys)| {
+                let mut builder = merkle.trie_builder();
+                for key in keys {
+                    builder.push(key, vec![b'v']).unwrap();
+                }
+                builder.finish().unwrap();
+                merkle
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
 fn bench_db<const N: usize>(criterion: &mut Criterion) {
     const KEY_LEN: usize = 4;
     let mut rng = StdRng::seed_from_u64(1234);
@@ -170,7 +280,7 @@ fn bench_db<const N: usize>(criterion: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default().with_profiler(FlamegraphProfiler::Init(100));
-    targets = bench_trie_hash, bench_merkle::<3>, bench_db::<100>
+    targets = bench_trie_hash, bench_merkle::<3>, bench_batch::<10000>, bench_build::<10000>, bench_db::<100>
 }
 
 criterion_main!(benches);
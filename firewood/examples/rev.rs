@@ -33,13 +33,13 @@ fn main() -> Result<(), DbError> {
 
     let revision = revision_tracker.get_revision(0);
     let revision_root_hash = revision
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-0 should exist");
     println!("{revision_root_hash:?}");
 
     let current_root_hash = revision_tracker
         .db
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for current state should exist");
     // The following is true as long as the current state is fresh after replaying from Wals.
     assert_eq!(revision_root_hash, current_root_hash);
@@ -79,7 +79,7 @@ fn main() -> Result<(), DbError> {
         scope.spawn(|| {
             let revision = rev2;
             let revision_root_hash = revision
-                .kv_root_hash()
+                .kv_root_hash(None)
                 .expect("root-hash for revision-2 should exist");
             println!("{revision_root_hash:?}");
             revision.kv_dump(&mut std::io::stdout()).unwrap();
@@ -140,14 +140,14 @@ impl RevisionTracker {
         }];
         self.db.new_proposal(batch)?.commit()?;
 
-        let hash = self.db.kv_root_hash().expect("root-hash should exist");
+        let hash = self.db.kv_root_hash(None).expect("root-hash should exist");
         self.hashes.push_front(hash);
         Ok(())
     }
 
     fn commit_proposal(&mut self, proposal: Proposal) {
         proposal.commit().unwrap();
-        let hash = self.db.kv_root_hash().expect("root-hash should exist");
+        let hash = self.db.kv_root_hash(None).expect("root-hash should exist");
         self.hashes.push_front(hash);
     }
 
@@ -180,13 +180,13 @@ fn build_proof(revision: &Revision<SharedStore>, items: &[(&str, &str)]) -> Proo
 fn verify_root_hashes(revision_tracker: &mut RevisionTracker) {
     let revision = revision_tracker.get_revision(0);
     let revision_root_hash = revision
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-0 should exist");
     println!("{revision_root_hash:?}");
 
     let current_root_hash = revision_tracker
         .db
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for current state should exist");
 
     // The following should always hold.
@@ -194,14 +194,14 @@ fn verify_root_hashes(revision_tracker: &mut RevisionTracker) {
 
     let revision = revision_tracker.get_revision(2);
     let revision_root_hash = revision
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-2 should exist");
     println!("{revision_root_hash:?}");
 
     // Get a revision while a batch is active.
     let revision = revision_tracker.get_revision(1);
     let revision_root_hash = revision
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-1 should exist");
     println!("{revision_root_hash:?}");
 
@@ -213,7 +213,7 @@ fn verify_root_hashes(revision_tracker: &mut RevisionTracker) {
 
     let actual_revision_root_hash = revision_tracker
         .get_revision(1)
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-1 should exist");
     assert_eq!(revision_root_hash, actual_revision_root_hash);
 
@@ -224,7 +224,7 @@ fn verify_root_hashes(revision_tracker: &mut RevisionTracker) {
 
     let new_revision_root_hash = revision_tracker
         .get_revision(1)
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-1 should exist");
     assert_ne!(revision_root_hash, new_revision_root_hash);
     let val = revision_tracker.db.kv_get("k").unwrap();
@@ -236,7 +236,7 @@ fn verify_root_hashes(revision_tracker: &mut RevisionTracker) {
     let val = revision.kv_get("dof").unwrap();
     assert_eq!("verb".as_bytes().to_vec(), val);
     let actual_revision_root_hash = revision
-        .kv_root_hash()
+        .kv_root_hash(None)
         .expect("root-hash for revision-2 should exist");
     assert_eq!(revision_root_hash, actual_revision_root_hash);
 }
@@ -0,0 +1,172 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+//! Ethereum-style accounts on top of the generic key-value storage.
+//!
+//! An [Account] is stored in the trie of the default keyspace under its address, in the RLP
+//! encoding Ethereum hashes it in, so the root hash of that trie commits to every account. The
+//! storage of each account is a trie of its own, whose root hash is kept in the account and is
+//! brought up to date by every proposal that changes the storage. Code is kept apart from the
+//! accounts, in a store addressed by the hash of the code.
+//!
+//! Accounts are changed with [crate::db::BatchOp::Account] and read from any revision, e.g.
+//! with [crate::db::DbRev::get_account].
+
+use crate::{
+    api::Nonce,
+    merkle::{Merkle, TrieHash, TRIE_HASH_LEN},
+    proof::ProofError,
+    v2::api::Proof,
+};
+pub use primitive_types::U256;
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+use sha3::{Digest, Keccak256};
+use std::sync::OnceLock;
+
+/// The state of an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub nonce: Nonce,
+    pub balance: U256,
+    /// The root hash of the storage trie of the account.
+    pub storage_root: TrieHash,
+    /// The hash of the code of the account.
+    pub code_hash: TrieHash,
+}
+
+impl Account {
+    /// The hash of empty code.
+    pub fn empty_code_hash() -> &'static TrieHash {
+        static V: OnceLock<TrieHash> = OnceLock::new();
+        V.get_or_init(|| code_hash(&[]))
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        rlp::encode(self).to_vec()
+    }
+
+    pub(crate) fn decode(raw: &[u8]) -> Result<Self, DecoderError> {
+        rlp::decode(raw)
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self {
+            nonce: 0,
+            balance: U256::zero(),
+            storage_root: Merkle::<()>::empty_root().clone(),
+            code_hash: Account::empty_code_hash().clone(),
+        }
+    }
+}
+
+impl Encodable for Account {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(4);
+        s.append(&self.nonce);
+        s.append(&self.balance);
+        s.append(&self.storage_root.0.as_slice());
+        s.append(&self.code_hash.0.as_slice());
+    }
+}
+
+impl Decodable for Account {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        if rlp.item_count()? != 4 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        let hash = |index| -> Result<TrieHash, DecoderError> {
+            let raw: Vec<u8> = rlp.val_at(index)?;
+            let raw: [u8; TRIE_HASH_LEN] =
+                raw.try_into().map_err(|_| DecoderError::RlpInvalidLength)?;
+            Ok(TrieHash(raw))
+        };
+        Ok(Self {
+            nonce: rlp.val_at(0)?,
+            balance: rlp.val_at(1)?,
+            storage_root: hash(2)?,
+            code_hash: hash(3)?,
+        })
+    }
+}
+
+/// The hash code is stored under.
+pub fn code_hash(code: &[u8]) -> TrieHash {
+    TrieHash(Keccak256::digest(code).into())
+}
+
+/// A change to a single account, which is created with default values by the first change
+/// made to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountOp {
+    SetNonce(Nonce),
+    SetBalance(U256),
+    SetCode(Vec<u8>),
+    /// Put (upsert) a key in the storage of the account
+    SetState {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Delete a key from the storage of the account
+    DeleteState {
+        key: Vec<u8>,
+    },
+    /// Delete the account along with its storage
+    Delete,
+}
+
+/// A proof of an account and of some of the keys in its storage, made with
+/// [crate::db::DbRev::prove_account].
+#[derive(Debug)]
+pub struct AccountProof {
+    /// The proof of the account in the trie of the default keyspace.
+    pub account: Proof<Vec<u8>>,
+    /// The proof of each of the storage keys, in the storage trie of the account.
+    pub storage: Vec<Proof<Vec<u8>>>,
+}
+
+/// What an [AccountProof] proves: the account, or `None` if it does not exist, and the value
+/// of each of the storage keys.
+pub type ProvenAccount = (Option<Account>, Vec<Option<Vec<u8>>>);
+
+impl AccountProof {
+    /// Check the proof of the account at `address` and of its storage `keys` against
+    /// `kv_root_hash`, the root hash of the default keyspace.
+    pub fn verify<A: AsRef<[u8]>, K: AsRef<[u8]>>(
+        &self,
+        address: A,
+        keys: &[K],
+        kv_root_hash: &TrieHash,
+    ) -> Result<ProvenAccount, ProofError> {
+        if self.storage.len() != keys.len() {
+            return Err(ProofError::InvalidProof);
+        }
+
+        let account = if self.account.0.is_empty() {
+            // only an empty trie has nothing to prove with
+            if kv_root_hash != Merkle::<()>::empty_root() {
+                return Err(ProofError::ProofNodeMissing);
+            }
+            None
+        } else {
+            self.account
+                .verify_proof(address, kv_root_hash.0)?
+                .map(|raw| Account::decode(&raw))
+                .transpose()
+                .map_err(|_| ProofError::InvalidData)?
+        };
+
+        let storage = match &account {
+            Some(account) if &account.storage_root != Merkle::<()>::empty_root() => self
+                .storage
+                .iter()
+                .zip(keys)
+                .map(|(proof, key)| proof.verify_proof(key, account.storage_root.0))
+                .collect::<Result<_, _>>()?,
+            _ => vec![None; keys.len()],
+        };
+
+        Ok((account, storage))
+    }
+}
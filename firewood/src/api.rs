@@ -4,7 +4,6 @@
 use std::io::Write;
 
 use crate::db::DbError;
-use crate::merkle::MerkleError;
 use crate::merkle::TrieHash;
 use crate::v2::api::Proof;
 
@@ -27,15 +26,28 @@ where
 
     async fn kv_dump<W: Write + Send + Sync>(&self, writer: W) -> Result<(), DbError>;
     async fn root_hash(&self) -> Result<TrieHash, DbError>;
-    async fn dump<W: Write + Send + Sync>(&self, writer: W) -> Result<(), DbError>;
 
-    async fn prove<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Proof<N>, MerkleError>;
-    async fn verify_range_proof<K: AsRef<[u8]> + Send + Sync>(
+    async fn prove<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Proof<N>, DbError>;
+
+    #[cfg(feature = "eth")]
+    async fn dump_account<W: Write + Send + Sync, K: AsRef<[u8]> + Send + Sync>(
+        &self,
+        key: K,
+        writer: W,
+    ) -> Result<(), DbError>;
+    #[cfg(feature = "eth")]
+    async fn get_balance<K: AsRef<[u8]> + Send + Sync>(
+        &self,
+        key: K,
+    ) -> Result<primitive_types::U256, DbError>;
+    #[cfg(feature = "eth")]
+    async fn get_code<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Vec<u8>, DbError>;
+    #[cfg(feature = "eth")]
+    async fn get_nonce<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Nonce, DbError>;
+    #[cfg(feature = "eth")]
+    async fn get_state<K: AsRef<[u8]> + Send + Sync>(
         &self,
-        proof: Proof<N>,
-        first_key: K,
-        last_key: K,
-        keys: Vec<K>,
-        values: Vec<K>,
-    );
+        key: K,
+        sub_key: K,
+    ) -> Result<Vec<u8>, DbError>;
 }
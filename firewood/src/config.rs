@@ -1,6 +1,8 @@
 // Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
 // See the file LICENSE.md for licensing terms.
 
+pub use crate::merkle::TrieEncoding;
+pub use crate::nibbles::BranchFactor;
 pub use crate::storage::{buffer::DiskBufferConfig, WalConfig};
 use typed_builder::TypedBuilder;
 
@@ -45,10 +47,54 @@ pub struct DbConfig {
     /// the power of 2 for the file size.
     #[builder(default = 22)] // 4MB file by default
     pub root_hash_file_nbit: u64,
+    /// How trie nodes are encoded to be hashed, which decides the root hashes of the DB. Like
+    /// the file sizes, it is fixed when the DB is created.
+    #[builder(default)]
+    pub trie_encoding: TrieEncoding,
+    /// How many children the branch nodes of the DB's tries have. Only the Firewood encoding
+    /// supports factors other than [BranchFactor::Sixteen]. Like the trie encoding, it is fixed
+    /// when the DB is created.
+    #[builder(default)]
+    pub branch_factor: BranchFactor,
+    /// Whether keys are hashed with Keccak-256 before they are put in the DB's tries, which keeps
+    /// them balanced however much keys share prefixes. The original keys are recorded in a
+    /// preimage table, so iteration and dumps still give them, while proofs are of the hashed
+    /// keys. Like the trie encoding, it is fixed when the DB is created.
+    #[builder(default = false)]
+    pub secure_keys: bool,
+    /// Values longer than this many bytes are stored out of line, in blobs shared by equal
+    /// values, so that large values such as contract code don't bloat the nodes they are in and
+    /// aren't rewritten with them. Root hashes don't depend on it. 0 keeps every value in its
+    /// node, otherwise it must be at least [crate::merkle::MIN_BLOB_THRESHOLD]. Like the trie
+    /// encoding, it is fixed when the DB is created.
+    #[builder(default = 4096)]
+    pub blob_threshold: u64,
     /// Whether to truncate the DB when opening it. If set, the DB will be reset and all its
     /// existing contents will be lost.
     #[builder(default = false)]
     pub truncate: bool,
+    /// Whether to keep the WAL record of every commit in an append-only history store, so that
+    /// [Db::get_revision](crate::db::Db::get_revision) can reconstruct any past revision, not
+    /// just the ones in the rolling window. The history grows without bound. Like the trie
+    /// encoding, it is fixed when the DB is created, so that no commit is left out of the history.
+    #[builder(default = false)]
+    pub archival: bool,
+    /// Whether to open an existing DB for inspection only, alongside a process that may be
+    /// writing to it. No file is created, locked or written, the WAL is replayed in memory, and
+    /// proposals are refused. Archival history is not available through it.
+    ///
+    /// The view is best-effort, not consistent: it is not pinned to a revision. It starts from
+    /// the latest commit when the DB is opened, but pages that the writer flushes afterwards are
+    /// read as they are on disk, so a lookup may mix that commit with later ones, or fail on a
+    /// node that has since moved. Use it for inspection and debugging, reopen it rather than
+    /// keeping it around, and take a [Db::checkpoint](crate::db::Db::checkpoint) for a
+    /// consistent copy.
+    #[builder(default = false)]
+    pub read_only: bool,
+    /// Maximum bytes of past revisions kept in memory for pinned revisions that have left the
+    /// rolling window. The oldest pinned revisions are released first when this is exceeded.
+    #[builder(default = 1 << 30)] // 1G by default
+    pub max_pinned_bytes: u64,
     /// Config for accessing a version of the DB.
     #[builder(default = DbRevConfig::builder().build())]
     pub rev: DbRevConfig,
@@ -66,4 +112,9 @@ pub struct DbRevConfig {
     /// Maximum cached Trie objects.
     #[builder(default = 1 << 20)]
     pub merkle_ncached_objs: usize,
+    /// Threads that the root hash of a trie is computed on: the subtrees under its top branch
+    /// node that changed are hashed in parallel, on a pool of threads shared by every trie of the
+    /// DB. 1 hashes on the committing thread alone.
+    #[builder(default = 1)]
+    pub hash_threads: usize,
 }
@@ -2,21 +2,23 @@
 // See the file LICENSE.md for licensing terms.
 
 pub use crate::{
-    config::{DbConfig, DbRevConfig},
+    config::{BranchFactor, DbConfig, DbRevConfig, TrieEncoding},
     storage::{buffer::DiskBufferConfig, WalConfig},
 };
 use crate::{
     file,
-    merkle::{Merkle, MerkleError, Node, TrieHash, TRIE_HASH_LEN},
+    merkle::{HashPool, Merkle, MerkleError, Node, TrieHash, MIN_BLOB_THRESHOLD},
     proof::ProofError,
     storage::{
         buffer::{DiskBuffer, DiskBufferRequester},
-        CachedSpace, MemStoreR, SpaceWrite, StoreConfig, StoreDelta, StoreRevMut, StoreRevShared,
-        ZeroStore, PAGE_SIZE_NBIT,
+        CachedSpace, MemStoreR, SpaceWrite, StoreConfig, StoreDelta, StoreError, StoreRevMut,
+        StoreRevShared, PAGE_SIZE_NBIT,
     },
     v2::api::Proof,
 };
+use aiofut::AioError;
 use bytemuck::{cast_slice, AnyBitPattern};
+use growthring::walerror::WalError;
 use metered::{metered, HitCount};
 use parking_lot::{Mutex, RwLock};
 use shale::{
@@ -27,74 +29,160 @@ use shale::{
 use std::{
     collections::VecDeque,
     error::Error,
-    fmt,
-    io::{Cursor, Write},
+    io::{Cursor, Read, Write},
     mem::size_of,
     num::NonZeroUsize,
     os::fd::{AsFd, BorrowedFd},
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
     thread::JoinHandle,
 };
 
+mod account;
+mod blob;
+mod history;
+mod keyspace;
+mod lock;
+mod migrate;
+mod pin;
+mod preimage;
 mod proposal;
+mod root_index;
 
+pub use migrate::MigrationPlan;
+pub use pin::{PinStats, RevisionPin};
 pub use proposal::{Batch, BatchOp, Proposal};
+pub use root_index::MAX_METADATA_LEN;
 
-use self::proposal::ProposalBase;
+use self::{
+    account::AccountHeader, blob::BlobHeader, history::History, keyspace::KeyspaceHeader,
+    lock::DirLock, pin::PinTable, preimage::PreimageHeader, proposal::ProposalBase,
+    root_index::RootHashIndex,
+};
 
 const MERKLE_META_SPACE: SpaceId = 0x0;
 const MERKLE_PAYLOAD_SPACE: SpaceId = 0x1;
 const ROOT_HASH_SPACE: SpaceId = 0x2;
 const SPACE_RESERVED: u64 = 0x1000;
 
-const MAGIC_STR: &[u8; 16] = b"firewood v0.1\0\0\0";
+const MAGIC_STR: &[u8; 16] = b"firewood\0\0\0\0\0\0\0\0";
+/// The part of [MAGIC_STR] shared with the magic of every older format.
+const MAGIC_PREFIX: &[u8] = b"firewood";
+
+/// The version of the on-disk format written by this build. Databases in an older format can
+/// be opened read-only, and are brought up to date with [Db::migrate].
+///
+/// * 0: the unversioned "firewood v0.1" format
+/// * 1: the format version and node encoding are recorded in [DbParams], which has a slot of
+///   its own ahead of the DB headers
+/// * 2: the DB may hold keyspaces, see [BatchOp::KeyspacePut]
+/// * 3: the DB may hold the storage and code of accounts, next to the account header
+/// * 4: the trie encoding is recorded in [DbParams], see [DbConfig::trie_encoding]
+/// * 5: the branch factor is recorded in [DbParams], see [DbConfig::branch_factor], and extension
+///   and leaf nodes may have paths of more than 255 bytes, which the keys of tries with a branch
+///   factor under 16 quickly reach
+/// * 6: whether keys are hashed is recorded in [DbParams], see [DbConfig::secure_keys]
+/// * 7: long values may be stored in blobs, after the blob header, see
+///   [DbConfig::blob_threshold]
+pub const FORMAT_VERSION: u64 = 7;
+
+/// [DbParams] has a slot of its own at the start of the meta space, so parameters can be added
+/// without moving the headers that follow. In older DBs, the added parameters read as zero.
+const PARAMS_SLOT: u64 = 0x100;
+
+/// The headers that follow [DbParams] in the meta space.
+type HeaderRefs = (
+    Obj<DbHeader>,
+    Obj<CompactSpaceHeader>,
+    Obj<KeyspaceHeader>,
+    Obj<AccountHeader>,
+    Obj<PreimageHeader>,
+    Obj<BlobHeader>,
+);
 
 type Store = CompactSpace<Node, StoreRevMut>;
 type SharedStore = CompactSpace<Node, StoreRevShared>;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum DbError {
+    #[error("invalid parameters provided")]
     InvalidParams,
-    Merkle(MerkleError),
-    System(nix::Error),
+    #[error("merkle error: {0:?}")]
+    Merkle(#[from] MerkleError),
+    #[error("system error: {0:?}")]
+    System(#[source] nix::Error),
+    #[error("not found")]
     KeyNotFound,
+    #[error("database create error")]
     CreateError,
-    Shale(ShaleError),
-    IO(std::io::Error),
+    #[error("shale error: {0:?}")]
+    Shale(#[from] ShaleError),
+    #[error("I/O error: {0:?}")]
+    IO(#[from] std::io::Error),
+    #[error("invalid proposal")]
     InvalidProposal,
+    #[error("storage error: {0}")]
+    Store(#[source] Box<dyn Error + Send + Sync>),
+    #[error("wal error: {0}")]
+    Wal(#[from] WalError),
+    #[error("archival history does not match the database")]
+    HistoryMismatch,
+    #[error("commit metadata is longer than {MAX_METADATA_LEN} bytes")]
+    MetadataTooLarge,
+    #[cfg(feature = "eth")]
+    #[error("invalid account: {0}")]
+    InvalidAccount(rlp::DecoderError),
+    #[error("keyspace {0:?} does not exist")]
+    KeyspaceNotFound(String),
+    #[error("revision {0:?} is not in the rolling window")]
+    RevisionNotFound(TrieHash),
+    #[error("the database is open read-only")]
+    ReadOnly,
+    #[error(
+        "the database is already open for writing{}",
+        pid.map(|pid| format!(" by process {pid}")).unwrap_or_default()
+    )]
+    Locked { pid: Option<u32> },
+    #[error("not a firewood database (magic {0:?})")]
+    BadMagic(String),
+    #[error("unsupported database format {0:?}")]
+    UnsupportedFormat(String),
+    #[error(
+        "the database has format version {found}, migrate it to version {FORMAT_VERSION} to open it for writing"
+    )]
+    MigrationRequired { found: u64 },
+    #[error("migration failed: {0}")]
+    MigrationFailed(String),
+    #[error("corrupted database parameters: {0}")]
+    CorruptedParams(String),
+    #[error(
+        "{path:?} is {len} bytes, more than the 2^{file_nbit} bytes files of this database have"
+    )]
+    FileNbitMismatch {
+        path: PathBuf,
+        len: u64,
+        file_nbit: u64,
+    },
+    #[error("cannot start the disk buffer: {0:?}")]
+    DiskBuffer(AioError),
+    #[error("cannot replay the WAL in {path:?}: {source}")]
+    WalReplay {
+        path: PathBuf,
+        #[source]
+        source: WalError,
+    },
 }
 
-impl fmt::Display for DbError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            DbError::InvalidParams => write!(f, "invalid parameters provided"),
-            DbError::Merkle(e) => write!(f, "merkle error: {e:?}"),
-            DbError::System(e) => write!(f, "system error: {e:?}"),
-            DbError::KeyNotFound => write!(f, "not found"),
-            DbError::CreateError => write!(f, "database create error"),
-            DbError::IO(e) => write!(f, "I/O error: {e:?}"),
-            DbError::Shale(e) => write!(f, "shale error: {e:?}"),
-            DbError::InvalidProposal => write!(f, "invalid proposal"),
-        }
-    }
-}
-
-impl From<std::io::Error> for DbError {
-    fn from(e: std::io::Error) -> Self {
-        DbError::IO(e)
-    }
-}
-
-impl From<ShaleError> for DbError {
-    fn from(e: ShaleError) -> Self {
-        DbError::Shale(e)
+impl<T> From<StoreError<T>> for DbError
+where
+    StoreError<T>: Error + Send + Sync + 'static,
+{
+    fn from(e: StoreError<T>) -> Self {
+        DbError::Store(Box::new(e))
     }
 }
 
-impl Error for DbError {}
-
 /// DbParams contains the constants that are fixed upon the creation of the DB, this ensures the
 /// correct parameters are used when the DB is opened later (the parameters here will override the
 /// parameters in [DbConfig] if the DB already exists).
@@ -102,12 +190,216 @@ impl Error for DbError {}
 #[derive(Debug, Clone, Copy, AnyBitPattern, bytemuck::NoUninit)]
 struct DbParams {
     magic: [u8; 16],
+    format_version: u64,
+    /// See [Node::ENCODING_VERSION].
+    node_encoding: u64,
     meta_file_nbit: u64,
     payload_file_nbit: u64,
     payload_regn_nbit: u64,
     wal_file_nbit: u64,
     wal_block_nbit: u64,
     root_hash_file_nbit: u64,
+    /// See [TrieEncoding], which is [TrieEncoding::Firewood] in DBs created before it was
+    /// recorded.
+    trie_encoding: u64,
+    /// See [BranchFactor], which is [BranchFactor::Sixteen] in DBs created before it was
+    /// recorded, where this is 0.
+    branch_factor: u64,
+    /// 1 if keys are hashed, see [DbConfig::secure_keys], and 0 otherwise.
+    secure_keys: u64,
+    /// See [DbConfig::blob_threshold], which is 0 in DBs created before values could be stored
+    /// in blobs.
+    blob_threshold: u64,
+    /// 1 if the DB keeps an archival history, see [DbConfig::archival], and 0 otherwise.
+    archival: u64,
+}
+
+/// How the tries of a DB are laid out and hashed, which is fixed when it is created.
+#[derive(Debug, Clone, Copy)]
+struct TrieOptions {
+    encoding: TrieEncoding,
+    branch_factor: BranchFactor,
+    secure_keys: bool,
+    blob_threshold: u64,
+}
+
+const _: () = assert!(size_of::<DbParams>() as u64 <= PARAMS_SLOT);
+const _: () = assert!(
+    PARAMS_SLOT
+        + DbHeader::MSIZE
+        + CompactSpaceHeader::MSIZE
+        + KeyspaceHeader::MSIZE
+        + AccountHeader::MSIZE
+        + PreimageHeader::MSIZE
+        + BlobHeader::MSIZE
+        <= SPACE_RESERVED
+);
+
+impl DbParams {
+    /// Read the parameters of the DB in `db_path`.
+    fn read(db_path: &Path) -> Result<Self, DbError> {
+        let mut raw = Vec::with_capacity(PARAMS_SLOT as usize);
+        std::fs::File::open(db_path.join("merkle/meta/00000000.fw"))?
+            .take(PARAMS_SLOT)
+            .read_to_end(&mut raw)?;
+        Self::decode(&raw)
+    }
+
+    /// Decode and validate the parameters from the start of the meta space, in any format
+    /// version.
+    fn decode(raw: &[u8]) -> Result<Self, DbError> {
+        let params = match migrate::legacy_params(raw) {
+            Some(params) => params,
+            None => {
+                // a short read leaves zeros that fail validation
+                let mut slot = [0; size_of::<DbParams>()];
+                let len = raw.len().min(slot.len());
+                slot[..len].copy_from_slice(&raw[..len]);
+                cast_slice(&slot)[0]
+            }
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// The encoding the tries of the DB are hashed in.
+    fn trie_encoding(&self) -> TrieEncoding {
+        // checked by [DbParams::validate]
+        TrieEncoding::try_from(self.trie_encoding).unwrap_or_default()
+    }
+
+    /// The number of children of the branch nodes of the tries of the DB.
+    fn branch_factor(&self) -> BranchFactor {
+        // checked by [DbParams::validate]
+        match self.branch_factor {
+            0 => BranchFactor::Sixteen,
+            n => BranchFactor::try_from(n).unwrap_or_default(),
+        }
+    }
+
+    /// How the tries of the DB are laid out and hashed.
+    fn trie_options(&self) -> TrieOptions {
+        TrieOptions {
+            encoding: self.trie_encoding(),
+            branch_factor: self.branch_factor(),
+            secure_keys: self.secure_keys == 1,
+            blob_threshold: self.blob_threshold,
+        }
+    }
+
+    /// Where the [DbHeader] is in the meta space, followed by the [CompactSpaceHeader], the
+    /// [KeyspaceHeader], the [AccountHeader], the [PreimageHeader] and the [BlobHeader].
+    fn header_offset(&self) -> u64 {
+        match self.format_version {
+            0 => migrate::LEGACY_PARAM_SIZE,
+            _ => PARAMS_SLOT,
+        }
+    }
+
+    /// Check the parameters read back from an existing DB, so that bad on-disk data is reported
+    /// instead of tripping up the layers below.
+    fn validate(&self) -> Result<(), DbError> {
+        if &self.magic != MAGIC_STR {
+            let found = String::from_utf8_lossy(&self.magic)
+                .trim_end_matches('\0')
+                .to_string();
+            return Err(if self.magic.starts_with(MAGIC_PREFIX) {
+                DbError::UnsupportedFormat(found)
+            } else {
+                DbError::BadMagic(found)
+            });
+        }
+        if self.format_version > FORMAT_VERSION {
+            return Err(DbError::UnsupportedFormat(format!(
+                "format version {}",
+                self.format_version
+            )));
+        }
+        if self.node_encoding != Node::ENCODING_VERSION {
+            return Err(DbError::UnsupportedFormat(format!(
+                "node encoding {}",
+                self.node_encoding
+            )));
+        }
+        if let Err(id) = TrieEncoding::try_from(self.trie_encoding) {
+            return Err(DbError::UnsupportedFormat(format!("trie encoding {id}")));
+        }
+        if self.branch_factor != 0 {
+            if let Err(n) = BranchFactor::try_from(self.branch_factor) {
+                return Err(DbError::UnsupportedFormat(format!("branch factor {n}")));
+            }
+        }
+        if self.secure_keys > 1 {
+            return Err(DbError::CorruptedParams(format!(
+                "secure_keys of {} is not a flag",
+                self.secure_keys
+            )));
+        }
+        if self.archival > 1 {
+            return Err(DbError::CorruptedParams(format!(
+                "archival of {} is not a flag",
+                self.archival
+            )));
+        }
+        if self.blob_threshold != 0 && self.blob_threshold < MIN_BLOB_THRESHOLD {
+            return Err(DbError::CorruptedParams(format!(
+                "blob_threshold of {} is too small",
+                self.blob_threshold
+            )));
+        }
+
+        let out_of_range = |name: &str, nbit: u64| {
+            DbError::CorruptedParams(format!("{name} of {nbit} is out of range"))
+        };
+        for (name, nbit) in [
+            ("meta_file_nbit", self.meta_file_nbit),
+            ("payload_file_nbit", self.payload_file_nbit),
+            ("root_hash_file_nbit", self.root_hash_file_nbit),
+        ] {
+            if !(PAGE_SIZE_NBIT..64).contains(&nbit) {
+                return Err(out_of_range(name, nbit));
+            }
+        }
+        if !(PAGE_SIZE_NBIT..=self.payload_file_nbit).contains(&self.payload_regn_nbit) {
+            return Err(out_of_range("payload_regn_nbit", self.payload_regn_nbit));
+        }
+        if !(MIN_WAL_BLOCK_NBIT..64).contains(&self.wal_file_nbit) {
+            return Err(out_of_range("wal_file_nbit", self.wal_file_nbit));
+        }
+        // a WAL block must hold a ring header and be smaller than a WAL file
+        if !(MIN_WAL_BLOCK_NBIT..self.wal_file_nbit).contains(&self.wal_block_nbit) {
+            return Err(out_of_range("wal_block_nbit", self.wal_block_nbit));
+        }
+
+        Ok(())
+    }
+}
+
+const MIN_WAL_BLOCK_NBIT: u64 = 5;
+
+/// Make sure that none of the files in `dir` with the extension `ext` is larger than the files
+/// of a space with `file_nbit`, which is what opening a DB with the wrong parameters looks like.
+fn check_file_nbit(dir: &Path, ext: &str, file_nbit: u64) -> Result<(), DbError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != ext) {
+            continue;
+        }
+        let len = std::fs::metadata(&path)?.len();
+        if len > 1 << file_nbit {
+            return Err(DbError::FileNbitMismatch {
+                path,
+                len,
+                file_nbit,
+            });
+        }
+    }
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
@@ -269,12 +561,20 @@ impl<T: MemStoreR + 'static> Universe<Arc<T>> {
 #[derive(Debug)]
 pub struct DbRev<S> {
     header: shale::Obj<DbHeader>,
+    keyspaces: shale::Obj<KeyspaceHeader>,
+    accounts: shale::Obj<AccountHeader>,
+    preimages: shale::Obj<PreimageHeader>,
+    blobs: shale::Obj<BlobHeader>,
     merkle: Merkle<S>,
 }
 
 impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
     fn flush_dirty(&mut self) -> Option<()> {
         self.header.flush_dirty();
+        self.keyspaces.flush_dirty();
+        self.accounts.flush_dirty();
+        self.preimages.flush_dirty();
+        self.blobs.flush_dirty();
         self.merkle.flush_dirty()?;
         Some(())
     }
@@ -283,11 +583,16 @@ impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
         (&mut self.header, &mut self.merkle)
     }
 
-    /// Get root hash of the generic key-value storage.
-    pub fn kv_root_hash(&self) -> Result<TrieHash, DbError> {
-        self.merkle
-            .root_hash(self.header.kv_root)
-            .map_err(DbError::Merkle)
+    /// Get root hash of `keyspace`, or of the generic key-value storage, the default keyspace,
+    /// if it is `None`.
+    pub fn kv_root_hash(&self, keyspace: Option<&str>) -> Result<TrieHash, DbError> {
+        let root = match keyspace {
+            None => self.header.kv_root,
+            Some(keyspace) => self
+                .keyspace_root(keyspace)?
+                .ok_or_else(|| DbError::KeyspaceNotFound(keyspace.to_string()))?,
+        };
+        self.merkle.root_hash(root).map_err(DbError::Merkle)
     }
 
     /// Get a value associated with a key.
@@ -310,6 +615,11 @@ impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
         self.merkle.prove::<K>(key, self.header.kv_root)
     }
 
+    /// Prove many keys of the generic key-value storage at once, see [Merkle::prove_many].
+    pub fn prove_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Proof<Vec<u8>>, MerkleError> {
+        self.merkle.prove_many(keys, self.header.kv_root)
+    }
+
     /// Verifies a range proof is valid for a set of keys.
     pub fn verify_range_proof<N: AsRef<[u8]> + Send, K: AsRef<[u8]>, V: AsRef<[u8]>>(
         &self,
@@ -319,7 +629,7 @@ impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
         keys: Vec<K>,
         values: Vec<V>,
     ) -> Result<bool, ProofError> {
-        let hash: [u8; 32] = *self.kv_root_hash()?;
+        let hash: [u8; 32] = *self.kv_root_hash(None)?;
         let valid = proof.verify_range_proof(hash, first_key, last_key, keys, values)?;
         Ok(valid)
     }
@@ -332,7 +642,17 @@ struct DbInner {
     cached_space: Universe<Arc<CachedSpace>>,
     // Whether to reset the store headers when creating a new store on top of the cached space.
     reset_store_headers: bool,
+    // Where the DbHeader is in the meta space, which depends on the format version.
+    header_offset: u64,
+    trie_options: TrieOptions,
+    // Shared by the merkles of every revision and proposal, see [DbRevConfig::hash_threads].
+    hash_pool: Option<Arc<HashPool>>,
+    root_hash_cache: Arc<CachedSpace>,
     root_hash_staging: StoreRevMut,
+    // Only kept in archival mode.
+    history: Option<History>,
+    // Released after the disk thread is done. A read-only DB does not take it.
+    _lock: Option<DirLock>,
 }
 
 impl Drop for DbInner {
@@ -345,7 +665,10 @@ impl Drop for DbInner {
 #[derive(Debug)]
 pub struct DbRevInner<T> {
     inner: VecDeque<Universe<StoreRevShared>>,
-    root_hashes: VecDeque<TrieHash>,
+    root_index: RootHashIndex,
+    pins: PinTable,
+    // Bumped by every rollback, which invalidates all outstanding proposals
+    generation: u64,
     max_revisions: usize,
     base: Universe<StoreRevShared>,
     base_revision: Arc<DbRev<T>>,
@@ -359,40 +682,60 @@ pub struct Db {
     payload_regn_nbit: u64,
     metrics: Arc<DbMetrics>,
     cfg: DbConfig,
+    path: PathBuf,
 }
 
 #[metered(registry = DbMetrics, visibility = pub)]
 impl Db {
-    const PARAM_SIZE: u64 = size_of::<DbParams>() as u64;
-
     /// Open a database.
     pub fn new<P: AsRef<Path>>(db_path: P, cfg: &DbConfig) -> Result<Self, DbError> {
-        // TODO: make sure all fds are released at the end
-        if cfg.truncate {
-            let _ = std::fs::remove_dir_all(db_path.as_ref());
+        if cfg.read_only && cfg.truncate {
+            return Err(DbError::InvalidParams);
         }
 
-        let open_options = if cfg.truncate {
-            file::Options::Truncate
+        // TODO: make sure all fds are released at the end
+        let (db_path, reset, dir_lock) = if cfg.read_only {
+            (db_path.as_ref().to_path_buf(), false, None)
         } else {
-            file::Options::NoTruncate
+            let (db_path, created) = file::open_dir(db_path, file::Options::NoTruncate)?;
+            // lock before truncating, so a second writer cannot wipe the DB under the first one
+            let dir_lock = DirLock::acquire(&db_path)?;
+            if cfg.truncate && !created {
+                file::clear_dir(&db_path, &[lock::LOCK_FILE])?;
+            }
+            (db_path, created || cfg.truncate, Some(dir_lock))
         };
 
-        let (db_path, reset) = file::open_dir(db_path, open_options)?;
+        // a read-only DB must already exist, so nothing is created
+        let subdir = |dirname: &str, rootdir: &Path| {
+            if cfg.read_only {
+                Ok(rootdir.join(dirname))
+            } else {
+                file::touch_dir(dirname, rootdir)
+            }
+        };
 
-        let merkle_path = file::touch_dir("merkle", &db_path)?;
-        let merkle_meta_path = file::touch_dir("meta", &merkle_path)?;
-        let merkle_payload_path = file::touch_dir("compact", &merkle_path)?;
+        let merkle_path = subdir("merkle", &db_path)?;
+        let merkle_meta_path = subdir("meta", &merkle_path)?;
+        let merkle_payload_path = subdir("compact", &merkle_path)?;
 
-        let root_hash_path = file::touch_dir("root_hash", &db_path)?;
+        let root_hash_path = subdir("root_hash", &db_path)?;
 
-        let file0 = crate::file::File::new(0, SPACE_RESERVED, &merkle_meta_path)?;
+        let file0 = if cfg.read_only {
+            crate::file::File::open_read_only(0, &merkle_meta_path)?
+        } else {
+            crate::file::File::new(0, SPACE_RESERVED, &merkle_meta_path)?
+        };
         let fd0 = file0.as_fd();
 
         if reset {
             // initialize dbparams
+            // only the Firewood encoding has branch nodes of other widths
             if cfg.payload_file_nbit < cfg.payload_regn_nbit
                 || cfg.payload_regn_nbit < PAGE_SIZE_NBIT
+                || (cfg.branch_factor != BranchFactor::Sixteen
+                    && cfg.trie_encoding != TrieEncoding::Firewood)
+                || (cfg.blob_threshold != 0 && cfg.blob_threshold < MIN_BLOB_THRESHOLD)
             {
                 return Err(DbError::InvalidParams);
             }
@@ -400,10 +743,26 @@ impl Db {
         }
 
         // read DbParams
-        let mut header_bytes = [0; size_of::<DbParams>()];
-        nix::sys::uio::pread(fd0, &mut header_bytes, 0).map_err(DbError::System)?;
+        let mut header_bytes = [0; PARAMS_SLOT as usize];
+        let len = nix::sys::uio::pread(fd0, &mut header_bytes, 0).map_err(DbError::System)?;
         drop(file0);
-        let params: DbParams = cast_slice(&header_bytes)[0];
+        let params = DbParams::decode(&header_bytes[..len])?;
+        if params.format_version < FORMAT_VERSION && !cfg.read_only {
+            return Err(DbError::MigrationRequired {
+                found: params.format_version,
+            });
+        }
+        let header_offset = params.header_offset();
+
+        let wal_path = db_path.join("wal");
+        for (dir, ext, file_nbit) in [
+            (&merkle_meta_path, "fw", params.meta_file_nbit),
+            (&merkle_payload_path, "fw", params.payload_file_nbit),
+            (&root_hash_path, "fw", params.root_hash_file_nbit),
+            (&wal_path, "log", params.wal_file_nbit),
+        ] {
+            check_file_nbit(dir, ext, file_nbit)?;
+        }
 
         let wal = WalConfig::builder()
             .file_nbit(params.wal_file_nbit)
@@ -412,55 +771,47 @@ impl Db {
             .build();
         let (sender, inbound) = tokio::sync::mpsc::channel(cfg.buffer.max_buffered);
         let disk_requester = DiskBufferRequester::new(sender);
-        let buffer = cfg.buffer.clone();
-        let disk_thread = Some(std::thread::spawn(move || {
-            let disk_buffer = DiskBuffer::new(inbound, &buffer, &wal).unwrap();
-            disk_buffer.run()
-        }));
-
-        let root_hash_cache = Arc::new(
-            CachedSpace::new(
-                &StoreConfig::builder()
-                    .ncached_pages(cfg.root_hash_ncached_pages)
-                    .ncached_files(cfg.root_hash_ncached_files)
-                    .space_id(ROOT_HASH_SPACE)
-                    .file_nbit(params.root_hash_file_nbit)
-                    .rootdir(root_hash_path)
-                    .build(),
-                disk_requester.clone(),
-            )
-            .unwrap(),
-        );
+        let disk_buffer =
+            DiskBuffer::new(inbound, &cfg.buffer, &wal).map_err(DbError::DiskBuffer)?;
+        let disk_thread = Some(std::thread::spawn(move || disk_buffer.run()));
+
+        let root_hash_cache = Arc::new(CachedSpace::new(
+            &StoreConfig::builder()
+                .ncached_pages(cfg.root_hash_ncached_pages)
+                .ncached_files(cfg.root_hash_ncached_files)
+                .space_id(ROOT_HASH_SPACE)
+                .file_nbit(params.root_hash_file_nbit)
+                .rootdir(root_hash_path)
+                .read_only(cfg.read_only)
+                .build(),
+            disk_requester.clone(),
+        )?);
 
         // setup disk buffer
         let data_cache = Universe {
             merkle: SubUniverse::new(
-                Arc::new(
-                    CachedSpace::new(
-                        &StoreConfig::builder()
-                            .ncached_pages(cfg.meta_ncached_pages)
-                            .ncached_files(cfg.meta_ncached_files)
-                            .space_id(MERKLE_META_SPACE)
-                            .file_nbit(params.meta_file_nbit)
-                            .rootdir(merkle_meta_path)
-                            .build(),
-                        disk_requester.clone(),
-                    )
-                    .unwrap(),
-                ),
-                Arc::new(
-                    CachedSpace::new(
-                        &StoreConfig::builder()
-                            .ncached_pages(cfg.payload_ncached_pages)
-                            .ncached_files(cfg.payload_ncached_files)
-                            .space_id(MERKLE_PAYLOAD_SPACE)
-                            .file_nbit(params.payload_file_nbit)
-                            .rootdir(merkle_payload_path)
-                            .build(),
-                        disk_requester.clone(),
-                    )
-                    .unwrap(),
-                ),
+                Arc::new(CachedSpace::new(
+                    &StoreConfig::builder()
+                        .ncached_pages(cfg.meta_ncached_pages)
+                        .ncached_files(cfg.meta_ncached_files)
+                        .space_id(MERKLE_META_SPACE)
+                        .file_nbit(params.meta_file_nbit)
+                        .rootdir(merkle_meta_path)
+                        .read_only(cfg.read_only)
+                        .build(),
+                    disk_requester.clone(),
+                )?),
+                Arc::new(CachedSpace::new(
+                    &StoreConfig::builder()
+                        .ncached_pages(cfg.payload_ncached_pages)
+                        .ncached_files(cfg.payload_ncached_files)
+                        .space_id(MERKLE_PAYLOAD_SPACE)
+                        .file_nbit(params.payload_file_nbit)
+                        .rootdir(merkle_payload_path)
+                        .read_only(cfg.read_only)
+                        .build(),
+                    disk_requester.clone(),
+                )?),
             ),
         };
 
@@ -475,41 +826,66 @@ impl Db {
         });
 
         // recover from Wal
-        disk_requester.init_wal("wal", &db_path);
+        if cfg.read_only {
+            disk_requester.peek_wal("wal", &db_path)
+        } else {
+            disk_requester.init_wal("wal", &db_path)
+        }
+        .map_err(|source| DbError::WalReplay {
+            path: wal_path,
+            source,
+        })?;
 
-        let root_hash_staging = StoreRevMut::new(root_hash_cache);
+        let root_hash_staging = StoreRevMut::new(root_hash_cache.clone());
+        // one slot more than the window, so the revision being evicted can still be named
+        let root_index = RootHashIndex::load(&root_hash_staging, cfg.wal.max_revisions as u64 + 1)?;
         let reset_headers = reset;
 
         let base = Universe {
             merkle: get_sub_universe_from_empty_delta(&data_cache.merkle),
         };
 
-        let db_header_ref = Db::get_db_header_ref(&base.merkle.meta)?;
-
-        let merkle_payload_header_ref =
-            Db::get_payload_header_ref(&base.merkle.meta, Db::PARAM_SIZE + DbHeader::MSIZE)?;
-
-        let header_refs = (db_header_ref, merkle_payload_header_ref);
+        let header_refs = Db::get_header_refs(&base.merkle.meta, header_offset)?;
 
+        let hash_pool = match cfg.rev.hash_threads {
+            0 | 1 => None,
+            threads => Some(Arc::new(HashPool::new(threads)?)),
+        };
         let base_revision = Db::new_revision(
             header_refs,
             (base.merkle.meta.clone(), base.merkle.payload.clone()),
             params.payload_regn_nbit,
             cfg.payload_max_walk,
+            params.trie_options(),
+            hash_pool.as_ref(),
             &cfg.rev,
         )?;
 
+        let history = if params.archival == 1 && !cfg.read_only {
+            Some(History::open(&db_path, root_index.next_seq())?)
+        } else {
+            None
+        };
+
         Ok(Self {
             inner: Arc::new(RwLock::new(DbInner {
                 disk_thread,
                 disk_requester,
                 cached_space: data_cache,
                 reset_store_headers: reset_headers,
+                header_offset,
+                trie_options: params.trie_options(),
+                hash_pool,
+                root_hash_cache,
                 root_hash_staging,
+                history,
+                _lock: dir_lock,
             })),
             revisions: Arc::new(Mutex::new(DbRevInner {
                 inner: VecDeque::new(),
-                root_hashes: VecDeque::new(),
+                root_index,
+                pins: PinTable::new(cfg.max_pinned_bytes),
+                generation: 0,
                 max_revisions: cfg.wal.max_revisions as usize,
                 base,
                 base_revision: Arc::new(base_revision),
@@ -517,32 +893,45 @@ impl Db {
             payload_regn_nbit: params.payload_regn_nbit,
             metrics: Arc::new(DbMetrics::default()),
             cfg: cfg.clone(),
+            path: db_path,
         })
     }
 
     fn initialize_header_on_disk(cfg: &DbConfig, fd0: BorrowedFd) -> Result<(), DbError> {
-        // The header consists of three parts:
+        // The header consists of seven parts:
         // DbParams
         // DbHeader (just a pointer to the sentinel)
         // CompactSpaceHeader for future allocations
-        let (params, hdr, csh);
+        // KeyspaceHeader (just a pointer to the keyspace directory)
+        // AccountHeader (pointers to the account storage and code)
+        // PreimageHeader (just a pointer to the preimage table)
+        // BlobHeader (just a pointer to the blob table)
+        let (params, hdr, csh, ksh, ash, psh, bsh);
         let header_bytes: Vec<u8> = {
             params = DbParams {
                 magic: *MAGIC_STR,
+                format_version: FORMAT_VERSION,
+                node_encoding: Node::ENCODING_VERSION,
                 meta_file_nbit: cfg.meta_file_nbit,
                 payload_file_nbit: cfg.payload_file_nbit,
                 payload_regn_nbit: cfg.payload_regn_nbit,
                 wal_file_nbit: cfg.wal.file_nbit,
                 wal_block_nbit: cfg.wal.block_nbit,
                 root_hash_file_nbit: cfg.root_hash_file_nbit,
+                trie_encoding: cfg.trie_encoding as u64,
+                branch_factor: cfg.branch_factor as u64,
+                secure_keys: cfg.secure_keys as u64,
+                blob_threshold: cfg.blob_threshold,
+                archival: cfg.archival as u64,
             };
-            let bytes = bytemuck::bytes_of(&params);
-            bytes.iter()
+            let mut bytes = bytemuck::bytes_of(&params).to_vec();
+            bytes.resize(PARAMS_SLOT as usize, 0);
+            bytes.into_iter()
         }
         .chain({
             // compute the DbHeader as bytes
             hdr = DbHeader::new_empty();
-            bytemuck::bytes_of(&hdr)
+            bytemuck::bytes_of(&hdr).iter().copied()
         })
         .chain({
             // write out the CompactSpaceHeader
@@ -550,9 +939,24 @@ impl Db {
                 NonZeroUsize::new(SPACE_RESERVED as usize).unwrap(),
                 NonZeroUsize::new(SPACE_RESERVED as usize).unwrap(),
             );
-            bytemuck::bytes_of(&csh)
+            bytemuck::bytes_of(&csh).iter().copied()
+        })
+        .chain({
+            ksh = KeyspaceHeader::new_empty();
+            bytemuck::bytes_of(&ksh).iter().copied()
+        })
+        .chain({
+            ash = AccountHeader::new_empty();
+            bytemuck::bytes_of(&ash).iter().copied()
+        })
+        .chain({
+            psh = PreimageHeader::new_empty();
+            bytemuck::bytes_of(&psh).iter().copied()
+        })
+        .chain({
+            bsh = BlobHeader::new_empty();
+            bytemuck::bytes_of(&bsh).iter().copied()
         })
-        .copied()
         .collect();
 
         nix::sys::uio::pwrite(fd0, &header_bytes, 0).map_err(DbError::System)?;
@@ -562,16 +966,25 @@ impl Db {
     /// Create a new mutable store and an alterable revision of the DB on top.
     fn new_store(
         cached_space: &Universe<Arc<CachedSpace>>,
+        header_offset: u64,
         reset_store_headers: bool,
         payload_regn_nbit: u64,
+        trie_options: TrieOptions,
+        hash_pool: Option<&Arc<HashPool>>,
         cfg: &DbConfig,
     ) -> Result<(Universe<Arc<StoreRevMut>>, DbRev<Store>), DbError> {
-        let mut offset = Db::PARAM_SIZE as usize;
+        let mut offset = header_offset as usize;
         let db_header: DiskAddress = DiskAddress::from(offset);
         offset += DbHeader::MSIZE as usize;
         let merkle_payload_header: DiskAddress = DiskAddress::from(offset);
         offset += CompactSpaceHeader::MSIZE as usize;
-        assert!(offset <= SPACE_RESERVED as usize);
+        let keyspace_header: DiskAddress = DiskAddress::from(offset);
+        offset += KeyspaceHeader::MSIZE as usize;
+        let account_header: DiskAddress = DiskAddress::from(offset);
+        offset += AccountHeader::MSIZE as usize;
+        let preimage_header: DiskAddress = DiskAddress::from(offset);
+        offset += PreimageHeader::MSIZE as usize;
+        let blob_header: DiskAddress = DiskAddress::from(offset);
 
         let mut merkle_meta_store = StoreRevMut::new(cached_space.merkle.meta.clone());
 
@@ -588,6 +1001,22 @@ impl Db {
                 db_header.into(),
                 &shale::to_dehydrated(&DbHeader::new_empty())?,
             );
+            merkle_meta_store.write(
+                keyspace_header.into(),
+                &shale::to_dehydrated(&KeyspaceHeader::new_empty())?,
+            );
+            merkle_meta_store.write(
+                account_header.into(),
+                &shale::to_dehydrated(&AccountHeader::new_empty())?,
+            );
+            merkle_meta_store.write(
+                preimage_header.into(),
+                &shale::to_dehydrated(&PreimageHeader::new_empty())?,
+            );
+            merkle_meta_store.write(
+                blob_header.into(),
+                &shale::to_dehydrated(&BlobHeader::new_empty())?,
+            );
         }
 
         let store = Universe {
@@ -597,20 +1026,15 @@ impl Db {
             ),
         };
 
-        let db_header_ref = Db::get_db_header_ref(store.merkle.meta.as_ref())?;
-
-        let merkle_payload_header_ref = Db::get_payload_header_ref(
-            store.merkle.meta.as_ref(),
-            Db::PARAM_SIZE + DbHeader::MSIZE,
-        )?;
-
-        let header_refs = (db_header_ref, merkle_payload_header_ref);
+        let header_refs = Db::get_header_refs(store.merkle.meta.as_ref(), header_offset)?;
 
         let mut rev: DbRev<CompactSpace<Node, StoreRevMut>> = Db::new_revision(
             header_refs,
             (store.merkle.meta.clone(), store.merkle.payload.clone()),
             payload_regn_nbit,
             cfg.payload_max_walk,
+            trie_options,
+            hash_pool,
             &cfg.rev,
         )?;
         rev.flush_dirty().unwrap();
@@ -631,25 +1055,72 @@ impl Db {
         .map_err(Into::into)
     }
 
-    fn get_db_header_ref<K: CachedStore>(meta_ref: &K) -> Result<Obj<DbHeader>, DbError> {
-        let db_header = DiskAddress::from(Db::PARAM_SIZE as usize);
+    fn get_db_header_ref<K: CachedStore>(
+        meta_ref: &K,
+        header_offset: u64,
+    ) -> Result<Obj<DbHeader>, DbError> {
+        let db_header = DiskAddress::from(header_offset as usize);
         StoredView::ptr_to_obj(meta_ref, db_header, DbHeader::MSIZE).map_err(Into::into)
     }
 
+    /// Get every header of the DB whose [DbHeader] is at `header_offset`, in the order they
+    /// follow each other in the meta space.
+    fn get_header_refs<K: CachedStore>(
+        meta_ref: &K,
+        header_offset: u64,
+    ) -> Result<HeaderRefs, DbError> {
+        let db_header_ref = Db::get_db_header_ref(meta_ref, header_offset)?;
+        let mut offset = header_offset + DbHeader::MSIZE;
+        let merkle_payload_header_ref = Db::get_payload_header_ref(meta_ref, offset)?;
+        offset += CompactSpaceHeader::MSIZE;
+        let keyspace_header_ref = StoredView::ptr_to_obj(
+            meta_ref,
+            DiskAddress::from(offset as usize),
+            KeyspaceHeader::MSIZE,
+        )?;
+        offset += KeyspaceHeader::MSIZE;
+        let account_header_ref = StoredView::ptr_to_obj(
+            meta_ref,
+            DiskAddress::from(offset as usize),
+            AccountHeader::MSIZE,
+        )?;
+        offset += AccountHeader::MSIZE;
+        let preimage_header_ref = StoredView::ptr_to_obj(
+            meta_ref,
+            DiskAddress::from(offset as usize),
+            PreimageHeader::MSIZE,
+        )?;
+        offset += PreimageHeader::MSIZE;
+        let blob_header_ref = StoredView::ptr_to_obj(
+            meta_ref,
+            DiskAddress::from(offset as usize),
+            BlobHeader::MSIZE,
+        )?;
+        Ok((
+            db_header_ref,
+            merkle_payload_header_ref,
+            keyspace_header_ref,
+            account_header_ref,
+            preimage_header_ref,
+            blob_header_ref,
+        ))
+    }
+
     fn new_revision<K: CachedStore, T: Into<Arc<K>>>(
-        header_refs: (Obj<DbHeader>, Obj<CompactSpaceHeader>),
+        header_refs: HeaderRefs,
         merkle: (T, T),
         payload_regn_nbit: u64,
         payload_max_walk: u64,
+        trie_options: TrieOptions,
+        hash_pool: Option<&Arc<HashPool>>,
         cfg: &DbRevConfig,
     ) -> Result<DbRev<CompactSpace<Node, K>>, DbError> {
-        // TODO: This should be a compile time check
-        const DB_OFFSET: u64 = Db::PARAM_SIZE;
-        let merkle_offset = DB_OFFSET + DbHeader::MSIZE;
-        assert!(merkle_offset + CompactSpaceHeader::MSIZE <= SPACE_RESERVED);
-
         let mut db_header_ref = header_refs.0;
         let merkle_payload_header_ref = header_refs.1;
+        let keyspace_header_ref = header_refs.2;
+        let account_header_ref = header_refs.3;
+        let mut preimage_header_ref = header_refs.4;
+        let mut blob_header_ref = header_refs.5;
 
         let merkle_meta = merkle.0.into();
         let merkle_payload = merkle.1.into();
@@ -661,10 +1132,13 @@ impl Db {
             shale::ObjCache::new(cfg.merkle_ncached_objs),
             payload_max_walk,
             payload_regn_nbit,
-        )
-        .unwrap();
+        )?;
 
-        let merkle = Merkle::new(Box::new(merkle_space));
+        let mut merkle = Merkle::with_encoding(Box::new(merkle_space), trie_options.encoding)
+            .with_branch_factor(trie_options.branch_factor);
+        if let Some(pool) = hash_pool {
+            merkle = merkle.with_hash_pool(pool.clone());
+        }
 
         if db_header_ref.kv_root.is_null() {
             let mut err = Ok(());
@@ -680,20 +1154,71 @@ impl Db {
             err.map_err(DbError::Merkle)?
         }
 
+        if trie_options.secure_keys {
+            if preimage_header_ref.table.is_null() {
+                let table = merkle.init_root()?;
+                preimage_header_ref
+                    .write(|header| header.table = table)
+                    .unwrap();
+            }
+            merkle = merkle.with_preimages(preimage_header_ref.table);
+        }
+
+        if trie_options.blob_threshold != 0 {
+            if blob_header_ref.table.is_null() {
+                let table = merkle.init_root()?;
+                blob_header_ref
+                    .write(|header| header.table = table)
+                    .unwrap();
+            }
+            merkle = merkle.with_blobs(blob_header_ref.table, trie_options.blob_threshold);
+        }
+
         Ok(DbRev {
             header: db_header_ref,
+            keyspaces: keyspace_header_ref,
+            accounts: account_header_ref,
+            preimages: preimage_header_ref,
+            blobs: blob_header_ref,
             merkle,
         })
     }
 
-    /// Create a proposal.
+    /// Create a proposal. Fails with [DbError::ReadOnly] on a read-only DB.
     pub fn new_proposal<K: AsRef<[u8]>>(&self, data: Batch<K>) -> Result<Proposal, DbError> {
+        self.propose(|rev| proposal::apply_batch(rev, data))
+    }
+
+    /// Create a proposal whose default key-value trie is made of `items` alone, and which leaves
+    /// the keyspaces as they are. The keys must be in order, which is the order of their hashes
+    /// in a DB with secure keys, and the trie is built bottom-up as they come, which is much
+    /// faster than putting them with [Db::new_proposal]. Fails with [DbError::Merkle] if a key is
+    /// not after the one before it. With the `eth` feature, the storage of every account is
+    /// removed, as the accounts are replaced too.
+    pub fn new_proposal_from_sorted<K: AsRef<[u8]>>(
+        &self,
+        items: impl IntoIterator<Item = (K, Vec<u8>)>,
+    ) -> Result<Proposal, DbError> {
+        self.propose(|rev| proposal::build_kv(rev, items))
+    }
+
+    fn propose(
+        &self,
+        apply: impl FnOnce(&mut DbRev<Store>) -> Result<(), DbError>,
+    ) -> Result<Proposal, DbError> {
+        if self.cfg.read_only {
+            return Err(DbError::ReadOnly);
+        }
+
         let mut inner = self.inner.write();
         let reset_store_headers = inner.reset_store_headers;
         let (store, mut rev) = Db::new_store(
             &inner.cached_space,
+            inner.header_offset,
             reset_store_headers,
             self.payload_regn_nbit,
+            inner.trie_options,
+            inner.hash_pool.as_ref(),
             &self.cfg,
         )?;
 
@@ -702,27 +1227,11 @@ impl Db {
             inner.reset_store_headers = false;
         }
 
-        data.into_iter().try_for_each(|op| -> Result<(), DbError> {
-            match op {
-                BatchOp::Put { key, value } => {
-                    let (header, merkle) = rev.borrow_split();
-                    merkle
-                        .insert(key, value, header.kv_root)
-                        .map_err(DbError::Merkle)?;
-                    Ok(())
-                }
-                BatchOp::Delete { key } => {
-                    let (header, merkle) = rev.borrow_split();
-                    merkle
-                        .remove(key, header.kv_root)
-                        .map_err(DbError::Merkle)?;
-                    Ok(())
-                }
-            }
-        })?;
+        apply(&mut rev)?;
         rev.flush_dirty().unwrap();
 
-        let parent = ProposalBase::View(Arc::clone(&self.revisions.lock().base_revision));
+        let revisions = self.revisions.lock();
+        let parent = ProposalBase::View(Arc::clone(&revisions.base_revision));
         Ok(Proposal {
             m: Arc::clone(&self.inner),
             r: Arc::clone(&self.revisions),
@@ -730,113 +1239,353 @@ impl Db {
             rev,
             store,
             committed: Arc::new(Mutex::new(false)),
+            generation: revisions.generation,
             parent,
         })
     }
 
     /// Get a handle that grants the access to any committed state of the entire DB,
-    /// with a given root hash. If the given root hash matches with more than one
-    /// revisions, we use the most recent one as the trie are the same.
+    /// with a given root hash (see [Db::root_hash]). If the given root hash matches with more
+    /// than one revisions, we use the most recent one as the trie are the same.
     ///
     /// If no revision with matching root hash found, returns None.
     // #[measure([HitCount])]
     pub fn get_revision(&self, root_hash: &TrieHash) -> Option<Revision<SharedStore>> {
+        let (space, header_offset, trie_options, hash_pool) = {
+            let mut revisions = self.revisions.lock();
+            let inner_lock = self.inner.read();
+            (
+                Db::find_universe(&mut revisions, &inner_lock, root_hash)?,
+                inner_lock.header_offset,
+                inner_lock.trie_options,
+                inner_lock.hash_pool.clone(),
+            )
+        };
+
+        let header_refs = Db::get_header_refs(&space.merkle.meta, header_offset).ok()?;
+
+        Revision {
+            rev: Db::new_revision(
+                header_refs,
+                (space.merkle.meta.clone(), space.merkle.payload.clone()),
+                self.payload_regn_nbit,
+                0,
+                trie_options,
+                hash_pool.as_ref(),
+                &self.cfg.rev,
+            )
+            .ok()?,
+        }
+        .into()
+    }
+
+    /// Make the revision with `root_hash` the latest one again, by undoing every newer commit.
+    /// The undo is committed like a [Proposal], as a new WAL record, so the rollback is durable
+    /// and the rolled back revisions stay readable in the rolling window. All outstanding
+    /// proposals are invalidated.
+    ///
+    /// Only revisions in the rolling window can be rolled back to.
+    pub fn rollback_to(&self, root_hash: &TrieHash) -> Result<(), DbError> {
+        if self.cfg.read_only {
+            return Err(DbError::ReadOnly);
+        }
+
         let mut revisions = self.revisions.lock();
-        let inner_lock = self.inner.read();
+        let mut inner = self.inner.write();
 
-        // Find the revision index with the given root hash.
-        let mut nback = revisions.root_hashes.iter().position(|r| r == root_hash);
-        let rlen = revisions.root_hashes.len();
+        let not_found = || DbError::RevisionNotFound(root_hash.clone());
+        let nback = revisions
+            .root_index
+            .depth(root_hash)
+            .filter(|nback| *nback < revisions.max_revisions)
+            .ok_or_else(not_found)?;
+        if nback == 0 {
+            return Ok(());
+        }
 
-        if nback.is_none() && rlen < revisions.max_revisions {
-            let ashes = inner_lock
-                .disk_requester
-                .collect_ash(revisions.max_revisions)
-                .ok()
-                .unwrap();
+        let ashes = inner.disk_requester.collect_ash(nback)?;
+        if ashes.len() < nback {
+            // the WAL no longer holds every record back to this revision
+            return Err(not_found());
+        }
 
-            nback = ashes
-                .iter()
-                .skip(rlen)
-                .map(|ash| {
-                    StoreRevShared::from_ash(
-                        Arc::new(ZeroStore::default()),
-                        &ash.0[&ROOT_HASH_SPACE].redo,
-                    )
-                })
-                .map(|root_hash_store| {
-                    root_hash_store
-                        .get_view(0, TRIE_HASH_LEN as u64)
-                        .unwrap()
-                        .as_deref()
-                })
-                .map(|data| TrieHash(data[..TRIE_HASH_LEN].try_into().unwrap()))
-                .position(|trie_hash| &trie_hash == root_hash);
+        // stage the undo writes of every newer commit, newest first
+        let mut meta = StoreRevMut::new(inner.cached_space.merkle.meta.clone());
+        let mut payload = StoreRevMut::new(inner.cached_space.merkle.payload.clone());
+        for ash in &ashes[..nback] {
+            for (store, space_id) in [
+                (&mut meta, MERKLE_META_SPACE),
+                (&mut payload, MERKLE_PAYLOAD_SPACE),
+            ] {
+                for undo in ash.0[&space_id].undo.iter().rev() {
+                    store.write(undo.offset() as usize, undo.data());
+                }
+            }
+        }
+
+        let metadata = revisions
+            .root_index
+            .metadata(root_hash)
+            .unwrap_or_default()
+            .to_vec();
+        proposal::apply_commit(
+            &mut inner,
+            &mut revisions,
+            &self.cfg,
+            meta.delta(),
+            payload.delta(),
+            root_hash.clone(),
+            &metadata,
+        )?;
+        revisions.generation += 1;
+
+        Ok(())
+    }
+
+    /// Write a consistent copy of the database to `dest`, a directory outside of the database
+    /// that must not exist yet. Commits are blocked until every pending write has reached the
+    /// disk and the files are copied, while reads carry on. The copy holds the data files and
+    /// a new WAL with only the records of the window of past revisions, and can be opened with
+    /// [Db::new] directly or put in place with [Db::restore]. The archival history is not
+    /// copied, so a copy of an archival DB starts a new one.
+    pub fn checkpoint<P: AsRef<Path>>(&self, dest: P) -> Result<(), DbError> {
+        // the files may be changing under a read-only DB
+        if self.cfg.read_only {
+            return Err(DbError::ReadOnly);
+        }
+
+        // commits need the write lock
+        let inner = self.inner.read();
+        inner.disk_requester.drain()?;
+        let dest = dest.as_ref();
+        std::fs::create_dir(dest)?;
+        for dir in ["merkle", "root_hash"] {
+            file::copy_dir(&self.path.join(dir), &dest.join(dir))?;
+        }
+        inner.disk_requester.copy_wal(&dest.join("wal"))?;
+        Ok(())
+    }
+
+    /// Restore a copy made by [Db::checkpoint] to `db_path`, which must not exist yet. The
+    /// database is then opened from `db_path` as usual.
+    pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+        checkpoint: P,
+        db_path: Q,
+    ) -> Result<(), DbError> {
+        let checkpoint = checkpoint.as_ref();
+        // a checkpoint always starts with the header of the meta space
+        DbParams::read(checkpoint)?;
+        file::copy_dir(checkpoint, db_path.as_ref())?;
+        Ok(())
+    }
+
+    /// Get the metadata stored by [Proposal::commit_with_metadata] with the most recent revision
+    /// with a given root hash. Commits without metadata have an empty one.
+    ///
+    /// If no revision in the rolling window or, in archival mode, in the history has a matching
+    /// root hash, returns None.
+    pub fn revision_metadata(&self, root_hash: &TrieHash) -> Option<Vec<u8>> {
+        let revisions = self.revisions.lock();
+        if revisions
+            .root_index
+            .depth(root_hash)
+            .is_some_and(|nback| nback < revisions.max_revisions)
+        {
+            return revisions.root_index.metadata(root_hash).map(Vec::from);
         }
+        drop(revisions);
+
+        let inner = self.inner.read();
+        let history = inner.history.as_ref()?;
+        history
+            .metadata(history.seq(root_hash)?)
+            .ok()
+            .map(Vec::from)
+    }
+
+    /// Get the most recent revision committed with exactly `metadata`.
+    ///
+    /// If no revision in the rolling window or, in archival mode, in the history has matching
+    /// metadata, returns None. Commits without metadata are never matched.
+    pub fn get_revision_by_metadata(&self, metadata: &[u8]) -> Option<Revision<SharedStore>> {
+        self.find_by_metadata(metadata, false)
+    }
 
-        let Some(nback) = nback else {
+    /// Get the most recent revision whose metadata starts with `prefix`, such as the revision at
+    /// some block height when the metadata is the height, in a fixed width, followed by the
+    /// block hash and timestamp. Like [Db::get_revision_by_metadata] otherwise.
+    pub fn get_revision_by_metadata_prefix(&self, prefix: &[u8]) -> Option<Revision<SharedStore>> {
+        self.find_by_metadata(prefix, true)
+    }
+
+    fn find_by_metadata(&self, metadata: &[u8], prefix: bool) -> Option<Revision<SharedStore>> {
+        if metadata.is_empty() {
             return None;
+        }
+        let root_hash = {
+            let revisions = self.revisions.lock();
+            match revisions.root_index.find_by_metadata(metadata, prefix) {
+                Some((root_hash, nback)) if nback < revisions.max_revisions => root_hash.clone(),
+                _ => {
+                    drop(revisions);
+                    let inner = self.inner.read();
+                    let history = inner.history.as_ref()?;
+                    history
+                        .root_hash(history.seq_by_metadata(metadata, prefix)?)
+                        .clone()
+                }
+            }
         };
+        self.get_revision(&root_hash)
+    }
 
-        let rlen = revisions.inner.len();
-        if rlen < nback {
-            // TODO: Remove unwrap
-            let ashes = inner_lock.disk_requester.collect_ash(nback).ok().unwrap();
-            for mut ash in ashes.into_iter().skip(rlen) {
-                for (_, a) in ash.0.iter_mut() {
-                    a.undo.reverse()
-                }
+    /// Keep the revision with `root_hash` readable through [Db::get_revision] after it leaves
+    /// the rolling window of `max_revisions`, for as long as the returned pin is alive. Memory
+    /// held for pinned revisions is bounded by [DbConfig::max_pinned_bytes].
+    ///
+    /// If no revision with matching root hash found, returns None.
+    #[measure(HitCount)]
+    pub fn pin_revision(&self, root_hash: &TrieHash) -> Option<RevisionPin> {
+        let mut revisions = self.revisions.lock();
+        let inner_lock = self.inner.read();
+        // make sure the window reaches back to the revision, so it is seen when it gets evicted
+        Db::find_universe(&mut revisions, &inner_lock, root_hash)?;
+        revisions.pins.pin(root_hash.clone());
+
+        Some(RevisionPin::new(root_hash.clone(), self.revisions.clone()))
+    }
+
+    /// Get the number of outstanding pins and the memory they keep alive.
+    pub fn pin_stats(&self) -> PinStats {
+        self.revisions.lock().pins.stats()
+    }
 
-                let u = match revisions.inner.back() {
-                    Some(u) => u.to_mem_store_r().rewind(
-                        &ash.0[&MERKLE_META_SPACE].undo,
-                        &ash.0[&MERKLE_PAYLOAD_SPACE].undo,
-                    ),
-                    None => inner_lock.cached_space.to_mem_store_r().rewind(
-                        &ash.0[&MERKLE_META_SPACE].undo,
-                        &ash.0[&MERKLE_PAYLOAD_SPACE].undo,
-                    ),
-                };
-                revisions.inner.push_back(u);
+    fn find_universe(
+        revisions: &mut DbRevInner<SharedStore>,
+        inner: &DbInner,
+        root_hash: &TrieHash,
+    ) -> Option<Universe<StoreRevShared>> {
+        // Find how many commits ago the given root hash was produced.
+        let Some(nback) = revisions
+            .root_index
+            .depth(root_hash)
+            .filter(|nback| *nback < revisions.max_revisions)
+        else {
+            if let Some(universe) = revisions.pins.get(root_hash) {
+                return Some(universe.clone());
             }
+            return Db::rewind_history(revisions, inner, root_hash);
+        };
+
+        Db::extend_window(revisions, inner, nback).ok()?;
+        if revisions.inner.len() < nback {
+            // the WAL no longer holds every record back to this revision
+            return None;
         }
 
-        let space = if nback == 0 {
-            &revisions.base
+        if nback == 0 {
+            Some(revisions.base.clone())
         } else {
-            &revisions.inner[nback - 1]
-        };
-        // Release the lock after we find the revision
-        drop(inner_lock);
+            Some(revisions.inner[nback - 1].clone())
+        }
+    }
 
-        let db_header_ref = Db::get_db_header_ref(&space.merkle.meta).unwrap();
+    /// Load past revisions from the WAL until the window reaches `nback` commits back, or the
+    /// WAL runs out of records.
+    fn extend_window(
+        revisions: &mut DbRevInner<SharedStore>,
+        inner: &DbInner,
+        nback: usize,
+    ) -> Result<(), DbError> {
+        let rlen = revisions.inner.len();
+        if rlen >= nback {
+            return Ok(());
+        }
 
-        let merkle_payload_header_ref =
-            Db::get_payload_header_ref(&space.merkle.meta, Db::PARAM_SIZE + DbHeader::MSIZE)
-                .unwrap();
+        let ashes = inner.disk_requester.collect_ash(nback)?;
+        for mut ash in ashes.into_iter().skip(rlen) {
+            for (_, a) in ash.0.iter_mut() {
+                a.undo.reverse()
+            }
 
-        let header_refs = (db_header_ref, merkle_payload_header_ref);
+            let u = match revisions.inner.back() {
+                Some(u) => u.to_mem_store_r().rewind(
+                    &ash.0[&MERKLE_META_SPACE].undo,
+                    &ash.0[&MERKLE_PAYLOAD_SPACE].undo,
+                ),
+                None => inner.cached_space.to_mem_store_r().rewind(
+                    &ash.0[&MERKLE_META_SPACE].undo,
+                    &ash.0[&MERKLE_PAYLOAD_SPACE].undo,
+                ),
+            };
+            revisions.inner.push_back(u);
+        }
+        Ok(())
+    }
 
-        Revision {
-            rev: Db::new_revision(
-                header_refs,
-                (space.merkle.meta.clone(), space.merkle.payload.clone()),
-                self.payload_regn_nbit,
-                0,
-                &self.cfg.rev,
-            )
-            .unwrap(),
+    /// Reconstruct a revision older than the window from the archival history, by undoing
+    /// every commit after it on top of the oldest revision in the window.
+    fn rewind_history(
+        revisions: &mut DbRevInner<SharedStore>,
+        inner: &DbInner,
+        root_hash: &TrieHash,
+    ) -> Option<Universe<StoreRevShared>> {
+        let history = inner.history.as_ref()?;
+        let seq = history.seq(root_hash)?;
+        let nback = history.len() - 1 - seq;
+
+        Db::extend_window(revisions, inner, nback.min(revisions.max_revisions)).ok()?;
+        let rlen = revisions.inner.len();
+        if nback == 0 {
+            return Some(revisions.base.clone());
+        } else if nback <= rlen {
+            return Some(revisions.inner[nback - 1].clone());
         }
-        .into()
+
+        // the oldest revision in the window is the state after commit `len - 1 - rlen`
+        let mut universe = revisions.inner.back()?.clone();
+        for seq in (seq + 1..history.len() - rlen).rev() {
+            let mut ash = history.record(seq).ok()?;
+            for (_, a) in ash.0.iter_mut() {
+                a.undo.reverse()
+            }
+            universe = universe.to_mem_store_r().rewind(
+                &ash.0[&MERKLE_META_SPACE].undo,
+                &ash.0[&MERKLE_PAYLOAD_SPACE].undo,
+            );
+        }
+        Some(universe)
     }
 
     /// Dump the Trie of the latest generic key-value storage.
     pub fn kv_dump(&self, w: &mut dyn Write) -> Result<(), DbError> {
         self.revisions.lock().base_revision.kv_dump(w)
     }
-    /// Get root hash of the latest generic key-value storage.
-    pub fn kv_root_hash(&self) -> Result<TrieHash, DbError> {
-        self.revisions.lock().base_revision.kv_root_hash()
+    /// Get root hash of `keyspace` in the latest revision, or of the generic key-value storage,
+    /// the default keyspace, if it is `None`.
+    pub fn kv_root_hash(&self, keyspace: Option<&str>) -> Result<TrieHash, DbError> {
+        self.revisions.lock().base_revision.kv_root_hash(keyspace)
+    }
+
+    /// Get the hash that identifies the latest revision, which covers every keyspace. It is the
+    /// same as the root hash of the default keyspace, see [Db::kv_root_hash], as long as no keyspace has been created.
+    pub fn root_hash(&self) -> Result<TrieHash, DbError> {
+        self.revisions.lock().base_revision.root_hash()
+    }
+
+    /// Get the names of the keyspaces besides the default one, in order.
+    pub fn keyspaces(&self) -> Result<Vec<String>, DbError> {
+        self.revisions.lock().base_revision.keyspaces()
+    }
+
+    /// Get a value in a keyspace associated with a particular key.
+    #[measure(HitCount)]
+    pub fn keyspace_get<K: AsRef<[u8]>>(&self, keyspace: &str, key: K) -> Result<Vec<u8>, DbError> {
+        self.revisions
+            .lock()
+            .base_revision
+            .keyspace_get(keyspace, key)
+            .ok_or(DbError::KeyNotFound)
     }
 
     /// Get a value in the kv store associated with a particular key.
@@ -0,0 +1,316 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use shale::{disk_address::DiskAddress, CachedStore, ShaleError, Storable};
+use std::io::{Cursor, Write};
+#[cfg(feature = "eth")]
+use {
+    super::{Db, DbError, DbRev, Store},
+    crate::{
+        account::{code_hash, Account, AccountOp, AccountProof, U256},
+        api::Nonce,
+        merkle::{Node, TrieHash},
+        v2::api::Proof,
+    },
+    shale::ShaleStore,
+    std::collections::BTreeSet,
+};
+
+/// Points to the tries that hold what accounts keep out of the default keyspace: `storage` maps
+/// the address of every account with storage to the root of its storage trie, and `code` maps
+/// the hash of every piece of code to the code. It follows the [super::KeyspaceHeader] in the
+/// meta space, where DBs created before accounts existed have zeros.
+///
+/// Code is never freed: accounts with the same code share it, and it stays in the code trie
+/// after the last account that has it is deleted or given other code, so that it can still be
+/// served by its hash, see [DbRev::get_code_by_hash]. The code trie only grows.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::NoUninit)]
+pub(super) struct AccountHeader {
+    storage: DiskAddress,
+    code: DiskAddress,
+}
+
+impl AccountHeader {
+    pub const MSIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    pub fn new_empty() -> Self {
+        Self {
+            storage: DiskAddress::null(),
+            code: DiskAddress::null(),
+        }
+    }
+}
+
+impl Storable for AccountHeader {
+    fn hydrate<T: CachedStore>(addr: usize, mem: &T) -> Result<Self, ShaleError> {
+        let raw = mem
+            .get_view(addr, Self::MSIZE)
+            .ok_or(ShaleError::InvalidCacheView {
+                offset: addr,
+                size: Self::MSIZE,
+            })?;
+        let raw = raw.as_deref();
+        Ok(Self {
+            storage: raw[..8].into(),
+            code: raw[8..].into(),
+        })
+    }
+
+    fn dehydrated_len(&self) -> u64 {
+        Self::MSIZE
+    }
+
+    fn dehydrate(&self, to: &mut [u8]) -> Result<(), ShaleError> {
+        let mut cur = Cursor::new(to);
+        cur.write_all(&self.storage.to_le_bytes())?;
+        cur.write_all(&self.code.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eth")]
+impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
+    /// The address of the root of the storage trie of the account at `address`, or `None` if
+    /// it has never had storage.
+    fn storage_trie(&self, address: &[u8]) -> Result<Option<DiskAddress>, DbError> {
+        let root = self
+            .merkle
+            .get(address, self.accounts.storage)?
+            .map(|root| DiskAddress::from(&*root));
+        Ok(root)
+    }
+
+    /// Get the account at `address`, or `None` if it does not exist.
+    pub fn get_account<K: AsRef<[u8]>>(&self, address: K) -> Result<Option<Account>, DbError> {
+        self.merkle
+            .get(address, self.header.kv_root)?
+            .map(|raw| Account::decode(&raw).map_err(DbError::InvalidAccount))
+            .transpose()
+    }
+
+    /// Get the balance of an account, which is zero if it does not exist.
+    pub fn get_balance<K: AsRef<[u8]>>(&self, address: K) -> Result<U256, DbError> {
+        Ok(self.get_account(address)?.unwrap_or_default().balance)
+    }
+
+    /// Get the nonce of an account, which is zero if it does not exist.
+    pub fn get_nonce<K: AsRef<[u8]>>(&self, address: K) -> Result<Nonce, DbError> {
+        Ok(self.get_account(address)?.unwrap_or_default().nonce)
+    }
+
+    /// Get the code of an account, which is empty if it does not exist.
+    pub fn get_code<K: AsRef<[u8]>>(&self, address: K) -> Result<Vec<u8>, DbError> {
+        let code_hash = self.get_account(address)?.unwrap_or_default().code_hash;
+        self.get_code_by_hash(&code_hash)
+    }
+
+    /// Get the code with hash `code_hash`, which any account has had up to this revision, even
+    /// if none has it anymore.
+    pub fn get_code_by_hash(&self, code_hash: &TrieHash) -> Result<Vec<u8>, DbError> {
+        if code_hash == Account::empty_code_hash() {
+            return Ok(Vec::new());
+        }
+        self.merkle
+            .get(**code_hash, self.accounts.code)?
+            .map(|code| code.to_vec())
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    /// Get the value associated with a key in the storage of an account.
+    pub fn get_state<K: AsRef<[u8]>>(&self, address: K, key: K) -> Result<Vec<u8>, DbError> {
+        let root = self
+            .storage_trie(address.as_ref())?
+            .ok_or(DbError::KeyNotFound)?;
+        self.merkle
+            .get(key, root)?
+            .map(|value| value.to_vec())
+            .ok_or(DbError::KeyNotFound)
+    }
+
+    /// Dump an account and the trie of its storage.
+    pub fn dump_account<K: AsRef<[u8]>>(
+        &self,
+        address: K,
+        w: &mut dyn Write,
+    ) -> Result<(), DbError> {
+        let account = self
+            .get_account(address.as_ref())?
+            .ok_or(DbError::KeyNotFound)?;
+        writeln!(w, "nonce: {}", account.nonce)?;
+        writeln!(w, "balance: {}", account.balance)?;
+        writeln!(w, "code_hash: {}", hex::encode(*account.code_hash))?;
+        writeln!(w, "storage_root: {}", hex::encode(*account.storage_root))?;
+        match self.storage_trie(address.as_ref())? {
+            Some(root) => self.merkle.dump(root, w)?,
+            None => write!(w, "<Empty>")?,
+        }
+        Ok(())
+    }
+
+    /// Prove the account at `address` and the values of the storage `keys`, in a single
+    /// [AccountProof] that is checked with [AccountProof::verify] against the root hash of the
+    /// generic key-value storage.
+    pub fn prove_account<A: AsRef<[u8]>, K: AsRef<[u8]>>(
+        &self,
+        address: A,
+        keys: &[K],
+    ) -> Result<AccountProof, DbError> {
+        let account = self.merkle.prove(address.as_ref(), self.header.kv_root)?;
+        let root = self.storage_trie(address.as_ref())?;
+        let storage = keys
+            .iter()
+            .map(|key| match root {
+                Some(root) => self.merkle.prove(key, root),
+                None => Ok(Proof(Default::default())),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(AccountProof { account, storage })
+    }
+}
+
+#[cfg(feature = "eth")]
+impl DbRev<Store> {
+    /// Apply `op` to the account at `address`. The address of every account whose storage is
+    /// changed is added to `touched`, so that its storage root can be brought up to date with
+    /// [DbRev::update_storage_roots] once the whole batch is applied.
+    pub(super) fn apply_account_op(
+        &mut self,
+        address: &[u8],
+        op: AccountOp,
+        touched: &mut BTreeSet<Vec<u8>>,
+    ) -> Result<(), DbError> {
+        let mut account = self.get_account(address)?.unwrap_or_default();
+        match op {
+            AccountOp::SetNonce(nonce) => account.nonce = nonce,
+            AccountOp::SetBalance(balance) => account.balance = balance,
+            AccountOp::SetCode(code) => {
+                account.code_hash = code_hash(&code);
+                if !code.is_empty() {
+                    if self.accounts.code.is_null() {
+                        let code = self.merkle.init_root()?;
+                        self.accounts.write(|header| header.code = code).unwrap();
+                    }
+                    self.merkle
+                        .insert(*account.code_hash, code, self.accounts.code)?;
+                }
+            }
+            AccountOp::SetState { key, value } => {
+                let root = match self.storage_trie(address)? {
+                    Some(root) => root,
+                    None => self.new_storage_trie(address)?,
+                };
+                self.merkle.insert(key, value, root)?;
+                touched.insert(address.to_vec());
+            }
+            AccountOp::DeleteState { key } => {
+                if let Some(root) = self.storage_trie(address)? {
+                    self.merkle.remove(key, root)?;
+                    touched.insert(address.to_vec());
+                }
+            }
+            AccountOp::Delete => {
+                if let Some(root) = self.storage_trie(address)? {
+                    self.merkle.remove_tree(root)?;
+                    self.merkle.remove(address, self.accounts.storage)?;
+                }
+                self.merkle.remove(address, self.header.kv_root)?;
+                touched.remove(address);
+                return Ok(());
+            }
+        }
+        self.merkle
+            .insert(address, account.encode(), self.header.kv_root)?;
+        Ok(())
+    }
+
+    fn new_storage_trie(&mut self, address: &[u8]) -> Result<DiskAddress, DbError> {
+        if self.accounts.storage.is_null() {
+            let storage = self.merkle.init_root()?;
+            self.accounts
+                .write(|header| header.storage = storage)
+                .unwrap();
+        }
+        let root = self.merkle.init_root()?;
+        self.merkle
+            .insert(address, root.to_le_bytes().to_vec(), self.accounts.storage)?;
+        Ok(root)
+    }
+
+    /// Remove the storage trie of every account, for when all the accounts are replaced.
+    pub(super) fn clear_storage(&mut self) -> Result<(), DbError> {
+        if self.accounts.storage.is_null() {
+            return Ok(());
+        }
+        let mut roots = Vec::new();
+        self.merkle.for_each_kv(self.accounts.storage, |_, root| {
+            roots.push(DiskAddress::from(root));
+            Ok::<_, DbError>(())
+        })?;
+        for root in roots {
+            self.merkle.remove_tree(root)?;
+        }
+        self.merkle.remove_tree(self.accounts.storage)?;
+        self.accounts
+            .write(|header| header.storage = DiskAddress::null())
+            .unwrap();
+        Ok(())
+    }
+
+    /// Record the root hash of the storage trie of every account in `touched` in the account.
+    pub(super) fn update_storage_roots(
+        &mut self,
+        touched: BTreeSet<Vec<u8>>,
+    ) -> Result<(), DbError> {
+        for address in touched {
+            let (Some(mut account), Some(root)) =
+                (self.get_account(&address)?, self.storage_trie(&address)?)
+            else {
+                continue;
+            };
+            account.storage_root = self.merkle.root_hash(root)?;
+            self.merkle
+                .insert(&address, account.encode(), self.header.kv_root)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eth")]
+impl Db {
+    /// Get an account in the latest revision, or `None` if it does not exist.
+    pub fn get_account<K: AsRef<[u8]>>(&self, address: K) -> Result<Option<Account>, DbError> {
+        self.revisions.lock().base_revision.get_account(address)
+    }
+
+    /// Get the code of an account in the latest revision.
+    pub fn get_code<K: AsRef<[u8]>>(&self, address: K) -> Result<Vec<u8>, DbError> {
+        self.revisions.lock().base_revision.get_code(address)
+    }
+
+    /// Get the code with hash `code_hash` in the latest revision, see [DbRev::get_code_by_hash].
+    pub fn get_code_by_hash(&self, code_hash: &TrieHash) -> Result<Vec<u8>, DbError> {
+        self.revisions
+            .lock()
+            .base_revision
+            .get_code_by_hash(code_hash)
+    }
+
+    /// Get a value in the storage of an account in the latest revision.
+    pub fn get_state<K: AsRef<[u8]>>(&self, address: K, key: K) -> Result<Vec<u8>, DbError> {
+        self.revisions.lock().base_revision.get_state(address, key)
+    }
+
+    /// Prove an account and some of its storage in the latest revision, see
+    /// [DbRev::prove_account].
+    pub fn prove_account<A: AsRef<[u8]>, K: AsRef<[u8]>>(
+        &self,
+        address: A,
+        keys: &[K],
+    ) -> Result<AccountProof, DbError> {
+        self.revisions
+            .lock()
+            .base_revision
+            .prove_account(address, keys)
+    }
+}
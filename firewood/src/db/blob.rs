@@ -0,0 +1,61 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{DbError, DbRev};
+use crate::merkle::Node;
+use shale::{disk_address::DiskAddress, CachedStore, ShaleError, ShaleStore, Storable};
+use std::io::{Cursor, Write};
+
+/// Points to the blob table of a DB that stores long values out of line, a trie in the merkle
+/// space that maps the hash of every value stored in a blob to the blob and its reference count.
+/// It follows the [super::PreimageHeader] in the meta space, where DBs created before values
+/// could be stored in blobs have zeros.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::NoUninit)]
+pub(super) struct BlobHeader {
+    pub(super) table: DiskAddress,
+}
+
+impl BlobHeader {
+    pub const MSIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    pub fn new_empty() -> Self {
+        Self {
+            table: DiskAddress::null(),
+        }
+    }
+}
+
+impl Storable for BlobHeader {
+    fn hydrate<T: CachedStore>(addr: usize, mem: &T) -> Result<Self, ShaleError> {
+        let raw = mem
+            .get_view(addr, Self::MSIZE)
+            .ok_or(ShaleError::InvalidCacheView {
+                offset: addr,
+                size: Self::MSIZE,
+            })?;
+        Ok(Self {
+            table: raw.as_deref().as_slice().into(),
+        })
+    }
+
+    fn dehydrated_len(&self) -> u64 {
+        Self::MSIZE
+    }
+
+    fn dehydrate(&self, to: &mut [u8]) -> Result<(), ShaleError> {
+        let mut cur = Cursor::new(to);
+        cur.write_all(&self.table.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
+    /// Get how many values in the tries of the revision are `value` and share a blob, which is
+    /// 0 for a value that is stored inline. See [crate::db::DbConfig::blob_threshold].
+    pub fn blob_refs<V: AsRef<[u8]>>(&self, value: V) -> Result<u64, DbError> {
+        self.merkle
+            .blob_refs(value.as_ref())
+            .map_err(DbError::Merkle)
+    }
+}
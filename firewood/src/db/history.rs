@@ -0,0 +1,225 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::DbError;
+use crate::{
+    file,
+    merkle::{TrieHash, TRIE_HASH_LEN},
+    storage::AshRecord,
+};
+use growthring::wal::Record;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::Read,
+    ops::Bound,
+    os::unix::fs::{FileExt, OpenOptionsExt},
+    path::Path,
+};
+
+/// The root hash produced by a commit, followed by its sequence number in the
+/// root hash index, the offset and length of its record in the log, and the
+/// length of its metadata, which follows the record.
+const ENTRY_SIZE: usize = TRIE_HASH_LEN + 32;
+
+#[derive(Debug)]
+struct Entry {
+    root_hash: TrieHash,
+    seq: u64,
+    offset: u64,
+    len: u64,
+    metadata_len: u64,
+}
+
+impl Entry {
+    fn decode(raw: &[u8]) -> Self {
+        let field = |i: usize| {
+            let at = TRIE_HASH_LEN + i * 8;
+            u64::from_le_bytes(raw[at..at + 8].try_into().expect("8 bytes"))
+        };
+        Self {
+            root_hash: TrieHash(raw[..TRIE_HASH_LEN].try_into().expect("hash length")),
+            seq: field(0),
+            offset: field(1),
+            len: field(2),
+            metadata_len: field(3),
+        }
+    }
+
+    /// Where the next entry starts in the log.
+    fn end(&self) -> u64 {
+        self.offset + self.len + self.metadata_len
+    }
+
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut raw = [0; ENTRY_SIZE];
+        raw[..TRIE_HASH_LEN].copy_from_slice(&self.root_hash.0);
+        for (i, field) in [self.seq, self.offset, self.len, self.metadata_len]
+            .into_iter()
+            .enumerate()
+        {
+            let at = TRIE_HASH_LEN + i * 8;
+            raw[at..at + 8].copy_from_slice(&field.to_le_bytes());
+        }
+        raw
+    }
+}
+
+/// Append-only store of the WAL record of every commit, kept in archival mode.
+///
+/// The records live in `history/log` in the same encoding as the WAL, each
+/// followed by the metadata of its commit, and `history/index` maps each
+/// commit, in order, to the root hash it produced, its sequence number and the
+/// position of its record. Both files are synced before the commit is handed
+/// to the WAL, so on open the history is at most ahead of the database, and
+/// the entries of the commits the database has not counted are dropped.
+#[derive(Debug)]
+pub(super) struct History {
+    log: File,
+    index: File,
+    entries: Vec<Entry>,
+    by_hash: HashMap<TrieHash, usize>,
+    by_metadata: BTreeMap<Box<[u8]>, usize>,
+}
+
+impl History {
+    /// Open the history under `db_path`, given the sequence number the next
+    /// commit to the database gets, see
+    /// [RootHashIndex::next_seq](super::root_index::RootHashIndex::next_seq).
+    pub(super) fn open(db_path: &Path, next_seq: u64) -> Result<Self, DbError> {
+        let dir = file::touch_dir("history", db_path)?;
+        let open = |name| {
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .read(true)
+                .write(true)
+                .mode(0o600)
+                .open(dir.join(name))
+        };
+        let log = open("log")?;
+        let mut index = open("index")?;
+
+        let mut raw = Vec::new();
+        index.read_to_end(&mut raw)?;
+        let log_len = log.metadata()?.len();
+        let mut entries: Vec<_> = raw
+            .chunks_exact(ENTRY_SIZE)
+            .map(Entry::decode)
+            .take_while(|entry| entry.end() <= log_len)
+            .collect();
+
+        // drop the commits that never made it to the WAL, which leaves the
+        // latest commit last, if the history has it
+        entries.truncate(entries.partition_point(|entry| entry.seq < next_seq));
+        if entries
+            .last()
+            .is_some_and(|entry| entry.seq + 1 != next_seq)
+        {
+            return Err(DbError::HistoryMismatch);
+        }
+        log.set_len(entries.last().map_or(0, Entry::end))?;
+        index.set_len((entries.len() * ENTRY_SIZE) as u64)?;
+
+        let by_hash = entries
+            .iter()
+            .enumerate()
+            .map(|(seq, entry)| (entry.root_hash.clone(), seq))
+            .collect();
+        let mut by_metadata = BTreeMap::new();
+        for (seq, entry) in entries.iter().enumerate() {
+            if entry.metadata_len != 0 {
+                by_metadata.insert(read_metadata(&log, entry)?, seq);
+            }
+        }
+
+        Ok(Self {
+            log,
+            index,
+            entries,
+            by_hash,
+            by_metadata,
+        })
+    }
+
+    /// The number of commits recorded.
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The position of the most recent commit that produced `root_hash`.
+    pub(super) fn seq(&self, root_hash: &TrieHash) -> Option<usize> {
+        self.by_hash.get(root_hash).copied()
+    }
+
+    /// The position of the most recent commit with exactly `metadata`, or with
+    /// metadata that starts with it if `prefix` is set. It must not be empty.
+    pub(super) fn seq_by_metadata(&self, metadata: &[u8], prefix: bool) -> Option<usize> {
+        if !prefix {
+            return self.by_metadata.get(metadata).copied();
+        }
+        self.by_metadata
+            .range::<[u8], _>((Bound::Included(metadata), Bound::Unbounded))
+            .take_while(|(other, _)| other.starts_with(metadata))
+            .map(|(_, seq)| *seq)
+            .max()
+    }
+
+    /// The root hash produced by the commit at position `seq`.
+    pub(super) fn root_hash(&self, seq: usize) -> &TrieHash {
+        &self.entries[seq].root_hash
+    }
+
+    /// Read back the metadata of the commit at position `seq`.
+    pub(super) fn metadata(&self, seq: usize) -> Result<Box<[u8]>, DbError> {
+        Ok(read_metadata(&self.log, &self.entries[seq])?)
+    }
+
+    /// Read back the record of the commit at position `seq`.
+    pub(super) fn record(&self, seq: usize) -> Result<AshRecord, DbError> {
+        let entry = &self.entries[seq];
+        let mut raw = vec![0; entry.len as usize];
+        self.log.read_exact_at(&mut raw, entry.offset)?;
+        AshRecord::deserialize(raw.into()).ok_or(DbError::HistoryMismatch)
+    }
+
+    /// Durably record the commit with sequence number `seq`, which produced
+    /// `root_hash`, with its metadata.
+    pub(super) fn append(
+        &mut self,
+        seq: u64,
+        root_hash: TrieHash,
+        record: &AshRecord,
+        metadata: &[u8],
+    ) -> Result<(), DbError> {
+        let mut raw = record.serialize().into_vec();
+        let entry = Entry {
+            root_hash,
+            seq,
+            offset: self.entries.last().map_or(0, Entry::end),
+            len: raw.len() as u64,
+            metadata_len: metadata.len() as u64,
+        };
+        raw.extend_from_slice(metadata);
+
+        self.log.write_all_at(&raw, entry.offset)?;
+        self.log.sync_data()?;
+        self.index
+            .write_all_at(&entry.encode(), (self.entries.len() * ENTRY_SIZE) as u64)?;
+        self.index.sync_data()?;
+
+        self.by_hash
+            .insert(entry.root_hash.clone(), self.entries.len());
+        if !metadata.is_empty() {
+            self.by_metadata.insert(metadata.into(), self.entries.len());
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+fn read_metadata(log: &File, entry: &Entry) -> std::io::Result<Box<[u8]>> {
+    let mut metadata = vec![0; entry.metadata_len as usize];
+    log.read_exact_at(&mut metadata, entry.offset + entry.len)?;
+    Ok(metadata.into())
+}
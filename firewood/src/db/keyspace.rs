@@ -0,0 +1,132 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{DbError, DbRev};
+use crate::merkle::{Node, TrieHash, TRIE_HASH_LEN};
+use sha3::{Digest, Keccak256};
+use shale::{disk_address::DiskAddress, CachedStore, ShaleError, ShaleStore, Storable};
+use std::io::{Cursor, Write};
+
+/// Points to the keyspace directory, a trie in the merkle space that maps the name of every
+/// keyspace to the address of its root. It follows the [shale::compact::CompactSpaceHeader] in
+/// the meta space, where DBs created before keyspaces existed have zeros.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::NoUninit)]
+pub(super) struct KeyspaceHeader {
+    directory: DiskAddress,
+}
+
+impl KeyspaceHeader {
+    pub const MSIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    pub fn new_empty() -> Self {
+        Self {
+            directory: DiskAddress::null(),
+        }
+    }
+}
+
+impl Storable for KeyspaceHeader {
+    fn hydrate<T: CachedStore>(addr: usize, mem: &T) -> Result<Self, ShaleError> {
+        let raw = mem
+            .get_view(addr, Self::MSIZE)
+            .ok_or(ShaleError::InvalidCacheView {
+                offset: addr,
+                size: Self::MSIZE,
+            })?;
+        Ok(Self {
+            directory: raw.as_deref().as_slice().into(),
+        })
+    }
+
+    fn dehydrated_len(&self) -> u64 {
+        Self::MSIZE
+    }
+
+    fn dehydrate(&self, to: &mut [u8]) -> Result<(), ShaleError> {
+        let mut cur = Cursor::new(to);
+        cur.write_all(&self.directory.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
+    /// The address of the root of `keyspace`, or `None` if it does not exist.
+    pub(super) fn keyspace_root(&self, keyspace: &str) -> Result<Option<DiskAddress>, DbError> {
+        let root = self
+            .merkle
+            .get(keyspace, self.keyspaces.directory)?
+            .map(|root| DiskAddress::from(&*root));
+        Ok(root)
+    }
+
+    /// The address of the root of `keyspace`, which is created empty if it does not exist yet.
+    pub(super) fn keyspace_root_or_create(
+        &mut self,
+        keyspace: &str,
+    ) -> Result<DiskAddress, DbError> {
+        if keyspace.is_empty() {
+            return Err(DbError::InvalidParams);
+        }
+        if let Some(root) = self.keyspace_root(keyspace)? {
+            return Ok(root);
+        }
+
+        if self.keyspaces.directory.is_null() {
+            let directory = self.merkle.init_root()?;
+            self.keyspaces
+                .write(|header| header.directory = directory)
+                .unwrap();
+        }
+        let root = self.merkle.init_root()?;
+        self.merkle.insert(
+            keyspace,
+            root.to_le_bytes().to_vec(),
+            self.keyspaces.directory,
+        )?;
+        Ok(root)
+    }
+
+    /// Get the names of the keyspaces besides the default one, in order.
+    pub fn keyspaces(&self) -> Result<Vec<String>, DbError> {
+        let mut names = Vec::new();
+        self.merkle
+            .for_each_kv(self.keyspaces.directory, |name, _| {
+                names.push(String::from_utf8_lossy(name).into_owned());
+                Ok::<_, DbError>(())
+            })?;
+        Ok(names)
+    }
+
+    /// Get a value associated with a key in a keyspace.
+    pub fn keyspace_get<K: AsRef<[u8]>>(&self, keyspace: &str, key: K) -> Option<Vec<u8>> {
+        let root = self.keyspace_root(keyspace).ok()??;
+        let obj_ref = self.merkle.get(key, root);
+        match obj_ref {
+            Err(_) => None,
+            Ok(obj) => obj.map(|o| o.to_vec()),
+        }
+    }
+
+    /// Get the hash that identifies this revision, which covers every keyspace. Without
+    /// keyspaces, it is the root hash of the generic key-value storage; otherwise it is the hash
+    /// of that root hash, followed by the name and root hash of every keyspace in order.
+    pub fn root_hash(&self) -> Result<TrieHash, DbError> {
+        let kv_root_hash = self.kv_root_hash(None)?;
+        if self.keyspaces.directory.is_null() {
+            return Ok(kv_root_hash);
+        }
+
+        let mut hasher = Keccak256::new();
+        hasher.update(*kv_root_hash);
+        self.merkle
+            .for_each_kv(self.keyspaces.directory, |name, root| {
+                hasher.update((name.len() as u64).to_le_bytes());
+                hasher.update(name);
+                hasher.update(*self.merkle.root_hash(DiskAddress::from(root))?);
+                Ok::<_, DbError>(())
+            })?;
+        let hash: [u8; TRIE_HASH_LEN] = hasher.finalize().into();
+        Ok(TrieHash(hash))
+    }
+}
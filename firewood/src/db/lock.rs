@@ -0,0 +1,57 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::DbError;
+use nix::fcntl::{flock, FlockArg};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+};
+
+/// Name of the lock file in the DB directory.
+pub(super) const LOCK_FILE: &str = "LOCK";
+
+/// Advisory lock that keeps a second writer out of a DB directory. The lock
+/// file holds the PID of the process that owns it, so the other writer can
+/// tell who is in the way. The lock is released when the file is closed,
+/// including when the process dies.
+#[derive(Debug)]
+pub(super) struct DirLock {
+    file: File,
+}
+
+impl DirLock {
+    pub(super) fn acquire(db_path: &Path) -> Result<Self, DbError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .mode(0o600)
+            .open(db_path.join(LOCK_FILE))?;
+
+        if flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)?;
+            return Err(DbError::Locked {
+                // the holder may not have written its PID yet
+                pid: pid.trim().parse().ok(),
+            });
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_data()?;
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        flock(self.file.as_raw_fd(), FlockArg::UnlockNonblock).ok();
+    }
+}
@@ -0,0 +1,506 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{
+    lock::{self, DirLock},
+    BatchOp, Db, DbConfig, DbError, DbParams, WalConfig, FORMAT_VERSION, MAGIC_STR,
+};
+use crate::{
+    file,
+    merkle::{Node, TrieEncoding},
+    nibbles::BranchFactor,
+};
+use bytemuck::{cast_slice, AnyBitPattern};
+use std::{
+    fs::OpenOptions,
+    mem::{offset_of, size_of},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+const LEGACY_MAGIC_STR: &[u8; 16] = b"firewood v0.1\0\0\0";
+
+/// The [DbParams] of format version 0, which the DB headers directly follow.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, AnyBitPattern)]
+struct LegacyParams {
+    magic: [u8; 16],
+    meta_file_nbit: u64,
+    payload_file_nbit: u64,
+    payload_regn_nbit: u64,
+    wal_file_nbit: u64,
+    wal_block_nbit: u64,
+    root_hash_file_nbit: u64,
+}
+
+pub(super) const LEGACY_PARAM_SIZE: u64 = size_of::<LegacyParams>() as u64;
+
+/// Decode the parameters of a DB in format version 0, or `None` if `raw` does not start with
+/// them.
+pub(super) fn legacy_params(raw: &[u8]) -> Option<DbParams> {
+    let raw = raw.get(..LEGACY_PARAM_SIZE as usize)?;
+    if !raw.starts_with(LEGACY_MAGIC_STR) {
+        return None;
+    }
+    let legacy: LegacyParams = cast_slice(raw)[0];
+    Some(DbParams {
+        magic: *MAGIC_STR,
+        format_version: 0,
+        // the node encoding has not changed since
+        node_encoding: Node::ENCODING_VERSION,
+        meta_file_nbit: legacy.meta_file_nbit,
+        payload_file_nbit: legacy.payload_file_nbit,
+        payload_regn_nbit: legacy.payload_regn_nbit,
+        wal_file_nbit: legacy.wal_file_nbit,
+        wal_block_nbit: legacy.wal_block_nbit,
+        root_hash_file_nbit: legacy.root_hash_file_nbit,
+        trie_encoding: TrieEncoding::Firewood as u64,
+        branch_factor: BranchFactor::Sixteen as u64,
+        secure_keys: 0,
+        blob_threshold: 0,
+        archival: 0,
+    })
+}
+
+/// Upgrades a DB from one format version to a newer one.
+struct Migration {
+    summary: &'static str,
+    step: Step,
+    /// The format version of the upgraded DB.
+    to: u64,
+}
+
+/// How a [Migration] upgrades a DB.
+enum Step {
+    /// Write the upgraded DB to a new directory.
+    Rebuild(fn(&Path, &Path) -> Result<(), DbError>),
+    /// Record the new format version in the DB itself, for an upgrade that leaves the on-disk
+    /// layout as it is.
+    Relabel,
+}
+
+/// `MIGRATIONS[v]` upgrades a DB from format version `v`.
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [
+    Migration {
+        summary: "rebuild the trie of the latest revision in the versioned layout",
+        step: Step::Rebuild(rebuild),
+        to: FORMAT_VERSION,
+    },
+    Migration {
+        summary: "mark the DB as able to hold keyspaces",
+        step: Step::Relabel,
+        to: 2,
+    },
+    Migration {
+        summary: "mark the DB as able to hold accounts",
+        step: Step::Relabel,
+        to: 3,
+    },
+    Migration {
+        summary: "record the trie encoding, which is the Firewood one",
+        step: Step::Relabel,
+        to: 4,
+    },
+    Migration {
+        summary: "record the branch factor, which is 16",
+        step: Step::Relabel,
+        to: 5,
+    },
+    Migration {
+        summary: "record that keys are not hashed",
+        step: Step::Relabel,
+        to: 6,
+    },
+    Migration {
+        summary: "record that values are stored inline",
+        step: Step::Relabel,
+        to: 7,
+    },
+];
+
+/// The migrations that bring a DB in format version `from` up to date, in order.
+fn migrations(from: u64) -> impl Iterator<Item = &'static Migration> {
+    std::iter::successors(MIGRATIONS.get(from as usize), |migration| {
+        MIGRATIONS.get(migration.to as usize)
+    })
+}
+
+/// The number of keys written by each commit of [rebuild].
+const REBUILD_BATCH_LEN: usize = 10_000;
+
+/// Copy the latest revision of the DB in `src`, with all of its keyspaces, into a new DB in
+/// `dest`, in the current format and with the same parameters. The window of past revisions,
+/// their commit metadata and the archival history are not carried over.
+fn rebuild(src: &Path, dest: &Path) -> Result<(), DbError> {
+    let params = DbParams::read(src)?;
+    let source = Db::new(src, &DbConfig::builder().read_only(true).build())?;
+    let rev = source.revisions.lock().base_revision.clone();
+
+    std::fs::create_dir(dest)?;
+    let target = Db::new(
+        dest,
+        &DbConfig::builder()
+            .truncate(true)
+            .meta_file_nbit(params.meta_file_nbit)
+            .payload_file_nbit(params.payload_file_nbit)
+            .payload_regn_nbit(params.payload_regn_nbit)
+            .root_hash_file_nbit(params.root_hash_file_nbit)
+            .trie_encoding(params.trie_encoding())
+            .branch_factor(params.branch_factor())
+            .secure_keys(params.secure_keys == 1)
+            .blob_threshold(params.blob_threshold)
+            .wal(
+                WalConfig::builder()
+                    .file_nbit(params.wal_file_nbit)
+                    .block_nbit(params.wal_block_nbit)
+                    .build(),
+            )
+            .build(),
+    )?;
+
+    let mut batch = Vec::with_capacity(REBUILD_BATCH_LEN);
+    let commit = |batch: Vec<BatchOp<Vec<u8>>>| target.new_proposal(batch)?.commit();
+    let mut copy = |root, keyspace: Option<&String>| {
+        rev.merkle.for_each_kv(root, |key, value| {
+            let (key, value) = (key.to_vec(), value.to_vec());
+            batch.push(match keyspace {
+                None => BatchOp::Put { key, value },
+                Some(keyspace) => BatchOp::KeyspacePut {
+                    keyspace: keyspace.clone(),
+                    key,
+                    value,
+                },
+            });
+            if batch.len() == REBUILD_BATCH_LEN {
+                commit(std::mem::take(&mut batch))?;
+            }
+            Ok::<_, DbError>(())
+        })
+    };
+    copy(rev.header.kv_root, None)?;
+    for keyspace in rev.keyspaces()? {
+        if let Some(root) = rev.keyspace_root(&keyspace)? {
+            copy(root, Some(&keyspace))?;
+        }
+    }
+    if !batch.is_empty() {
+        commit(batch)?;
+    }
+
+    let expected = rev.root_hash()?;
+    let found = target.root_hash()?;
+    if found != expected {
+        return Err(DbError::MigrationFailed(format!(
+            "the rebuilt trie has root hash {found:?} instead of {expected:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Record the format version `to` in the DB in `db_path`, which nobody else has open.
+fn relabel(db_path: &Path, to: u64) -> Result<(), DbError> {
+    let meta = OpenOptions::new()
+        .write(true)
+        .open(db_path.join("merkle/meta/00000000.fw"))?;
+    meta.write_all_at(
+        &to.to_le_bytes(),
+        offset_of!(DbParams, format_version) as u64,
+    )?;
+    meta.sync_all()?;
+    Ok(())
+}
+
+/// Copy the DB in `src` to `dest`, which nobody has open.
+fn copy(src: &Path, dest: &Path) -> Result<(), DbError> {
+    file::copy_dir(src, dest)?;
+    match std::fs::remove_file(dest.join(lock::LOCK_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The upgrade of a DB to the current format, see [Db::migrate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// The format version of the DB.
+    pub from: u64,
+    /// The format version the DB is upgraded to, [FORMAT_VERSION].
+    pub to: u64,
+    /// What each step of the upgrade does, in order. Empty if the DB is up to date.
+    pub steps: Vec<&'static str>,
+}
+
+impl Db {
+    /// Find out what [Db::migrate] would do to the DB in `db_path`, without changing it.
+    pub fn migration_plan<P: AsRef<Path>>(db_path: P) -> Result<MigrationPlan, DbError> {
+        let from = DbParams::read(db_path.as_ref())?.format_version;
+        Ok(MigrationPlan {
+            from,
+            to: FORMAT_VERSION,
+            steps: migrations(from)
+                .map(|migration| migration.summary)
+                .collect(),
+        })
+    }
+
+    /// Upgrade the DB in `db_path` to the current [FORMAT_VERSION]. The upgraded DB is written to
+    /// `dest`, a directory that must not exist yet, leaving the original in place; without
+    /// `dest`, the DB is upgraded in place, or replaced once the upgrade is complete if it has to
+    /// be rebuilt. The DB must not be open for writing, and nothing is done if it is already up
+    /// to date.
+    ///
+    /// An upgrade may have to rebuild the DB from its latest revision, which does not preserve
+    /// the window of past revisions. The returned plan tells which steps were taken.
+    pub fn migrate<P: AsRef<Path>>(
+        db_path: P,
+        dest: Option<&Path>,
+    ) -> Result<MigrationPlan, DbError> {
+        let db_path = db_path.as_ref();
+        let plan = Db::migration_plan(db_path)?;
+        if plan.steps.is_empty() {
+            return Ok(plan);
+        }
+        if dest.is_some_and(Path::exists) {
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+        }
+
+        // keep writers out while the DB is read
+        let _lock = DirLock::acquire(db_path)?;
+
+        let target = dest.map_or_else(|| sibling(db_path, "migrated"), Path::to_path_buf);
+        let mut current = db_path.to_path_buf();
+        for migration in migrations(plan.from) {
+            let next = sibling(&target, &format!("v{}", migration.to));
+            match migration.step {
+                Step::Rebuild(rebuild) => {
+                    rebuild(&current, &next)?;
+                    if current != db_path {
+                        std::fs::remove_dir_all(&current)?;
+                    }
+                    current = next;
+                }
+                Step::Relabel => {
+                    if current == db_path && dest.is_some() {
+                        // the original is left as it was
+                        copy(&current, &next)?;
+                        current = next;
+                    }
+                    relabel(&current, migration.to)?;
+                }
+            }
+        }
+
+        match dest {
+            Some(dest) => std::fs::rename(&current, dest)?,
+            None if current != db_path => {
+                let old = sibling(db_path, "old");
+                std::fs::rename(db_path, &old)?;
+                std::fs::rename(&current, db_path)?;
+                std::fs::remove_dir_all(&old)?;
+            }
+            None => (),
+        }
+        Ok(plan)
+    }
+}
+
+/// A directory next to `path`, with `suffix` appended to its name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{DbHeader, PARAMS_SLOT};
+    use shale::compact::CompactSpaceHeader;
+    use std::os::unix::fs::FileExt;
+
+    fn get_tmp_dir() -> PathBuf {
+        option_env!("CARGO_TARGET_TMPDIR")
+            .map(Into::into)
+            .unwrap_or(std::env::temp_dir())
+            .join("firewood")
+    }
+
+    /// Rewrite the headers of a closed DB in the layout of format version 0.
+    fn downgrade(db_path: &Path) {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(db_path.join("merkle/meta/00000000.fw"))
+            .unwrap();
+        let mut params = [0; PARAMS_SLOT as usize];
+        file.read_exact_at(&mut params, 0).unwrap();
+        let params: DbParams = cast_slice(&params[..size_of::<DbParams>()])[0];
+
+        let mut headers = vec![0; (DbHeader::MSIZE + CompactSpaceHeader::MSIZE) as usize];
+        file.read_exact_at(&mut headers, PARAMS_SLOT).unwrap();
+        let mut legacy: Vec<u8> = LEGACY_MAGIC_STR
+            .iter()
+            .copied()
+            .chain(
+                [
+                    params.meta_file_nbit,
+                    params.payload_file_nbit,
+                    params.payload_regn_nbit,
+                    params.wal_file_nbit,
+                    params.wal_block_nbit,
+                    params.root_hash_file_nbit,
+                ]
+                .iter()
+                .flat_map(|nbit| nbit.to_le_bytes()),
+            )
+            .chain(headers)
+            .collect();
+        // the headers that legacy DBs don't have are zeros
+        legacy.resize(PARAMS_SLOT as usize, 0);
+        file.write_all_at(&legacy, 0).unwrap();
+    }
+
+    #[test]
+    fn legacy_db_is_rebuilt() -> Result<(), DbError> {
+        let dir = get_tmp_dir().join("migrate_legacy_db_is_rebuilt");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let db_path = dir.join("db");
+        let dest = dir.join("upgraded");
+
+        let cfg = DbConfig::builder()
+            .payload_file_nbit(16)
+            .payload_regn_nbit(16)
+            .wal(WalConfig::builder().max_revisions(5).build());
+        let keys: Vec<Vec<u8>> = (0..100u8).map(|n| vec![n % 7; n as usize % 5]).collect();
+        let root_hash = {
+            let db = Db::new(&db_path, &cfg.clone().truncate(true).build())?;
+            for chunk in keys.chunks(30) {
+                db.new_proposal(
+                    chunk
+                        .iter()
+                        .map(|key| BatchOp::Put {
+                            key,
+                            value: [key.as_slice(), b"value"].concat(),
+                        })
+                        .collect(),
+                )?
+                .commit()?;
+            }
+            db.kv_root_hash(None)?
+        };
+        downgrade(&db_path);
+
+        assert!(matches!(
+            Db::new(&db_path, &cfg.clone().build()),
+            Err(DbError::MigrationRequired { found: 0 })
+        ));
+        let plan = Db::migration_plan(&db_path)?;
+        assert_eq!(
+            (plan.from, plan.to, plan.steps.len()),
+            (0, FORMAT_VERSION, 1)
+        );
+        {
+            let db = Db::new(&db_path, &cfg.clone().read_only(true).build())?;
+            assert_eq!(db.kv_root_hash(None)?, root_hash);
+        }
+
+        // into a new directory, which leaves the original as it was
+        assert_eq!(Db::migrate(&db_path, Some(&dest))?, plan);
+        assert_eq!(Db::migration_plan(&db_path)?.from, 0);
+        assert!(Db::migrate(&db_path, Some(&dest)).is_err());
+        {
+            let db = Db::new(&dest, &cfg.clone().build())?;
+            assert_eq!(db.kv_root_hash(None)?, root_hash);
+            for key in &keys {
+                assert_eq!(db.kv_get(key)?, [key.as_slice(), b"value"].concat());
+            }
+        }
+
+        // in place
+        Db::migrate(&db_path, None)?;
+        let plan = Db::migration_plan(&db_path)?;
+        assert!(plan.steps.is_empty());
+        assert_eq!(Db::migrate(&db_path, None)?, plan);
+        let db = Db::new(&db_path, &cfg.build())?;
+        assert_eq!(db.kv_root_hash(None)?, root_hash);
+        drop(db);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn version_1_db_is_relabelled() -> Result<(), DbError> {
+        let dir = get_tmp_dir().join("migrate_version_1_db_is_relabelled");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let db_path = dir.join("db");
+        let dest = dir.join("upgraded");
+        let rebuilt = dir.join("rebuilt");
+
+        let cfg = DbConfig::builder()
+            .payload_file_nbit(16)
+            .payload_regn_nbit(16);
+        let (first_hash, root_hash) = {
+            let db = Db::new(&db_path, &cfg.clone().truncate(true).build())?;
+            let put = |key: &[u8]| {
+                db.new_proposal(vec![BatchOp::Put {
+                    key,
+                    value: b"v".to_vec(),
+                }])?
+                .commit()?;
+                db.kv_root_hash(None)
+            };
+            (put(b"k")?, put(b"l")?)
+        };
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(db_path.join("merkle/meta/00000000.fw"))?
+            .write_all_at(
+                &1u64.to_le_bytes(),
+                offset_of!(DbParams, format_version) as u64,
+            )?;
+
+        assert!(matches!(
+            Db::new(&db_path, &cfg.clone().build()),
+            Err(DbError::MigrationRequired { found: 1 })
+        ));
+        assert_eq!(Db::migration_plan(&db_path)?.steps.len(), 6);
+
+        // into a new directory, which leaves the original as it was
+        Db::migrate(&db_path, Some(&dest))?;
+        assert_eq!(Db::migration_plan(&db_path)?.from, 1);
+        {
+            let db = Db::new(&dest, &cfg.clone().build())?;
+            assert_eq!(db.kv_root_hash(None)?, root_hash);
+        }
+
+        // in place, which keeps the window of past revisions
+        Db::migrate(&db_path, None)?;
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 2);
+        let db = Db::new(&db_path, &cfg.clone().build())?;
+        assert_eq!(db.kv_root_hash(None)?, root_hash);
+        let rev = db.get_revision(&first_hash).unwrap();
+        assert!(rev.kv_get(b"l").is_none());
+        drop(rev);
+
+        // a rebuild carries the keyspaces over
+        db.new_proposal(vec![BatchOp::KeyspacePut {
+            keyspace: "state".to_string(),
+            key: b"a",
+            value: b"b".to_vec(),
+        }])?
+        .commit()?;
+        let root_hash = db.root_hash()?;
+        drop(db);
+        rebuild(&db_path, &rebuilt)?;
+        let db = Db::new(&rebuilt, &cfg.build())?;
+        assert_eq!(db.root_hash()?, root_hash);
+        assert_eq!(db.keyspace_get("state", b"a")?, b"b");
+        drop(db);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
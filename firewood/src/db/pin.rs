@@ -0,0 +1,172 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{DbRevInner, SharedStore, Universe};
+use crate::{merkle::TrieHash, storage::StoreRevShared};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+/// A revision that has left the rolling window but is still readable.
+#[derive(Debug)]
+struct RetainedRevision {
+    root_hash: Option<TrieHash>,
+    universe: Universe<StoreRevShared>,
+    size: u64,
+}
+
+/// Bookkeeping for pinned revisions.
+///
+/// A pinned revision that is still inside the window needs nothing special.
+/// Once it is evicted, it is moved here together with every revision evicted
+/// after it: each past revision is stored as a delta on top of the next newer
+/// one, so the newer ones must stay in memory for the pinned one to be read.
+#[derive(Debug)]
+pub(super) struct PinTable {
+    counts: HashMap<TrieHash, usize>,
+    /// Evicted revisions, newest first. The oldest entry is always pinned.
+    retained: VecDeque<RetainedRevision>,
+    retained_bytes: u64,
+    max_bytes: u64,
+    forced_releases: u64,
+}
+
+/// A snapshot of the pinned revision bookkeeping, see [Db::pin_stats](super::Db::pin_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PinStats {
+    /// Number of [RevisionPin]s that have not been dropped yet.
+    pub pins: usize,
+    /// Number of revisions kept alive past the rolling window.
+    pub retained_revisions: usize,
+    /// Bytes of delta pages kept alive past the rolling window.
+    pub retained_bytes: u64,
+    /// Number of pinned revisions that were released early to stay within
+    /// [DbConfig::max_pinned_bytes](super::DbConfig::max_pinned_bytes).
+    pub forced_releases: u64,
+}
+
+impl PinTable {
+    pub(super) fn new(max_bytes: u64) -> Self {
+        Self {
+            counts: HashMap::new(),
+            retained: VecDeque::new(),
+            retained_bytes: 0,
+            max_bytes,
+            forced_releases: 0,
+        }
+    }
+
+    fn is_pinned(&self, root_hash: &TrieHash) -> bool {
+        self.counts.contains_key(root_hash)
+    }
+
+    pub(super) fn pin(&mut self, root_hash: TrieHash) {
+        *self.counts.entry(root_hash).or_default() += 1;
+    }
+
+    fn unpin(&mut self, root_hash: &TrieHash) {
+        if let Some(count) = self.counts.get_mut(root_hash) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(root_hash);
+            }
+        }
+        self.trim();
+    }
+
+    /// A pinned revision that has already left the window.
+    pub(super) fn get(&self, root_hash: &TrieHash) -> Option<&Universe<StoreRevShared>> {
+        if !self.is_pinned(root_hash) {
+            return None;
+        }
+        self.retained
+            .iter()
+            .find(|rev| rev.root_hash.as_ref() == Some(root_hash))
+            .map(|rev| &rev.universe)
+    }
+
+    /// Called for every revision evicted from the window, oldest first.
+    pub(super) fn evicted(
+        &mut self,
+        root_hash: Option<TrieHash>,
+        universe: Universe<StoreRevShared>,
+    ) {
+        let pinned = root_hash.as_ref().is_some_and(|hash| self.is_pinned(hash));
+        if !pinned && self.retained.is_empty() {
+            return;
+        }
+
+        let size = universe.merkle.meta.delta_size() + universe.merkle.payload.delta_size();
+        self.retained_bytes += size;
+        self.retained.push_front(RetainedRevision {
+            root_hash,
+            universe,
+            size,
+        });
+
+        // give up the oldest pinned revisions first when over the limit
+        while self.retained_bytes > self.max_bytes {
+            let Some(oldest) = self.retained.pop_back() else {
+                break;
+            };
+            self.retained_bytes -= oldest.size;
+            self.forced_releases += 1;
+            self.trim();
+        }
+    }
+
+    /// Drop the oldest retained revisions until the oldest one is pinned.
+    fn trim(&mut self) {
+        while let Some(oldest) = self.retained.back() {
+            if oldest
+                .root_hash
+                .as_ref()
+                .is_some_and(|hash| self.is_pinned(hash))
+            {
+                break;
+            }
+            self.retained_bytes -= oldest.size;
+            self.retained.pop_back();
+        }
+    }
+
+    pub(super) fn stats(&self) -> PinStats {
+        PinStats {
+            pins: self.counts.values().sum(),
+            retained_revisions: self.retained.len(),
+            retained_bytes: self.retained_bytes,
+            forced_releases: self.forced_releases,
+        }
+    }
+}
+
+/// Keeps a revision readable through [Db::get_revision](super::Db::get_revision)
+/// after it leaves the rolling window of `max_revisions`, until the pin is
+/// dropped. Pins are held in memory only and do not survive a restart.
+#[derive(Debug)]
+pub struct RevisionPin {
+    root_hash: TrieHash,
+    revisions: Arc<Mutex<DbRevInner<SharedStore>>>,
+}
+
+impl RevisionPin {
+    pub(super) fn new(root_hash: TrieHash, revisions: Arc<Mutex<DbRevInner<SharedStore>>>) -> Self {
+        Self {
+            root_hash,
+            revisions,
+        }
+    }
+
+    /// The root hash of the pinned revision.
+    pub fn root_hash(&self) -> &TrieHash {
+        &self.root_hash
+    }
+}
+
+impl Drop for RevisionPin {
+    fn drop(&mut self) {
+        self.revisions.lock().pins.unpin(&self.root_hash);
+    }
+}
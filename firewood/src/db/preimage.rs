@@ -0,0 +1,61 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{DbError, DbRev};
+use crate::merkle::Node;
+use shale::{disk_address::DiskAddress, CachedStore, ShaleError, ShaleStore, Storable};
+use std::io::{Cursor, Write};
+
+/// Points to the preimage table of a DB whose keys are hashed, a trie in the merkle space that
+/// maps the hash of every key ever put in the DB to the key. It follows the
+/// [super::AccountHeader] in the meta space, where DBs created before keys could be hashed have
+/// zeros.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::NoUninit)]
+pub(super) struct PreimageHeader {
+    pub(super) table: DiskAddress,
+}
+
+impl PreimageHeader {
+    pub const MSIZE: u64 = std::mem::size_of::<Self>() as u64;
+
+    pub fn new_empty() -> Self {
+        Self {
+            table: DiskAddress::null(),
+        }
+    }
+}
+
+impl Storable for PreimageHeader {
+    fn hydrate<T: CachedStore>(addr: usize, mem: &T) -> Result<Self, ShaleError> {
+        let raw = mem
+            .get_view(addr, Self::MSIZE)
+            .ok_or(ShaleError::InvalidCacheView {
+                offset: addr,
+                size: Self::MSIZE,
+            })?;
+        Ok(Self {
+            table: raw.as_deref().as_slice().into(),
+        })
+    }
+
+    fn dehydrated_len(&self) -> u64 {
+        Self::MSIZE
+    }
+
+    fn dehydrate(&self, to: &mut [u8]) -> Result<(), ShaleError> {
+        let mut cur = Cursor::new(to);
+        cur.write_all(&self.table.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> DbRev<S> {
+    /// Get the key that hashes to `trie_key`, if the DB hashes its keys and the key has been put
+    /// in it. See [crate::db::DbConfig::secure_keys].
+    pub fn preimage<K: AsRef<[u8]>>(&self, trie_key: K) -> Result<Option<Vec<u8>>, DbError> {
+        self.merkle
+            .preimage(trie_key.as_ref())
+            .map_err(DbError::Merkle)
+    }
+}
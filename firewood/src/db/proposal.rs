@@ -1,25 +1,50 @@
 // Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
 // See the file LICENSE.md for licensing terms.
 
+use super::root_index::MAX_METADATA_LEN;
 use super::{
     get_sub_universe_from_deltas, get_sub_universe_from_empty_delta, Db, DbConfig, DbError,
-    DbHeader, DbInner, DbRev, DbRevInner, SharedStore, Store, Universe, MERKLE_META_SPACE,
+    DbInner, DbRev, DbRevInner, SharedStore, Store, Universe, MERKLE_META_SPACE,
     MERKLE_PAYLOAD_SPACE, ROOT_HASH_SPACE,
 };
+#[cfg(feature = "eth")]
+use crate::account::AccountOp;
 use crate::{
-    merkle::{TrieHash, TRIE_HASH_LEN},
-    storage::{buffer::BufferWrite, AshRecord, StoreRevMut},
+    merkle::TrieHash,
+    storage::{buffer::BufferWrite, Ash, AshRecord, StoreDelta, StoreRevMut},
 };
 use parking_lot::{Mutex, RwLock};
 use shale::CachedStore;
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 /// A key/value pair operation. Only put (upsert) and delete are
-/// supported
+/// supported, either in the default keyspace or in a named one
 #[derive(Debug)]
 pub enum BatchOp<K> {
-    Put { key: K, value: Vec<u8> },
-    Delete { key: K },
+    Put {
+        key: K,
+        value: Vec<u8>,
+    },
+    Delete {
+        key: K,
+    },
+    /// A put in `keyspace`, which is created if it does not exist yet
+    KeyspacePut {
+        keyspace: String,
+        key: K,
+        value: Vec<u8>,
+    },
+    /// A delete in `keyspace`
+    KeyspaceDelete {
+        keyspace: String,
+        key: K,
+    },
+    /// A change to the account at `address`
+    #[cfg(feature = "eth")]
+    Account {
+        address: K,
+        op: AccountOp,
+    },
 }
 
 /// A list of operations to consist of a batch that
@@ -41,6 +66,8 @@ pub struct Proposal {
     pub(super) rev: DbRev<Store>,
     pub(super) store: Universe<Arc<StoreRevMut>>,
     pub(super) committed: Arc<Mutex<bool>>,
+    // The rollback generation of the Db this proposal was created in
+    pub(super) generation: u64,
 
     pub(super) parent: ProposalBase,
 }
@@ -60,42 +87,29 @@ impl Proposal {
         let r = Arc::clone(&self.r);
         let cfg = self.cfg.clone();
 
-        let db_header_ref = Db::get_db_header_ref(store.merkle.meta.as_ref())?;
-
-        let merkle_payload_header_ref = Db::get_payload_header_ref(
-            store.merkle.meta.as_ref(),
-            Db::PARAM_SIZE + DbHeader::MSIZE,
-        )?;
-
-        let header_refs = (db_header_ref, merkle_payload_header_ref);
+        let (header_offset, trie_options, hash_pool) = {
+            let inner = m.read();
+            (
+                inner.header_offset,
+                inner.trie_options,
+                inner.hash_pool.clone(),
+            )
+        };
+        let header_refs = Db::get_header_refs(store.merkle.meta.as_ref(), header_offset)?;
 
         let mut rev = Db::new_revision(
             header_refs,
             (store.merkle.meta.clone(), store.merkle.payload.clone()),
             cfg.payload_regn_nbit,
             cfg.payload_max_walk,
+            trie_options,
+            hash_pool.as_ref(),
             &cfg.rev,
         )?;
-        data.into_iter().try_for_each(|op| -> Result<(), DbError> {
-            match op {
-                BatchOp::Put { key, value } => {
-                    let (header, merkle) = rev.borrow_split();
-                    merkle
-                        .insert(key, value, header.kv_root)
-                        .map_err(DbError::Merkle)?;
-                    Ok(())
-                }
-                BatchOp::Delete { key } => {
-                    let (header, merkle) = rev.borrow_split();
-                    merkle
-                        .remove(key, header.kv_root)
-                        .map_err(DbError::Merkle)?;
-                    Ok(())
-                }
-            }
-        })?;
+        apply_batch(&mut rev, data)?;
         rev.flush_dirty().unwrap();
 
+        let generation = self.generation;
         let parent = ProposalBase::Proposal(self);
 
         Ok(Proposal {
@@ -105,6 +119,7 @@ impl Proposal {
             rev,
             store,
             committed: Arc::new(Mutex::new(false)),
+            generation,
             parent,
         })
     }
@@ -112,6 +127,19 @@ impl Proposal {
     /// Persist all changes to the DB. The atomicity of the [Proposal] guarantees all changes are
     /// either retained on disk or lost together during a crash.
     pub fn commit(&self) -> Result<(), DbError> {
+        self.commit_with_metadata(&[])
+    }
+
+    /// Like [Proposal::commit], but also stores `metadata` with the new revision, in the same
+    /// WAL record as the commit itself. It can be read back with [Db::revision_metadata] and
+    /// searched with [Db::get_revision_by_metadata] and [Db::get_revision_by_metadata_prefix] while
+    /// the revision is in the rolling window.
+    /// Any parent proposals that still need to be committed are committed without metadata.
+    pub fn commit_with_metadata(&self, metadata: &[u8]) -> Result<(), DbError> {
+        if metadata.len() > MAX_METADATA_LEN {
+            return Err(DbError::MetadataTooLarge);
+        }
+
         let mut committed = self.committed.lock();
         if *committed {
             return Ok(());
@@ -123,12 +151,16 @@ impl Proposal {
 
         // Check for if it can be committed
         let mut revisions = self.r.lock();
-        let committed_root_hash = revisions.base_revision.kv_root_hash().ok();
+        if self.generation != revisions.generation {
+            // the Db has been rolled back since this proposal was created
+            return Err(DbError::InvalidProposal);
+        }
+        let committed_root_hash = revisions.base_revision.root_hash().ok();
         let committed_root_hash =
             committed_root_hash.expect("committed_root_hash should not be none");
         match &self.parent {
             ProposalBase::Proposal(p) => {
-                let parent_root_hash = p.rev.kv_root_hash().ok();
+                let parent_root_hash = p.rev.root_hash().ok();
                 let parent_root_hash =
                     parent_root_hash.expect("parent_root_hash should not be none");
                 if parent_root_hash != committed_root_hash {
@@ -136,7 +168,7 @@ impl Proposal {
                 }
             }
             ProposalBase::View(p) => {
-                let parent_root_hash = p.kv_root_hash().ok();
+                let parent_root_hash = p.root_hash().ok();
                 let parent_root_hash =
                     parent_root_hash.expect("parent_root_hash should not be none");
                 if parent_root_hash != committed_root_hash {
@@ -145,110 +177,246 @@ impl Proposal {
             }
         };
 
-        let kv_root_hash = self.rev.kv_root_hash().ok();
-        let kv_root_hash = kv_root_hash.expect("kv_root_hash should not be none");
+        let root_hash = self.rev.root_hash().ok();
+        let root_hash = root_hash.expect("root_hash should not be none");
 
         // clear the staging layer and apply changes to the CachedSpace
-        let (merkle_payload_redo, merkle_payload_wal) = self.store.merkle.payload.delta();
-        let (merkle_meta_redo, merkle_meta_wal) = self.store.merkle.meta.delta();
-
         let mut rev_inner = self.m.write();
-        let merkle_meta_undo = rev_inner
-            .cached_space
-            .merkle
-            .meta
-            .update(&merkle_meta_redo)
-            .unwrap();
-        let merkle_payload_undo = rev_inner
-            .cached_space
-            .merkle
-            .payload
-            .update(&merkle_payload_redo)
-            .unwrap();
-
-        // update the rolling window of past revisions
-        let latest_past = Universe {
-            merkle: get_sub_universe_from_deltas(
-                &rev_inner.cached_space.merkle,
-                merkle_meta_undo,
-                merkle_payload_undo,
-            ),
-        };
-
-        let max_revisions = revisions.max_revisions;
-        if let Some(rev) = revisions.inner.front_mut() {
-            rev.merkle
-                .meta
-                .set_base_space(latest_past.merkle.meta.inner().clone());
-            rev.merkle
-                .payload
-                .set_base_space(latest_past.merkle.payload.inner().clone());
-        }
-        revisions.inner.push_front(latest_past);
-        while revisions.inner.len() > max_revisions {
-            revisions.inner.pop_back();
-        }
+        apply_commit(
+            &mut rev_inner,
+            &mut revisions,
+            &self.cfg,
+            self.store.merkle.meta.delta(),
+            self.store.merkle.payload.delta(),
+            root_hash,
+            metadata,
+        )?;
+        *committed = true;
+        Ok(())
+    }
+}
 
-        let base = Universe {
-            merkle: get_sub_universe_from_empty_delta(&rev_inner.cached_space.merkle),
-        };
+/// Puts and deletes of the keys of a trie, as [crate::merkle::Merkle::apply_batch] takes them.
+type Changes<K> = Vec<(K, Option<Vec<u8>>)>;
 
-        let db_header_ref = Db::get_db_header_ref(&base.merkle.meta)?;
+/// The changes of a batch that are yet to be applied, by the trie they are in.
+struct Pending<K> {
+    kv: Changes<K>,
+    keyspaces: BTreeMap<String, Changes<K>>,
+}
 
-        let merkle_payload_header_ref =
-            Db::get_payload_header_ref(&base.merkle.meta, Db::PARAM_SIZE + DbHeader::MSIZE)?;
+impl<K: AsRef<[u8]>> Pending<K> {
+    /// Apply the changes to each trie in one pass, see [crate::merkle::Merkle::apply_batch].
+    fn apply(&mut self, rev: &mut DbRev<Store>) -> Result<(), DbError> {
+        let kv = std::mem::take(&mut self.kv);
+        if !kv.is_empty() {
+            let (header, merkle) = rev.borrow_split();
+            merkle
+                .apply_batch(kv, header.kv_root)
+                .map_err(DbError::Merkle)?;
+        }
+        for (keyspace, changes) in std::mem::take(&mut self.keyspaces) {
+            let root = if changes.iter().any(|(_, value)| value.is_some()) {
+                Some(rev.keyspace_root_or_create(&keyspace)?)
+            } else {
+                // nothing to delete from a keyspace that does not exist
+                rev.keyspace_root(&keyspace)?
+            };
+            if let Some(root) = root {
+                rev.merkle
+                    .apply_batch(changes, root)
+                    .map_err(DbError::Merkle)?;
+            }
+        }
+        Ok(())
+    }
+}
 
-        let header_refs = (db_header_ref, merkle_payload_header_ref);
+/// Replace the default key-value trie of the revision of a new proposal with one built from the
+/// sorted `items`, see [crate::merkle::TrieBuilder]. The storage of the accounts that were in it
+/// goes with them.
+pub(super) fn build_kv<K: AsRef<[u8]>>(
+    rev: &mut DbRev<Store>,
+    items: impl IntoIterator<Item = (K, Vec<u8>)>,
+) -> Result<(), DbError> {
+    #[cfg(feature = "eth")]
+    rev.clear_storage()?;
+    let (header, merkle) = rev.borrow_split();
+    let mut builder = merkle.trie_builder();
+    for (key, value) in items {
+        builder.push(key, value)?;
+    }
+    let (root, _) = builder.finish()?;
+    merkle.remove_tree(header.kv_root)?;
+    header.write(|r| r.kv_root = root).unwrap();
+    Ok(())
+}
 
-        let base_revision = Db::new_revision(
-            header_refs,
-            (base.merkle.meta.clone(), base.merkle.payload.clone()),
-            0,
-            self.cfg.payload_max_walk,
-            &self.cfg.rev,
-        )?;
-        revisions.base = base;
-        revisions.base_revision = Arc::new(base_revision);
-
-        // update the rolling window of root hashes
-        revisions.root_hashes.push_front(kv_root_hash.clone());
-        if revisions.root_hashes.len() > max_revisions {
-            revisions
-                .root_hashes
-                .resize(max_revisions, TrieHash([0; TRIE_HASH_LEN]));
+/// Apply the operations of a batch to the revision of a new proposal. Puts and deletes are
+/// applied together, trie by trie, as if one by one in order.
+pub(super) fn apply_batch<K: AsRef<[u8]>>(
+    rev: &mut DbRev<Store>,
+    data: Batch<K>,
+) -> Result<(), DbError> {
+    #[cfg(feature = "eth")]
+    let mut touched = std::collections::BTreeSet::new();
+    let mut pending = Pending {
+        kv: Vec::new(),
+        keyspaces: BTreeMap::new(),
+    };
+    for op in data {
+        match op {
+            BatchOp::Put { key, value } => pending.kv.push((key, Some(value))),
+            BatchOp::Delete { key } => pending.kv.push((key, None)),
+            BatchOp::KeyspacePut {
+                keyspace,
+                key,
+                value,
+            } => pending
+                .keyspaces
+                .entry(keyspace)
+                .or_default()
+                .push((key, Some(value))),
+            BatchOp::KeyspaceDelete { keyspace, key } => pending
+                .keyspaces
+                .entry(keyspace)
+                .or_default()
+                .push((key, None)),
+            #[cfg(feature = "eth")]
+            BatchOp::Account { address, op } => {
+                // an account op reads the account it changes, so what comes before it is applied
+                pending.apply(rev)?;
+                rev.apply_account_op(address.as_ref(), op, &mut touched)?
+            }
         }
+    }
+    pending.apply(rev)?;
+    #[cfg(feature = "eth")]
+    rev.update_storage_roots(touched)?;
+    Ok(())
+}
 
-        rev_inner.root_hash_staging.write(0, &kv_root_hash.0);
-        let (root_hash_redo, root_hash_wal) = rev_inner.root_hash_staging.delta();
-
-        // schedule writes to the disk
-        rev_inner.disk_requester.write(
-            vec![
-                BufferWrite {
-                    space_id: self.store.merkle.payload.id(),
-                    delta: merkle_payload_redo,
-                },
-                BufferWrite {
-                    space_id: self.store.merkle.meta.id(),
-                    delta: merkle_meta_redo,
-                },
-                BufferWrite {
-                    space_id: rev_inner.root_hash_staging.id(),
-                    delta: root_hash_redo,
-                },
-            ],
-            AshRecord(
-                [
-                    (MERKLE_META_SPACE, merkle_meta_wal),
-                    (MERKLE_PAYLOAD_SPACE, merkle_payload_wal),
-                    (ROOT_HASH_SPACE, root_hash_wal),
-                ]
-                .into(),
-            ),
+/// Apply the changes of a commit that produces the revision with `root_hash` to the cached spaces, the rolling
+/// window of past revisions and the root hash index, and schedule them to be written to disk as
+/// a single WAL record.
+pub(super) fn apply_commit(
+    rev_inner: &mut DbInner,
+    revisions: &mut DbRevInner<SharedStore>,
+    cfg: &DbConfig,
+    (merkle_meta_redo, merkle_meta_wal): (StoreDelta, Ash),
+    (merkle_payload_redo, merkle_payload_wal): (StoreDelta, Ash),
+    root_hash: TrieHash,
+    metadata: &[u8],
+) -> Result<(), DbError> {
+    if let Some(history) = rev_inner.history.as_mut() {
+        let record = AshRecord(
+            [
+                (MERKLE_META_SPACE, merkle_meta_wal.clone()),
+                (MERKLE_PAYLOAD_SPACE, merkle_payload_wal.clone()),
+            ]
+            .into(),
         );
-        *committed = true;
-        Ok(())
+        let seq = revisions.root_index.next_seq();
+        history.append(seq, root_hash.clone(), &record, metadata)?;
+    }
+
+    let merkle_meta_undo = rev_inner
+        .cached_space
+        .merkle
+        .meta
+        .update(&merkle_meta_redo)
+        .unwrap();
+    let merkle_payload_undo = rev_inner
+        .cached_space
+        .merkle
+        .payload
+        .update(&merkle_payload_redo)
+        .unwrap();
+
+    // update the rolling window of past revisions
+    let latest_past = Universe {
+        merkle: get_sub_universe_from_deltas(
+            &rev_inner.cached_space.merkle,
+            merkle_meta_undo,
+            merkle_payload_undo,
+        ),
+    };
+
+    let max_revisions = revisions.max_revisions;
+    if let Some(rev) = revisions.inner.front_mut() {
+        rev.merkle
+            .meta
+            .set_base_space(latest_past.merkle.meta.inner().clone());
+        rev.merkle
+            .payload
+            .set_base_space(latest_past.merkle.payload.inner().clone());
     }
+    revisions.inner.push_front(latest_past);
+    while revisions.inner.len() > max_revisions {
+        // the root index has not recorded this commit yet, so the revision
+        // at the back is `len - 1` commits older than the previous one
+        let root_hash = revisions
+            .root_index
+            .hash_at_depth(revisions.inner.len() - 1)
+            .cloned();
+        if let Some(evicted) = revisions.inner.pop_back() {
+            revisions.pins.evicted(root_hash, evicted);
+        }
+    }
+
+    let base = Universe {
+        merkle: get_sub_universe_from_empty_delta(&rev_inner.cached_space.merkle),
+    };
+
+    let header_refs = Db::get_header_refs(&base.merkle.meta, rev_inner.header_offset)?;
+
+    let base_revision = Db::new_revision(
+        header_refs,
+        (base.merkle.meta.clone(), base.merkle.payload.clone()),
+        0,
+        cfg.payload_max_walk,
+        rev_inner.trie_options,
+        rev_inner.hash_pool.as_ref(),
+        &cfg.rev,
+    )?;
+    revisions.base = base;
+    revisions.base_revision = Arc::new(base_revision);
+
+    // record the new root hash in the index, in the same WAL record as the commit
+    rev_inner.root_hash_staging.write(0, &root_hash.0);
+    revisions
+        .root_index
+        .record(root_hash, metadata, &mut rev_inner.root_hash_staging);
+    let (root_hash_redo, root_hash_wal) = rev_inner.root_hash_staging.delta();
+    rev_inner.root_hash_staging.reset_deltas();
+    rev_inner.root_hash_cache.update(&root_hash_redo).unwrap();
+
+    // schedule writes to the disk
+    rev_inner.disk_requester.write(
+        vec![
+            BufferWrite {
+                space_id: MERKLE_PAYLOAD_SPACE,
+                delta: merkle_payload_redo,
+            },
+            BufferWrite {
+                space_id: MERKLE_META_SPACE,
+                delta: merkle_meta_redo,
+            },
+            BufferWrite {
+                space_id: rev_inner.root_hash_staging.id(),
+                delta: root_hash_redo,
+            },
+        ],
+        AshRecord(
+            [
+                (MERKLE_META_SPACE, merkle_meta_wal),
+                (MERKLE_PAYLOAD_SPACE, merkle_payload_wal),
+                (ROOT_HASH_SPACE, root_hash_wal),
+            ]
+            .into(),
+        ),
+    );
+    Ok(())
 }
 
 impl Proposal {
@@ -0,0 +1,290 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::DbError;
+use crate::{
+    merkle::{TrieHash, TRIE_HASH_LEN},
+    storage::StoreRevMut,
+};
+use shale::{CachedStore, ShaleError};
+use std::collections::HashMap;
+
+/// The index lives in the root hash space, right after the latest root hash.
+const INDEX_OFFSET: usize = TRIE_HASH_LEN;
+/// `nslots` and `next_seq`, both u64.
+const HEADER_SIZE: usize = 16;
+/// Maximum length of the metadata stored with a commit.
+pub const MAX_METADATA_LEN: usize = 256;
+/// A root hash, the sequence number of the commit that produced it, stored
+/// plus one so an all-zero slot reads as empty, and the length-prefixed
+/// metadata of the commit.
+const ENTRY_SIZE: usize = TRIE_HASH_LEN + 8 + 2 + MAX_METADATA_LEN;
+const METADATA_OFFSET: usize = TRIE_HASH_LEN + 8;
+
+#[derive(Debug, Clone)]
+struct Slot {
+    root_hash: TrieHash,
+    seq: u64,
+    metadata: Box<[u8]>,
+}
+
+/// Persistent index from root hash to the position of its commit in the WAL
+/// window. Every commit is assigned an increasing sequence number and recorded
+/// in a ring of `nslots` entries, which is written through the root hash
+/// staging space so it lands in the same WAL record as the commit itself.
+///
+/// The whole ring is loaded when the database is opened, so finding how many
+/// commits ago a root hash was produced, or which commit carried some
+/// metadata, never has to read the WAL.
+#[derive(Debug)]
+pub(super) struct RootHashIndex {
+    nslots: u64,
+    next_seq: u64,
+    slots: Vec<Option<Slot>>,
+    by_hash: HashMap<TrieHash, u64>,
+}
+
+impl RootHashIndex {
+    /// Load the index from the root hash space. A space that has never been
+    /// indexed is set up with `default_nslots` entries on the first commit.
+    pub(super) fn load(store: &StoreRevMut, default_nslots: u64) -> Result<Self, DbError> {
+        let header = read(store, INDEX_OFFSET, HEADER_SIZE as u64)?;
+        let nslots = u64::from_le_bytes(header[..8].try_into().expect("8 bytes"));
+        let next_seq = u64::from_le_bytes(header[8..].try_into().expect("8 bytes"));
+
+        if nslots == 0 {
+            return Ok(Self {
+                nslots: default_nslots.max(1),
+                next_seq: 0,
+                slots: vec![None; default_nslots.max(1) as usize],
+                by_hash: HashMap::new(),
+            });
+        }
+
+        let raw = read(
+            store,
+            INDEX_OFFSET + HEADER_SIZE,
+            nslots * ENTRY_SIZE as u64,
+        )?;
+        let slots: Vec<_> = raw
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let seq = u64::from_le_bytes(
+                    entry[TRIE_HASH_LEN..METADATA_OFFSET]
+                        .try_into()
+                        .expect("8 bytes"),
+                );
+                let metadata_len = u16::from_le_bytes(
+                    entry[METADATA_OFFSET..METADATA_OFFSET + 2]
+                        .try_into()
+                        .expect("2 bytes"),
+                ) as usize;
+                seq.checked_sub(1).map(|seq| Slot {
+                    root_hash: TrieHash(entry[..TRIE_HASH_LEN].try_into().expect("hash length")),
+                    seq,
+                    metadata: entry[METADATA_OFFSET + 2..][..metadata_len.min(MAX_METADATA_LEN)]
+                        .into(),
+                })
+            })
+            .collect();
+
+        let mut by_hash = HashMap::with_capacity(slots.len());
+        for slot in slots.iter().flatten() {
+            let latest = by_hash.entry(slot.root_hash.clone()).or_insert(slot.seq);
+            *latest = (*latest).max(slot.seq);
+        }
+
+        Ok(Self {
+            nslots,
+            next_seq,
+            slots,
+            by_hash,
+        })
+    }
+
+    /// The sequence number of the next commit, which is the number of commits
+    /// made so far.
+    pub(super) fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// The number of commits between the latest revision and the most recent
+    /// revision with `root_hash`, or `None` if it is not in the index.
+    pub(super) fn depth(&self, root_hash: &TrieHash) -> Option<usize> {
+        let seq = self.by_hash.get(root_hash)?;
+        Some((self.next_seq - 1 - seq) as usize)
+    }
+
+    /// The root hash produced `depth` commits before the latest revision, if
+    /// it is still held by the ring.
+    pub(super) fn hash_at_depth(&self, depth: usize) -> Option<&TrieHash> {
+        self.slot_at_depth(depth).map(|slot| &slot.root_hash)
+    }
+
+    fn slot_at_depth(&self, depth: usize) -> Option<&Slot> {
+        let seq = self.next_seq.checked_sub(1 + depth as u64)?;
+        self.slots[(seq % self.nslots) as usize]
+            .as_ref()
+            .filter(|slot| slot.seq == seq)
+    }
+
+    /// The metadata of the most recent commit that produced `root_hash`.
+    pub(super) fn metadata(&self, root_hash: &TrieHash) -> Option<&[u8]> {
+        let depth = self.depth(root_hash)?;
+        self.slot_at_depth(depth).map(|slot| &*slot.metadata)
+    }
+
+    /// The root hash and depth of the most recent commit with exactly
+    /// `metadata`, or with metadata that starts with it if `prefix` is set. It
+    /// must not be empty.
+    pub(super) fn find_by_metadata(
+        &self,
+        metadata: &[u8],
+        prefix: bool,
+    ) -> Option<(&TrieHash, usize)> {
+        (0..self.nslots as usize).find_map(|depth| {
+            self.slot_at_depth(depth)
+                .filter(|slot| {
+                    if prefix {
+                        slot.metadata.starts_with(metadata)
+                    } else {
+                        *slot.metadata == *metadata
+                    }
+                })
+                .map(|slot| (&slot.root_hash, depth))
+        })
+    }
+
+    /// Record a new commit with `root_hash` as the latest revision, staging
+    /// the change in `store`. The metadata must not be longer than
+    /// [MAX_METADATA_LEN].
+    pub(super) fn record(&mut self, root_hash: TrieHash, metadata: &[u8], store: &mut StoreRevMut) {
+        let seq = self.next_seq;
+        let slot = (seq % self.nslots) as usize;
+
+        if let Some(evicted) = self.slots[slot].take() {
+            if self.by_hash.get(&evicted.root_hash) == Some(&evicted.seq) {
+                self.by_hash.remove(&evicted.root_hash);
+            }
+        }
+
+        let mut entry = [0; ENTRY_SIZE];
+        entry[..TRIE_HASH_LEN].copy_from_slice(&root_hash.0);
+        entry[TRIE_HASH_LEN..METADATA_OFFSET].copy_from_slice(&(seq + 1).to_le_bytes());
+        entry[METADATA_OFFSET..METADATA_OFFSET + 2]
+            .copy_from_slice(&(metadata.len() as u16).to_le_bytes());
+        entry[METADATA_OFFSET + 2..][..metadata.len()].copy_from_slice(metadata);
+        store.write(INDEX_OFFSET + HEADER_SIZE + slot * ENTRY_SIZE, &entry);
+
+        self.next_seq += 1;
+        let mut header = [0; HEADER_SIZE];
+        header[..8].copy_from_slice(&self.nslots.to_le_bytes());
+        header[8..].copy_from_slice(&self.next_seq.to_le_bytes());
+        store.write(INDEX_OFFSET, &header);
+
+        self.slots[slot] = Some(Slot {
+            root_hash: root_hash.clone(),
+            seq,
+            metadata: metadata.into(),
+        });
+        self.by_hash.insert(root_hash, seq);
+    }
+}
+
+fn read(store: &StoreRevMut, offset: usize, length: u64) -> Result<Vec<u8>, DbError> {
+    store
+        .get_view(offset, length)
+        .map(|view| view.as_deref())
+        .ok_or(DbError::Shale(ShaleError::InvalidCacheView {
+            offset,
+            size: length,
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ZeroStore;
+    use std::sync::Arc;
+
+    fn hash(n: u8) -> TrieHash {
+        TrieHash([n; TRIE_HASH_LEN])
+    }
+
+    #[test]
+    fn depth_follows_commits() -> Result<(), DbError> {
+        let mut store = StoreRevMut::new(Arc::new(ZeroStore::default()));
+        let mut index = RootHashIndex::load(&store, 3)?;
+        assert_eq!(index.depth(&hash(1)), None);
+
+        for n in 1..=4 {
+            index.record(hash(n), &[], &mut store);
+        }
+
+        // the oldest commit has been evicted from the ring
+        assert_eq!(index.depth(&hash(1)), None);
+        assert_eq!(index.depth(&hash(2)), Some(2));
+        assert_eq!(index.depth(&hash(4)), Some(0));
+        assert_eq!(index.hash_at_depth(1), Some(&hash(3)));
+        assert_eq!(index.hash_at_depth(3), None);
+
+        // a repeated root hash resolves to its most recent commit
+        index.record(hash(3), &[], &mut store);
+        assert_eq!(index.depth(&hash(3)), Some(0));
+        assert_eq!(index.depth(&hash(2)), None);
+
+        let reloaded = RootHashIndex::load(&store, 100)?;
+        assert_eq!(reloaded.nslots, 3);
+        assert_eq!(reloaded.depth(&hash(3)), Some(0));
+        assert_eq!(reloaded.depth(&hash(4)), Some(1));
+        assert_eq!(reloaded.depth(&hash(2)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_lookup() -> Result<(), DbError> {
+        let mut store = StoreRevMut::new(Arc::new(ZeroStore::default()));
+        let mut index = RootHashIndex::load(&store, 3)?;
+
+        index.record(hash(1), b"height=1", &mut store);
+        index.record(hash(2), b"height=2", &mut store);
+        index.record(hash(3), &[], &mut store);
+        index.record(hash(4), b"height=2;again", &mut store);
+
+        assert_eq!(index.metadata(&hash(2)), Some(&b"height=2"[..]));
+        assert_eq!(index.metadata(&hash(3)), Some(&[][..]));
+        assert_eq!(index.metadata(&hash(1)), None);
+        // only the whole metadata matches, unless a prefix is asked for
+        assert_eq!(
+            index.find_by_metadata(b"height=2", false),
+            Some((&hash(2), 2))
+        );
+        assert_eq!(index.find_by_metadata(b"height=", false), None);
+        assert_eq!(index.find_by_metadata(b"height=1", false), None);
+        assert_eq!(
+            index.find_by_metadata(b"height=", true),
+            Some((&hash(4), 0))
+        );
+
+        // the most recent match wins
+        index.record(hash(5), b"height=2", &mut store);
+        assert_eq!(
+            index.find_by_metadata(b"height=2", false),
+            Some((&hash(5), 0))
+        );
+        assert_eq!(
+            index.find_by_metadata(b"height=2;", true),
+            Some((&hash(4), 1))
+        );
+
+        let reloaded = RootHashIndex::load(&store, 100)?;
+        assert_eq!(reloaded.metadata(&hash(4)), Some(&b"height=2;again"[..]));
+        assert_eq!(
+            reloaded.find_by_metadata(b"height=2;again", false),
+            Some((&hash(4), 1))
+        );
+
+        Ok(())
+    }
+}
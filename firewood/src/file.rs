@@ -64,6 +64,13 @@ impl File {
         };
         Ok(File { fd })
     }
+
+    /// Open an existing file without write access, for a read-only database.
+    pub fn open_read_only<P: AsRef<Path>>(fid: u64, rootdir: P) -> Result<Self, std::io::Error> {
+        let filepath = rootdir.as_ref().join(Self::_get_fname(fid));
+        let fd = std::fs::File::options().read(true).open(filepath)?.into();
+        Ok(File { fd })
+    }
 }
 
 impl Deref for File {
@@ -85,6 +92,37 @@ pub fn touch_dir(dirname: &str, rootdir: &Path) -> Result<PathBuf, std::io::Erro
     Ok(path)
 }
 
+/// Recursively copy the directory `src` to `dst`, which must not exist yet.
+pub fn copy_dir(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove everything in the directory `path` except the entries named in `keep`.
+pub fn clear_dir(path: &Path, keep: &[&str]) -> Result<(), std::io::Error> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if keep.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
 pub fn open_dir<P: AsRef<Path>>(
     path: P,
     options: Options,
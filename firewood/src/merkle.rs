@@ -1,25 +1,41 @@
 // Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
 // See the file LICENSE.md for licensing terms.
 
-use crate::{nibbles::Nibbles, v2::api::Proof};
+use crate::{
+    nibbles::{BranchFactor, Nibbles},
+    v2::api::Proof,
+};
+use rayon::prelude::*;
 use sha3::Digest;
 use shale::{disk_address::DiskAddress, ObjRef, ShaleError, ShaleStore};
 use std::{
+    borrow::Cow,
     collections::HashMap,
     io::Write,
-    sync::{atomic::Ordering, OnceLock},
+    sync::{atomic::Ordering, Arc, OnceLock},
 };
 use thiserror::Error;
 
+mod batch;
+mod blob;
+mod builder;
+mod hasher;
 mod node;
 mod partial_path;
 mod trie_hash;
 
+use blob::Blobs;
+pub use builder::TrieBuilder;
+pub use hasher::{EthereumHasher, FirewoodHasher, MerkleDbHasher, NodeHasher, TrieEncoding};
 pub(crate) use node::Encoded;
 pub use node::{BranchNode, Data, ExtNode, LeafNode, Node, NodeType, NBRANCH};
 pub use partial_path::PartialPath;
 pub use trie_hash::{TrieHash, TRIE_HASH_LEN};
 
+/// The shortest threshold for values to be stored in blobs, see [Merkle::with_blobs], so that a
+/// blob is never smaller than the address that refers to it.
+pub const MIN_BLOB_THRESHOLD: u64 = TRIE_HASH_LEN as u64;
+
 #[derive(Debug, Error)]
 pub enum MerkleError {
     #[error("merkle datastore error: {0:?}")]
@@ -34,6 +50,10 @@ pub enum MerkleError {
     ParentLeafBranch,
     #[error("removing internal node references failed")]
     UnsetInternal,
+    #[error("proofs are not supported with the {0:?} encoding")]
+    UnprovableEncoding(TrieEncoding),
+    #[error("key {0:?} is not after the key before it")]
+    UnsortedKey(Vec<u8>),
 }
 
 macro_rules! write_node {
@@ -52,6 +72,30 @@ macro_rules! write_node {
 #[derive(Debug)]
 pub struct Merkle<S> {
     store: Box<S>,
+    encoding: TrieEncoding,
+    branch_factor: BranchFactor,
+    /// The root of the trie that maps the hash of every key to the key, when keys are hashed.
+    preimages: Option<DiskAddress>,
+    blobs: Option<Blobs>,
+    /// The threads [Merkle::root_hash] hashes subtrees on, if not the calling thread alone.
+    hash_pool: Option<Arc<HashPool>>,
+}
+
+/// Threads that the dirty subtrees of tries are hashed on, which are meant to be shared by all
+/// the merkles of a DB, see [Merkle::with_hash_pool].
+#[derive(Debug)]
+pub struct HashPool(rayon::ThreadPool);
+
+impl HashPool {
+    /// Start a pool of `threads` threads.
+    pub fn new(threads: usize) -> Result<Self, std::io::Error> {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("firewood-hash-{i}"))
+            .build()
+            .map(Self)
+            .map_err(std::io::Error::other)
+    }
 }
 
 impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
@@ -66,20 +110,124 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
     }
 }
 
+impl<S> Merkle<S> {
+    pub fn empty_root() -> &'static TrieHash {
+        static V: OnceLock<TrieHash> = OnceLock::new();
+        V.get_or_init(|| {
+            TrieHash(
+                hex::decode("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+            )
+        })
+    }
+}
+
 impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
     pub fn new(store: Box<S>) -> Self {
-        Self { store }
+        Self::with_encoding(store, TrieEncoding::default())
+    }
+
+    /// Create a merkle whose nodes are hashed in `encoding`, which must be the one every trie in
+    /// `store` was hashed in.
+    pub fn with_encoding(store: Box<S>, encoding: TrieEncoding) -> Self {
+        Self {
+            store,
+            encoding,
+            branch_factor: BranchFactor::default(),
+            preimages: None,
+            blobs: None,
+            hash_pool: None,
+        }
+    }
+
+    /// Split keys for branch nodes with `branch_factor` children, which must be the branch factor
+    /// of every trie in the store.
+    pub fn with_branch_factor(self, branch_factor: BranchFactor) -> Self {
+        Self {
+            branch_factor,
+            ..self
+        }
+    }
+
+    /// Hash keys with Keccak-256 before they are looked up or put in a trie, like Ethereum's
+    /// secure trie, so that keys with long shared prefixes don't make tries deep. The original
+    /// keys are recorded in the trie at `preimages`, made with [Merkle::init_root], for
+    /// [Merkle::for_each_kv] and [Merkle::dump] to give. It must be the same for every trie in
+    /// the store.
+    pub fn with_preimages(self, preimages: DiskAddress) -> Self {
+        Self {
+            preimages: Some(preimages),
+            ..self
+        }
+    }
+
+    /// Store values longer than `threshold` bytes out of line, in blobs that the nodes only refer
+    /// to, so that rewriting a node doesn't rewrite its value. Blobs are shared by equal values,
+    /// and recorded in the trie at `table`, made with [Merkle::init_root]. The threshold must be
+    /// at least [MIN_BLOB_THRESHOLD], and the table the same for every trie in the store.
+    pub fn with_blobs(self, table: DiskAddress, threshold: u64) -> Self {
+        debug_assert!(threshold >= MIN_BLOB_THRESHOLD);
+        Self {
+            blobs: Some(Blobs {
+                table,
+                threshold: threshold as usize,
+            }),
+            ..self
+        }
+    }
+
+    /// Hash the dirty subtrees under the branch node at the top of a trie on the threads of
+    /// `pool` in [Merkle::root_hash], rather than on the calling thread alone. The hashes are the
+    /// same either way.
+    pub fn with_hash_pool(self, pool: Arc<HashPool>) -> Self {
+        Self {
+            hash_pool: Some(pool),
+            ..self
+        }
+    }
+
+    pub fn encoding(&self) -> TrieEncoding {
+        self.encoding
+    }
+
+    pub fn branch_factor(&self) -> BranchFactor {
+        self.branch_factor
+    }
+
+    /// Whether keys are hashed, see [Merkle::with_preimages].
+    pub fn is_secure(&self) -> bool {
+        self.preimages.is_some()
+    }
+
+    /// The key `key` is stored under in a trie, which is its Keccak-256 hash if keys are hashed.
+    /// Proofs are of this key.
+    pub fn trie_key<'k>(&self, key: &'k [u8]) -> Cow<'k, [u8]> {
+        if self.is_secure() {
+            Cow::Owned(sha3::Keccak256::digest(key).to_vec())
+        } else {
+            Cow::Borrowed(key)
+        }
+    }
+
+    /// The key that hashes to `trie_key`, if keys are hashed and it has been put in a trie.
+    pub fn preimage(&self, trie_key: &[u8]) -> Result<Option<Vec<u8>>, MerkleError> {
+        match self.preimages {
+            Some(preimages) => Ok(self.get_(trie_key, preimages)?.map(|key| key.to_vec())),
+            None => Ok(None),
+        }
     }
 
     pub fn init_root(&self) -> Result<DiskAddress, MerkleError> {
         self.store
             .put_item(
                 Node::new(NodeType::Branch(BranchNode {
-                    chd: [None; NBRANCH],
+                    chd: vec![None; self.branch_factor as usize],
                     value: None,
-                    chd_encoded: Default::default(),
+                    chd_encoded: vec![None; self.branch_factor as usize],
                 })),
-                Node::max_branch_node_size(),
+                Node::max_branch_node_size(self.branch_factor),
             )
             .map_err(MerkleError::Shale)
             .map(|node| node.as_ptr())
@@ -89,18 +237,6 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         self.store.as_ref()
     }
 
-    pub fn empty_root() -> &'static TrieHash {
-        static V: OnceLock<TrieHash> = OnceLock::new();
-        V.get_or_init(|| {
-            TrieHash(
-                hex::decode("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
-                    .unwrap()
-                    .try_into()
-                    .unwrap(),
-            )
-        })
-    }
-
     pub fn root_hash(&self, root: DiskAddress) -> Result<TrieHash, MerkleError> {
         let root = self
             .get_node(root)?
@@ -109,19 +245,67 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
             .ok_or(MerkleError::NotBranchNode)?
             .chd[0];
         Ok(if let Some(root) = root {
+            self.encode_subtrees(root)?;
             let mut node = self.get_node(root)?;
-            let res = node.get_root_hash::<S>(self.store.as_ref()).clone();
+            let res = self.encoding.root_hash(&node, self.store.as_ref());
             if node.lazy_dirty.load(Ordering::Relaxed) {
                 node.write(|_| {}).unwrap();
                 node.lazy_dirty.store(false, Ordering::Relaxed);
             }
             res
         } else {
-            Self::empty_root().clone()
+            self.encoding.empty_root()
         })
     }
 
-    fn dump_(&self, u: DiskAddress, w: &mut dyn Write) -> Result<(), MerkleError> {
+    /// Encode the dirty subtrees under the branch node at the top of the trie whose root node is
+    /// at `root` in parallel, see [Merkle::with_hash_pool], so that hashing the top of the trie
+    /// finds them done.
+    fn encode_subtrees(&self, root: DiskAddress) -> Result<(), MerkleError> {
+        let Some(pool) = &self.hash_pool else {
+            return Ok(());
+        };
+        let is_dirty = |node: &ObjRef<Node>| node.root_hash.get().is_none();
+        let node = self.get_node(root)?;
+        if !is_dirty(&node) {
+            return Ok(());
+        }
+        let (branch, path) = match &node.inner {
+            NodeType::Branch(_) => (root, Vec::new()),
+            NodeType::Extension(n) if !n.1.is_null() => (n.1, n.0.to_vec()),
+            _ => return Ok(()),
+        };
+        drop(node);
+
+        let mut subtrees = Vec::new();
+        if let NodeType::Branch(n) = &self.get_node(branch)?.inner {
+            for (i, c) in n.chd.iter().enumerate() {
+                let Some(c) = c else { continue };
+                if is_dirty(&self.get_node(*c)?) {
+                    subtrees.push((*c, [&path[..], &[i as u8]].concat()));
+                }
+            }
+        }
+        if subtrees.len() < 2 {
+            return Ok(());
+        }
+
+        // the subtrees are independent, so each is encoded on whichever thread takes it next
+        let store = self.store.as_ref();
+        pool.0.install(|| {
+            subtrees.par_iter().try_for_each(|(c, path)| {
+                self.get_node(*c)?.get_encoded(store, self.encoding, path);
+                Ok(())
+            })
+        })
+    }
+
+    fn dump_(
+        &self,
+        u: DiskAddress,
+        nibbles: &mut Vec<u8>,
+        w: &mut dyn Write,
+    ) -> Result<(), MerkleError> {
         let u_ref = self.get_node(u)?;
         write!(
             w,
@@ -133,29 +317,125 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         )?;
         match &u_ref.inner {
             NodeType::Branch(n) => {
-                writeln!(w, "{n:?}")?;
-                for c in n.chd.iter().flatten() {
-                    self.dump_(*c, w)?
+                write!(w, "{n:?}")?;
+                if n.value.is_some() {
+                    self.dump_key(nibbles, w)?;
                 }
+                writeln!(w)?;
+                for (nib, c) in n.chd.iter().enumerate() {
+                    if let Some(c) = c {
+                        nibbles.push(nib as u8);
+                        self.dump_(*c, nibbles, w)?;
+                        nibbles.pop();
+                    }
+                }
+            }
+            NodeType::Leaf(n) => {
+                write!(w, "{n:?}")?;
+                let len = nibbles.len();
+                nibbles.extend_from_slice(&n.0);
+                self.dump_key(nibbles, w)?;
+                nibbles.truncate(len);
+                writeln!(w)?;
             }
-            NodeType::Leaf(n) => writeln!(w, "{n:?}").unwrap(),
             NodeType::Extension(n) => {
                 writeln!(w, "{n:?}")?;
-                self.dump_(n.1, w)?
+                let len = nibbles.len();
+                nibbles.extend_from_slice(&n.0);
+                self.dump_(n.1, nibbles, w)?;
+                nibbles.truncate(len);
             }
         }
         Ok(())
     }
 
+    /// Write the original key of the value at `nibbles`, if keys are hashed.
+    fn dump_key(&self, nibbles: &[u8], w: &mut dyn Write) -> Result<(), MerkleError> {
+        if !self.is_secure() {
+            return Ok(());
+        }
+        // the first nibble is the one of the sentinel node
+        let trie_key = self.branch_factor.join(&nibbles[1..]);
+        match self.preimage(&trie_key)? {
+            Some(key) => write!(w, " key={}", hex::encode(key))?,
+            None => write!(w, " key=<unknown>")?,
+        }
+        Ok(())
+    }
+
     pub fn dump(&self, root: DiskAddress, w: &mut dyn Write) -> Result<(), MerkleError> {
         if root.is_null() {
             write!(w, "<Empty>")?;
         } else {
-            self.dump_(root, w)?;
+            self.dump_(root, &mut Vec::new(), w)?;
         };
         Ok(())
     }
 
+    fn for_each_kv_<E, F>(&self, u: DiskAddress, nibbles: &mut Vec<u8>, f: &mut F) -> Result<(), E>
+    where
+        E: From<MerkleError>,
+        F: FnMut(&[u8], &[u8]) -> Result<(), E>,
+    {
+        // the first nibble is the one of the sentinel node
+        let key = |nibbles: &[u8]| self.branch_factor.join(&nibbles[1..]);
+
+        let u_ref = self.get_node(u)?;
+        match &u_ref.inner {
+            NodeType::Branch(n) => {
+                if let Some(value) = &n.value {
+                    self.visit_kv(&key(nibbles), value, f)?;
+                }
+                for (nib, c) in n.chd.iter().enumerate() {
+                    if let Some(c) = c {
+                        nibbles.push(nib as u8);
+                        self.for_each_kv_(*c, nibbles, f)?;
+                        nibbles.pop();
+                    }
+                }
+            }
+            NodeType::Leaf(n) => {
+                let len = nibbles.len();
+                nibbles.extend_from_slice(&n.0);
+                self.visit_kv(&key(nibbles), &n.1, f)?;
+                nibbles.truncate(len);
+            }
+            NodeType::Extension(n) => {
+                let len = nibbles.len();
+                nibbles.extend_from_slice(&n.0);
+                self.for_each_kv_(n.1, nibbles, f)?;
+                nibbles.truncate(len);
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `f` with the pair at `trie_key`, giving it the original key if keys are hashed.
+    fn visit_kv<E, F>(&self, trie_key: &[u8], value: &[u8], f: &mut F) -> Result<(), E>
+    where
+        E: From<MerkleError>,
+        F: FnMut(&[u8], &[u8]) -> Result<(), E>,
+    {
+        match self.preimage(trie_key)? {
+            Some(key) => f(&key, value),
+            None => f(trie_key, value),
+        }
+    }
+
+    /// Call `f` with every key/value pair of the trie at `root`, in key order. If keys are
+    /// hashed, the pairs are in the order of the hashes of their keys.
+    pub fn for_each_kv<E: From<MerkleError>>(
+        &self,
+        root: DiskAddress,
+        mut f: impl FnMut(&[u8], &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if root.is_null() {
+            return Ok(());
+        }
+        // the sentinel holds no value and the trie hangs off its first child
+        self.for_each_kv_(root, &mut Vec::new(), &mut f)
+    }
+
     fn set_parent(&self, new_chd: DiskAddress, parents: &mut [(ObjRef<'_, Node>, u8)]) {
         let (p_ref, idx) = parents.last_mut().unwrap();
         p_ref
@@ -178,9 +458,10 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         rem_path: &[u8],
         n_path: Vec<u8>,
         n_value: Option<Data>,
-        val: Vec<u8>,
+        val: Data,
         deleted: &mut Vec<DiskAddress>,
-    ) -> Result<Option<Vec<u8>>, MerkleError> {
+        replaced: &mut Option<Data>,
+    ) -> Result<Option<Data>, MerkleError> {
         let u_ptr = u_ref.as_ptr();
         let new_chd = match rem_path.iter().zip(n_path.iter()).position(|(a, b)| a != b) {
             Some(idx) => {
@@ -201,10 +482,10 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                 let leaf_ptr = self
                     .new_node(Node::new(NodeType::Leaf(LeafNode(
                         PartialPath(rem_path[idx + 1..].to_vec()),
-                        Data(val),
+                        val,
                     ))))?
                     .as_ptr();
-                let mut chd = [None; NBRANCH];
+                let mut chd = vec![None; self.branch_factor as usize];
                 chd[rem_path[idx] as usize] = Some(leaf_ptr);
                 chd[n_path[idx] as usize] = Some(match &u_ref.inner {
                     NodeType::Extension(u) => {
@@ -221,7 +502,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                 let t = NodeType::Branch(BranchNode {
                     chd,
                     value: None,
-                    chd_encoded: Default::default(),
+                    chd_encoded: vec![None; self.branch_factor as usize],
                 });
                 let branch_ptr = self.new_node(Node::new(t))?.as_ptr();
                 if idx > 0 {
@@ -244,12 +525,14 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                         u_ref,
                         |u| {
                             match &mut u.inner {
-                                NodeType::Leaf(u) => u.1 = Data(val),
+                                NodeType::Leaf(u) => {
+                                    *replaced = Some(std::mem::replace(&mut u.1, val))
+                                }
                                 NodeType::Extension(u) => {
                                     let write_result = self.get_node(u.1).and_then(|mut b_ref| {
                                         let write_result = b_ref.write(|b| {
-                                            b.inner.as_branch_mut().unwrap().value =
-                                                Some(Data(val));
+                                            *replaced =
+                                                b.inner.as_branch_mut().unwrap().value.replace(val);
                                             b.rehash()
                                         });
 
@@ -302,7 +585,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                         },
                         rem_path,
                         n_path[rem_path.len()],
-                        Some(Data(val)),
+                        Some(val),
                     )
                 } else {
                     // key path extends the path to u
@@ -312,20 +595,20 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                     }
                     let leaf = self.new_node(Node::new(NodeType::Leaf(LeafNode(
                         PartialPath(rem_path[n_path.len() + 1..].to_vec()),
-                        Data(val),
+                        val,
                     ))))?;
                     deleted.push(u_ptr);
                     (leaf.as_ptr(), &n_path[..], rem_path[n_path.len()], n_value)
                 };
                 drop(u_ref);
                 // [parent] (-> [ExtNode]) -> [branch with v] -> [Leaf]
-                let mut chd = [None; NBRANCH];
+                let mut chd = vec![None; self.branch_factor as usize];
                 chd[idx as usize] = Some(leaf_ptr);
                 let branch_ptr = self
                     .new_node(Node::new(NodeType::Branch(BranchNode {
                         chd,
                         value: v,
-                        chd_encoded: Default::default(),
+                        chd_encoded: vec![None; self.branch_factor as usize],
                     })))?
                     .as_ptr();
                 if !prefix.is_empty() {
@@ -353,14 +636,30 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         val: Vec<u8>,
         root: DiskAddress,
     ) -> Result<(), MerkleError> {
+        let key = key.as_ref();
+        let Some(preimages) = self.preimages else {
+            return self.insert_(key, val, root);
+        };
+        let trie_key = self.trie_key(key);
+        // preimages are never removed, as other tries may have the same key
+        if self.get_(&trie_key, preimages)?.is_none() {
+            self.insert_(&trie_key, key.to_vec(), preimages)?;
+        }
+        self.insert_(&trie_key, val, root)
+    }
+
+    fn insert_(&mut self, key: &[u8], val: Vec<u8>, root: DiskAddress) -> Result<(), MerkleError> {
+        let val = self.new_data(val)?;
         // as we split a node, we need to track deleted nodes and parents
         let mut deleted = Vec::new();
         let mut parents = Vec::new();
+        // the value that was at the key, if any, which is no longer in the trie
+        let mut replaced = None;
 
         // we use Nibbles::<1> so that 1 zero nibble is at the front
         // this is for the sentinel node, which avoids moving the root
         // and always only has one child
-        let key_nibbles = Nibbles::<1>::new(key.as_ref());
+        let key_nibbles = Nibbles::<1>::with_branch_factor(key, self.branch_factor);
 
         let mut next_node = Some(self.get_node(root)?);
         let mut nskip = 0;
@@ -397,7 +696,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                                 PartialPath(
                                     key_nibbles.into_iter().skip(key_nib_offset + 1).collect(),
                                 ),
-                                Data(val.take().unwrap()),
+                                val.take().unwrap(),
                             ))))?
                             .as_ptr();
                         // set the current child to point to this leaf
@@ -427,6 +726,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                         n_value,
                         val.take().unwrap(),
                         &mut deleted,
+                        &mut replaced,
                     )?;
                     break;
                 }
@@ -447,6 +747,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                         None,
                         val.take().unwrap(),
                         &mut deleted,
+                        &mut replaced,
                     )? {
                         // we couldn't split this, so we
                         // skip n_path items and follow the
@@ -476,12 +777,13 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                     |u| {
                         info = match &mut u.inner {
                             NodeType::Branch(n) => {
-                                n.value = Some(Data(val.take().unwrap()));
+                                replaced = n.value.replace(val.take().unwrap());
                                 None
                             }
                             NodeType::Leaf(n) => {
                                 if n.0.len() == 0 {
-                                    n.1 = Data(val.take().unwrap());
+                                    replaced =
+                                        Some(std::mem::replace(&mut n.1, val.take().unwrap()));
                                     None
                                 } else {
                                     let idx = n.0[0];
@@ -510,7 +812,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
             };
 
             if let Some((idx, more, ext)) = info {
-                let mut chd = [None; NBRANCH];
+                let mut chd = vec![None; self.branch_factor as usize];
                 let c_ptr = if more {
                     u_ptr
                 } else {
@@ -521,8 +823,8 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                 let branch = self
                     .new_node(Node::new(NodeType::Branch(BranchNode {
                         chd,
-                        value: Some(Data(val.take().unwrap())),
-                        chd_encoded: Default::default(),
+                        value: Some(val.take().unwrap()),
+                        chd_encoded: vec![None; self.branch_factor as usize],
                     })))?
                     .as_ptr();
                 self.set_parent(branch, &mut parents);
@@ -538,7 +840,10 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         for ptr in deleted.into_iter() {
             self.free_node(ptr)?
         }
-        Ok(())
+        match replaced {
+            Some(replaced) => self.release_data(&replaced),
+            None => Ok(()),
+        }
     }
 
     fn after_remove_leaf(
@@ -782,7 +1087,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
                         .insert(0, idx);
                         c.rehash()
                     });
-                    if write_result.is_err() {
+                    let c_ptr = if write_result.is_err() {
                         deleted.push(c_ptr);
                         self.new_node(c_ref.clone())?.as_ptr()
                     } else {
@@ -834,8 +1139,14 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         key: K,
         root: DiskAddress,
     ) -> Result<Option<Vec<u8>>, MerkleError> {
-        let mut chunks = vec![0];
-        chunks.extend(key.as_ref().iter().copied().flat_map(to_nibble_array));
+        let trie_key = self.trie_key(key.as_ref()).into_owned();
+        self.remove_(&trie_key, root)
+    }
+
+    fn remove_(&mut self, key: &[u8], root: DiskAddress) -> Result<Option<Vec<u8>>, MerkleError> {
+        let chunks: Vec<_> = Nibbles::<1>::with_branch_factor(key, self.branch_factor)
+            .into_iter()
+            .collect();
 
         if root.is_null() {
             return Ok(None);
@@ -919,6 +1230,9 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         for ptr in deleted.into_iter() {
             self.free_node(ptr)?;
         }
+        if let Some(found) = &found {
+            self.release_data(found)?;
+        }
         Ok(found.map(|e| e.0))
     }
 
@@ -926,16 +1240,18 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         &self,
         u: DiskAddress,
         deleted: &mut Vec<DiskAddress>,
+        in_blobs: &mut Vec<Data>,
     ) -> Result<(), MerkleError> {
         let u_ref = self.get_node(u)?;
         match &u_ref.inner {
             NodeType::Branch(n) => {
+                in_blobs.extend(n.value.iter().filter(|v| v.1.is_some()).cloned());
                 for c in n.chd.iter().flatten() {
-                    self.remove_tree_(*c, deleted)?
+                    self.remove_tree_(*c, deleted, in_blobs)?
                 }
             }
-            NodeType::Leaf(_) => (),
-            NodeType::Extension(n) => self.remove_tree_(n.1, deleted)?,
+            NodeType::Leaf(n) => in_blobs.extend(n.1 .1.is_some().then(|| n.1.clone())),
+            NodeType::Extension(n) => self.remove_tree_(n.1, deleted, in_blobs)?,
         }
         deleted.push(u);
         Ok(())
@@ -946,10 +1262,14 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         if root.is_null() {
             return Ok(());
         }
-        self.remove_tree_(root, &mut deleted)?;
+        let mut in_blobs = Vec::new();
+        self.remove_tree_(root, &mut deleted, &mut in_blobs)?;
         for ptr in deleted.into_iter() {
             self.free_node(ptr)?;
         }
+        for data in in_blobs.iter() {
+            self.release_data(data)?;
+        }
         Ok(())
     }
 
@@ -958,8 +1278,18 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         key: K,
         root: DiskAddress,
     ) -> Result<Option<RefMut<S>>, MerkleError> {
-        let mut chunks = vec![0];
-        chunks.extend(key.as_ref().iter().copied().flat_map(to_nibble_array));
+        let trie_key = self.trie_key(key.as_ref()).into_owned();
+        self.get_mut_(&trie_key, root)
+    }
+
+    fn get_mut_(
+        &mut self,
+        key: &[u8],
+        root: DiskAddress,
+    ) -> Result<Option<RefMut<'_, S>>, MerkleError> {
+        let chunks: Vec<_> = Nibbles::<1>::with_branch_factor(key, self.branch_factor)
+            .into_iter()
+            .collect();
         let mut parents = Vec::new();
 
         if root.is_null() {
@@ -1027,16 +1357,22 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
     ///
     /// If the trie does not contain a value for key, the returned proof contains
     /// all nodes of the longest existing prefix of the key, ending with the node
-    /// that proves the absence of the key (at least the root node).
+    /// that proves the absence of the key (at least the root node). [Proof::verify] tells
+    /// which of the two the proof shows.
     pub fn prove<K>(&self, key: K, root: DiskAddress) -> Result<Proof<Vec<u8>>, MerkleError>
     where
         K: AsRef<[u8]>,
     {
-        let key_nibbles = Nibbles::<0>::new(key.as_ref());
+        // the proofs are checked in the Firewood encoding
+        if self.encoding != TrieEncoding::Firewood {
+            return Err(MerkleError::UnprovableEncoding(self.encoding));
+        }
+
+        let trie_key = self.trie_key(key.as_ref());
+        let key_nibbles = Nibbles::<0>::with_branch_factor(&trie_key, self.branch_factor);
 
-        let mut proofs = HashMap::new();
         if root.is_null() {
-            return Ok(Proof(proofs));
+            return Ok(Proof(HashMap::new()));
         }
 
         // Skip the sentinel root
@@ -1048,7 +1384,7 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
             .chd[0];
         let mut u_ref = match root {
             Some(root) => self.get_node(root)?,
-            None => return Ok(Proof(proofs)),
+            None => return Ok(Proof(HashMap::new())),
         };
 
         let mut nskip = 0;
@@ -1085,25 +1421,110 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
             u_ref = self.get_node(next_ptr)?;
         }
 
+        // the node the key ends at shows if it has a value or not, and if the walk stopped
+        // earlier, the node it stopped at is already in
+        nodes.push(u_ref.as_ptr());
+        nodes.dedup();
+
+        drop(u_ref);
+        self.proof_of(nodes)
+    }
+
+    /// Like [Merkle::prove], for many keys at once: the proof has the nodes of the proofs of all
+    /// the keys, each of them once, and the trie is walked once for all of them, so the nodes
+    /// that their paths share are read once. It is checked with [Proof::verify_multi_proof].
+    pub fn prove_many<K: AsRef<[u8]>>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        root: DiskAddress,
+    ) -> Result<Proof<Vec<u8>>, MerkleError> {
+        if self.encoding != TrieEncoding::Firewood {
+            return Err(MerkleError::UnprovableEncoding(self.encoding));
+        }
+        if root.is_null() {
+            return Ok(Proof(HashMap::new()));
+        }
+
+        let mut paths: Vec<Vec<u8>> = keys
+            .into_iter()
+            .map(|key| {
+                let trie_key = self.trie_key(key.as_ref());
+                Nibbles::<0>::with_branch_factor(&trie_key, self.branch_factor)
+                    .into_iter()
+                    .collect()
+            })
+            .collect();
+        // the keys that go through a node are next to each other
+        paths.sort();
+        paths.dedup();
+
+        // Skip the sentinel root
+        let root = self
+            .get_node(root)?
+            .inner
+            .as_branch()
+            .ok_or(MerkleError::NotBranchNode)?
+            .chd[0];
+        let mut nodes = Vec::new();
+        match root {
+            Some(root) if !paths.is_empty() => self.prove_paths(root, 0, &paths, &mut nodes)?,
+            _ => (),
+        }
+        nodes.sort();
+        nodes.dedup();
+        self.proof_of(nodes)
+    }
+
+    /// Collect the nodes that [Merkle::prove] collects for each of `paths`, which all reach the
+    /// node at `u` after `depth` symbols.
+    fn prove_paths(
+        &self,
+        u: DiskAddress,
+        depth: usize,
+        paths: &[Vec<u8>],
+        nodes: &mut Vec<DiskAddress>,
+    ) -> Result<(), MerkleError> {
+        let u_ref = self.get_node(u)?;
+        nodes.push(u);
+        // the paths that end at the node come first
+        let paths = &paths[paths.partition_point(|path| path.len() == depth)..];
+
         match &u_ref.inner {
             NodeType::Branch(n) => {
-                if n.value.as_ref().is_some() {
-                    nodes.push(u_ref.as_ptr());
+                let chd = n.chd.clone();
+                drop(u_ref);
+                for group in paths.chunk_by(|a, b| a[depth] == b[depth]) {
+                    if let Some(c) = chd[group[0][depth] as usize] {
+                        self.prove_paths(c, depth + 1, group, nodes)?;
+                    }
                 }
             }
-            NodeType::Leaf(n) => {
-                if n.0.len() == 0 {
-                    nodes.push(u_ref.as_ptr());
+            NodeType::Leaf(_) => (),
+            NodeType::Extension(n) => {
+                let (n_path, c) = (n.0.to_vec(), n.1);
+                drop(u_ref);
+                // the paths that go on with the whole path of the node are next to each other
+                let start = paths.partition_point(|path| path[depth..] < n_path[..]);
+                let len = paths[start..]
+                    .iter()
+                    .take_while(|path| path[depth..].starts_with(&n_path))
+                    .count();
+                if len > 0 {
+                    self.prove_paths(c, depth + n_path.len(), &paths[start..start + len], nodes)?;
                 }
             }
-            _ => (),
         }
+        Ok(())
+    }
 
-        drop(u_ref);
-        // Get the hashes of the nodes.
+    /// The proof made of the encodings of `nodes`.
+    fn proof_of(&self, nodes: Vec<DiskAddress>) -> Result<Proof<Vec<u8>>, MerkleError> {
+        let mut proofs = HashMap::new();
         for node in nodes {
             let node = self.get_node(node)?;
-            let encoded = <&[u8]>::clone(&node.get_encoded::<S>(self.store.as_ref()));
+            // the Firewood encoding of a node doesn't depend on where it is
+            let encoded =
+                <&[u8]>::clone(&node.get_encoded::<S>(self.store.as_ref(), self.encoding, &[]));
             let hash: [u8; TRIE_HASH_LEN] = sha3::Keccak256::digest(encoded).into();
             proofs.insert(hash, encoded.to_vec());
         }
@@ -1115,11 +1536,15 @@ impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
         key: K,
         root: DiskAddress,
     ) -> Result<Option<Ref>, MerkleError> {
+        self.get_(&self.trie_key(key.as_ref()), root)
+    }
+
+    fn get_(&self, key: &[u8], root: DiskAddress) -> Result<Option<Ref<'_>>, MerkleError> {
         if root.is_null() {
             return Ok(None);
         }
 
-        let key_nibbles = Nibbles::<1>::new(key.as_ref());
+        let key_nibbles = Nibbles::<1>::with_branch_factor(key, self.branch_factor);
 
         let mut u_ref = self.get_node(root)?;
         let mut nskip = 0;
@@ -1211,6 +1636,10 @@ impl<'a, S: ShaleStore<Node> + Send + Sync> RefMut<'a, S> {
     }
 
     pub fn write(&mut self, modify: impl FnOnce(&mut Vec<u8>)) -> Result<(), MerkleError> {
+        let mut value = self.get().to_vec();
+        modify(&mut value);
+        let value = self.merkle.new_data(value)?;
+        let mut replaced = None;
         let mut deleted = Vec::new();
         {
             let mut u_ref = self.merkle.get_node(self.ptr).unwrap();
@@ -1223,11 +1652,14 @@ impl<'a, S: ShaleStore<Node> + Send + Sync> RefMut<'a, S> {
                 self.merkle,
                 u_ref,
                 |u| {
-                    modify(match &mut u.inner {
-                        NodeType::Branch(n) => &mut n.value.as_mut().unwrap().0,
-                        NodeType::Leaf(n) => &mut n.1 .0,
-                        _ => unreachable!(),
-                    });
+                    replaced = Some(std::mem::replace(
+                        match &mut u.inner {
+                            NodeType::Branch(n) => n.value.as_mut().unwrap(),
+                            NodeType::Leaf(n) => &mut n.1,
+                            _ => unreachable!(),
+                        },
+                        value,
+                    ));
                     u.rehash()
                 },
                 &mut parents,
@@ -1237,7 +1669,10 @@ impl<'a, S: ShaleStore<Node> + Send + Sync> RefMut<'a, S> {
         for ptr in deleted.into_iter() {
             self.merkle.free_node(ptr)?;
         }
-        Ok(())
+        match replaced {
+            Some(replaced) => self.merkle.release_data(&replaced),
+            None => Ok(()),
+        }
     }
 }
 
@@ -1325,12 +1760,12 @@ mod test {
             let node_ = Node::hydrate(0, &mem).unwrap();
             assert!(node == node_);
         };
-        let chd0 = [None; NBRANCH];
-        let mut chd1 = chd0;
+        let chd0 = vec![None; NBRANCH];
+        let mut chd1 = chd0.clone();
         for node in chd1.iter_mut().take(NBRANCH / 2) {
             *node = Some(DiskAddress::from(0xa));
         }
-        let mut chd_encoded: [Option<Vec<u8>>; NBRANCH] = Default::default();
+        let mut chd_encoded = vec![None; NBRANCH];
         for encoded in chd_encoded.iter_mut().take(NBRANCH / 2) {
             *encoded = Some(vec![0x1, 0x2, 0x3]);
         }
@@ -1340,7 +1775,7 @@ mod test {
                 None,
                 NodeType::Leaf(LeafNode(
                     PartialPath(vec![0x1, 0x2, 0x3]),
-                    Data(vec![0x4, 0x5]),
+                    Data::from(vec![0x4, 0x5]),
                 )),
             ),
             Node::new_from_hash(
@@ -1366,8 +1801,26 @@ mod test {
                 None,
                 NodeType::Branch(BranchNode {
                     chd: chd0,
-                    value: Some(Data("hello, world!".as_bytes().to_vec())),
-                    chd_encoded: Default::default(),
+                    value: Some(Data::from("hello, world!".as_bytes().to_vec())),
+                    chd_encoded: vec![None; NBRANCH],
+                }),
+            ),
+            // a leaf and a branch of a trie with another branch factor
+            Node::new_from_hash(
+                None,
+                None,
+                NodeType::Leaf(LeafNode(
+                    PartialPath(vec![0xff, 0x1, 0x80]),
+                    Data::from(vec![0x4, 0x5]),
+                )),
+            ),
+            Node::new_from_hash(
+                None,
+                None,
+                NodeType::Branch(BranchNode {
+                    chd: vec![Some(DiskAddress::from(0xa)); 256],
+                    value: None,
+                    chd_encoded: vec![None; 256],
                 }),
             ),
             Node::new_from_hash(
@@ -1384,6 +1837,32 @@ mod test {
         }
     }
     #[test]
+    fn test_value_in_blob() {
+        let value = vec![0x7; 100];
+        let blob = Node::new(NodeType::Leaf(LeafNode(
+            PartialPath(Vec::new()),
+            value.clone().into(),
+        )));
+        let node = Node::new_from_hash(
+            None,
+            None,
+            NodeType::Leaf(LeafNode(
+                PartialPath(vec![0x1, 0x2]),
+                Data(value, Some(DiskAddress::from(0x100))),
+            )),
+        );
+        // the record only has the address of the blob
+        assert!(node.dehydrated_len() < blob.dehydrated_len());
+
+        let mut mem = PlainMem::new(0x200, 0x0);
+        for (addr, node) in [(0, &node), (0x100, &blob)] {
+            let mut bytes = vec![0; node.dehydrated_len() as usize];
+            node.dehydrate(&mut bytes).unwrap();
+            mem.write(addr, &bytes);
+        }
+        assert!(Node::hydrate(0, &mem).unwrap() == node);
+    }
+    #[test]
     fn test_encode() {
         const RESERVED: usize = 0x1000;
 
@@ -1417,40 +1896,40 @@ mod test {
         {
             let chd = Node::new(NodeType::Leaf(LeafNode(
                 PartialPath(vec![0x1, 0x2, 0x3]),
-                Data(vec![0x4, 0x5]),
+                Data::from(vec![0x4, 0x5]),
             )));
             let chd_ref = merkle.new_node(chd.clone()).unwrap();
-            let chd_encoded = chd_ref.get_encoded(merkle.store.as_ref());
+            let chd_encoded = chd_ref.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             let new_chd = Node::new(NodeType::decode(chd_encoded).unwrap());
-            let new_chd_encoded = new_chd.get_encoded(merkle.store.as_ref());
+            let new_chd_encoded = new_chd.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             assert_eq!(chd_encoded, new_chd_encoded);
 
-            let mut chd_encoded: [Option<Vec<u8>>; NBRANCH] = Default::default();
+            let mut chd_encoded = vec![None; NBRANCH];
             chd_encoded[0] = Some(new_chd_encoded.to_vec());
             let node = Node::new(NodeType::Branch(BranchNode {
-                chd: [None; NBRANCH],
-                value: Some(Data("value1".as_bytes().to_vec())),
+                chd: vec![None; NBRANCH],
+                value: Some(Data::from("value1".as_bytes().to_vec())),
                 chd_encoded,
             }));
 
             let node_ref = merkle.new_node(node.clone()).unwrap();
 
-            let r = node_ref.get_encoded(merkle.store.as_ref());
+            let r = node_ref.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             let new_node = Node::new(NodeType::decode(r).unwrap());
-            let new_encoded = new_node.get_encoded(merkle.store.as_ref());
+            let new_encoded = new_node.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             assert_eq!(r, new_encoded);
         }
 
         {
             let chd = Node::new(NodeType::Branch(BranchNode {
-                chd: [None; NBRANCH],
-                value: Some(Data("value1".as_bytes().to_vec())),
-                chd_encoded: Default::default(),
+                chd: vec![None; NBRANCH],
+                value: Some(Data::from("value1".as_bytes().to_vec())),
+                chd_encoded: vec![None; NBRANCH],
             }));
             let chd_ref = merkle.new_node(chd.clone()).unwrap();
-            let chd_encoded = chd_ref.get_encoded(merkle.store.as_ref());
+            let chd_encoded = chd_ref.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             let new_chd = Node::new(NodeType::decode(chd_encoded).unwrap());
-            let new_chd_encoded = new_chd.get_encoded(merkle.store.as_ref());
+            let new_chd_encoded = new_chd.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             assert_eq!(chd_encoded, new_chd_encoded);
 
             let node = Node::new(NodeType::Extension(ExtNode(
@@ -1460,9 +1939,9 @@ mod test {
             )));
             let node_ref = merkle.new_node(node.clone()).unwrap();
 
-            let r = node_ref.get_encoded(merkle.store.as_ref());
+            let r = node_ref.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             let new_node = Node::new(NodeType::decode(r).unwrap());
-            let new_encoded = new_node.get_encoded(merkle.store.as_ref());
+            let new_encoded = new_node.get_encoded(merkle.store.as_ref(), merkle.encoding, &[]);
             assert_eq!(r, new_encoded);
         }
     }
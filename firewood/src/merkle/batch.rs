@@ -0,0 +1,468 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{
+    BranchNode, Data, ExtNode, LeafNode, Merkle, MerkleError, Node, NodeType, PartialPath,
+};
+use crate::nibbles::Nibbles;
+use shale::{disk_address::DiskAddress, ShaleStore};
+use std::{collections::BTreeMap, ops::Range};
+
+/// A key of a batch, as the symbols of its path from the sentinel node, and the value it is put
+/// with, or `None` if it is removed.
+type Op = (Vec<u8>, Option<Data>);
+
+/// What applying ops to a subtree made of it.
+enum Applied {
+    Unchanged,
+    /// The subtree is now at the address, or empty.
+    Changed(Option<DiskAddress>),
+}
+
+impl Applied {
+    /// The subtree, which was at `old`.
+    fn into_child(self, old: Option<DiskAddress>) -> Option<DiskAddress> {
+        match self {
+            Applied::Unchanged => old,
+            Applied::Changed(new) => new,
+        }
+    }
+}
+
+/// The range of the sorted `ops` whose paths continue with `prefix` after `depth` symbols.
+fn prefix_range(ops: &[Op], depth: usize, prefix: &[u8]) -> Range<usize> {
+    let start = ops.partition_point(|op| op.0[depth..] < *prefix);
+    let len = ops[start..]
+        .iter()
+        .take_while(|op| op.0[depth..].starts_with(prefix))
+        .count();
+    start..start + len
+}
+
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// The puts of `ops`, taken out of it.
+fn take_puts(ops: &mut [Op]) -> Vec<Op> {
+    ops.iter_mut()
+        .filter(|op| op.1.is_some())
+        .map(|op| (std::mem::take(&mut op.0), op.1.take()))
+        .collect()
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
+    /// Put every key of `batch` that has a value in the trie at `root`, and remove every key that
+    /// has none, leaving the trie as [Merkle::insert] and [Merkle::remove] would one by one, where
+    /// the last change to a key wins. The changes are sorted and applied in one walk down the
+    /// trie, which reads and writes each node they change once, rather than once per key.
+    pub fn apply_batch<K: AsRef<[u8]>>(
+        &mut self,
+        batch: impl IntoIterator<Item = (K, Option<Vec<u8>>)>,
+        root: DiskAddress,
+    ) -> Result<(), MerkleError> {
+        let mut changes = BTreeMap::new();
+        let mut preimages = BTreeMap::new();
+        for (key, value) in batch {
+            let key = key.as_ref();
+            let trie_key = self.trie_key(key).into_owned();
+            if self.is_secure() && value.is_some() {
+                preimages.insert(trie_key.clone(), Some(key.to_vec()));
+            }
+            changes.insert(trie_key, value);
+        }
+        if let Some(table) = self.preimages {
+            // preimages are never removed, as other tries may have the same key
+            for trie_key in preimages.keys().cloned().collect::<Vec<_>>() {
+                if self.get_(&trie_key, table)?.is_some() {
+                    preimages.remove(&trie_key);
+                }
+            }
+            self.apply_batch_(preimages, table)?;
+        }
+        self.apply_batch_(changes, root)
+    }
+
+    fn apply_batch_(
+        &mut self,
+        changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        root: DiskAddress,
+    ) -> Result<(), MerkleError> {
+        // the keys are in order, and so are their paths
+        let mut ops = Vec::with_capacity(changes.len());
+        for (key, value) in changes {
+            let path = Nibbles::<1>::with_branch_factor(&key, self.branch_factor)
+                .into_iter()
+                .collect();
+            ops.push((path, value.map(|value| self.new_data(value)).transpose()?));
+        }
+        let mut deleted = Vec::new();
+        // the values that are no longer in the trie
+        let mut released = Vec::new();
+
+        // the sentinel node is never moved, and only has the child at the leading zero
+        let chd = self
+            .get_node(root)?
+            .inner
+            .as_branch()
+            .ok_or(MerkleError::NotBranchNode)?
+            .chd[0];
+        if let Applied::Changed(chd) =
+            self.apply_node(chd, 1, &mut ops, &mut deleted, &mut released)?
+        {
+            self.get_node(root)?
+                .write(|r| {
+                    r.inner.as_branch_mut().unwrap().chd[0] = chd;
+                    r.rehash()
+                })
+                .unwrap();
+        }
+
+        for ptr in deleted.into_iter() {
+            self.free_node(ptr)?
+        }
+        for data in released.iter() {
+            self.release_data(data)?
+        }
+        Ok(())
+    }
+
+    /// Apply `ops`, whose paths all go to the subtree at `ptr` after `depth` symbols.
+    fn apply_node(
+        &self,
+        ptr: Option<DiskAddress>,
+        depth: usize,
+        ops: &mut [Op],
+        deleted: &mut Vec<DiskAddress>,
+        released: &mut Vec<Data>,
+    ) -> Result<Applied, MerkleError> {
+        let Some(ptr) = ptr else {
+            if ops.iter().all(|op| op.1.is_none()) {
+                return Ok(Applied::Unchanged);
+            }
+            return Ok(Applied::Changed(self.build(
+                None,
+                depth,
+                &mut take_puts(ops),
+                deleted,
+            )?));
+        };
+        if ops.is_empty() {
+            return Ok(Applied::Unchanged);
+        }
+        let inner = self.get_node(ptr)?.inner.clone();
+        match inner {
+            NodeType::Branch(n) => self.apply_branch(Some(ptr), n, depth, ops, deleted, released),
+            NodeType::Leaf(LeafNode(path, data)) => {
+                let mut key = ops[0].0[..depth].to_vec();
+                key.extend_from_slice(&path);
+                let found = ops.binary_search_by(|op| op.0.cmp(&key)).is_ok();
+                if !found && ops.iter().all(|op| op.1.is_none()) {
+                    return Ok(Applied::Unchanged);
+                }
+                // the leaf is rebuilt with the keys put next to it
+                let mut puts = take_puts(ops);
+                if found {
+                    released.push(data);
+                } else {
+                    let at = puts.partition_point(|op| op.0 < key);
+                    puts.insert(at, (key, Some(data)));
+                }
+                Ok(Applied::Changed(self.build(
+                    Some(ptr),
+                    depth,
+                    &mut puts,
+                    deleted,
+                )?))
+            }
+            NodeType::Extension(ExtNode(path, chd, _)) => {
+                self.apply_ext(ptr, false, path.0, chd, depth, ops, deleted, released)
+            }
+        }
+    }
+
+    /// Apply `ops` to the branch node `n` at `ptr`, or to a new one if there is no `ptr`.
+    fn apply_branch(
+        &self,
+        ptr: Option<DiskAddress>,
+        mut n: BranchNode,
+        depth: usize,
+        mut ops: &mut [Op],
+        deleted: &mut Vec<DiskAddress>,
+        released: &mut Vec<Data>,
+    ) -> Result<Applied, MerkleError> {
+        let mut changed = ptr.is_none();
+        // a key that ends here has the value of the branch
+        if ops.first().is_some_and(|op| op.0.len() == depth) {
+            let ((_, value), rest) = std::mem::take(&mut ops).split_first_mut().unwrap();
+            if value.is_some() || n.value.is_some() {
+                released.extend(std::mem::replace(&mut n.value, value.take()));
+                changed = true;
+            }
+            ops = rest;
+        }
+        while let Some(idx) = ops.first().map(|op| op.0[depth]) {
+            let len = ops.iter().take_while(|op| op.0[depth] == idx).count();
+            let (group, rest) = std::mem::take(&mut ops).split_at_mut(len);
+            ops = rest;
+            let chd = &mut n.chd[idx as usize];
+            if let Applied::Changed(c) =
+                self.apply_node(*chd, depth + 1, group, deleted, released)?
+            {
+                *chd = c;
+                changed = true;
+            }
+        }
+        if !changed {
+            return Ok(Applied::Unchanged);
+        }
+
+        let only_child = {
+            let mut chd = n
+                .chd
+                .iter()
+                .enumerate()
+                .filter_map(|(i, c)| Some((i as u8, (*c)?)));
+            chd.next().filter(|_| chd.next().is_none())
+        };
+        let new = match (n.value.take(), n.chd.iter().all(Option::is_none)) {
+            (None, true) => {
+                deleted.extend(ptr);
+                None
+            }
+            (Some(value), true) => Some(self.put_node(
+                ptr,
+                NodeType::Leaf(LeafNode(PartialPath(Vec::new()), value)),
+                deleted,
+            )?),
+            // a branch with one child and no value is merged into the path to the child
+            (None, false) if only_child.is_some() => {
+                let (idx, c) = only_child.unwrap();
+                self.extend(ptr, vec![idx], Some(c), deleted)?
+            }
+            (value, false) => {
+                n.value = value;
+                Some(self.put_node(ptr, NodeType::Branch(n), deleted)?)
+            }
+        };
+        Ok(Applied::Changed(new))
+    }
+
+    /// Apply `ops` to the extension node at `ptr` with `path`, which is still to be written if
+    /// `moved`, as it had another path.
+    #[allow(clippy::too_many_arguments)]
+    fn apply_ext(
+        &self,
+        ptr: DiskAddress,
+        moved: bool,
+        path: Vec<u8>,
+        chd: DiskAddress,
+        depth: usize,
+        ops: &mut [Op],
+        deleted: &mut Vec<DiskAddress>,
+        released: &mut Vec<Data>,
+    ) -> Result<Applied, MerkleError> {
+        let follow = prefix_range(ops, depth, &path);
+        // keys that diverge from the path are only in the trie if they are put
+        let diverge = ops
+            .iter()
+            .enumerate()
+            .filter(|(i, op)| op.1.is_some() && !follow.contains(i))
+            .map(|(_, op)| common_len(&op.0[depth..], &path))
+            .min();
+        let Some(len) = diverge else {
+            let new = match self.apply_node(
+                Some(chd),
+                depth + path.len(),
+                &mut ops[follow],
+                deleted,
+                released,
+            )? {
+                Applied::Unchanged if !moved => return Ok(Applied::Unchanged),
+                applied => applied.into_child(Some(chd)),
+            };
+            return Ok(Applied::Changed(self.extend(
+                Some(ptr),
+                path,
+                new,
+                deleted,
+            )?));
+        };
+
+        // the path branches where the first put diverges from it
+        let under = prefix_range(ops, depth, &path[..len]);
+        let ops = &mut ops[under];
+        let at = depth + len;
+        let group = prefix_range(ops, at, &path[len..=len]);
+        let rest = if path.len() > len + 1 {
+            // the node keeps the part of the path after the branch
+            self.apply_ext(
+                ptr,
+                true,
+                path[len + 1..].to_vec(),
+                chd,
+                at + 1,
+                &mut ops[group.clone()],
+                deleted,
+                released,
+            )?
+            .into_child(Some(ptr))
+        } else {
+            deleted.push(ptr);
+            self.apply_node(
+                Some(chd),
+                at + 1,
+                &mut ops[group.clone()],
+                deleted,
+                released,
+            )?
+            .into_child(Some(chd))
+        };
+        let mut n = BranchNode {
+            chd: vec![None; self.branch_factor as usize],
+            value: None,
+            chd_encoded: vec![None; self.branch_factor as usize],
+        };
+        n.chd[path[len] as usize] = rest;
+        // the keys under the rest of the path are applied, so they are moved out of the way
+        ops[group.start..].rotate_left(group.len());
+        let others = ops.len() - group.len();
+        let branch = self
+            .apply_branch(None, n, at, &mut ops[..others], deleted, released)?
+            .into_child(None);
+        if len == 0 {
+            return Ok(Applied::Changed(branch));
+        }
+        Ok(Applied::Changed(self.extend(
+            None,
+            path[..len].to_vec(),
+            branch,
+            deleted,
+        )?))
+    }
+
+    /// The subtree that is `path` followed by the subtree at `chd`, as the extension node at
+    /// `ptr` if one is needed, or a new one if there is no `ptr`.
+    fn extend(
+        &self,
+        ptr: Option<DiskAddress>,
+        mut path: Vec<u8>,
+        chd: Option<DiskAddress>,
+        deleted: &mut Vec<DiskAddress>,
+    ) -> Result<Option<DiskAddress>, MerkleError> {
+        let Some(chd) = chd else {
+            deleted.extend(ptr);
+            return Ok(None);
+        };
+        // only a branch node can be the child of an extension node, other nodes take its path
+        let merged = match &self.get_node(chd)?.inner {
+            NodeType::Branch(_) => None,
+            NodeType::Leaf(LeafNode(p, value)) => {
+                path.extend_from_slice(p);
+                Some(NodeType::Leaf(LeafNode(
+                    PartialPath(path.clone()),
+                    value.clone(),
+                )))
+            }
+            NodeType::Extension(ExtNode(p, c, encoded)) => {
+                path.extend_from_slice(p);
+                Some(NodeType::Extension(ExtNode(
+                    PartialPath(path.clone()),
+                    *c,
+                    encoded.clone(),
+                )))
+            }
+        };
+        let new = match merged {
+            Some(merged) => {
+                deleted.extend(ptr);
+                self.put_node(Some(chd), merged, deleted)?
+            }
+            None => self.put_node(
+                ptr,
+                NodeType::Extension(ExtNode(PartialPath(path), chd, None)),
+                deleted,
+            )?,
+        };
+        Ok(Some(new))
+    }
+
+    /// A new subtree of the sorted `puts`, whose paths all go to it after `depth` symbols. Its
+    /// top node is the node at `ptr`, if there is one to reuse.
+    fn build(
+        &self,
+        ptr: Option<DiskAddress>,
+        depth: usize,
+        puts: &mut [Op],
+        deleted: &mut Vec<DiskAddress>,
+    ) -> Result<Option<DiskAddress>, MerkleError> {
+        let (first, last) = match puts {
+            [] => {
+                deleted.extend(ptr);
+                return Ok(None);
+            }
+            [(path, value)] => {
+                let leaf = LeafNode(PartialPath(path[depth..].to_vec()), value.take().unwrap());
+                return Ok(Some(self.put_node(ptr, NodeType::Leaf(leaf), deleted)?));
+            }
+            [first, .., last] => (&first.0, &last.0),
+        };
+        // as the paths are in order, the first and the last share the fewest symbols
+        let len = common_len(&first[depth..], &last[depth..]);
+        let prefix = first[depth..depth + len].to_vec();
+
+        let at = depth + len;
+        let mut n = BranchNode {
+            chd: vec![None; self.branch_factor as usize],
+            value: None,
+            chd_encoded: vec![None; self.branch_factor as usize],
+        };
+        let mut puts = puts;
+        if puts[0].0.len() == at {
+            n.value = puts[0].1.take();
+            puts = &mut puts[1..];
+        }
+        while let Some(idx) = puts.first().map(|op| op.0[at]) {
+            let len = puts.iter().take_while(|op| op.0[at] == idx).count();
+            let (group, rest) = std::mem::take(&mut puts).split_at_mut(len);
+            puts = rest;
+            n.chd[idx as usize] = self.build(None, at + 1, group, deleted)?;
+        }
+        if prefix.is_empty() {
+            return Ok(Some(self.put_node(ptr, NodeType::Branch(n), deleted)?));
+        }
+        let branch = self.new_node(Node::new(NodeType::Branch(n)))?.as_ptr();
+        let ext = ExtNode(PartialPath(prefix), branch, None);
+        Ok(Some(self.put_node(
+            ptr,
+            NodeType::Extension(ext),
+            deleted,
+        )?))
+    }
+
+    /// Make `inner` the node at `ptr`, which is moved if it no longer fits, or a new node if
+    /// there is no `ptr`.
+    fn put_node(
+        &self,
+        ptr: Option<DiskAddress>,
+        inner: NodeType,
+        deleted: &mut Vec<DiskAddress>,
+    ) -> Result<DiskAddress, MerkleError> {
+        let Some(ptr) = ptr else {
+            return Ok(self.new_node(Node::new(inner))?.as_ptr());
+        };
+        let mut node = self.get_node(ptr)?;
+        let mut inner = Some(inner);
+        if node
+            .write(|u| {
+                u.inner = inner.take().unwrap();
+                u.rehash()
+            })
+            .is_ok()
+        {
+            return Ok(ptr);
+        }
+        deleted.push(ptr);
+        Ok(self.new_node(node.clone())?.as_ptr())
+    }
+}
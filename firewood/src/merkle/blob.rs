@@ -0,0 +1,95 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{Data, LeafNode, Merkle, MerkleError, Node, NodeType, PartialPath};
+use sha3::Digest;
+use shale::{disk_address::DiskAddress, ShaleStore};
+
+/// The length of an entry of the blob table: the address of the blob and how many values are
+/// stored in it.
+const ENTRY_LEN: usize = 16;
+
+/// Where the values of a merkle that are longer than `threshold` are stored, see
+/// [Merkle::with_blobs].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Blobs {
+    /// The root of the trie that maps the Keccak-256 hash of every value stored in a blob to the
+    /// blob and the number of values stored in it.
+    pub(super) table: DiskAddress,
+    pub(super) threshold: usize,
+}
+
+fn decode_entry(entry: &[u8]) -> (DiskAddress, u64) {
+    let (blob, refs) = entry.split_at(8);
+    (
+        DiskAddress::from(usize::from_le_bytes(blob.try_into().unwrap())),
+        u64::from_le_bytes(refs.try_into().unwrap()),
+    )
+}
+
+fn encode_entry(blob: DiskAddress, refs: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(ENTRY_LEN);
+    entry.extend_from_slice(&blob.to_le_bytes());
+    entry.extend_from_slice(&refs.to_le_bytes());
+    entry
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
+    /// The number of values in the tries of the store that are `value` and are stored in a blob.
+    pub fn blob_refs(&self, value: &[u8]) -> Result<u64, MerkleError> {
+        let Some(blobs) = self.blobs else {
+            return Ok(0);
+        };
+        let hash = sha3::Keccak256::digest(value);
+        Ok(self
+            .get_(&hash, blobs.table)?
+            .map_or(0, |entry| decode_entry(&entry).1))
+    }
+
+    /// Make `value` the value of a node, storing it in a blob if it is too long. A value that is
+    /// already in a blob shares it.
+    pub(super) fn new_data(&mut self, value: Vec<u8>) -> Result<Data, MerkleError> {
+        let blobs = match self.blobs {
+            Some(blobs) if value.len() > blobs.threshold => blobs,
+            _ => return Ok(value.into()),
+        };
+        let hash = sha3::Keccak256::digest(&value);
+        let entry = self.get_(&hash, blobs.table)?.map(|entry| entry.to_vec());
+        let (blob, refs) = match entry {
+            Some(entry) => decode_entry(&entry),
+            None => {
+                // a blob is a leaf with no path that isn't in any trie
+                let blob = self
+                    .new_node(Node::new(NodeType::Leaf(LeafNode(
+                        PartialPath(Vec::new()),
+                        value.clone().into(),
+                    ))))?
+                    .as_ptr();
+                // nodes read their blob straight from the space, so it is written right away
+                self.store.flush_dirty();
+                (blob, 0)
+            }
+        };
+        self.insert_(&hash, encode_entry(blob, refs + 1), blobs.table)?;
+        Ok(Data(value, Some(blob)))
+    }
+
+    /// Let go of `data`, which is no longer the value of a node, freeing its blob if no other
+    /// value is stored in it.
+    pub(super) fn release_data(&mut self, data: &Data) -> Result<(), MerkleError> {
+        let (Some(blobs), Some(_)) = (self.blobs, data.1) else {
+            return Ok(());
+        };
+        let hash = sha3::Keccak256::digest(&data.0);
+        let Some(entry) = self.get_(&hash, blobs.table)?.map(|entry| entry.to_vec()) else {
+            return Ok(());
+        };
+        match decode_entry(&entry) {
+            (blob, 1) => {
+                self.remove_(&hash, blobs.table)?;
+                self.free_node(blob)
+            }
+            (blob, refs) => self.insert_(&hash, encode_entry(blob, refs - 1), blobs.table),
+        }
+    }
+}
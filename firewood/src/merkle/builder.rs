@@ -0,0 +1,162 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use super::{
+    BranchNode, Data, ExtNode, LeafNode, Merkle, MerkleError, Node, NodeType, PartialPath, TrieHash,
+};
+use crate::nibbles::Nibbles;
+use shale::{disk_address::DiskAddress, ShaleStore};
+
+/// A branch node that may still get children, after `depth` symbols of the keys under it.
+struct Frame {
+    depth: usize,
+    n: BranchNode,
+}
+
+/// Builds a new trie from keys that come in order, bottom-up: every subtree is written to the
+/// store and hashed as soon as no later key can be in it, so that only the path to the last key
+/// is held in memory. Made with [Merkle::trie_builder].
+pub struct TrieBuilder<'a, S> {
+    merkle: &'a mut Merkle<S>,
+    /// The branch nodes on the path to the last key, from the sentinel node down.
+    stack: Vec<Frame>,
+    /// The path to the last key and its value, which are only put in a leaf once the next key
+    /// tells where they branch off.
+    last: Option<(Vec<u8>, Data)>,
+}
+
+impl<S: ShaleStore<Node> + Send + Sync> Merkle<S> {
+    /// Build a new trie from keys that are pushed in the order of their trie keys, see
+    /// [Merkle::trie_key], which is much faster than inserting them.
+    pub fn trie_builder(&mut self) -> TrieBuilder<'_, S> {
+        let sentinel = Frame {
+            depth: 0,
+            n: self.new_branch(),
+        };
+        TrieBuilder {
+            merkle: self,
+            stack: vec![sentinel],
+            last: None,
+        }
+    }
+
+    fn new_branch(&self) -> BranchNode {
+        BranchNode {
+            chd: vec![None; self.branch_factor as usize],
+            value: None,
+            chd_encoded: vec![None; self.branch_factor as usize],
+        }
+    }
+}
+
+impl<'a, S: ShaleStore<Node> + Send + Sync> TrieBuilder<'a, S> {
+    /// Put `key` with `value` in the trie. The trie key of `key` must come after the one of the
+    /// key pushed before it.
+    pub fn push<K: AsRef<[u8]>>(&mut self, key: K, value: Vec<u8>) -> Result<(), MerkleError> {
+        let key = key.as_ref();
+        let trie_key = self.merkle.trie_key(key).into_owned();
+        let path: Vec<u8> = Nibbles::<1>::with_branch_factor(&trie_key, self.merkle.branch_factor)
+            .into_iter()
+            .collect();
+        if let Some((last, _)) = &self.last {
+            if path <= *last {
+                return Err(MerkleError::UnsortedKey(key.to_vec()));
+            }
+        }
+        if let Some(preimages) = self.merkle.preimages {
+            // preimages are never removed, as other tries may have the same key
+            if self.merkle.get_(&trie_key, preimages)?.is_none() {
+                self.merkle.insert_(&trie_key, key.to_vec(), preimages)?;
+            }
+        }
+        let value = self.merkle.new_data(value)?;
+
+        if let Some((last, last_value)) = self.last.take() {
+            // the last key and this one branch off where they stop sharing symbols, and no
+            // later key is in a subtree below that
+            let depth = last.iter().zip(&path).take_while(|(a, b)| a == b).count();
+            if self.top().depth < depth {
+                self.stack.push(Frame {
+                    depth,
+                    n: self.merkle.new_branch(),
+                });
+            }
+            self.put_last(&last, last_value)?;
+            while self.top().depth > depth {
+                self.close(&last, Some(depth))?;
+            }
+        }
+        self.last = Some((path, value));
+        Ok(())
+    }
+
+    /// Write the rest of the trie, and give the address of its sentinel node, which is the root
+    /// to pass to the [Merkle], and its root hash.
+    pub fn finish(mut self) -> Result<(DiskAddress, TrieHash), MerkleError> {
+        if let Some((last, value)) = self.last.take() {
+            self.put_last(&last, value)?;
+            while self.stack.len() > 1 {
+                self.close(&last, None)?;
+            }
+        }
+        let chd = self.stack.pop().unwrap().n.chd[0];
+        let root = self.merkle.init_root()?;
+        self.merkle
+            .get_node(root)?
+            .write(|r| {
+                r.inner.as_branch_mut().unwrap().chd[0] = chd;
+                r.rehash()
+            })
+            .unwrap();
+        let root_hash = self.merkle.root_hash(root)?;
+        Ok((root, root_hash))
+    }
+
+    fn top(&mut self) -> &mut Frame {
+        self.stack.last_mut().unwrap()
+    }
+
+    /// Put the last key in the branch node at the top of the stack, which is on its path.
+    fn put_last(&mut self, last: &[u8], value: Data) -> Result<(), MerkleError> {
+        let depth = self.top().depth;
+        if last.len() == depth {
+            self.top().n.value = Some(value);
+            return Ok(());
+        }
+        let leaf = LeafNode(PartialPath(last[depth + 1..].to_vec()), value);
+        let leaf = self.write(NodeType::Leaf(leaf), &last[..depth + 1])?;
+        self.top().n.chd[last[depth] as usize] = Some(leaf);
+        Ok(())
+    }
+
+    /// Write the branch node at the top of the stack, which is on the path to the last key, and
+    /// put it in the one below it, or in a new one after `depth` symbols if that is deeper.
+    fn close(&mut self, last: &[u8], depth: Option<usize>) -> Result<(), MerkleError> {
+        let Frame { depth: at, n } = self.stack.pop().unwrap();
+        if let Some(depth) = depth.filter(|depth| self.top().depth < *depth) {
+            self.stack.push(Frame {
+                depth,
+                n: self.merkle.new_branch(),
+            });
+        }
+        let parent = self.top().depth;
+        let mut chd = self.write(NodeType::Branch(n), &last[..at])?;
+        if at > parent + 1 {
+            let ext = ExtNode(PartialPath(last[parent + 1..at].to_vec()), chd, None);
+            chd = self.write(NodeType::Extension(ext), &last[..parent + 1])?;
+        }
+        self.top().n.chd[last[parent] as usize] = Some(chd);
+        Ok(())
+    }
+
+    /// Write a finished node, whose path from the sentinel node is `path`, and encode it, which
+    /// hashes its children, so that hashing the nodes above it doesn't go further down.
+    fn write(&self, node: NodeType, path: &[u8]) -> Result<DiskAddress, MerkleError> {
+        let node = self.merkle.new_node(Node::new(node))?;
+        if !matches!(node.inner, NodeType::Leaf(_)) {
+            // paths of nodes being hashed don't have the symbol of the sentinel node
+            node.get_encoded(self.merkle.store.as_ref(), self.merkle.encoding, &path[1..]);
+        }
+        Ok(node.as_ptr())
+    }
+}
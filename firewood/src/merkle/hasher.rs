@@ -0,0 +1,290 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+use shale::{disk_address::DiskAddress, ShaleStore};
+use std::sync::atomic::Ordering;
+
+use super::{ExtNode, LeafNode, Node, NodeType, TrieHash, TRIE_HASH_LEN};
+
+/// How nodes are encoded to be hashed, which decides the root hash of a trie. It is chosen when
+/// a DB is created and can't be changed afterwards. Each encoding is a [NodeHasher].
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrieEncoding {
+    /// Firewood's own encoding, built on bincode. See [FirewoodHasher].
+    #[default]
+    Firewood = 0,
+    /// The RLP encoding of Ethereum's Merkle Patricia Trie. See [EthereumHasher].
+    Ethereum = 1,
+    /// The encoding of avalanchego's MerkleDB. See [MerkleDbHasher].
+    MerkleDb = 2,
+}
+
+impl TryFrom<u64> for TrieEncoding {
+    type Error = u64;
+
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        match id {
+            0 => Ok(Self::Firewood),
+            1 => Ok(Self::Ethereum),
+            2 => Ok(Self::MerkleDb),
+            _ => Err(id),
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($encoding: expr, $f: ident ( $($arg: expr),* )) => {
+        match $encoding {
+            TrieEncoding::Firewood => FirewoodHasher::$f($($arg),*),
+            TrieEncoding::Ethereum => EthereumHasher::$f($($arg),*),
+            TrieEncoding::MerkleDb => MerkleDbHasher::$f($($arg),*),
+        }
+    };
+}
+
+impl TrieEncoding {
+    pub(super) fn encode<S: ShaleStore<Node>>(
+        self,
+        node: &NodeType,
+        store: &S,
+        path: &[u8],
+    ) -> Vec<u8> {
+        dispatch!(self, encode(node, store, path))
+    }
+
+    pub(super) fn hash(self, node: &NodeType, encoded: &[u8]) -> TrieHash {
+        dispatch!(self, hash(node, encoded))
+    }
+
+    pub(super) fn is_hashed(self, encoded: &[u8]) -> bool {
+        dispatch!(self, is_hashed(encoded))
+    }
+
+    pub(super) fn root_hash<S: ShaleStore<Node>>(self, root: &Node, store: &S) -> TrieHash {
+        dispatch!(self, root_hash(root, store))
+    }
+
+    pub(super) fn empty_root(self) -> TrieHash {
+        dispatch!(self, empty_root())
+    }
+}
+
+/// Encodes and hashes the nodes of a trie. Paths are in nibbles, and the path of a node is the
+/// key it is reached by from the root, before its own partial path.
+pub trait NodeHasher {
+    /// The encoding a DB records to pick this hasher.
+    const ENCODING: TrieEncoding;
+
+    /// Encode `node`, which is at `path` in its trie.
+    fn encode<S: ShaleStore<Node>>(node: &NodeType, store: &S, path: &[u8]) -> Vec<u8>;
+
+    /// The hash of `node`, given its encoding.
+    fn hash(node: &NodeType, encoded: &[u8]) -> TrieHash;
+
+    /// Whether a parent refers to a node by its hash rather than by its encoding.
+    fn is_hashed(encoded: &[u8]) -> bool {
+        encoded.len() >= TRIE_HASH_LEN
+    }
+
+    /// The root hash of a trie whose root node is `root`.
+    fn root_hash<S: ShaleStore<Node>>(root: &Node, store: &S) -> TrieHash {
+        root.get_root_hash(store, Self::ENCODING, &[]).clone()
+    }
+
+    /// The root hash of a trie without any keys.
+    fn empty_root() -> TrieHash {
+        TrieHash(
+            hex::decode("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        )
+    }
+}
+
+/// Get the hash of the child at `chd`, which is at `path`, writing it back to the store so it
+/// isn't computed again.
+pub(super) fn child_hash<S: ShaleStore<Node>>(
+    store: &S,
+    chd: DiskAddress,
+    encoding: TrieEncoding,
+    path: &[u8],
+) -> TrieHash {
+    let mut c_ref = store.get_item(chd).unwrap();
+    let hash = c_ref.get_root_hash(store, encoding, path).clone();
+
+    // See struct docs for ordering requirements
+    if c_ref.lazy_dirty.load(Ordering::Relaxed) {
+        c_ref.write(|_| {}).unwrap();
+        c_ref.lazy_dirty.store(false, Ordering::Relaxed)
+    }
+    hash
+}
+
+/// Firewood's own encoding: nodes are bincode lists, in which nodes shorter than a hash are
+/// inlined in their parent, hashed with Keccak-256.
+pub struct FirewoodHasher;
+
+impl NodeHasher for FirewoodHasher {
+    const ENCODING: TrieEncoding = TrieEncoding::Firewood;
+
+    fn encode<S: ShaleStore<Node>>(node: &NodeType, store: &S, path: &[u8]) -> Vec<u8> {
+        match node {
+            NodeType::Leaf(n) => n.encode_firewood(),
+            NodeType::Extension(n) => n.encode_firewood(store, path),
+            NodeType::Branch(n) => n.encode_firewood(store, path),
+        }
+    }
+
+    fn hash(_node: &NodeType, encoded: &[u8]) -> TrieHash {
+        TrieHash(Keccak256::digest(encoded).into())
+    }
+}
+
+/// The RLP encoding of Ethereum's Merkle Patricia Trie, where nodes shorter than a hash are
+/// inlined in their parent, hashed with Keccak-256. A trie has the root hash Ethereum gives the
+/// same keys and values.
+pub struct EthereumHasher;
+
+impl NodeHasher for EthereumHasher {
+    const ENCODING: TrieEncoding = TrieEncoding::Ethereum;
+
+    fn encode<S: ShaleStore<Node>>(node: &NodeType, store: &S, path: &[u8]) -> Vec<u8> {
+        match node {
+            NodeType::Leaf(n) => n.encode_rlp(),
+            NodeType::Extension(n) => n.encode_rlp(store, path),
+            NodeType::Branch(n) => n.encode_rlp(store, path),
+        }
+    }
+
+    fn hash(_node: &NodeType, encoded: &[u8]) -> TrieHash {
+        TrieHash(Keccak256::digest(encoded).into())
+    }
+}
+
+/// The encoding avalanchego's MerkleDB (`x/merkledb`, as of v1.10) hashes its nodes in, hashed
+/// with SHA-256. A trie is meant to have the root hash MerkleDB gives the same keys and values,
+/// but that is not yet checked against MerkleDB itself, see `tests/merkledb_vectors`.
+///
+/// MerkleDB has no extension nodes: a node's children are referred to by their ID, whatever the
+/// path that leads to them, so an extension node hashes to the hash of its child. Every node
+/// hashes its full key, and the root is always the node at the empty key.
+pub struct MerkleDbHasher;
+
+impl MerkleDbHasher {
+    /// Encode a node like MerkleDB's `encodeHashValues`: its children's IDs by index, its value,
+    /// and its key.
+    fn encode_node(children: &[(u8, TrieHash)], value: Option<&[u8]>, key: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_varint(&mut buf, children.len() as i64);
+        for (index, id) in children {
+            put_varint(&mut buf, *index as i64);
+            buf.extend_from_slice(&id.0);
+        }
+
+        // values at least as long as a hash are hashed first
+        match value {
+            Some(value) => {
+                let digest;
+                let value = if value.len() >= TRIE_HASH_LEN {
+                    digest = Sha256::digest(value);
+                    &digest[..]
+                } else {
+                    value
+                };
+                buf.push(1);
+                put_varint(&mut buf, value.len() as i64);
+                buf.extend_from_slice(value);
+            }
+            None => buf.push(0),
+        }
+
+        // keys are nibble counts followed by packed nibbles, padded with a zero nibble
+        put_varint(&mut buf, key.len() as i64);
+        buf.extend(
+            key.chunks(2)
+                .map(|pair| pair[0] << 4 | pair.get(1).unwrap_or(&0)),
+        );
+        buf
+    }
+}
+
+/// Append `value` the way Go's `binary.PutVarint` does, zig-zag encoded in base 128.
+fn put_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut ux = ((value << 1) ^ (value >> 63)) as u64;
+    while ux >= 0x80 {
+        buf.push(ux as u8 | 0x80);
+        ux >>= 7;
+    }
+    buf.push(ux as u8);
+}
+
+impl NodeHasher for MerkleDbHasher {
+    const ENCODING: TrieEncoding = TrieEncoding::MerkleDb;
+
+    fn encode<S: ShaleStore<Node>>(node: &NodeType, store: &S, path: &[u8]) -> Vec<u8> {
+        match node {
+            NodeType::Leaf(LeafNode(partial, value)) => {
+                let key = [path, &partial[..]].concat();
+                Self::encode_node(&[], Some(&value[..]), &key)
+            }
+            // the encoding of an extension node is the hash of its child
+            NodeType::Extension(ExtNode(partial, chd, chd_encoded)) => {
+                if chd.is_null() {
+                    return chd_encoded.clone().unwrap_or_default();
+                }
+                let key = [path, &partial[..]].concat();
+                child_hash(store, *chd, Self::ENCODING, &key).0.to_vec()
+            }
+            NodeType::Branch(n) => {
+                let children = n
+                    .chd
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| {
+                        let index = i as u8;
+                        let chd_path = [path, &[index]].concat();
+                        Some((index, child_hash(store, (*c)?, Self::ENCODING, &chd_path)))
+                    })
+                    .collect::<Vec<_>>();
+                Self::encode_node(&children, n.value.as_deref(), path)
+            }
+        }
+    }
+
+    fn hash(node: &NodeType, encoded: &[u8]) -> TrieHash {
+        match node {
+            NodeType::Extension(_) => TrieHash(
+                encoded
+                    .try_into()
+                    .expect("an extension node is encoded as the hash of its child"),
+            ),
+            _ => TrieHash(Sha256::digest(encoded).into()),
+        }
+    }
+
+    fn is_hashed(_encoded: &[u8]) -> bool {
+        true
+    }
+
+    fn root_hash<S: ShaleStore<Node>>(root: &Node, store: &S) -> TrieHash {
+        let hash = root.get_root_hash(store, Self::ENCODING, &[]).clone();
+        match &root.inner {
+            // a root at a longer key is the only child of the node at the empty key
+            NodeType::Leaf(LeafNode(partial, _)) | NodeType::Extension(ExtNode(partial, ..))
+                if !partial.is_empty() =>
+            {
+                let encoded = Self::encode_node(&[(partial[0], hash)], None, &[]);
+                TrieHash(Sha256::digest(encoded).into())
+            }
+            _ => hash,
+        }
+    }
+
+    fn empty_root() -> TrieHash {
+        TrieHash(Sha256::digest(Self::encode_node(&[], None, &[])).into())
+    }
+}
@@ -3,8 +3,8 @@
 
 use bincode::{Error, Options};
 use enum_as_inner::EnumAsInner;
+use rlp::RlpStream;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use sha3::{Digest, Keccak256};
 use shale::{disk_address::DiskAddress, CachedStore, ShaleError, ShaleStore, Storable};
 use std::{
     fmt::{self, Debug},
@@ -15,18 +15,56 @@ use std::{
     },
 };
 
-use crate::merkle::to_nibble_array;
-use crate::nibbles::Nibbles;
+use crate::nibbles::BranchFactor;
 
-use super::{from_nibbles, PartialPath, TrieHash, TRIE_HASH_LEN};
+use super::{
+    hasher::{child_hash, TrieEncoding},
+    PartialPath, TrieHash, TRIE_HASH_LEN,
+};
 
-pub const NBRANCH: usize = 16;
+/// The number of children of a branch node with the default [BranchFactor].
+pub const NBRANCH: usize = BranchFactor::Sixteen as usize;
 
 const EXT_NODE_SIZE: usize = 2;
-const BRANCH_NODE_SIZE: usize = 17;
 
+/// Append the RLP reference to a child: its hash if its encoding is at least as long as a hash,
+/// the encoding itself if it is shorter, or empty data if there is no child. `chd_encoded` is
+/// used for a child that isn't in the store, which is what a trie built from a proof has.
+fn append_rlp_child<S: ShaleStore<Node>>(
+    stream: &mut RlpStream,
+    store: &S,
+    chd: Option<DiskAddress>,
+    chd_encoded: Option<&[u8]>,
+    path: &[u8],
+) {
+    let encoding = TrieEncoding::Ethereum;
+    match (chd, chd_encoded) {
+        (Some(c), _) => {
+            let c_ref = store.get_item(c).unwrap();
+            if c_ref.is_encoded_big(store, encoding, path) {
+                drop(c_ref);
+                stream.append(&&child_hash(store, c, encoding, path)[..]);
+            } else {
+                stream.append_raw(c_ref.get_encoded(store, encoding, path), 1);
+            }
+        }
+        (None, Some(v)) if v.len() == TRIE_HASH_LEN => {
+            stream.append(&v);
+        }
+        (None, Some(v)) => {
+            stream.append_raw(v, 1);
+        }
+        (None, None) => {
+            stream.append_empty_data();
+        }
+    }
+}
+
+/// The value of a node, with the address of the blob it is stored in when it is too long to be
+/// stored in the node record, see [super::Merkle::with_blobs]. The whole value is always in
+/// memory.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Data(pub(super) Vec<u8>);
+pub struct Data(pub(super) Vec<u8>, pub(super) Option<DiskAddress>);
 
 impl std::ops::Deref for Data {
     type Target = [u8];
@@ -35,6 +73,46 @@ impl std::ops::Deref for Data {
     }
 }
 
+impl From<Vec<u8>> for Data {
+    fn from(value: Vec<u8>) -> Self {
+        Data(value, None)
+    }
+}
+
+impl Data {
+    /// The bytes that stand for the value in the node record: the value, or the address of its
+    /// blob.
+    fn write_stored(&self, to: &mut impl Write) -> std::io::Result<()> {
+        match self.1 {
+            Some(blob) => to.write_all(&blob.to_le_bytes()),
+            None => to.write_all(&self.0),
+        }
+    }
+
+    fn stored_len(&self) -> u64 {
+        match self.1 {
+            Some(_) => 8,
+            None => self.0.len() as u64,
+        }
+    }
+
+    /// The value that `stored` stands for in the node record at `mem`.
+    fn hydrate<T: CachedStore>(stored: &[u8], in_blob: bool, mem: &T) -> Result<Self, ShaleError> {
+        if !in_blob {
+            return Ok(stored.to_vec().into());
+        }
+        let blob =
+            usize::from_le_bytes(stored.try_into().map_err(|_| ShaleError::InvalidNodeType)?);
+        // a blob is a leaf with no path that isn't in any trie
+        match Node::hydrate(blob, mem)?.inner {
+            NodeType::Leaf(LeafNode(_, Data(value, None))) => {
+                Ok(Data(value, Some(DiskAddress::from(blob))))
+            }
+            _ => Err(ShaleError::InvalidNodeType),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) enum Encoded<T> {
     Raw(T),
@@ -59,9 +137,9 @@ impl<T: DeserializeOwned + AsRef<[u8]>> Encoded<T> {
 
 #[derive(PartialEq, Eq, Clone)]
 pub struct BranchNode {
-    pub(super) chd: [Option<DiskAddress>; NBRANCH],
+    pub(super) chd: Vec<Option<DiskAddress>>,
     pub(super) value: Option<Data>,
-    pub(super) chd_encoded: [Option<Vec<u8>>; NBRANCH],
+    pub(super) chd_encoded: Vec<Option<Vec<u8>>>,
 }
 
 impl Debug for BranchNode {
@@ -113,40 +191,53 @@ impl BranchNode {
         // Extract the value of the branch node and set to None if it's an empty Vec
         let value = Some(data).filter(|data| !data.is_empty());
 
-        // encode all children.
-        let mut chd_encoded: [Option<Vec<u8>>; NBRANCH] = Default::default();
+        // we popped the last element, so there should only be the children left
+        let chd_encoded = items
+            .into_iter()
+            .map(|chd| Ok(Some(chd.decode()?).filter(|data| !data.is_empty())))
+            .collect::<Result<Vec<_>, Error>>()?;
 
-        // we popped the last element, so their should only be NBRANCH items left
-        for (i, chd) in items.into_iter().enumerate() {
-            let data = chd.decode()?;
-            chd_encoded[i] = Some(data).filter(|data| !data.is_empty());
-        }
+        Ok(BranchNode::new(
+            vec![None; chd_encoded.len()],
+            value,
+            chd_encoded,
+        ))
+    }
 
-        Ok(BranchNode::new([None; NBRANCH], value, chd_encoded))
+    pub(super) fn encode_rlp<S: ShaleStore<Node>>(&self, store: &S, path: &[u8]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(self.chd.len() + 1);
+        for (i, (c, encoded)) in self.chd.iter().zip(self.chd_encoded.iter()).enumerate() {
+            let chd_path = [path, &[i as u8]].concat();
+            append_rlp_child(&mut stream, store, *c, encoded.as_deref(), &chd_path);
+        }
+        match &self.value {
+            Some(Data(val, _)) => stream.append(val),
+            None => stream.append_empty_data(),
+        };
+        stream.out().into()
     }
 
-    fn encode<S: ShaleStore<Node>>(&self, store: &S) -> Vec<u8> {
-        let mut list = <[Encoded<Vec<u8>>; NBRANCH + 1]>::default();
+    pub(super) fn encode_firewood<S: ShaleStore<Node>>(&self, store: &S, path: &[u8]) -> Vec<u8> {
+        let encoding = TrieEncoding::Firewood;
+        let mut list: Vec<Encoded<Vec<u8>>> =
+            (0..=self.chd.len()).map(|_| Encoded::default()).collect();
 
         for (i, c) in self.chd.iter().enumerate() {
             match c {
                 Some(c) => {
-                    let mut c_ref = store.get_item(*c).unwrap();
+                    let chd_path = [path, &[i as u8]].concat();
+                    let c_ref = store.get_item(*c).unwrap();
 
-                    if c_ref.is_encoded_big::<S>(store) {
+                    if c_ref.is_encoded_big::<S>(store, encoding, &chd_path) {
+                        drop(c_ref);
+                        let hash = child_hash(store, *c, encoding, &chd_path);
                         list[i] = Encoded::Data(
                             bincode::DefaultOptions::new()
-                                .serialize(&&(*c_ref.get_root_hash::<S>(store))[..])
+                                .serialize(&&hash[..])
                                 .unwrap(),
                         );
-
-                        // See struct docs for ordering requirements
-                        if c_ref.lazy_dirty.load(Ordering::Relaxed) {
-                            c_ref.write(|_| {}).unwrap();
-                            c_ref.lazy_dirty.store(false, Ordering::Relaxed)
-                        }
                     } else {
-                        let child_encoded = &c_ref.get_encoded::<S>(store);
+                        let child_encoded = &c_ref.get_encoded::<S>(store, encoding, &chd_path);
                         list[i] = Encoded::Raw(child_encoded.to_vec());
                     }
                 }
@@ -165,8 +256,9 @@ impl BranchNode {
             };
         }
 
-        if let Some(Data(val)) = &self.value {
-            list[NBRANCH] = Encoded::Data(bincode::DefaultOptions::new().serialize(val).unwrap());
+        if let Some(Data(val, _)) = &self.value {
+            list[self.chd.len()] =
+                Encoded::Data(bincode::DefaultOptions::new().serialize(val).unwrap());
         }
 
         bincode::DefaultOptions::new()
@@ -174,14 +266,17 @@ impl BranchNode {
             .unwrap()
     }
 
+    /// A branch node with `chd.len()` children, the branch factor of its trie, which
+    /// `chd_encoded` has as many of.
     pub fn new(
-        chd: [Option<DiskAddress>; NBRANCH],
+        chd: Vec<Option<DiskAddress>>,
         value: Option<Vec<u8>>,
-        chd_encoded: [Option<Vec<u8>>; NBRANCH],
+        chd_encoded: Vec<Option<Vec<u8>>>,
     ) -> Self {
+        debug_assert_eq!(chd.len(), chd_encoded.len());
         BranchNode {
             chd,
-            value: value.map(Data),
+            value: value.map(Data::from),
             chd_encoded,
         }
     }
@@ -190,19 +285,19 @@ impl BranchNode {
         &self.value
     }
 
-    pub fn chd(&self) -> &[Option<DiskAddress>; NBRANCH] {
+    pub fn chd(&self) -> &[Option<DiskAddress>] {
         &self.chd
     }
 
-    pub fn chd_mut(&mut self) -> &mut [Option<DiskAddress>; NBRANCH] {
+    pub fn chd_mut(&mut self) -> &mut [Option<DiskAddress>] {
         &mut self.chd
     }
 
-    pub fn chd_encode(&self) -> &[Option<Vec<u8>>; NBRANCH] {
+    pub fn chd_encode(&self) -> &[Option<Vec<u8>>] {
         &self.chd_encoded
     }
 
-    pub fn chd_encoded_mut(&mut self) -> &mut [Option<Vec<u8>>; NBRANCH] {
+    pub fn chd_encoded_mut(&mut self) -> &mut [Option<Vec<u8>>] {
         &mut self.chd_encoded
     }
 }
@@ -217,11 +312,18 @@ impl Debug for LeafNode {
 }
 
 impl LeafNode {
-    fn encode(&self) -> Vec<u8> {
+    pub(super) fn encode_rlp(&self) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&self.0.to_bytes(true));
+        stream.append(&self.1 .0);
+        stream.out().into()
+    }
+
+    pub(super) fn encode_firewood(&self) -> Vec<u8> {
         bincode::DefaultOptions::new()
             .serialize(
                 [
-                    Encoded::Raw(from_nibbles(&self.0.encode(true)).collect()),
+                    Encoded::Raw(self.0.to_bytes(true)),
                     Encoded::Raw(self.1.to_vec()),
                 ]
                 .as_slice(),
@@ -230,7 +332,7 @@ impl LeafNode {
     }
 
     pub fn new(path: Vec<u8>, data: Vec<u8>) -> Self {
-        LeafNode(PartialPath(path), Data(data))
+        LeafNode(PartialPath(path), data.into())
     }
 
     pub fn path(&self) -> &PartialPath {
@@ -256,30 +358,38 @@ impl Debug for ExtNode {
 }
 
 impl ExtNode {
-    fn encode<S: ShaleStore<Node>>(&self, store: &S) -> Vec<u8> {
+    pub(super) fn encode_rlp<S: ShaleStore<Node>>(&self, store: &S, path: &[u8]) -> Vec<u8> {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&self.0.to_bytes(false));
+        let chd = Some(self.1).filter(|chd| !chd.is_null());
+        let chd_path = [path, &self.0[..]].concat();
+        append_rlp_child(&mut stream, store, chd, self.2.as_deref(), &chd_path);
+        stream.out().into()
+    }
+
+    pub(super) fn encode_firewood<S: ShaleStore<Node>>(&self, store: &S, path: &[u8]) -> Vec<u8> {
+        let encoding = TrieEncoding::Firewood;
+        let chd_path = [path, &self.0[..]].concat();
         let mut list = <[Encoded<Vec<u8>>; 2]>::default();
         list[0] = Encoded::Data(
             bincode::DefaultOptions::new()
-                .serialize(&from_nibbles(&self.0.encode(false)).collect::<Vec<_>>())
+                .serialize(&self.0.to_bytes(false))
                 .unwrap(),
         );
 
         if !self.1.is_null() {
-            let mut r = store.get_item(self.1).unwrap();
+            let r = store.get_item(self.1).unwrap();
 
-            if r.is_encoded_big(store) {
+            if r.is_encoded_big(store, encoding, &chd_path) {
+                drop(r);
+                let hash = child_hash(store, self.1, encoding, &chd_path);
                 list[1] = Encoded::Data(
                     bincode::DefaultOptions::new()
-                        .serialize(&&(*r.get_root_hash(store))[..])
+                        .serialize(&&hash[..])
                         .unwrap(),
                 );
-
-                if r.lazy_dirty.load(Ordering::Relaxed) {
-                    r.write(|_| {}).unwrap();
-                    r.lazy_dirty.store(false, Ordering::Relaxed);
-                }
             } else {
-                list[1] = Encoded::Raw(r.get_encoded(store).to_vec());
+                list[1] = Encoded::Raw(r.get_encoded(store, encoding, &chd_path).to_vec());
             }
         } else {
             // Check if there is already a caclucated encoded value for the child, which
@@ -369,12 +479,14 @@ pub enum NodeType {
 }
 
 impl NodeType {
-    pub fn encode<S: ShaleStore<Node>>(&self, store: &S) -> Vec<u8> {
-        match &self {
-            NodeType::Leaf(n) => n.encode(),
-            NodeType::Extension(n) => n.encode(store),
-            NodeType::Branch(n) => n.encode(store),
-        }
+    /// Encode the node, which is at `path` in its trie, to be hashed in `encoding`.
+    pub fn encode<S: ShaleStore<Node>>(
+        &self,
+        store: &S,
+        encoding: TrieEncoding,
+        path: &[u8],
+    ) -> Vec<u8> {
+        encoding.encode(self, store, path)
     }
 
     pub fn decode(buf: &[u8]) -> Result<NodeType, Error> {
@@ -385,10 +497,7 @@ impl NodeType {
                 let mut items = items.into_iter();
                 let decoded_key: Vec<u8> = items.next().unwrap().decode()?;
 
-                let decoded_key_nibbles = Nibbles::<0>::new(&decoded_key);
-
-                let (cur_key_path, term) =
-                    PartialPath::from_nibbles(decoded_key_nibbles.into_iter());
+                let (cur_key_path, term) = PartialPath::from_bytes(&decoded_key);
                 let cur_key = cur_key_path.into_inner();
                 let data: Vec<u8> = items.next().unwrap().decode()?;
 
@@ -402,7 +511,9 @@ impl NodeType {
                     )))
                 }
             }
-            BRANCH_NODE_SIZE => Ok(NodeType::Branch(BranchNode::decode(buf)?)),
+            size if BranchFactor::try_from(size as u64 - 1).is_ok() => {
+                Ok(NodeType::Branch(BranchNode::decode(buf)?))
+            }
             size => Err(Box::new(bincode::ErrorKind::Custom(format!(
                 "invalid size: {size}"
             )))),
@@ -411,43 +522,76 @@ impl NodeType {
 }
 
 impl Node {
+    /// The version of the encoding of stored nodes, which each DB records. It is only bumped when
+    /// nodes already on disk can no longer be read or hash differently, which takes rebuilding
+    /// the DB. A change that only adds to [Storable], such as a new node type, leaves existing
+    /// nodes as they are and bumps [FORMAT_VERSION](crate::db::FORMAT_VERSION) instead, so that
+    /// older builds refuse a DB that may hold the new nodes.
+    pub const ENCODING_VERSION: u64 = 1;
+
     const BRANCH_NODE: u8 = 0x0;
     const EXT_NODE: u8 = 0x1;
     const LEAF_NODE: u8 = 0x2;
-
-    pub(super) fn max_branch_node_size() -> u64 {
-        let max_size: OnceLock<u64> = OnceLock::new();
-        *max_size.get_or_init(|| {
-            Self {
-                root_hash: OnceLock::new(),
-                is_encoded_big: OnceLock::new(),
-                encoded: OnceLock::new(),
-                inner: NodeType::Branch(BranchNode {
-                    chd: [Some(DiskAddress::null()); NBRANCH],
-                    value: Some(Data(Vec::new())),
-                    chd_encoded: Default::default(),
-                }),
-                lazy_dirty: AtomicBool::new(false),
-            }
-            .dehydrated_len()
-        })
-    }
-
-    pub(super) fn get_encoded<S: ShaleStore<Node>>(&self, store: &S) -> &[u8] {
-        self.encoded.get_or_init(|| self.inner.encode::<S>(store))
+    /// A branch node with other than [NBRANCH] children, which are counted after the type.
+    const SIZED_BRANCH_NODE: u8 = 0x3;
+    /// Extension and leaf nodes whose paths take more than [u8::MAX] bytes, so that the length of
+    /// their path takes 4 bytes instead of 1.
+    const LONG_EXT_NODE: u8 = 0x4;
+    const LONG_LEAF_NODE: u8 = 0x5;
+
+    /// The number of bytes the length of a path of `len` bytes is stored in, see
+    /// [Node::LONG_EXT_NODE].
+    fn path_len_size(len: u64) -> u64 {
+        if len > u8::MAX as u64 {
+            4
+        } else {
+            1
+        }
     }
 
-    pub(super) fn get_root_hash<S: ShaleStore<Node>>(&self, store: &S) -> &TrieHash {
+    pub(super) fn max_branch_node_size(branch_factor: BranchFactor) -> u64 {
+        let n = branch_factor as usize;
+        Self::new(NodeType::Branch(BranchNode {
+            chd: vec![Some(DiskAddress::null()); n],
+            value: Some(Vec::new().into()),
+            chd_encoded: vec![None; n],
+        }))
+        .dehydrated_len()
+    }
+
+    /// The encoding of the node, which is at `path` in its trie. It is cached, so a node must
+    /// always be encoded the same way, which holds as a node never moves within its trie.
+    pub(super) fn get_encoded<S: ShaleStore<Node>>(
+        &self,
+        store: &S,
+        encoding: TrieEncoding,
+        path: &[u8],
+    ) -> &[u8] {
+        self.encoded
+            .get_or_init(|| self.inner.encode::<S>(store, encoding, path))
+    }
+
+    pub(super) fn get_root_hash<S: ShaleStore<Node>>(
+        &self,
+        store: &S,
+        encoding: TrieEncoding,
+        path: &[u8],
+    ) -> &TrieHash {
         self.root_hash.get_or_init(|| {
             self.lazy_dirty.store(true, Ordering::Relaxed);
-            TrieHash(Keccak256::digest(self.get_encoded::<S>(store)).into())
+            encoding.hash(&self.inner, self.get_encoded::<S>(store, encoding, path))
         })
     }
 
-    fn is_encoded_big<S: ShaleStore<Node>>(&self, store: &S) -> bool {
+    fn is_encoded_big<S: ShaleStore<Node>>(
+        &self,
+        store: &S,
+        encoding: TrieEncoding,
+        path: &[u8],
+    ) -> bool {
         *self.is_encoded_big.get_or_init(|| {
             self.lazy_dirty.store(true, Ordering::Relaxed);
-            self.get_encoded(store).len() >= TRIE_HASH_LEN
+            encoding.is_hashed(self.get_encoded(store, encoding, path))
         })
     }
 
@@ -501,6 +645,8 @@ impl Node {
     // TODO: why are these different?
     const IS_ENCODED_BIG_VALID: u8 = 1 << 1;
     const LONG_BIT: u8 = 1 << 2;
+    /// The value of the node is stored in a blob, whose address the record has in its place.
+    const VALUE_IN_BLOB_BIT: u8 = 1 << 3;
 }
 
 impl Storable for Node {
@@ -527,9 +673,23 @@ impl Storable for Node {
         } else {
             Some(attrs & Node::LONG_BIT != 0)
         };
+        let in_blob = attrs & Node::VALUE_IN_BLOB_BIT != 0;
         match meta_raw.as_deref()[33] {
-            Self::BRANCH_NODE => {
-                let branch_header_size = NBRANCH as u64 * 8 + 4;
+            node_type @ (Self::BRANCH_NODE | Self::SIZED_BRANCH_NODE) => {
+                let (addr, nchd) = if node_type == Self::BRANCH_NODE {
+                    (addr, NBRANCH)
+                } else {
+                    let raw =
+                        mem.get_view(addr + META_SIZE, 2)
+                            .ok_or(ShaleError::InvalidCacheView {
+                                offset: addr + META_SIZE,
+                                size: 2,
+                            })?;
+                    let nchd = u16::from_le_bytes(raw.as_deref()[..2].try_into().unwrap());
+                    // the rest of the node is laid out as if the count was part of the meta
+                    (addr + 2, nchd as usize)
+                };
+                let branch_header_size = nchd as u64 * 8 + 4;
                 let node_raw = mem.get_view(addr + META_SIZE, branch_header_size).ok_or(
                     ShaleError::InvalidCacheView {
                         offset: addr + META_SIZE,
@@ -537,7 +697,7 @@ impl Storable for Node {
                     },
                 )?;
                 let mut cur = Cursor::new(node_raw.as_deref());
-                let mut chd = [None; NBRANCH];
+                let mut chd = vec![None; nchd];
                 let mut buff = [0; 8];
                 for chd in chd.iter_mut() {
                     cur.read_exact(&mut buff)?;
@@ -552,16 +712,18 @@ impl Storable for Node {
                 let value = if raw_len == u32::MAX as u64 {
                     None
                 } else {
-                    Some(Data(
-                        mem.get_view(addr + META_SIZE + branch_header_size as usize, raw_len)
+                    Some(Data::hydrate(
+                        &mem.get_view(addr + META_SIZE + branch_header_size as usize, raw_len)
                             .ok_or(ShaleError::InvalidCacheView {
                                 offset: addr + META_SIZE + branch_header_size as usize,
                                 size: raw_len,
                             })?
                             .as_deref(),
-                    ))
+                        in_blob,
+                        mem,
+                    )?)
                 };
-                let mut chd_encoded: [Option<Vec<u8>>; NBRANCH] = Default::default();
+                let mut chd_encoded = vec![None; nchd];
                 let offset = if raw_len == u32::MAX as u64 {
                     addr + META_SIZE + branch_header_size as usize
                 } else {
@@ -603,8 +765,9 @@ impl Storable for Node {
                     }),
                 ))
             }
-            Self::EXT_NODE => {
-                let ext_header_size = 1 + 8;
+            node_type @ (Self::EXT_NODE | Self::LONG_EXT_NODE) => {
+                let path_len_size = if node_type == Self::EXT_NODE { 1 } else { 4 };
+                let ext_header_size = path_len_size + 8;
                 let node_raw = mem.get_view(addr + META_SIZE, ext_header_size).ok_or(
                     ShaleError::InvalidCacheView {
                         offset: addr + META_SIZE,
@@ -613,23 +776,19 @@ impl Storable for Node {
                 )?;
                 let mut cur = Cursor::new(node_raw.as_deref());
                 let mut buff = [0; 8];
-                cur.read_exact(&mut buff[..1])?;
-                let path_len = buff[0] as u64;
+                cur.read_exact(&mut buff[..path_len_size as usize])?;
+                let path_len = u64::from_le_bytes(buff);
                 cur.read_exact(&mut buff)?;
                 let ptr = u64::from_le_bytes(buff);
 
-                let nibbles: Vec<u8> = mem
+                let path_raw = mem
                     .get_view(addr + META_SIZE + ext_header_size as usize, path_len)
                     .ok_or(ShaleError::InvalidCacheView {
                         offset: addr + META_SIZE + ext_header_size as usize,
                         size: path_len,
-                    })?
-                    .as_deref()
-                    .into_iter()
-                    .flat_map(to_nibble_array)
-                    .collect();
+                    })?;
 
-                let (path, _) = PartialPath::decode(&nibbles);
+                let (path, _) = PartialPath::from_bytes(&path_raw.as_deref());
 
                 let mut buff = [0_u8; 1];
                 let encoded_len_raw = mem
@@ -670,8 +829,9 @@ impl Storable for Node {
                     NodeType::Extension(ExtNode(path, DiskAddress::from(ptr as usize), encoded)),
                 ))
             }
-            Self::LEAF_NODE => {
-                let leaf_header_size = 1 + 4;
+            node_type @ (Self::LEAF_NODE | Self::LONG_LEAF_NODE) => {
+                let path_len_size = if node_type == Self::LEAF_NODE { 1 } else { 4 };
+                let leaf_header_size = path_len_size + 4;
                 let node_raw = mem.get_view(addr + META_SIZE, leaf_header_size).ok_or(
                     ShaleError::InvalidCacheView {
                         offset: addr + META_SIZE,
@@ -680,8 +840,8 @@ impl Storable for Node {
                 )?;
                 let mut cur = Cursor::new(node_raw.as_deref());
                 let mut buff = [0; 4];
-                cur.read_exact(&mut buff[..1])?;
-                let path_len = buff[0] as u64;
+                cur.read_exact(&mut buff[..path_len_size as usize])?;
+                let path_len = u32::from_le_bytes(buff) as u64;
                 cur.read_exact(&mut buff)?;
                 let data_len = u32::from_le_bytes(buff) as u64;
                 let remainder = mem
@@ -694,15 +854,9 @@ impl Storable for Node {
                         size: path_len + data_len,
                     })?;
 
-                let nibbles: Vec<_> = remainder
-                    .as_deref()
-                    .into_iter()
-                    .take(path_len as usize)
-                    .flat_map(to_nibble_array)
-                    .collect();
-
-                let (path, _) = PartialPath::decode(&nibbles);
-                let value = Data(remainder.as_deref()[path_len as usize..].to_vec());
+                let (path, _) = PartialPath::from_bytes(&remainder.as_deref()[..path_len as usize]);
+                let value =
+                    Data::hydrate(&remainder.as_deref()[path_len as usize..], in_blob, mem)?;
                 Ok(Self::new_from_hash(
                     root_hash,
                     encoded_big,
@@ -725,23 +879,32 @@ impl Storable for Node {
                             None => 1,
                         }
                     }
-                    NBRANCH as u64 * 8
+                    let nchd = n.chd.len() as u64;
+                    // see [Node::SIZED_BRANCH_NODE]
+                    let count_len = if nchd == NBRANCH as u64 { 0 } else { 2 };
+                    count_len
+                        + nchd * 8
                         + 4
                         + match &n.value {
-                            Some(val) => val.len() as u64,
+                            Some(val) => val.stored_len(),
                             None => 0,
                         }
                         + encoded_len
                 }
                 NodeType::Extension(n) => {
-                    1 + 8
-                        + n.0.dehydrated_len()
+                    let path_len = n.0.dehydrated_len();
+                    Self::path_len_size(path_len)
+                        + 8
+                        + path_len
                         + match &n.2 {
                             Some(v) => 1 + v.len() as u64,
                             None => 1,
                         }
                 }
-                NodeType::Leaf(n) => 1 + 4 + n.0.dehydrated_len() + n.1.len() as u64,
+                NodeType::Leaf(n) => {
+                    let path_len = n.0.dehydrated_len();
+                    Self::path_len_size(path_len) + 4 + path_len + n.1.stored_len()
+                }
             }
     }
 
@@ -763,11 +926,24 @@ impl Storable for Node {
             Some(b) => (if *b { Node::LONG_BIT } else { 0 } | Node::IS_ENCODED_BIG_VALID),
             None => 0,
         };
+        let value = match &self.inner {
+            NodeType::Branch(n) => n.value.as_ref(),
+            NodeType::Leaf(n) => Some(&n.1),
+            NodeType::Extension(_) => None,
+        };
+        if value.is_some_and(|value| value.1.is_some()) {
+            attrs |= Node::VALUE_IN_BLOB_BIT;
+        }
         cur.write_all(&[attrs]).unwrap();
 
         match &self.inner {
             NodeType::Branch(n) => {
-                cur.write_all(&[Self::BRANCH_NODE]).unwrap();
+                if n.chd.len() == NBRANCH {
+                    cur.write_all(&[Self::BRANCH_NODE])?;
+                } else {
+                    cur.write_all(&[Self::SIZED_BRANCH_NODE])?;
+                    cur.write_all(&(n.chd.len() as u16).to_le_bytes())?;
+                }
                 for c in n.chd.iter() {
                     cur.write_all(&match c {
                         Some(p) => p.to_le_bytes(),
@@ -776,8 +952,8 @@ impl Storable for Node {
                 }
                 match &n.value {
                     Some(val) => {
-                        cur.write_all(&(val.len() as u32).to_le_bytes())?;
-                        cur.write_all(val)?
+                        cur.write_all(&(val.stored_len() as u32).to_le_bytes())?;
+                        val.write_stored(&mut cur)?
                     }
                     None => {
                         cur.write_all(&u32::MAX.to_le_bytes())?;
@@ -797,9 +973,13 @@ impl Storable for Node {
                 Ok(())
             }
             NodeType::Extension(n) => {
-                cur.write_all(&[Self::EXT_NODE])?;
-                let path: Vec<u8> = from_nibbles(&n.0.encode(false)).collect();
-                cur.write_all(&[path.len() as u8])?;
+                let path = n.0.to_bytes(false);
+                if let Ok(len) = u8::try_from(path.len()) {
+                    cur.write_all(&[Self::EXT_NODE, len])?;
+                } else {
+                    cur.write_all(&[Self::LONG_EXT_NODE])?;
+                    cur.write_all(&(path.len() as u32).to_le_bytes())?;
+                }
                 cur.write_all(&n.1.to_le_bytes())?;
                 cur.write_all(&path)?;
                 if n.2.is_some() {
@@ -810,12 +990,16 @@ impl Storable for Node {
                 Ok(())
             }
             NodeType::Leaf(n) => {
-                cur.write_all(&[Self::LEAF_NODE])?;
-                let path: Vec<u8> = from_nibbles(&n.0.encode(true)).collect();
-                cur.write_all(&[path.len() as u8])?;
-                cur.write_all(&(n.1.len() as u32).to_le_bytes())?;
+                let path = n.0.to_bytes(true);
+                if let Ok(len) = u8::try_from(path.len()) {
+                    cur.write_all(&[Self::LEAF_NODE, len])?;
+                } else {
+                    cur.write_all(&[Self::LONG_LEAF_NODE])?;
+                    cur.write_all(&(path.len() as u32).to_le_bytes())?;
+                }
+                cur.write_all(&(n.1.stored_len() as u32).to_le_bytes())?;
                 cur.write_all(&path)?;
-                cur.write_all(&n.1).map_err(ShaleError::Io)
+                n.1.write_stored(&mut cur).map_err(ShaleError::Io)
             }
         }
     }
@@ -4,14 +4,16 @@
 use crate::nibbles::NibblesIterator;
 use std::fmt::{self, Debug};
 
-/// PartialPath keeps a list of nibbles to represent a path on the Trie.
+/// PartialPath keeps a list of nibbles to represent a path on the Trie. In a trie with another
+/// [BranchFactor](crate::nibbles::BranchFactor), it keeps the symbols that pick the children of
+/// its branch nodes instead.
 #[derive(PartialEq, Eq, Clone)]
 pub struct PartialPath(pub Vec<u8>);
 
 impl Debug for PartialPath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         for nib in self.0.iter() {
-            write!(f, "{:x}", *nib & 0xf)?;
+            write!(f, "{:x}", *nib)?;
         }
         Ok(())
     }
@@ -41,6 +43,40 @@ impl PartialPath {
         res
     }
 
+    /// The bytes the path is stored and hashed as, which tell whether it is terminal. A path of
+    /// nibbles is [PartialPath::encode]d two nibbles to a byte. A path with wider symbols takes a
+    /// byte for each one, after a flag byte that no path of nibbles starts with.
+    pub(super) fn to_bytes(&self, term: bool) -> Vec<u8> {
+        if self.0.iter().all(|&symbol| symbol < 16) {
+            return super::from_nibbles(&self.encode(term)).collect();
+        }
+        let flags = Self::WIDE_FLAG | if term { 0x20 } else { 0 };
+        std::iter::once(flags)
+            .chain(self.0.iter().copied())
+            .collect()
+    }
+
+    /// returns a tuple of the path decoded from [PartialPath::to_bytes] and whether it is
+    /// terminal
+    pub fn from_bytes(raw: &[u8]) -> (Self, bool) {
+        match raw.first() {
+            Some(&flags) if flags & Self::WIDE_FLAG != 0 => {
+                (Self(raw[1..].to_vec()), flags & 0x20 != 0)
+            }
+            _ => {
+                let nibbles: Vec<_> = raw
+                    .iter()
+                    .copied()
+                    .flat_map(super::to_nibble_array)
+                    .collect();
+                Self::decode(&nibbles)
+            }
+        }
+    }
+
+    // the flags nibble of an encoded path of nibbles is at most 3
+    const WIDE_FLAG: u8 = 0x40;
+
     // TODO: remove all non `Nibbles` usages and delete this function.
     // I also think `PartialPath` could probably borrow instead of own data.
     //
@@ -64,6 +100,9 @@ impl PartialPath {
 
     pub(super) fn dehydrated_len(&self) -> u64 {
         let len = self.0.len() as u64;
+        if self.0.iter().any(|&symbol| symbol >= 16) {
+            return len + 1;
+        }
         if len & 1 == 1 {
             (len + 1) >> 1
         } else {
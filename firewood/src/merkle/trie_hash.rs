@@ -9,7 +9,7 @@ use std::{
 
 pub const TRIE_HASH_LEN: usize = 32;
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Clone, Hash)]
 pub struct TrieHash(pub [u8; TRIE_HASH_LEN]);
 
 impl TrieHash {
@@ -2,8 +2,9 @@
 // See the file LICENSE.md for licensing terms.
 
 use crate::{
-    merkle::{Merkle, Node, Ref, RefMut, TrieHash},
-    proof::ProofError,
+    merkle::{HashPool, Merkle, Node, Ref, RefMut, TrieEncoding, TrieHash},
+    nibbles::BranchFactor,
+    proof::{ProofError, ProofResult},
     v2::api::Proof,
 };
 use shale::{
@@ -53,6 +54,36 @@ impl<S: ShaleStore<Node> + Send + Sync> MerkleSetup<S> {
             .map_err(|_err| DataStoreError::RemovalError)
     }
 
+    pub fn apply_batch<K: AsRef<[u8]>>(
+        &mut self,
+        batch: Vec<(K, Option<Vec<u8>>)>,
+    ) -> Result<(), DataStoreError> {
+        self.merkle
+            .apply_batch(batch, self.root)
+            .map_err(|_err| DataStoreError::InsertionError)
+    }
+
+    /// Replace the trie with one built from the sorted `items`, see [Merkle::trie_builder].
+    pub fn build_sorted<K: AsRef<[u8]>>(
+        &mut self,
+        items: Vec<(K, Vec<u8>)>,
+    ) -> Result<(), DataStoreError> {
+        let mut builder = self.merkle.trie_builder();
+        for (key, val) in items {
+            builder
+                .push(key, val)
+                .map_err(|_err| DataStoreError::InsertionError)?;
+        }
+        let (root, _) = builder
+            .finish()
+            .map_err(|_err| DataStoreError::InsertionError)?;
+        self.merkle
+            .remove_tree(self.root)
+            .map_err(|_err| DataStoreError::RemovalError)?;
+        self.root = root;
+        Ok(())
+    }
+
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Ref>, DataStoreError> {
         self.merkle
             .get(key, self.root)
@@ -99,8 +130,42 @@ impl<S: ShaleStore<Node> + Send + Sync> MerkleSetup<S> {
         proof: &Proof<N>,
     ) -> Result<Option<Vec<u8>>, DataStoreError> {
         let hash: [u8; 32] = *self.root_hash()?;
+        let key = self.merkle.trie_key(key.as_ref());
+        proof
+            .verify_proof_with_branch_factor(key, hash, self.merkle.branch_factor())
+            .map_err(|_err| DataStoreError::ProofVerificationError)
+    }
+
+    pub fn verify<N: AsRef<[u8]> + Send, K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        proof: &Proof<N>,
+    ) -> Result<ProofResult, DataStoreError> {
+        let hash: [u8; 32] = *self.root_hash()?;
+        let key = self.merkle.trie_key(key.as_ref());
         proof
-            .verify_proof(key, hash)
+            .verify_with_branch_factor(key, hash, self.merkle.branch_factor())
+            .map_err(|_err| DataStoreError::ProofVerificationError)
+    }
+
+    pub fn prove_many<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Proof<Vec<u8>>, DataStoreError> {
+        self.merkle
+            .prove_many(keys, self.root)
+            .map_err(|_err| DataStoreError::ProofError)
+    }
+
+    pub fn verify_multi_proof<N: AsRef<[u8]> + Send, K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        proof: &Proof<N>,
+    ) -> Result<Vec<ProofResult>, DataStoreError> {
+        let hash: [u8; 32] = *self.root_hash()?;
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| self.merkle.trie_key(key.as_ref()))
+            .collect();
+        proof
+            .verify_multi_proof_with_branch_factor(&keys, hash, self.merkle.branch_factor())
             .map_err(|_err| DataStoreError::ProofVerificationError)
     }
 
@@ -113,13 +178,91 @@ impl<S: ShaleStore<Node> + Send + Sync> MerkleSetup<S> {
         vals: Vec<V>,
     ) -> Result<bool, ProofError> {
         let hash: [u8; 32] = *self.root_hash()?;
-        proof.verify_range_proof(hash, first_key, last_key, keys, vals)
+        proof.verify_range_proof_with_branch_factor(
+            hash,
+            first_key,
+            last_key,
+            keys,
+            vals,
+            self.merkle.branch_factor(),
+        )
     }
 }
 
 pub fn new_merkle(
     meta_size: u64,
     compact_size: u64,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    new_merkle_with_encoding(meta_size, compact_size, TrieEncoding::default())
+}
+
+pub fn new_merkle_with_encoding(
+    meta_size: u64,
+    compact_size: u64,
+    encoding: TrieEncoding,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    new_merkle_with(meta_size, compact_size, encoding, BranchFactor::default())
+}
+
+pub fn new_merkle_with_branch_factor(
+    meta_size: u64,
+    compact_size: u64,
+    branch_factor: BranchFactor,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    new_merkle_with(
+        meta_size,
+        compact_size,
+        TrieEncoding::default(),
+        branch_factor,
+    )
+}
+
+/// A merkle whose keys are hashed, see [Merkle::with_preimages].
+pub fn new_secure_merkle(
+    meta_size: u64,
+    compact_size: u64,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    let MerkleSetup { root, merkle } = new_merkle(meta_size, compact_size);
+    let preimages = merkle.init_root().unwrap();
+    MerkleSetup {
+        root,
+        merkle: merkle.with_preimages(preimages),
+    }
+}
+
+/// A merkle that stores values longer than `threshold` in blobs, see [Merkle::with_blobs].
+pub fn new_merkle_with_blobs(
+    meta_size: u64,
+    compact_size: u64,
+    threshold: u64,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    let MerkleSetup { root, merkle } = new_merkle(meta_size, compact_size);
+    let table = merkle.init_root().unwrap();
+    MerkleSetup {
+        root,
+        merkle: merkle.with_blobs(table, threshold),
+    }
+}
+
+/// A merkle that hashes on a pool of `hash_threads` threads, see [Merkle::with_hash_pool].
+pub fn new_merkle_with_hash_threads(
+    meta_size: u64,
+    compact_size: u64,
+    hash_threads: usize,
+) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
+    let MerkleSetup { root, merkle } = new_merkle(meta_size, compact_size);
+    let pool = HashPool::new(hash_threads).expect("hash threads");
+    MerkleSetup {
+        root,
+        merkle: merkle.with_hash_pool(Arc::new(pool)),
+    }
+}
+
+fn new_merkle_with(
+    meta_size: u64,
+    compact_size: u64,
+    encoding: TrieEncoding,
+    branch_factor: BranchFactor,
 ) -> MerkleSetup<CompactSpace<Node, DynamicMem>> {
     const RESERVED: usize = 0x1000;
     assert!(meta_size as usize > RESERVED);
@@ -144,7 +287,7 @@ pub fn new_merkle(
         shale::compact::CompactSpace::new(mem_meta, mem_payload, compact_header, cache, 10, 16)
             .expect("CompactSpace init fail");
 
-    let merkle = Merkle::new(Box::new(space));
+    let merkle = Merkle::with_encoding(Box::new(space), encoding).with_branch_factor(branch_factor);
     let root = merkle.init_root().unwrap();
 
     MerkleSetup { root, merkle }
@@ -3,12 +3,78 @@
 
 use std::{iter::FusedIterator, ops::Index};
 
-static NIBBLES: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+static SYMBOLS: [u8; 256] = {
+    let mut symbols = [0; 256];
+    let mut i = 0;
+    while i < symbols.len() {
+        symbols[i] = i as u8;
+        i += 1;
+    }
+    symbols
+};
+
+/// The number of children of a branch node, which decides how many bits of a key each level of
+/// the trie takes. It is chosen when a DB is created and can't be changed afterwards.
+#[repr(u64)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BranchFactor {
+    /// Keys are split into bits.
+    Two = 2,
+    /// Keys are split into pairs of bits.
+    Four = 4,
+    /// Keys are split into nibbles, which the Ethereum and MerkleDB encodings require.
+    #[default]
+    Sixteen = 16,
+    /// Keys are split into bytes, which makes tries of hashed keys a lot shallower.
+    TwoFiftySix = 256,
+}
+
+impl TryFrom<u64> for BranchFactor {
+    type Error = u64;
+
+    fn try_from(n: u64) -> Result<Self, Self::Error> {
+        match n {
+            2 => Ok(Self::Two),
+            4 => Ok(Self::Four),
+            16 => Ok(Self::Sixteen),
+            256 => Ok(Self::TwoFiftySix),
+            _ => Err(n),
+        }
+    }
+}
+
+impl BranchFactor {
+    /// The number of bits of a key that pick the child of a branch node.
+    pub fn bits(self) -> u8 {
+        match self {
+            Self::Two => 1,
+            Self::Four => 2,
+            Self::Sixteen => 4,
+            Self::TwoFiftySix => 8,
+        }
+    }
+
+    /// Join the symbols of a key, as [Nibbles] gives them, back into its bytes.
+    pub fn join(self, symbols: &[u8]) -> Vec<u8> {
+        let bits = self.bits();
+        symbols
+            .chunks_exact(8 / bits as usize)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0u16, |byte, s| (byte << bits) | *s as u16) as u8
+            })
+            .collect()
+    }
+}
 
 /// Nibbles is a newtype that contains only a reference to a [u8], and produces
 /// nibbles. Nibbles can be indexed using nib\[x\] or you can get an iterator
 /// with `into_iter()`
 ///
+/// Tries with another [BranchFactor] split keys into symbols of its number of
+/// bits instead, see [Nibbles::with_branch_factor].
+///
 /// Nibbles can be constructed with a number of leading zeroes. This is used
 /// in firewood because there is a sentinel node, so we always want the first
 /// byte to be 0
@@ -38,19 +104,23 @@ static NIBBLES: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
 /// # }
 /// ```
 #[derive(Debug, Copy, Clone)]
-pub struct Nibbles<'a, const LEADING_ZEROES: usize>(&'a [u8]);
+pub struct Nibbles<'a, const LEADING_ZEROES: usize> {
+    data: &'a [u8],
+    bits: u8,
+}
 
 impl<'a, const LEADING_ZEROES: usize> Index<usize> for Nibbles<'a, LEADING_ZEROES> {
     type Output = u8;
 
     fn index(&self, index: usize) -> &Self::Output {
-        match index {
-            _ if index < LEADING_ZEROES => &NIBBLES[0],
-            _ if (index - LEADING_ZEROES) % 2 == 0 => {
-                &NIBBLES[(self.0[(index - LEADING_ZEROES) / 2] >> 4) as usize]
-            }
-            _ => &NIBBLES[(self.0[(index - LEADING_ZEROES) / 2] & 0xf) as usize],
+        if index < LEADING_ZEROES {
+            return &SYMBOLS[0];
         }
+        let bits = self.bits as usize;
+        let offset = (index - LEADING_ZEROES) * bits;
+        let shift = 8 - bits - offset % 8;
+        let symbol = (self.data[offset / 8] >> shift) & (u8::MAX >> (8 - bits));
+        &SYMBOLS[symbol as usize]
     }
 }
 
@@ -71,16 +141,25 @@ impl<'a, const LEADING_ZEROES: usize> IntoIterator for Nibbles<'a, LEADING_ZEROE
 impl<'a, const LEADING_ZEROES: usize> Nibbles<'a, LEADING_ZEROES> {
     #[must_use]
     pub fn len(&self) -> usize {
-        LEADING_ZEROES + 2 * self.0.len()
+        LEADING_ZEROES + self.data.len() * 8 / self.bits as usize
     }
 
     #[must_use]
     pub fn is_empty(&self) -> bool {
-        LEADING_ZEROES == 0 && self.0.is_empty()
+        LEADING_ZEROES == 0 && self.data.is_empty()
     }
 
     pub fn new(inner: &'a [u8]) -> Self {
-        Nibbles(inner)
+        Self::with_branch_factor(inner, BranchFactor::Sixteen)
+    }
+
+    /// Split `inner` into the symbols that pick the children of branch nodes with
+    /// `branch_factor` children, rather than into nibbles.
+    pub fn with_branch_factor(inner: &'a [u8], branch_factor: BranchFactor) -> Self {
+        Nibbles {
+            data: inner,
+            bits: branch_factor.bits(),
+        }
     }
 }
 
@@ -142,12 +221,12 @@ impl<'a, const LEADING_ZEROES: usize> DoubleEndedIterator for NibblesIterator<'a
 
 #[cfg(test)]
 mod test {
-    use super::Nibbles;
+    use super::{BranchFactor, Nibbles};
     static TEST_BYTES: [u8; 4] = [0xdeu8, 0xad, 0xbe, 0xef];
 
     #[test]
     fn happy_regular_nibbles() {
-        let nib = Nibbles::<0>(&TEST_BYTES);
+        let nib = Nibbles::<0>::new(&TEST_BYTES);
         let expected = [0xdu8, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         for v in expected.into_iter().enumerate() {
             assert_eq!(nib[v.0], v.1, "{v:?}");
@@ -156,7 +235,7 @@ mod test {
 
     #[test]
     fn leading_zero_nibbles_index() {
-        let nib = Nibbles::<1>(&TEST_BYTES);
+        let nib = Nibbles::<1>::new(&TEST_BYTES);
         let expected = [0u8, 0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         for v in expected.into_iter().enumerate() {
             assert_eq!(nib[v.0], v.1, "{v:?}");
@@ -164,34 +243,34 @@ mod test {
     }
     #[test]
     fn leading_zero_nibbles_iter() {
-        let nib = Nibbles::<1>(&TEST_BYTES);
+        let nib = Nibbles::<1>::new(&TEST_BYTES);
         let expected: [u8; 9] = [0u8, 0xd, 0xe, 0xa, 0xd, 0xb, 0xe, 0xe, 0xf];
         expected.into_iter().eq(nib);
     }
 
     #[test]
     fn skip_skips_zeroes() {
-        let nib1 = Nibbles::<1>(&TEST_BYTES);
-        let nib0 = Nibbles::<0>(&TEST_BYTES);
+        let nib1 = Nibbles::<1>::new(&TEST_BYTES);
+        let nib0 = Nibbles::<0>::new(&TEST_BYTES);
         assert!(nib1.into_iter().skip(1).eq(nib0.into_iter()));
     }
 
     #[test]
     #[should_panic]
     fn out_of_bounds_panics() {
-        let nib = Nibbles::<0>(&TEST_BYTES);
+        let nib = Nibbles::<0>::new(&TEST_BYTES);
         let _ = nib[8];
     }
 
     #[test]
     fn last_nibble() {
-        let nib = Nibbles::<0>(&TEST_BYTES);
+        let nib = Nibbles::<0>::new(&TEST_BYTES);
         assert_eq!(nib[7], 0xf);
     }
 
     #[test]
     fn size_hint_0() {
-        let nib = Nibbles::<0>(&TEST_BYTES);
+        let nib = Nibbles::<0>::new(&TEST_BYTES);
         let mut nib_iter = nib.into_iter();
         assert_eq!((8, Some(8)), nib_iter.size_hint());
         let _ = nib_iter.next();
@@ -200,16 +279,32 @@ mod test {
 
     #[test]
     fn size_hint_1() {
-        let nib = Nibbles::<1>(&TEST_BYTES);
+        let nib = Nibbles::<1>::new(&TEST_BYTES);
         let mut nib_iter = nib.into_iter();
         assert_eq!((9, Some(9)), nib_iter.size_hint());
         let _ = nib_iter.next();
         assert_eq!((8, Some(8)), nib_iter.size_hint());
     }
 
+    #[test]
+    fn other_branch_factors() {
+        let key = [0xb4u8];
+        for (branch_factor, expected) in [
+            (BranchFactor::Two, &[1u8, 0, 1, 1, 0, 1, 0, 0][..]),
+            (BranchFactor::Four, &[2, 3, 1, 0]),
+            (BranchFactor::Sixteen, &[0xb, 0x4]),
+            (BranchFactor::TwoFiftySix, &[0xb4]),
+        ] {
+            let nib = Nibbles::<1>::with_branch_factor(&key, branch_factor);
+            assert_eq!(nib.len(), expected.len() + 1);
+            assert!(nib.into_iter().skip(1).eq(expected.iter().copied()));
+            assert_eq!(branch_factor.join(expected), key);
+        }
+    }
+
     #[test]
     fn backwards() {
-        let nib = Nibbles::<1>(&TEST_BYTES);
+        let nib = Nibbles::<1>::new(&TEST_BYTES);
         let nib_iter = nib.into_iter().rev();
         let expected = [0xf, 0xe, 0xe, 0xb, 0xd, 0xa, 0xe, 0xd, 0x0];
 
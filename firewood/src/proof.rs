@@ -6,6 +6,7 @@ use std::ops::Deref;
 
 use bincode::Options;
 use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
 use sha3::Digest;
 use shale::disk_address::DiskAddress;
 use shale::ShaleError;
@@ -13,15 +14,12 @@ use shale::ShaleStore;
 use thiserror::Error;
 
 use crate::merkle::Encoded;
+use crate::nibbles::BranchFactor;
 use crate::nibbles::Nibbles;
-use crate::nibbles::NibblesIterator;
 use crate::{
     db::DbError,
-    merkle::{
-        to_nibble_array, BranchNode, ExtNode, LeafNode, Merkle, MerkleError, Node, NodeType,
-        PartialPath, NBRANCH,
-    },
-    merkle_util::{new_merkle, DataStoreError, MerkleSetup},
+    merkle::{BranchNode, ExtNode, LeafNode, Merkle, MerkleError, Node, NodeType, PartialPath},
+    merkle_util::{new_merkle_with_branch_factor, DataStoreError, MerkleSetup},
     v2::api::Proof,
 };
 
@@ -60,11 +58,15 @@ pub enum ProofError {
     #[error("fork right")]
     ForkRight,
     #[error("system error: {0:?}")]
-    SystemError(Errno),
+    SystemError(#[source] Errno),
     #[error("shale error: {0:?}")]
-    Shale(ShaleError),
+    Shale(#[source] ShaleError),
     #[error("invalid root hash")]
     InvalidRootHash,
+    #[error("database error: {0}")]
+    Db(#[source] DbError),
+    #[error("key is present")]
+    KeyPresent,
 }
 
 impl From<DataStoreError> for ProofError {
@@ -80,128 +82,402 @@ impl From<DataStoreError> for ProofError {
 impl From<DbError> for ProofError {
     fn from(d: DbError) -> ProofError {
         match d {
-            DbError::InvalidParams => ProofError::InvalidProof,
             DbError::Merkle(e) => ProofError::InvalidNode(e),
             DbError::System(e) => ProofError::SystemError(e),
-            DbError::KeyNotFound => ProofError::InvalidEdgeKeys,
-            DbError::CreateError => ProofError::NoSuchNode,
-            // TODO: fix better by adding a new error to ProofError
-            DbError::IO(e) => {
-                ProofError::SystemError(nix::errno::Errno::from_i32(e.raw_os_error().unwrap()))
-            }
             DbError::Shale(e) => ProofError::Shale(e),
-            DbError::InvalidProposal => ProofError::InvalidProof,
+            e => ProofError::Db(e),
         }
     }
 }
 
 const EXT_NODE_SIZE: usize = 2;
-const BRANCH_NODE_SIZE: usize = 17;
 
-/// SubProof contains the encoded value and the hash value of a node that maps
-/// to a single proof step. If reaches an end step during proof verification,
-/// the hash value will be none, and the encoded value will be the value of the
-/// node.
+/// The number of items in the encoding of a branch node with `branch_factor` children.
+fn branch_node_size(branch_factor: BranchFactor) -> usize {
+    branch_factor as usize + 1
+}
+
+/// SubProof contains the hash value of the node that a single proof step leads
+/// to. If reaches an end step during proof verification, the hash value will be
+/// none.
 pub struct SubProof {
-    encoded: Vec<u8>,
     hash: Option<[u8; 32]>,
 }
 
+/// What a proof shows of a key, see [Proof::verify].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofResult {
+    /// The key is in the trie, with the value.
+    Present(Vec<u8>),
+    /// The key is not in the trie, whose paths leave the path of the key at the divergence.
+    Absent(Divergence),
+}
+
+impl ProofResult {
+    /// The value of the key, if it is in the trie.
+    pub fn into_value(self) -> Option<Vec<u8>> {
+        match self {
+            ProofResult::Present(value) => Some(value),
+            ProofResult::Absent(_) => None,
+        }
+    }
+}
+
+/// Where the path of a key that is not in a trie leaves the paths of the trie, which tells how a
+/// proof shows that the key is absent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Divergence {
+    /// The number of symbols of the key that are on a path of the trie, which is all of them if
+    /// the key ends at a node without a value.
+    pub depth: usize,
+    /// The hash of the last node of the proof on the path of the key, or `None` if the trie is
+    /// empty.
+    pub node: Option<[u8; 32]>,
+}
+
+/// A proof of one key with what it shows of the key, which can be sent on its own, see
+/// [KeyProof::to_bytes]. A receiver that knows the root hash of the trie checks both with
+/// [KeyProof::verify], whether the key is in the trie or not.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyProof {
+    /// The key, as it is in the trie, see [Merkle::trie_key].
+    pub key: Vec<u8>,
+    /// What the proof shows of the key.
+    pub result: ProofResult,
+    /// The encodings of the nodes of the proof, in the order of their hashes.
+    pub nodes: Vec<Vec<u8>>,
+}
+
+impl KeyProof {
+    /// The proof of `key`, with what it shows of the key in the trie with the root hash, whose
+    /// branch nodes have `branch_factor` children.
+    pub fn new<N: AsRef<[u8]> + Send>(
+        key: Vec<u8>,
+        proof: Proof<N>,
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<Self, ProofError> {
+        let result = proof.verify_with_branch_factor(&key, root_hash, branch_factor)?;
+        let mut nodes: Vec<_> = proof.0.into_iter().collect();
+        nodes.sort_by_key(|(hash, _)| *hash);
+        let nodes = nodes
+            .into_iter()
+            .map(|(_, node)| node.as_ref().to_vec())
+            .collect();
+        Ok(Self { key, result, nodes })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::DefaultOptions::new().serialize(self).unwrap()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofError> {
+        bincode::DefaultOptions::new()
+            .deserialize(bytes)
+            .map_err(ProofError::DecodeError)
+    }
+
+    /// Check that the proof shows what it says of its key in the trie with the root hash, whose
+    /// branch nodes have `branch_factor` children.
+    pub fn verify(
+        &self,
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<(), ProofError> {
+        let proof = Proof(
+            self.nodes
+                .iter()
+                .map(|node| (sha3::Keccak256::digest(node).into(), node))
+                .collect(),
+        );
+        if proof.verify_with_branch_factor(&self.key, root_hash, branch_factor)? != self.result {
+            return Err(ProofError::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+/// A node of a proof, decoded.
+enum ProofNode {
+    /// An extension node, or a leaf node if `term`, with the encoding of its child, or its value.
+    Ext {
+        path: Vec<u8>,
+        term: bool,
+        data: Vec<u8>,
+    },
+    /// A branch node, with the encodings of its children, and its value, which is empty if it
+    /// has none.
+    Branch { chd: Vec<Vec<u8>>, value: Vec<u8> },
+}
+
+/// Where a key goes from a node of a proof.
+enum Step {
+    /// The proof ends for the key, with what it shows of it.
+    Done(ProofResult),
+    /// The key goes on to the node with the hash, after the number of symbols.
+    Next([u8; 32], usize),
+}
+
 impl<N: AsRef<[u8]> + Send> Proof<N> {
-    /// verify_proof checks merkle proofs. The given proof must contain the value for
-    /// key in a trie with the given root hash. VerifyProof returns an error if the
-    /// proof contains invalid trie nodes or the wrong value.
-    ///
-    /// The generic N represents the storage for the node data
+    /// Like [Proof::verify], with the value of `key` if the trie has it, and `None` if the proof
+    /// shows that it doesn't.
     pub fn verify_proof<K: AsRef<[u8]>>(
         &self,
         key: K,
         root_hash: [u8; 32],
     ) -> Result<Option<Vec<u8>>, ProofError> {
-        let mut key_nibbles = Nibbles::<0>::new(key.as_ref()).into_iter();
+        self.verify_proof_with_branch_factor(key, root_hash, BranchFactor::default())
+    }
+
+    /// Like [Proof::verify_proof], for a trie whose branch nodes have `branch_factor` children.
+    pub fn verify_proof_with_branch_factor<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<Option<Vec<u8>>, ProofError> {
+        self.verify_with_branch_factor(key, root_hash, branch_factor)
+            .map(ProofResult::into_value)
+    }
 
-        let mut cur_hash = root_hash;
-        let proofs_map = &self.0;
+    /// Verify the proof of `key` against the root hash of a trie, which shows that the key is in
+    /// the trie with its value, or that it is not. Fails if a node on the path of the key is
+    /// missing from the proof or invalid, so that a proof never shows a key to be absent that it
+    /// can't show to be present.
+    pub fn verify<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        root_hash: [u8; 32],
+    ) -> Result<ProofResult, ProofError> {
+        self.verify_with_branch_factor(key, root_hash, BranchFactor::default())
+    }
 
+    /// Like [Proof::verify], for a trie whose branch nodes have `branch_factor` children.
+    pub fn verify_with_branch_factor<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<ProofResult, ProofError> {
+        if self.is_of_empty_trie(root_hash) {
+            return Ok(ProofResult::Absent(Divergence {
+                depth: 0,
+                node: None,
+            }));
+        }
+        let key: Vec<u8> = Nibbles::<0>::with_branch_factor(key.as_ref(), branch_factor)
+            .into_iter()
+            .collect();
+        let mut depth = 0;
+        let mut cur_hash = root_hash;
         loop {
-            let cur_proof = proofs_map
-                .get(&cur_hash)
-                .ok_or(ProofError::ProofNodeMissing)?;
-            let (sub_proof, traversed_nibbles) =
-                self.locate_subproof(key_nibbles, cur_proof.as_ref())?;
-            key_nibbles = traversed_nibbles;
-
-            cur_hash = match sub_proof {
-                // Return when reaching the end of the key.
-                Some(p) if key_nibbles.size_hint().0 == 0 => return Ok(Some(p.encoded)),
-                // The trie doesn't contain the key.
-                Some(SubProof {
-                    hash: Some(hash), ..
-                }) => hash,
-                _ => return Ok(None),
-            };
+            let node = self.decode_proof_node(&cur_hash, branch_factor)?;
+            match self.step(&cur_hash, &node, &key, depth)? {
+                Step::Done(result) => return Ok(result),
+                Step::Next(hash, next) => (cur_hash, depth) = (hash, next),
+            }
+        }
+    }
+
+    /// Verify that `key` is not in the trie with the root hash, see [Proof::verify], and give
+    /// where its path leaves the trie. Fails with [ProofError::KeyPresent] if the proof shows that
+    /// the key is in the trie.
+    pub fn verify_absence<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        root_hash: [u8; 32],
+    ) -> Result<Divergence, ProofError> {
+        self.verify_absence_with_branch_factor(key, root_hash, BranchFactor::default())
+    }
+
+    /// Like [Proof::verify_absence], for a trie whose branch nodes have `branch_factor` children.
+    pub fn verify_absence_with_branch_factor<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<Divergence, ProofError> {
+        match self.verify_with_branch_factor(key, root_hash, branch_factor)? {
+            ProofResult::Present(_) => Err(ProofError::KeyPresent),
+            ProofResult::Absent(divergence) => Ok(divergence),
+        }
+    }
+
+    /// An empty trie has no nodes to prove with, so any proof shows what is in it.
+    fn is_of_empty_trie(&self, root_hash: [u8; 32]) -> bool {
+        root_hash == Merkle::<()>::empty_root().0 && !self.0.contains_key(&root_hash)
+    }
+
+    /// Like [Proof::verify], for many keys, which may or may not be in the trie, at once: the
+    /// proof is walked once for all of them, and each of its nodes is decoded once, however many
+    /// of the keys go through it. The results are in the order of `keys`.
+    pub fn verify_multi_proof<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        root_hash: [u8; 32],
+    ) -> Result<Vec<ProofResult>, ProofError> {
+        self.verify_multi_proof_with_branch_factor(keys, root_hash, BranchFactor::default())
+    }
+
+    /// Like [Proof::verify_multi_proof], for a trie whose branch nodes have `branch_factor`
+    /// children.
+    pub fn verify_multi_proof_with_branch_factor<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        root_hash: [u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<Vec<ProofResult>, ProofError> {
+        if self.is_of_empty_trie(root_hash) {
+            let absent = ProofResult::Absent(Divergence {
+                depth: 0,
+                node: None,
+            });
+            return Ok(vec![absent; keys.len()]);
+        }
+        let mut paths: Vec<(Vec<u8>, usize)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let path = Nibbles::<0>::with_branch_factor(key.as_ref(), branch_factor)
+                    .into_iter()
+                    .collect();
+                (path, i)
+            })
+            .collect();
+        // the keys that go through a node are next to each other
+        paths.sort();
+        let paths: Vec<_> = paths.iter().collect();
+        let mut results = vec![None; keys.len()];
+        if !paths.is_empty() {
+            self.verify_paths(root_hash, 0, &paths, &mut results, branch_factor)?;
+        }
+        // the proof ends somewhere for every key
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Verify the `paths`, which all reach the node with `hash` after `depth` symbols.
+    fn verify_paths(
+        &self,
+        hash: [u8; 32],
+        depth: usize,
+        paths: &[&(Vec<u8>, usize)],
+        results: &mut [Option<ProofResult>],
+        branch_factor: BranchFactor,
+    ) -> Result<(), ProofError> {
+        let node = self.decode_proof_node(&hash, branch_factor)?;
+        let mut next = Vec::with_capacity(paths.len());
+        for path in paths {
+            match self.step(&hash, &node, &path.0, depth)? {
+                Step::Done(result) => results[path.1] = Some(result),
+                Step::Next(hash, depth) => next.push(((hash, depth), *path)),
+            }
+        }
+        // the paths that go on are still in order, so the ones that go to the same child are
+        // next to each other
+        for group in next.chunk_by(|a, b| a.0 == b.0) {
+            let (hash, depth) = group[0].0;
+            let paths: Vec<_> = group.iter().map(|(_, path)| *path).collect();
+            self.verify_paths(hash, depth, &paths, results, branch_factor)?;
         }
+        Ok(())
     }
 
-    fn locate_subproof<'a>(
+    fn decode_proof_node(
         &self,
-        mut key_nibbles: NibblesIterator<'a, 0>,
-        encoded_node: &[u8],
-    ) -> Result<(Option<SubProof>, NibblesIterator<'a, 0>), ProofError> {
-        let items: Vec<Encoded<Vec<u8>>> = bincode::DefaultOptions::new()
-            .deserialize(encoded_node)
+        hash: &[u8; 32],
+        branch_factor: BranchFactor,
+    ) -> Result<ProofNode, ProofError> {
+        let encoded_node = self.0.get(hash).ok_or(ProofError::ProofNodeMissing)?;
+        let mut items: Vec<Encoded<Vec<u8>>> = bincode::DefaultOptions::new()
+            .deserialize(encoded_node.as_ref())
             .map_err(ProofError::DecodeError)?;
 
         match items.len() {
             EXT_NODE_SIZE => {
                 let mut items = items.into_iter();
                 let decoded_key: Vec<u8> = items.next().unwrap().decode()?;
+                let (path, term) = PartialPath::from_bytes(&decoded_key);
+                let data = items.next().unwrap().decode()?;
+                Ok(ProofNode::Ext {
+                    path: path.into_inner(),
+                    term,
+                    data,
+                })
+            }
 
-                let decoded_key_nibbles = Nibbles::<0>::new(&decoded_key);
-
-                let (cur_key_path, term) =
-                    PartialPath::from_nibbles(decoded_key_nibbles.into_iter());
-                let cur_key = cur_key_path.into_inner();
-
-                let data: Vec<u8> = items.next().unwrap().decode()?;
-
-                // Check if the key of current node match with the given key
-                // and consume the current-key portion of the nibbles-iterator
-                let does_not_match = key_nibbles.size_hint().0 < cur_key.len()
-                    || !cur_key.iter().all(|val| key_nibbles.next() == Some(*val));
-
-                if does_not_match {
-                    return Ok((None, Nibbles::<0>::new(&[]).into_iter()));
-                }
+            size if size == branch_node_size(branch_factor) => {
+                // the children come before the value
+                let value = items.pop().unwrap().decode()?;
+                let chd = items
+                    .into_iter()
+                    .map(|chd| chd.decode())
+                    .collect::<Result<_, _>>()?;
+                Ok(ProofNode::Branch { chd, value })
+            }
 
-                let sub_proof = if term {
-                    SubProof {
-                        encoded: data,
-                        hash: None,
-                    }
-                } else {
-                    self.generate_subproof(data)?
-                };
+            size => Err(ProofError::DecodeError(Box::new(
+                bincode::ErrorKind::Custom(format!("invalid size: {size}")),
+            ))),
+        }
+    }
 
-                Ok((sub_proof.into(), key_nibbles))
+    /// Where `key`, whose first `depth` symbols led to `node`, which has `hash`, goes from it.
+    fn step(
+        &self,
+        hash: &[u8; 32],
+        node: &ProofNode,
+        key: &[u8],
+        depth: usize,
+    ) -> Result<Step, ProofError> {
+        let rest = &key[depth..];
+        let absent = |depth| {
+            Step::Done(ProofResult::Absent(Divergence {
+                depth,
+                node: Some(*hash),
+            }))
+        };
+        Ok(match node {
+            // the key must go on with the whole path of the node
+            ProofNode::Ext { path, .. } if !rest.starts_with(path) => {
+                absent(depth + rest.iter().zip(path).take_while(|(a, b)| a == b).count())
             }
 
-            BRANCH_NODE_SIZE if key_nibbles.size_hint().0 == 0 => Err(ProofError::NoSuchNode),
+            // a leaf node has the value of the key that ends with it
+            ProofNode::Ext {
+                path,
+                term: true,
+                data,
+            } if rest.len() == path.len() => Step::Done(ProofResult::Present(data.clone())),
 
-            BRANCH_NODE_SIZE => {
-                let index = key_nibbles.next().unwrap() as usize;
+            ProofNode::Ext {
+                path, term: true, ..
+            } => absent(depth + path.len()),
 
-                // consume items returning the item at index
-                let data: Vec<u8> = items.into_iter().nth(index).unwrap().decode()?;
+            ProofNode::Ext { path, data, .. } => {
+                Step::Next(self.child_hash(data)?, depth + path.len())
+            }
 
-                self.generate_subproof(data)
-                    .map(|subproof| (Some(subproof), key_nibbles))
+            // a branch node has the value of the key that ends at it, if any
+            ProofNode::Branch { value, .. } if rest.is_empty() => {
+                if value.is_empty() {
+                    absent(depth)
+                } else {
+                    Step::Done(ProofResult::Present(value.clone()))
+                }
             }
 
-            size => Err(ProofError::DecodeError(Box::new(
-                bincode::ErrorKind::Custom(format!("invalid size: {size}")),
-            ))),
-        }
+            ProofNode::Branch { chd, .. } => match &chd[rest[0] as usize] {
+                // an empty child means the trie doesn't contain the key
+                data if data.is_empty() => absent(depth),
+                data => Step::Next(self.child_hash(data)?, depth + 1),
+            },
+        })
+    }
+
+    /// The hash of the child of a node, from its encoding in the node.
+    fn child_hash(&self, data: &[u8]) -> Result<[u8; 32], ProofError> {
+        let sub_proof = self.generate_subproof(data.to_vec())?;
+        Ok(sub_proof.hash.expect("a child always has a hash"))
     }
 
     fn generate_subproof(&self, data: Vec<u8>) -> Result<SubProof, ProofError> {
@@ -209,7 +485,6 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
             0..=31 => {
                 let sub_hash = sha3::Keccak256::digest(&data).into();
                 Ok(SubProof {
-                    encoded: data,
                     hash: Some(sub_hash),
                 })
             }
@@ -219,7 +494,6 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
                 let sub_hash = sub_hash.try_into().unwrap();
 
                 Ok(SubProof {
-                    encoded: data,
                     hash: Some(sub_hash),
                 })
             }
@@ -241,6 +515,27 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
         last_key: K,
         keys: Vec<K>,
         vals: Vec<V>,
+    ) -> Result<bool, ProofError> {
+        self.verify_range_proof_with_branch_factor(
+            root_hash,
+            first_key,
+            last_key,
+            keys,
+            vals,
+            BranchFactor::default(),
+        )
+    }
+
+    /// Like [Proof::verify_range_proof], for a trie whose branch nodes have `branch_factor`
+    /// children.
+    pub fn verify_range_proof_with_branch_factor<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        root_hash: [u8; 32],
+        first_key: K,
+        last_key: K,
+        keys: Vec<K>,
+        vals: Vec<V>,
+        branch_factor: BranchFactor,
     ) -> Result<bool, ProofError> {
         if keys.len() != vals.len() {
             return Err(ProofError::InconsistentProofData);
@@ -256,7 +551,7 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
         }
 
         // Use in-memory merkle
-        let mut merkle_setup = new_merkle(0x10000, 0x10000);
+        let mut merkle_setup = new_merkle_with_branch_factor(0x10000, 0x10000, branch_factor);
 
         // Special case, there is no edge proof at all. The given range is expected
         // to be the whole leaf-set in the trie.
@@ -331,7 +626,7 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
 
         // If the fork point is the root, the trie should be empty, start with a new one.
         if fork_at_root {
-            merkle_setup = new_merkle(0x100000, 0x100000);
+            merkle_setup = new_merkle_with_branch_factor(0x100000, 0x100000, branch_factor);
         }
 
         for (key, val) in keys.iter().zip(vals.iter()) {
@@ -365,8 +660,9 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
         let merkle = merkle_setup.get_merkle_mut();
         let mut u_ref = merkle.get_node(root).map_err(|_| ProofError::NoSuchNode)?;
 
-        let mut chunks = Vec::new();
-        chunks.extend(key.as_ref().iter().copied().flat_map(to_nibble_array));
+        let chunks: Vec<_> = Nibbles::<0>::with_branch_factor(key.as_ref(), merkle.branch_factor())
+            .into_iter()
+            .collect();
 
         let mut cur_key: &[u8] = &chunks;
         let mut cur_hash = root_hash;
@@ -549,15 +845,9 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
             EXT_NODE_SIZE => {
                 let mut items = items.into_iter();
 
-                let cur_key_path: Vec<u8> = items
-                    .next()
-                    .unwrap()
-                    .decode()?
-                    .into_iter()
-                    .flat_map(to_nibble_array)
-                    .collect();
+                let cur_key_path: Vec<u8> = items.next().unwrap().decode()?;
 
-                let (cur_key_path, term) = PartialPath::decode(&cur_key_path);
+                let (cur_key_path, term) = PartialPath::from_bytes(&cur_key_path);
                 let cur_key = cur_key_path.into_inner();
 
                 let data: Vec<u8> = items.next().unwrap().decode()?;
@@ -570,10 +860,7 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
                 }
 
                 let subproof = if term {
-                    Some(SubProof {
-                        encoded: data.clone(),
-                        hash: None,
-                    })
+                    Some(SubProof { hash: None })
                 } else {
                     self.generate_subproof(data.clone()).map(Some)?
                 };
@@ -585,20 +872,17 @@ impl<N: AsRef<[u8]> + Send> Proof<N> {
                 Ok((ext_ptr, subproof, cur_key_len))
             }
 
-            BRANCH_NODE_SIZE => {
+            size if size == branch_node_size(merkle.branch_factor()) => {
                 // we've already validated the size, that's why we can safely unwrap
                 let data = items.pop().unwrap().decode()?;
                 // Extract the value of the branch node and set to None if it's an empty Vec
                 let value = Some(data).filter(|data| !data.is_empty());
 
-                // Record encoded values of all children.
-                let mut chd_encoded: [Option<Vec<u8>>; NBRANCH] = Default::default();
-
-                // we popped the last element, so their should only be NBRANCH items left
-                for (i, chd) in items.into_iter().enumerate() {
-                    let data = chd.decode()?;
-                    chd_encoded[i] = Some(data).filter(|data| !data.is_empty());
-                }
+                // Record encoded values of all children, which are the items left after the value.
+                let chd_encoded = items
+                    .into_iter()
+                    .map(|chd| Ok(Some(chd.decode()?).filter(|data| !data.is_empty())))
+                    .collect::<Result<Vec<_>, ProofError>>()?;
 
                 // If the node is the last one to be decoded, then no subproof to be extracted.
                 if end_node {
@@ -657,9 +941,9 @@ fn get_ext_ptr<S: ShaleStore<Node> + Send + Sync>(
 fn build_branch_ptr<S: ShaleStore<Node> + Send + Sync>(
     merkle: &Merkle<S>,
     value: Option<Vec<u8>>,
-    chd_encoded: [Option<Vec<u8>>; NBRANCH],
+    chd_encoded: Vec<Option<Vec<u8>>>,
 ) -> Result<DiskAddress, ProofError> {
-    let node = BranchNode::new([None; NBRANCH], value, chd_encoded);
+    let node = BranchNode::new(vec![None; chd_encoded.len()], value, chd_encoded);
     let node = NodeType::Branch(node);
     let node = Node::new(node);
 
@@ -687,14 +971,16 @@ fn unset_internal<K: AsRef<[u8]>, S: ShaleStore<Node> + Send + Sync>(
     left: K,
     right: K,
 ) -> Result<bool, ProofError> {
-    // Add the sentinel root
-    let mut left_chunks = vec![0];
-    left_chunks.extend(left.as_ref().iter().copied().flat_map(to_nibble_array));
-    // Add the sentinel root
-    let mut right_chunks = vec![0];
-    right_chunks.extend(right.as_ref().iter().copied().flat_map(to_nibble_array));
     let root = merkle_setup.get_root();
     let merkle = merkle_setup.get_merkle_mut();
+    // Add the sentinel root
+    let chunks = |key: &[u8]| -> Vec<u8> {
+        Nibbles::<1>::with_branch_factor(key, merkle.branch_factor())
+            .into_iter()
+            .collect()
+    };
+    let left_chunks = chunks(left.as_ref());
+    let right_chunks = chunks(right.as_ref());
     let mut u_ref = merkle.get_node(root).map_err(|_| ProofError::NoSuchNode)?;
     let mut parent = DiskAddress::null();
 
@@ -954,7 +1240,7 @@ fn unset_node_ref<K: AsRef<[u8]>, S: ShaleStore<Node> + Send + Sync>(
             let iter = if remove_left {
                 0..child_index
             } else {
-                child_index + 1..16
+                child_index + 1..n.chd().len()
             };
 
             for i in iter {
@@ -13,16 +13,15 @@ use tokio::sync::{mpsc, oneshot};
 
 use crate::api::Revision;
 
-use crate::merkle::MerkleError;
 use crate::v2::api::Proof;
 use crate::{
-    db::{DbConfig, DbError},
+    db::{Batch, BatchOp, DbConfig, DbError},
     merkle::TrieHash,
 };
 use async_trait::async_trait;
 
 use super::server::FirewoodService;
-use super::{Request, RevRequest, RevisionHandle};
+use super::{ProposalHandle, ProposalRequest, Request, RevRequest, RevisionHandle};
 
 /// A `Connection` represents a connection to the thread running firewood
 /// The type specified is how you want to refer to your key values; this is
@@ -43,27 +42,114 @@ impl<N: Send> Drop for Connection<N> {
     }
 }
 
-impl<N: Send + 'static> Connection<N> {
-    #[allow(dead_code)]
-    fn new<P: AsRef<Path>>(path: P, cfg: DbConfig) -> Self {
+impl<N: Send + From<Vec<u8>> + 'static> Connection<N> {
+    /// Start the firewood thread and open the database at `path` on it.
+    /// Errors from opening the database are returned here rather than
+    /// from the first request.
+    pub async fn new<P: AsRef<Path>>(path: P, cfg: DbConfig) -> Result<Self, DbError> {
         let (sender, receiver) = mpsc::channel(1_000)
             as (
                 tokio::sync::mpsc::Sender<Request<N>>,
                 tokio::sync::mpsc::Receiver<Request<N>>,
             );
+        let (ready, opened) = oneshot::channel();
         let owned_path = path.as_ref().to_path_buf();
         let handle = thread::Builder::new()
             .name("firewood-receiver".to_owned())
-            .spawn(move || FirewoodService::new(receiver, owned_path, cfg))
+            .spawn(move || FirewoodService::new(receiver, owned_path, cfg, ready))
             .expect("thread creation failed");
-        Self {
+        let connection = Self {
             sender: Some(sender),
             handle: Some(handle),
-        }
+        };
+        opened
+            .await
+            .expect("Actor task has been killed")
+            .map(|_| connection)
     }
 }
 
+impl<N: Send> Connection<N> {
+    fn sender(&self) -> &mpsc::Sender<Request<N>> {
+        self.sender.as_ref().expect("connection is open")
+    }
+
+    /// Get the root hash of the latest committed revision.
+    pub async fn root_hash(&self) -> Result<TrieHash, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RootHash { respond_to: send };
+        self.sender().send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
+    }
+
+    /// Create a proposal on top of the latest committed revision.
+    pub async fn new_proposal<K: AsRef<[u8]>>(
+        &self,
+        data: Batch<K>,
+    ) -> Result<ProposalHandle<N>, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::NewProposal {
+            data: into_owned_batch(data),
+            respond_to: send,
+        };
+        self.sender().send(msg).await.expect("channel failed");
+        let id = recv.await.expect("channel failed")?;
+        Ok(ProposalHandle {
+            sender: self.sender().clone(),
+            id,
+        })
+    }
+}
+
+fn into_owned_batch<K: AsRef<[u8]>>(data: Batch<K>) -> Batch<Vec<u8>> {
+    data.into_iter()
+        .map(|op| match op {
+            BatchOp::Put { key, value } => BatchOp::Put {
+                key: key.as_ref().to_vec(),
+                value,
+            },
+            BatchOp::Delete { key } => BatchOp::Delete {
+                key: key.as_ref().to_vec(),
+            },
+            BatchOp::KeyspacePut {
+                keyspace,
+                key,
+                value,
+            } => BatchOp::KeyspacePut {
+                keyspace,
+                key: key.as_ref().to_vec(),
+                value,
+            },
+            BatchOp::KeyspaceDelete { keyspace, key } => BatchOp::KeyspaceDelete {
+                keyspace,
+                key: key.as_ref().to_vec(),
+            },
+            #[cfg(feature = "eth")]
+            BatchOp::Account { address, op } => BatchOp::Account {
+                address: address.as_ref().to_vec(),
+                op,
+            },
+        })
+        .collect()
+}
+
 impl<N: Send> super::RevisionHandle<N> {
+    /// Get an account, or `None` if it does not exist.
+    #[cfg(feature = "eth")]
+    pub async fn get_account<K: AsRef<[u8]>>(
+        &self,
+        address: K,
+    ) -> Result<Option<crate::account::Account>, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RevRequest(RevRequest::Account {
+            handle: self.id,
+            address: address.as_ref().to_vec(),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
+    }
+
     pub async fn close(self) {
         let _ = self
             .sender
@@ -72,6 +158,71 @@ impl<N: Send> super::RevisionHandle<N> {
     }
 }
 
+impl<N: Send> ProposalHandle<N> {
+    /// Get a value from the state this proposal would commit.
+    pub async fn kv_get<K: AsRef<[u8]>>(&self, key: K) -> Result<Vec<u8>, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::ProposalRequest(ProposalRequest::Get {
+            handle: self.id,
+            key: key.as_ref().to_vec(),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
+    }
+
+    /// Get the root hash this proposal would commit.
+    pub async fn kv_root_hash(&self) -> Result<TrieHash, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::ProposalRequest(ProposalRequest::RootHash {
+            handle: self.id,
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
+    }
+
+    /// Create a child proposal on top of this one.
+    pub async fn propose<K: AsRef<[u8]>>(
+        &self,
+        data: Batch<K>,
+    ) -> Result<ProposalHandle<N>, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::ProposalRequest(ProposalRequest::Propose {
+            handle: self.id,
+            data: into_owned_batch(data),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        let id = recv.await.expect("channel failed")?;
+        Ok(ProposalHandle {
+            sender: self.sender.clone(),
+            id,
+        })
+    }
+
+    /// Commit this proposal, and any uncommitted proposals it was built on.
+    pub async fn commit(self) -> Result<(), DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::ProposalRequest(ProposalRequest::Commit {
+            handle: self.id,
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
+    }
+
+    /// Discard this proposal without committing it.
+    pub async fn close(self) {
+        let _ = self
+            .sender
+            .send(Request::ProposalRequest(ProposalRequest::Drop {
+                handle: self.id,
+            }))
+            .await;
+    }
+}
+
 #[async_trait]
 impl<N: Send> Revision<N> for super::RevisionHandle<N> {
     async fn kv_root_hash(&self) -> Result<TrieHash, DbError> {
@@ -86,15 +237,16 @@ impl<N: Send> Revision<N> for super::RevisionHandle<N> {
 
     async fn kv_get<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Vec<u8>, DbError> {
         let (send, recv) = oneshot::channel();
-        let _ = Request::RevRequest::<N>(RevRequest::Get {
+        let msg = Request::RevRequest(RevRequest::Get {
             handle: self.id,
             key: key.as_ref().to_vec(),
             respond_to: send,
         });
+        self.sender.send(msg).await.expect("channel failed");
         recv.await.expect("Actor task has been killed")
     }
 
-    async fn prove<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Proof<N>, MerkleError> {
+    async fn prove<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Proof<N>, DbError> {
         let (send, recv) = oneshot::channel();
         let msg = Request::RevRequest(RevRequest::Prove {
             handle: self.id,
@@ -105,16 +257,6 @@ impl<N: Send> Revision<N> for super::RevisionHandle<N> {
         recv.await.expect("channel failed")
     }
 
-    async fn verify_range_proof<K: AsRef<[u8]> + Send + Sync>(
-        &self,
-        _proof: Proof<N>,
-        _first_key: K,
-        _last_key: K,
-        _keys: Vec<K>,
-        _values: Vec<K>,
-    ) {
-        todo!()
-    }
     async fn root_hash(&self) -> Result<TrieHash, DbError> {
         let (send, recv) = oneshot::channel();
         let msg = Request::RevRequest(RevRequest::RootHash {
@@ -125,51 +267,79 @@ impl<N: Send> Revision<N> for super::RevisionHandle<N> {
         recv.await.expect("channel failed")
     }
 
-    async fn dump<W: std::io::Write + Send + Sync>(&self, _writer: W) -> Result<(), DbError> {
-        todo!()
-    }
-
     #[cfg(feature = "eth")]
     async fn dump_account<W: std::io::Write + Send + Sync, K: AsRef<[u8]> + Send + Sync>(
         &self,
-        _key: K,
-        _writer: W,
+        key: K,
+        mut writer: W,
     ) -> Result<(), DbError> {
-        todo!()
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RevRequest(RevRequest::DumpAccount {
+            handle: self.id,
+            address: key.as_ref().to_vec(),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        let dump = recv.await.expect("channel failed")?;
+        writer.write_all(&dump)?;
+        Ok(())
     }
 
-    async fn kv_dump<W: std::io::Write + Send + Sync>(&self, _writer: W) -> Result<(), DbError> {
-        unimplemented!();
+    async fn kv_dump<W: std::io::Write + Send + Sync>(&self, mut writer: W) -> Result<(), DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RevRequest(RevRequest::KvDump {
+            handle: self.id,
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        let dump = recv.await.expect("channel failed")?;
+        writer.write_all(&dump)?;
+        Ok(())
     }
 
     #[cfg(feature = "eth")]
     async fn get_balance<K: AsRef<[u8]> + Send + Sync>(
         &self,
-        _key: K,
+        key: K,
     ) -> Result<primitive_types::U256, DbError> {
-        todo!()
+        Ok(self.get_account(key).await?.unwrap_or_default().balance)
     }
 
     #[cfg(feature = "eth")]
-    async fn get_code<K: AsRef<[u8]> + Send + Sync>(&self, _key: K) -> Result<Vec<u8>, DbError> {
-        todo!()
+    async fn get_code<K: AsRef<[u8]> + Send + Sync>(&self, key: K) -> Result<Vec<u8>, DbError> {
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RevRequest(RevRequest::Code {
+            handle: self.id,
+            address: key.as_ref().to_vec(),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
     }
 
     #[cfg(feature = "eth")]
     async fn get_nonce<K: AsRef<[u8]> + Send + Sync>(
         &self,
-        _key: K,
+        key: K,
     ) -> Result<crate::api::Nonce, DbError> {
-        todo!()
+        Ok(self.get_account(key).await?.unwrap_or_default().nonce)
     }
 
     #[cfg(feature = "eth")]
     async fn get_state<K: AsRef<[u8]> + Send + Sync>(
         &self,
-        _key: K,
-        _sub_key: K,
+        key: K,
+        sub_key: K,
     ) -> Result<Vec<u8>, DbError> {
-        todo!()
+        let (send, recv) = oneshot::channel();
+        let msg = Request::RevRequest(RevRequest::State {
+            handle: self.id,
+            address: key.as_ref().to_vec(),
+            key: sub_key.as_ref().to_vec(),
+            respond_to: send,
+        });
+        self.sender.send(msg).await.expect("channel failed");
+        recv.await.expect("channel failed")
     }
 }
 
@@ -184,18 +354,96 @@ where
             root_hash,
             respond_to: send,
         };
-        self.sender
-            .as_ref()
-            .unwrap()
-            .send(msg)
-            .await
-            .expect("channel failed");
+        self.sender().send(msg).await.expect("channel failed");
         let id = recv.await.unwrap();
         id.map(|id| RevisionHandle {
-            sender: self.sender.as_ref().unwrap().clone(),
+            sender: self.sender().clone(),
             id,
         })
     }
 }
 
-// TODO: add a meaningful test with `Proposal` and `Revisions`.
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::Db as _;
+    use std::path::PathBuf;
+
+    fn get_tmp_dir() -> PathBuf {
+        option_env!("CARGO_TARGET_TMPDIR")
+            .map(Into::into)
+            .unwrap_or(std::env::temp_dir())
+            .join("firewood")
+    }
+
+    #[test]
+    fn proposals_and_revisions() -> Result<(), DbError> {
+        let path = get_tmp_dir().join("service_proposals_and_revisions");
+        let cfg = DbConfig::builder().truncate(true).build();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            // opening the database does not block the runtime it is awaited on
+            let conn = Connection::<Vec<u8>>::new(path, cfg).await?;
+            let proposal = conn
+                .new_proposal(vec![BatchOp::Put {
+                    key: b"k",
+                    value: b"v".to_vec(),
+                }])
+                .await?;
+            assert_eq!(proposal.kv_get(b"k").await?, b"v");
+
+            let child = proposal
+                .propose(vec![BatchOp::Put {
+                    key: b"k2",
+                    value: b"v2".to_vec(),
+                }])
+                .await?;
+            let expected_hash = child.kv_root_hash().await?;
+            proposal.close().await;
+            child.commit().await?;
+
+            let root_hash = conn.root_hash().await?;
+            assert_eq!(root_hash, expected_hash);
+
+            let rev = conn
+                .get_revision(root_hash.clone())
+                .await
+                .expect("latest revision should exist");
+            assert_eq!(rev.kv_get(b"k").await?, b"v");
+            assert_eq!(rev.kv_get(b"k2").await?, b"v2");
+            assert!(matches!(
+                rev.kv_get(b"missing").await,
+                Err(DbError::KeyNotFound)
+            ));
+            assert_eq!(rev.kv_root_hash().await?, root_hash);
+
+            let proof = rev.prove(b"k").await?;
+            assert!(proof.verify_proof(b"k", *root_hash).unwrap().is_some());
+            let mut dump = Vec::new();
+            rev.kv_dump(&mut dump).await?;
+            assert!(!dump.is_empty());
+            rev.close().await;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn open_failure_is_reported() {
+        let path = get_tmp_dir().join("service_open_failure");
+        let cfg = DbConfig::builder()
+            .truncate(true)
+            .payload_regn_nbit(1)
+            .build();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let err = runtime
+            .block_on(Connection::<Vec<u8>>::new(path, cfg))
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidParams));
+    }
+}
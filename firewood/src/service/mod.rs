@@ -3,22 +3,34 @@
 
 use tokio::sync::{mpsc, oneshot};
 
+#[cfg(feature = "eth")]
+use crate::account::Account;
+
 use crate::{
-    db::DbError,
-    merkle::{MerkleError, TrieHash},
+    db::{Batch, DbError},
+    merkle::TrieHash,
     v2::api::Proof,
 };
 
-mod client;
+pub mod client;
 mod server;
 
+pub use client::Connection;
+
 pub type BatchId = u32;
 pub type RevId = u32;
+pub type ProposalId = u32;
 
 #[derive(Debug)]
 pub struct RevisionHandle<N: Send> {
     sender: mpsc::Sender<Request<N>>,
-    id: u32,
+    id: RevId,
+}
+
+#[derive(Debug)]
+pub struct ProposalHandle<N: Send> {
+    sender: mpsc::Sender<Request<N>>,
+    id: ProposalId,
 }
 
 /// Client side request object
@@ -28,8 +40,16 @@ pub enum Request<N: Send> {
         root_hash: TrieHash,
         respond_to: oneshot::Sender<Option<RevId>>,
     },
+    NewProposal {
+        data: Batch<OwnedKey>,
+        respond_to: oneshot::Sender<Result<ProposalId, DbError>>,
+    },
+    RootHash {
+        respond_to: oneshot::Sender<Result<TrieHash, DbError>>,
+    },
 
     RevRequest(RevRequest<N>),
+    ProposalRequest(ProposalRequest),
 }
 
 type OwnedKey = Vec<u8>;
@@ -46,13 +66,67 @@ pub enum RevRequest<N: Send> {
     Prove {
         handle: RevId,
         key: OwnedKey,
-        respond_to: oneshot::Sender<Result<Proof<N>, MerkleError>>,
+        respond_to: oneshot::Sender<Result<Proof<N>, DbError>>,
     },
     RootHash {
         handle: RevId,
         respond_to: oneshot::Sender<Result<TrieHash, DbError>>,
     },
+    KvDump {
+        handle: RevId,
+        respond_to: oneshot::Sender<Result<Vec<u8>, DbError>>,
+    },
+    #[cfg(feature = "eth")]
+    Account {
+        handle: RevId,
+        address: OwnedKey,
+        respond_to: oneshot::Sender<Result<Option<Account>, DbError>>,
+    },
+    #[cfg(feature = "eth")]
+    Code {
+        handle: RevId,
+        address: OwnedKey,
+        respond_to: oneshot::Sender<Result<Vec<u8>, DbError>>,
+    },
+    #[cfg(feature = "eth")]
+    State {
+        handle: RevId,
+        address: OwnedKey,
+        key: OwnedKey,
+        respond_to: oneshot::Sender<Result<Vec<u8>, DbError>>,
+    },
+    #[cfg(feature = "eth")]
+    DumpAccount {
+        handle: RevId,
+        address: OwnedKey,
+        respond_to: oneshot::Sender<Result<Vec<u8>, DbError>>,
+    },
     Drop {
         handle: RevId,
     },
 }
+
+#[derive(Debug)]
+pub enum ProposalRequest {
+    Get {
+        handle: ProposalId,
+        key: OwnedKey,
+        respond_to: oneshot::Sender<Result<Vec<u8>, DbError>>,
+    },
+    RootHash {
+        handle: ProposalId,
+        respond_to: oneshot::Sender<Result<TrieHash, DbError>>,
+    },
+    Propose {
+        handle: ProposalId,
+        data: Batch<OwnedKey>,
+        respond_to: oneshot::Sender<Result<ProposalId, DbError>>,
+    },
+    Commit {
+        handle: ProposalId,
+        respond_to: oneshot::Sender<Result<(), DbError>>,
+    },
+    Drop {
+        handle: ProposalId,
+    },
+}
@@ -4,13 +4,19 @@
 use std::{
     collections::HashMap,
     path::PathBuf,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc::Receiver, oneshot};
 
-use crate::db::{Db, DbConfig, DbError};
+use crate::{
+    db::{Db, DbConfig, DbError},
+    v2::api::Proof,
+};
 
-use super::{Request, RevId};
+use super::{ProposalId, ProposalRequest, Request, RevId, RevRequest};
 
 macro_rules! get_rev {
     ($rev: ident, $handle: ident, $out: expr) => {
@@ -27,19 +33,35 @@ macro_rules! get_rev {
 pub struct FirewoodService {}
 
 impl FirewoodService {
-    pub fn new<N: Send>(
+    /// Open the database and serve requests until every sender is dropped.
+    /// The outcome of opening the database is reported through `ready`
+    /// before any request is processed.
+    pub fn new<N: Send + From<Vec<u8>>>(
         mut receiver: Receiver<Request<N>>,
         owned_path: PathBuf,
         cfg: DbConfig,
+        ready: oneshot::Sender<Result<(), DbError>>,
     ) -> Self {
-        let db = Db::new(owned_path, &cfg).unwrap();
+        let db = match Db::new(owned_path, &cfg) {
+            Ok(db) => {
+                let _ = ready.send(Ok(()));
+                db
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                return FirewoodService {};
+            }
+        };
         let mut revs = HashMap::new();
+        let mut proposals = HashMap::new();
         let lastid = AtomicU32::new(0);
-        loop {
-            let msg = match receiver.blocking_recv() {
-                Some(msg) => msg,
-                None => break,
-            };
+        let last_proposal_id = AtomicU32::new(0);
+        let insert_proposal = |proposals: &mut HashMap<_, _>, proposal| {
+            let id: ProposalId = last_proposal_id.fetch_add(1, Ordering::Relaxed);
+            proposals.insert(id, Arc::new(proposal));
+            id
+        };
+        while let Some(msg) = receiver.blocking_recv() {
             match msg {
                 Request::NewRevision {
                     root_hash,
@@ -55,33 +77,133 @@ impl FirewoodService {
                     };
                     let _ = respond_to.send(msg);
                 }
+                Request::NewProposal { data, respond_to } => {
+                    let msg = db
+                        .new_proposal(data)
+                        .map(|proposal| insert_proposal(&mut proposals, proposal));
+                    let _ = respond_to.send(msg);
+                }
+                Request::RootHash { respond_to } => {
+                    let _ = respond_to.send(db.kv_root_hash(None));
+                }
 
                 Request::RevRequest(req) => match req {
-                    super::RevRequest::Get {
+                    RevRequest::Get {
                         handle,
                         key,
                         respond_to,
                     } => {
                         let rev = get_rev!(revs, handle, respond_to);
                         let msg = rev.kv_get(key);
-                        let _ = respond_to.send(msg.map_or(Err(DbError::KeyNotFound), Ok));
+                        let _ = respond_to.send(msg.ok_or(DbError::KeyNotFound));
+                    }
+                    RevRequest::Prove {
+                        handle,
+                        key,
+                        respond_to,
+                    } => {
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let msg = rev
+                            .prove(key)
+                            .map(|proof| {
+                                Proof(proof.0.into_iter().map(|(k, v)| (k, v.into())).collect())
+                            })
+                            .map_err(DbError::Merkle);
+                        let _ = respond_to.send(msg);
+                    }
+                    RevRequest::RootHash { handle, respond_to } => {
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let msg = rev.kv_root_hash(None);
+                        let _ = respond_to.send(msg);
+                    }
+                    RevRequest::KvDump { handle, respond_to } => {
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let mut dump = Vec::new();
+                        let msg = rev.kv_dump(&mut dump).map(|_| dump);
+                        let _ = respond_to.send(msg);
+                    }
+                    #[cfg(feature = "eth")]
+                    RevRequest::Account {
+                        handle,
+                        address,
+                        respond_to,
+                    } => {
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let _ = respond_to.send(rev.get_account(address));
+                    }
+                    #[cfg(feature = "eth")]
+                    RevRequest::Code {
+                        handle,
+                        address,
+                        respond_to,
+                    } => {
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let _ = respond_to.send(rev.get_code(address));
                     }
-                    super::RevRequest::Prove {
-                        handle: _handle,
-                        key: _key,
-                        respond_to: _respond_to,
+                    #[cfg(feature = "eth")]
+                    RevRequest::State {
+                        handle,
+                        address,
+                        key,
+                        respond_to,
                     } => {
-                        todo!()
+                        let rev = get_rev!(revs, handle, respond_to);
+                        let _ = respond_to.send(rev.get_state(address, key));
                     }
-                    super::RevRequest::RootHash { handle, respond_to } => {
+                    #[cfg(feature = "eth")]
+                    RevRequest::DumpAccount {
+                        handle,
+                        address,
+                        respond_to,
+                    } => {
                         let rev = get_rev!(revs, handle, respond_to);
-                        let msg = rev.kv_root_hash();
+                        let mut dump = Vec::new();
+                        let msg = rev.dump_account(address, &mut dump).map(|_| dump);
                         let _ = respond_to.send(msg);
                     }
-                    super::RevRequest::Drop { handle } => {
+                    RevRequest::Drop { handle } => {
                         revs.remove(&handle);
                     }
                 },
+
+                Request::ProposalRequest(req) => match req {
+                    ProposalRequest::Get {
+                        handle,
+                        key,
+                        respond_to,
+                    } => {
+                        let proposal = get_rev!(proposals, handle, respond_to);
+                        let msg = proposal.get_revision().kv_get(key);
+                        let _ = respond_to.send(msg.ok_or(DbError::KeyNotFound));
+                    }
+                    ProposalRequest::RootHash { handle, respond_to } => {
+                        let proposal = get_rev!(proposals, handle, respond_to);
+                        let msg = proposal.get_revision().kv_root_hash(None);
+                        let _ = respond_to.send(msg);
+                    }
+                    ProposalRequest::Propose {
+                        handle,
+                        data,
+                        respond_to,
+                    } => {
+                        let proposal = get_rev!(proposals, handle, respond_to).clone();
+                        let msg = proposal
+                            .propose(data)
+                            .map(|proposal| insert_proposal(&mut proposals, proposal));
+                        let _ = respond_to.send(msg);
+                    }
+                    ProposalRequest::Commit { handle, respond_to } => {
+                        let proposal = get_rev!(proposals, handle, respond_to);
+                        let msg = proposal.commit();
+                        if msg.is_ok() {
+                            proposals.remove(&handle);
+                        }
+                        let _ = respond_to.send(msg);
+                    }
+                    ProposalRequest::Drop { handle } => {
+                        proposals.remove(&handle);
+                    }
+                },
             }
         }
         FirewoodService {}
@@ -8,14 +8,20 @@ use std::os::fd::{AsFd, AsRawFd};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+};
 
-use super::{AshRecord, FilePool, Page, StoreDelta, StoreError, WalConfig, PAGE_SIZE_NBIT};
+use super::{
+    AshRecord, FilePool, Page, StoreDelta, StoreError, WalConfig, PAGE_MASK, PAGE_SIZE,
+    PAGE_SIZE_NBIT,
+};
 use crate::storage::DeltaPage;
 use aiofut::{AioBuilder, AioError, AioManager};
 use futures::future::join_all;
 use growthring::{
-    wal::{RecoverPolicy, WalLoader, WalWriter},
+    wal::{RecoverPolicy, WalBytes, WalLoader, WalWriter},
     walerror::WalError,
     WalFileImpl, WalStoreImpl,
 };
@@ -33,14 +39,20 @@ use typed_builder::TypedBuilder;
 #[derive(Debug)]
 pub enum BufferCmd {
     /// Initialize the Wal.
-    InitWal(PathBuf, String),
+    InitWal(PathBuf, String, oneshot::Sender<Result<(), WalError>>),
+    /// Recover from the Wal of a read-only database, without writing to it or to any space.
+    PeekWal(PathBuf, String, oneshot::Sender<Result<(), WalError>>),
     /// Process a write batch against the underlying store.
     WriteBatch(Vec<BufferWrite>, AshRecord),
     /// Get a page from the disk buffer.
     GetPage((SpaceId, u64), oneshot::Sender<Option<Page>>),
     CollectAsh(usize, oneshot::Sender<Vec<AshRecord>>),
+    /// Write the records of the Wal that are in the window of past revisions to a new Wal.
+    CopyWal(PathBuf, oneshot::Sender<Result<(), WalError>>),
     /// Register a new space and add the files to a memory mapped pool.
     RegCachedSpace(SpaceId, Arc<FilePool>),
+    /// Reply once every write batch received so far is on disk.
+    Drain(oneshot::Sender<()>),
     /// Returns false if the
     Shutdown,
 }
@@ -108,6 +120,27 @@ impl Notifiers {
     }
 }
 
+/// Write batches that have been accepted but are not fully on disk yet: a
+/// batch is done once its pages are written and the Wal has been pruned.
+#[derive(Debug, Default)]
+struct Inflight {
+    batches: Cell<usize>,
+    /// Notified whenever batches are done.
+    drained: Notify,
+}
+
+/// The Wal as seen by the disk buffer, once initialized.
+enum Wal {
+    Writer(Rc<Mutex<WalWriter<WalFileImpl, WalStoreImpl>>>),
+    /// The state recovered by [`BufferCmd::PeekWal`], kept in memory only.
+    ReadOnly {
+        /// The most recent records, newest first.
+        records: Vec<WalBytes>,
+        /// The pages changed by the replayed records.
+        pages: HashMap<(SpaceId, u64), Page>,
+    },
+}
+
 /// Responsible for processing [`BufferCmd`]s from the [`DiskBufferRequester`]
 /// and managing the persistance of pages.
 pub struct DiskBuffer {
@@ -162,6 +195,7 @@ impl DiskBuffer {
         let mut wal = None;
 
         let notifier = Rc::new(Notify::new());
+        let inflight = Rc::new(Inflight::default());
 
         local_pool
             // everything needs to be moved into this future in order to be properly dropped
@@ -174,6 +208,11 @@ impl DiskBuffer {
                         notifier.notified().await;
                     }
 
+                    // every requester is gone, e.g. when opening the DB failed
+                    let Some(req) = inbound.recv().await else {
+                        break;
+                    };
+
                     // process the the request
                     let process_result = process(
                         pending_writes.clone(),
@@ -182,10 +221,11 @@ impl DiskBuffer {
                         aiomgr.clone(),
                         &mut wal,
                         &wal_cfg,
-                        inbound.recv().await.unwrap(),
+                        req,
                         max,
                         wal_in.clone(),
                         &mut writes,
+                        inflight.clone(),
                     )
                     .await;
 
@@ -281,48 +321,64 @@ fn schedule_write(
     task::spawn_local(task);
 }
 
+/// Decode a record being replayed.
+fn decode_record(raw: WalBytes) -> Result<AshRecord, WalError> {
+    AshRecord::deserialize(raw).ok_or_else(|| WalError::Other("malformed record".to_string()))
+}
+
+/// The files of the space a replayed record writes to.
+fn replay_target(
+    file_pools: &[Option<Arc<FilePool>>; 255],
+    space_id: SpaceId,
+) -> Result<&Arc<FilePool>, WalError> {
+    file_pools
+        .get(space_id as usize)
+        .and_then(Option::as_ref)
+        .ok_or_else(|| WalError::Other(format!("record for unknown space {space_id}")))
+}
+
 /// Initialize the Wal subsystem if it does not exists and attempts to replay the Wal if exists.
 async fn init_wal(
     file_pools: &Rc<RefCell<[Option<Arc<FilePool>>; 255]>>,
     store: WalStoreImpl,
     loader: WalLoader,
     max_revisions: u32,
-    final_path: &Path,
 ) -> Result<Rc<Mutex<WalWriter<WalFileImpl, WalStoreImpl>>>, WalError> {
     let wal = loader
         .load(
             store,
             |raw, _| {
-                let batch = AshRecord::deserialize(raw);
+                let batch = decode_record(raw)?;
 
                 for (space_id, ash) in batch.0 {
+                    let file_pools = file_pools.borrow();
+                    let file_pool = replay_target(&file_pools, space_id)?;
+
+                    let file_nbit = file_pool.get_file_nbit();
+                    let file_mask = (1 << file_nbit) - 1;
+
                     for (undo, redo) in ash.iter() {
-                        let offset = undo.offset;
-                        let file_pools = file_pools.borrow();
-                        let file_pool = file_pools[space_id as usize].as_ref().unwrap();
-                        let file_nbit = file_pool.get_file_nbit();
-                        let file_mask = (1 << file_nbit) - 1;
-                        let fid = offset >> file_nbit;
-
-                        nix::sys::uio::pwrite(
-                            file_pool
-                                .get_file(fid)
-                                .map_err(|e| {
-                                    WalError::Other(format!(
-                                        "file pool error: {:?} - final path {:?}",
-                                        e, final_path
-                                    ))
-                                })?
-                                .as_fd(),
-                            &redo.data,
-                            (offset & file_mask) as nix::libc::off_t,
-                        )
-                        .map_err(|e| {
-                            WalError::Other(format!(
-                                "wal loader error: {:?} - final path {:?}",
-                                e, final_path
-                            ))
-                        })?;
+                        let mut offset = undo.offset;
+                        let mut data = &redo.data[..];
+
+                        // a write never spills over the end of a file
+                        while !data.is_empty() {
+                            let fid = offset >> file_nbit;
+                            let file_offset = offset & file_mask;
+                            let len = data.len().min(((1 << file_nbit) - file_offset) as usize);
+
+                            nix::sys::uio::pwrite(
+                                file_pool
+                                    .get_file(fid)
+                                    .map_err(|e| WalError::Other(format!("file pool error: {e}")))?
+                                    .as_fd(),
+                                &data[..len],
+                                file_offset as nix::libc::off_t,
+                            )?;
+
+                            offset += len as u64;
+                            data = &data[len..];
+                        }
                     }
                 }
 
@@ -335,6 +391,92 @@ async fn init_wal(
     Ok(Rc::new(Mutex::new(wal)))
 }
 
+/// Replay the Wal of a read-only database into in-memory pages, and keep the most recent
+/// `max_revisions` records.
+async fn peek_wal(
+    file_pools: &Rc<RefCell<[Option<Arc<FilePool>>; 255]>>,
+    store: WalStoreImpl,
+    loader: WalLoader,
+    max_revisions: u32,
+) -> Result<Wal, WalError> {
+    use std::collections::hash_map::Entry::*;
+
+    let mut records = VecDeque::new();
+    let mut pages: HashMap<(SpaceId, u64), Page> = HashMap::new();
+
+    loader
+        .peek(store, |raw, _| {
+            let batch = decode_record(raw.clone())?;
+
+            for (space_id, ash) in batch.0 {
+                let file_pools = file_pools.borrow();
+                let file_pool = replay_target(&file_pools, space_id)?;
+
+                for (undo, redo) in ash.iter() {
+                    let mut offset = undo.offset;
+                    let mut data = &redo.data[..];
+
+                    while !data.is_empty() {
+                        let pid = offset >> PAGE_SIZE_NBIT;
+                        let page_offset = (offset & PAGE_MASK) as usize;
+                        let len = data.len().min(PAGE_SIZE as usize - page_offset);
+
+                        let page =
+                            match pages.entry((space_id, pid)) {
+                                Occupied(e) => e.into_mut(),
+                                Vacant(e) => e.insert(file_pool.read_page(pid).map_err(|e| {
+                                    WalError::Other(format!("file pool error: {e}"))
+                                })?),
+                            };
+                        page[page_offset..page_offset + len].copy_from_slice(&data[..len]);
+
+                        offset += len as u64;
+                        data = &data[len..];
+                    }
+                }
+            }
+
+            records.push_front(raw);
+            records.truncate(max_revisions as usize);
+
+            Ok(())
+        })
+        .await?;
+
+    Ok(Wal::ReadOnly {
+        records: records.into(),
+        pages,
+    })
+}
+
+/// Write the most recent `max_revisions` records of `wal` to a new Wal at `dest`, oldest first,
+/// so that the files it no longer needs are left behind.
+async fn copy_wal(
+    wal: &WalWriter<WalFileImpl, WalStoreImpl>,
+    dest: PathBuf,
+    wal_cfg: &WalConfig,
+) -> Result<(), WalError> {
+    let mut records = wal
+        .read_recent_records(wal_cfg.max_revisions as usize, &RecoverPolicy::Strict)
+        .await?;
+    records.reverse();
+
+    let mut loader = WalLoader::new();
+    loader
+        .file_nbit(wal_cfg.file_nbit)
+        .block_nbit(wal_cfg.block_nbit)
+        .recover_policy(RecoverPolicy::Strict);
+    let store = WalStoreImpl::new(dest, false)?;
+    let mut copy = loader
+        .load(store, |_, _| Ok(()), wal_cfg.max_revisions)
+        .await?;
+    for written in join_all(copy.grow(records)).await {
+        written.map_err(|_| WalError::Other("cannot write the copy of the Wal".to_string()))?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_wal_queue(
     max: WalQueueMax,
     wal: Rc<Mutex<WalWriter<WalFileImpl, WalStoreImpl>>>,
@@ -343,6 +485,7 @@ async fn run_wal_queue(
     mut writes: mpsc::Receiver<(Vec<BufferWrite>, AshRecord)>,
     fc_notifier: Rc<Notify>,
     aiomgr: Rc<AioManager>,
+    inflight: Rc<Inflight>,
 ) {
     use std::collections::hash_map::Entry::*;
 
@@ -367,6 +510,8 @@ async fn run_wal_queue(
             }
         }
 
+        let nbatches = records.len();
+
         // first write to Wal
         let ring_ids = join_all(wal.clone().lock().await.grow(records))
             .await
@@ -425,6 +570,7 @@ async fn run_wal_queue(
             }
         }
 
+        let inflight = inflight.clone();
         let task = async move {
             let _ = sem.acquire_many(npermit).await.unwrap();
 
@@ -433,7 +579,10 @@ async fn run_wal_queue(
                 .peel(ring_ids, max.revisions)
                 .await
                 .map_err(|_| "Wal errore while pruning")
-                .unwrap()
+                .unwrap();
+
+            inflight.batches.set(inflight.batches.get() - nbatches);
+            inflight.drained.notify_waiters();
         };
 
         task::spawn_local(task);
@@ -443,33 +592,24 @@ async fn run_wal_queue(
     fc_notifier.notify_one();
 }
 
-fn panic_on_intialization_failure_with<'a, T>(
-    rootpath: &'a Path,
-    waldir: &'a str,
-) -> impl Fn(WalError) -> T + 'a {
-    move |e| panic!("Initialize Wal in dir {rootpath:?} failed creating {waldir:?}: {e:?}")
-}
-
 #[allow(clippy::too_many_arguments)]
 async fn process(
     pending: Rc<RefCell<HashMap<(SpaceId, u64), PendingPage>>>,
     fc_notifier: Rc<Notify>,
     file_pools: Rc<RefCell<[Option<Arc<FilePool>>; 255]>>,
     aiomgr: Rc<AioManager>,
-    wal: &mut Option<Rc<Mutex<WalWriter<WalFileImpl, WalStoreImpl>>>>,
+    wal: &mut Option<Wal>,
     wal_cfg: &WalConfig,
     req: BufferCmd,
     max: WalQueueMax,
     wal_in: mpsc::Sender<(Vec<BufferWrite>, AshRecord)>,
     writes: &mut Option<mpsc::Receiver<(Vec<BufferWrite>, AshRecord)>>,
+    inflight: Rc<Inflight>,
 ) -> bool {
     match req {
         BufferCmd::Shutdown => return false,
-        BufferCmd::InitWal(rootpath, waldir) => {
-            let final_path = rootpath.join(&waldir);
-
-            let store = WalStoreImpl::new(final_path.clone(), false)
-                .unwrap_or_else(panic_on_intialization_failure_with(&rootpath, &waldir));
+        BufferCmd::InitWal(rootpath, waldir, tx) => {
+            let final_path = rootpath.join(waldir);
 
             let mut loader = WalLoader::new();
             loader
@@ -477,17 +617,19 @@ async fn process(
                 .block_nbit(wal_cfg.block_nbit)
                 .recover_policy(RecoverPolicy::Strict);
 
-            let initialized_wal = init_wal(
-                &file_pools,
-                store,
-                loader,
-                wal_cfg.max_revisions,
-                final_path.as_path(),
-            )
-            .await
-            .unwrap_or_else(panic_on_intialization_failure_with(&rootpath, &waldir));
+            let initialized_wal = match WalStoreImpl::new(final_path, false) {
+                Ok(store) => init_wal(&file_pools, store, loader, wal_cfg.max_revisions).await,
+                Err(e) => Err(e),
+            };
+            let initialized_wal = match initialized_wal {
+                Ok(initialized_wal) => initialized_wal,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return true;
+                }
+            };
 
-            wal.replace(initialized_wal.clone());
+            wal.replace(Wal::Writer(initialized_wal.clone()));
 
             let writes = writes.take().unwrap();
 
@@ -499,36 +641,70 @@ async fn process(
                 writes,
                 fc_notifier,
                 aiomgr,
+                inflight,
             );
 
             task::spawn_local(task);
+            let _ = tx.send(Ok(()));
+        }
+        BufferCmd::PeekWal(rootpath, waldir, tx) => {
+            let final_path = rootpath.join(waldir);
+
+            let mut loader = WalLoader::new();
+            loader
+                .file_nbit(wal_cfg.file_nbit)
+                .block_nbit(wal_cfg.block_nbit)
+                // the record being written by the live writer, if any, ends the Wal
+                .recover_policy(RecoverPolicy::BestEffort);
+
+            let recovered = match WalStoreImpl::new_read_only(final_path) {
+                Ok(store) => peek_wal(&file_pools, store, loader, wal_cfg.max_revisions).await,
+                Err(e) => Err(e),
+            };
+            let _ = tx.send(recovered.map(|recovered| {
+                wal.replace(recovered);
+            }));
+        }
+        BufferCmd::GetPage(page_key, tx) => {
+            let page = pending
+                .borrow()
+                .get(&page_key)
+                .map(|e| e.staging_data.clone())
+                .or_else(|| match wal {
+                    Some(Wal::ReadOnly { pages, .. }) => pages.get(&page_key).cloned(),
+                    _ => None,
+                });
+            tx.send(page).unwrap()
         }
-        BufferCmd::GetPage(page_key, tx) => tx
-            .send(
-                pending
-                    .borrow()
-                    .get(&page_key)
-                    .map(|e| e.staging_data.clone()),
-            )
-            .unwrap(),
         BufferCmd::WriteBatch(writes, wal_writes) => {
+            inflight.batches.set(inflight.batches.get() + 1);
             wal_in.send((writes, wal_writes)).await.unwrap();
         }
         BufferCmd::CollectAsh(nrecords, tx) => {
-            // wait to ensure writes are paused for Wal
-            let ash = wal
-                .as_ref()
-                .unwrap()
-                .lock()
-                .await
-                .read_recent_records(nrecords, &RecoverPolicy::Strict)
-                .await
-                .unwrap()
+            let records = match wal.as_ref().unwrap() {
+                // wait to ensure writes are paused for Wal
+                Wal::Writer(wal) => wal
+                    .lock()
+                    .await
+                    .read_recent_records(nrecords, &RecoverPolicy::Strict)
+                    .await
+                    .unwrap(),
+                Wal::ReadOnly { records, .. } => records.iter().take(nrecords).cloned().collect(),
+            };
+            // the window ends at the first record that cannot be decoded
+            let ash = records
                 .into_iter()
-                .map(AshRecord::deserialize)
+                .map_while(AshRecord::deserialize)
                 .collect();
             tx.send(ash).unwrap();
         }
+        BufferCmd::CopyWal(dest, tx) => {
+            let copied = match wal.as_ref().unwrap() {
+                Wal::Writer(wal) => copy_wal(&*wal.lock().await, dest, wal_cfg).await,
+                Wal::ReadOnly { .. } => Err(WalError::Other("the Wal is read-only".to_string())),
+            };
+            let _ = tx.send(copied);
+        }
         BufferCmd::RegCachedSpace(space_id, files) => {
             file_pools
                 .borrow_mut()
@@ -536,6 +712,23 @@ async fn process(
                 .index_mut(space_id as usize)
                 .replace(files);
         }
+        BufferCmd::Drain(tx) => {
+            let task = async move {
+                loop {
+                    let drained = inflight.drained.notified();
+                    tokio::pin!(drained);
+                    // register for notifications before checking, so none is missed
+                    drained.as_mut().enable();
+                    if inflight.batches.get() == 0 {
+                        break;
+                    }
+                    drained.await;
+                }
+                let _ = tx.send(());
+            };
+
+            task::spawn_local(task);
+        }
     }
 
     true
@@ -587,15 +780,39 @@ impl DiskBufferRequester {
         self.sender.blocking_send(BufferCmd::Shutdown).ok().unwrap()
     }
 
-    /// Initialize the Wal.
-    pub fn init_wal(&self, waldir: &str, rootpath: &Path) {
-        self.sender
-            .blocking_send(BufferCmd::InitWal(
-                rootpath.to_path_buf(),
-                waldir.to_string(),
-            ))
-            .map_err(StoreError::Send)
-            .ok();
+    /// Initialize the Wal, replaying it onto the registered spaces.
+    pub fn init_wal(&self, waldir: &str, rootpath: &Path) -> Result<(), WalError> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send_wal_cmd(
+            BufferCmd::InitWal(rootpath.to_path_buf(), waldir.to_string(), resp_tx),
+            resp_rx,
+        )
+    }
+
+    /// Recover the state of a read-only database from its Wal, in memory only.
+    pub fn peek_wal(&self, waldir: &str, rootpath: &Path) -> Result<(), WalError> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send_wal_cmd(
+            BufferCmd::PeekWal(rootpath.to_path_buf(), waldir.to_string(), resp_tx),
+            resp_rx,
+        )
+    }
+
+    fn send_wal_cmd(
+        &self,
+        cmd: BufferCmd,
+        resp_rx: oneshot::Receiver<Result<(), WalError>>,
+    ) -> Result<(), WalError> {
+        let stopped = || WalError::Other("the disk buffer has stopped".to_string());
+        self.sender.blocking_send(cmd).map_err(|_| stopped())?;
+        resp_rx.blocking_recv().map_err(|_| stopped())?
+    }
+
+    /// Write the records of the Wal that are in the window of past revisions to a new Wal in
+    /// `dest`, which is created if it does not exist.
+    pub fn copy_wal(&self, dest: &Path) -> Result<(), WalError> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.send_wal_cmd(BufferCmd::CopyWal(dest.to_path_buf(), resp_tx), resp_rx)
     }
 
     /// Collect the last N records from the Wal.
@@ -608,6 +825,17 @@ impl DiskBufferRequester {
         resp_rx.blocking_recv().map_err(StoreError::Receive)
     }
 
+    /// Wait until every batch of writes sent so far has been written to the Wal and to the
+    /// files of its space.
+    pub fn drain(&self) -> Result<(), StoreError<RecvError>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.sender
+            .blocking_send(BufferCmd::Drain(resp_tx))
+            .map_err(StoreError::Send)
+            .ok();
+        resp_rx.blocking_recv().map_err(StoreError::Receive)
+    }
+
     /// Register a cached space to the buffer.
     pub fn reg_cached_space(&self, space_id: SpaceId, files: Arc<FilePool>) {
         self.sender
@@ -659,7 +887,7 @@ mod tests {
         let state_path = file::touch_dir("state", &root_db_path).unwrap();
         assert!(reset);
         // create a new wal directory on top of root_db_fd
-        disk_requester.init_wal("wal", &root_db_path);
+        disk_requester.init_wal("wal", &root_db_path).unwrap();
 
         // create a new state cache which tracks on disk state.
         let state_cache = Arc::new(
@@ -747,7 +975,7 @@ mod tests {
         let state_path = file::touch_dir("state", &root_db_path).unwrap();
         assert!(reset);
         // create a new wal directory on top of root_db_fd
-        disk_requester.init_wal("wal", &root_db_path);
+        disk_requester.init_wal("wal", &root_db_path).unwrap();
 
         // create a new state cache which tracks on disk state.
         let state_cache = Arc::new(
@@ -832,7 +1060,7 @@ mod tests {
         let state_path = file::touch_dir("state", &root_db_path).unwrap();
         assert!(reset);
         // create a new wal directory on top of root_db_fd
-        disk_requester.init_wal("wal", &root_db_path);
+        disk_requester.init_wal("wal", &root_db_path).unwrap();
 
         // create a new state cache which tracks on disk state.
         let state_cache = Arc::new(
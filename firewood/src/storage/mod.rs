@@ -10,6 +10,7 @@ use shale::{CachedStore, CachedView, SendSyncDerefMut, SpaceId};
 use std::{
     collections::HashMap,
     fmt::{self, Debug},
+    io::ErrorKind,
     num::NonZeroUsize,
     ops::{Deref, DerefMut},
     os::fd::{AsFd, AsRawFd},
@@ -72,6 +73,16 @@ pub struct Ash {
     pub redo: Vec<SpaceWrite>,
 }
 
+impl SpaceWrite {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
 impl Ash {
     fn iter(&self) -> impl Iterator<Item = (&SpaceWrite, &SpaceWrite)> {
         self.undo.iter().zip(self.redo.iter())
@@ -117,39 +128,44 @@ impl growthring::wal::Record for AshRecord {
 
 impl AshRecord {
     #[allow(clippy::boxed_local)]
-    fn deserialize(raw: growthring::wal::WalBytes) -> Self {
+    /// Decode a record as it is written to the WAL, or `None` if it is malformed.
+    pub(crate) fn deserialize(raw: growthring::wal::WalBytes) -> Option<Self> {
+        fn take<'a>(r: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if r.len() < len {
+                return None;
+            }
+            let (head, tail) = r.split_at(len);
+            *r = tail;
+            Some(head)
+        }
+
         let mut r = &raw[..];
-        let len = u64::from_le_bytes(r[..8].try_into().unwrap());
-        r = &r[8..];
+        let len = u64::from_le_bytes(take(&mut r, 8)?.try_into().ok()?);
         let writes = (0..len)
             .map(|_| {
-                let space_id = u8::from_le_bytes(r[..1].try_into().unwrap());
-                let wlen = u32::from_le_bytes(r[1..5].try_into().unwrap());
-                r = &r[5..];
+                let space_id = take(&mut r, 1)?[0];
+                let wlen = u32::from_le_bytes(take(&mut r, 4)?.try_into().ok()?);
                 let mut undo = Vec::new();
                 let mut redo = Vec::new();
                 for _ in 0..wlen {
-                    let offset = u64::from_le_bytes(r[..8].try_into().unwrap());
-                    let data_len = u64::from_le_bytes(r[8..16].try_into().unwrap());
-                    r = &r[16..];
+                    let offset = u64::from_le_bytes(take(&mut r, 8)?.try_into().ok()?);
+                    let data_len = u64::from_le_bytes(take(&mut r, 8)?.try_into().ok()?);
+                    let data_len = usize::try_from(data_len).ok()?;
                     let undo_write = SpaceWrite {
                         offset,
-                        data: r[..data_len as usize].into(),
+                        data: take(&mut r, data_len)?.into(),
                     };
-                    r = &r[data_len as usize..];
-                    // let new_data: Box<[u8]> = r[..data_len as usize].into();
                     let redo_write = SpaceWrite {
                         offset,
-                        data: r[..data_len as usize].into(),
+                        data: take(&mut r, data_len)?.into(),
                     };
-                    r = &r[data_len as usize..];
                     undo.push(undo_write);
                     redo.push(redo_write);
                 }
-                (space_id, Ash { undo, redo })
+                Some((space_id, Ash { undo, redo }))
             })
-            .collect();
-        Self(writes)
+            .collect::<Option<_>>()?;
+        Some(Self(writes))
     }
 }
 
@@ -362,6 +378,11 @@ impl StoreRevShared {
     pub fn inner(&self) -> &Arc<StoreRev> {
         &self.0
     }
+
+    /// Bytes held by the delta pages of this revision.
+    pub fn delta_size(&self) -> u64 {
+        self.0.delta.len() as u64 * PAGE_SIZE
+    }
 }
 
 impl CachedStore for StoreRevShared {
@@ -703,6 +724,9 @@ pub struct StoreConfig {
     file_nbit: u64,
     space_id: SpaceId,
     rootdir: PathBuf,
+    /// Open the files without write access or locking, and never create them.
+    #[builder(default = false)]
+    read_only: bool,
 }
 
 #[derive(Debug)]
@@ -775,22 +799,7 @@ impl CachedSpaceInner {
                     .or_else(|| self.disk_requester.get_page(space_id, pid));
                 let mut page = match page {
                     Some(page) => page,
-                    None => {
-                        let file_nbit = self.files.get_file_nbit();
-                        let file_size = 1 << file_nbit;
-                        let poff = pid << PAGE_SIZE_NBIT;
-                        let file = self.files.get_file(poff >> file_nbit)?;
-                        let mut page: Page = Page::new([0; PAGE_SIZE as usize]);
-
-                        nix::sys::uio::pread(
-                            file.as_fd(),
-                            page.deref_mut(),
-                            (poff & (file_size - 1)) as nix::libc::off_t,
-                        )
-                        .map_err(StoreError::System)?;
-
-                        page
-                    }
+                    None => self.files.read_page(pid)?,
                 };
 
                 let ptr = page.as_mut_ptr();
@@ -891,6 +900,7 @@ pub struct FilePool {
     files: parking_lot::Mutex<lru::LruCache<u64, Arc<File>>>,
     file_nbit: u64,
     rootdir: PathBuf,
+    read_only: bool,
 }
 
 impl FilePool {
@@ -903,7 +913,12 @@ impl FilePool {
             )),
             file_nbit,
             rootdir: rootdir.to_path_buf(),
+            read_only: cfg.read_only,
         };
+        if s.read_only {
+            // the writer holds the lock
+            return Ok(s);
+        }
         let f0 = s.get_file(0)?;
         if flock(f0.as_raw_fd(), FlockArg::LockExclusiveNonblock).is_err() {
             return Err(StoreError::Init("the store is busy".into()));
@@ -918,7 +933,12 @@ impl FilePool {
             Some(f) => f.clone(),
             None => {
                 let file_size = 1 << self.file_nbit;
-                let file = Arc::new(File::new(fid, file_size, &self.rootdir)?);
+                let file = if self.read_only {
+                    File::open_read_only(fid, &self.rootdir)?
+                } else {
+                    File::new(fid, file_size, &self.rootdir)?
+                };
+                let file = Arc::new(file);
                 files.put(fid, file.clone());
                 file
             }
@@ -930,10 +950,38 @@ impl FilePool {
     fn get_file_nbit(&self) -> u64 {
         self.file_nbit
     }
+
+    /// Read page `pid` from its file. In a read-only pool the files that were never written
+    /// may not exist, and read as zeros.
+    fn read_page(&self, pid: u64) -> Result<Page, StoreError<std::io::Error>> {
+        let file_size = 1 << self.file_nbit;
+        let poff = pid << PAGE_SIZE_NBIT;
+        let mut page: Page = Page::new([0; PAGE_SIZE as usize]);
+
+        let file = match self.get_file(poff >> self.file_nbit) {
+            Ok(file) => file,
+            Err(StoreError::Io(e)) if self.read_only && e.kind() == ErrorKind::NotFound => {
+                return Ok(page)
+            }
+            Err(e) => return Err(e),
+        };
+
+        nix::sys::uio::pread(
+            file.as_fd(),
+            page.deref_mut(),
+            (poff & (file_size - 1)) as nix::libc::off_t,
+        )
+        .map_err(StoreError::System)?;
+
+        Ok(page)
+    }
 }
 
 impl Drop for FilePool {
     fn drop(&mut self) {
+        if self.read_only {
+            return;
+        }
         let f0 = self.get_file(0).unwrap();
         flock(f0.as_raw_fd(), FlockArg::UnlockNonblock).ok();
     }
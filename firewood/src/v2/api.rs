@@ -4,6 +4,10 @@
 use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
 use async_trait::async_trait;
+use growthring::walerror::WalError;
+use shale::ShaleError;
+
+use crate::{db::DbError, merkle::MerkleError, proof::ProofError, storage::StoreError};
 
 /// A `KeyType` is something that can be xcast to a u8 reference,
 /// and can be sent and shared across threads. References with
@@ -53,6 +57,12 @@ pub fn vec_into_batch<K: KeyType, V: ValueType>(value: Vec<(K, V)>) -> Batch<K,
 }
 
 /// Errors returned through the API
+///
+/// Errors from the lower layers are wrapped rather than flattened, so
+/// [std::error::Error::source] always leads back to the original failure.
+/// Variants that describe a condition a caller can react to (an unknown
+/// hash, a stale proposal) are surfaced directly regardless of the layer
+/// that detected them.
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
@@ -65,10 +75,76 @@ pub enum Error {
     IncorrectRootHash { provided: HashKey, current: HashKey },
 
     #[error("IO error: {0}")]
-    IO(std::io::Error),
+    IO(#[from] std::io::Error),
 
     #[error("Invalid proposal")]
     InvalidProposal,
+
+    /// The request was rejected because of the parameters it was given
+    #[error("Invalid parameters")]
+    InvalidParams,
+
+    /// An error from the persisted database
+    #[error("Database error: {0}")]
+    Db(#[source] DbError),
+
+    /// An error from the merkle trie
+    #[error("Merkle error: {0}")]
+    Merkle(#[source] MerkleError),
+
+    /// An error while generating or verifying a proof
+    #[error("Proof error: {0}")]
+    Proof(#[source] ProofError),
+
+    /// An error from the object store backing the trie
+    #[error("Storage error: {0}")]
+    Shale(#[from] ShaleError),
+
+    /// An error from the write-ahead log
+    #[error("WAL error: {0}")]
+    Wal(#[from] WalError),
+}
+
+impl From<DbError> for Error {
+    fn from(err: DbError) -> Self {
+        match err {
+            DbError::InvalidParams => Error::InvalidParams,
+            DbError::InvalidProposal => Error::InvalidProposal,
+            DbError::IO(e) => Error::IO(e),
+            DbError::Merkle(e) => Error::Merkle(e),
+            DbError::Shale(e) => Error::Shale(e),
+            DbError::Wal(e) => Error::Wal(e),
+            DbError::RevisionNotFound(hash) => Error::HashNotFound { provided: hash.0 },
+            e => Error::Db(e),
+        }
+    }
+}
+
+impl From<MerkleError> for Error {
+    fn from(err: MerkleError) -> Self {
+        match err {
+            MerkleError::Shale(e) => Error::Shale(e),
+            e => Error::Merkle(e),
+        }
+    }
+}
+
+impl From<ProofError> for Error {
+    fn from(err: ProofError) -> Self {
+        match err {
+            ProofError::Db(e) => e.into(),
+            e => Error::Proof(e),
+        }
+    }
+}
+
+impl<T> From<StoreError<T>> for Error
+where
+    StoreError<T>: std::error::Error + Send + Sync + 'static,
+{
+    fn from(err: StoreError<T>) -> Self {
+        DbError::from(err).into()
+    }
 }
 
 /// A range proof, consisting of a proof of the first key and the last key,
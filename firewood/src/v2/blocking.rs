@@ -0,0 +1,209 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+//! A synchronous facade over the async [api] traits.
+//!
+//! Every wrapper holds a shared handle to a runtime owned by the [Db], and
+//! each call blocks the calling thread until the underlying future resolves.
+//! These calls must not be made from within an async context, as blocking
+//! inside a runtime panics.
+
+use std::{ops::Deref, sync::Arc};
+
+use tokio::runtime::{Builder, Runtime};
+
+use super::api::{self, Batch, Error, HashKey, KeyType, ValueType};
+
+/// A blocking wrapper around any [api::Db] implementation
+#[derive(Debug)]
+pub struct Db<T> {
+    db: T,
+    runtime: Arc<Runtime>,
+}
+
+impl<T: api::Db> Db<T> {
+    /// Wrap `db` with a new single-threaded runtime owned by the returned handle
+    pub fn new(db: T) -> Result<Self, Error> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        Ok(Self::with_runtime(db, Arc::new(runtime)))
+    }
+
+    /// Wrap `db` using an existing runtime, which may be shared with other handles
+    pub fn with_runtime(db: T, runtime: Arc<Runtime>) -> Self {
+        Self { db, runtime }
+    }
+
+    /// Get a view of a specific revision, see [api::Db::revision]
+    pub fn revision(&self, hash: HashKey) -> Result<DbView<T::Historical>, Error> {
+        let view = self.runtime.block_on(self.db.revision(hash))?;
+        Ok(DbView::new(view, self.runtime.clone()))
+    }
+
+    /// Get the hash of the most recently committed version, see [api::Db::root_hash]
+    pub fn root_hash(&self) -> Result<HashKey, Error> {
+        self.runtime.block_on(self.db.root_hash())
+    }
+
+    /// Propose a batch against the latest committed revision, see [api::Db::propose]
+    pub fn propose<K: KeyType, V: ValueType>(
+        &self,
+        data: Batch<K, V>,
+    ) -> Result<Proposal<T::Proposal>, Error> {
+        let proposal = self.runtime.block_on(self.db.propose(data))?;
+        Ok(Proposal(DbView::new(
+            Arc::new(proposal),
+            self.runtime.clone(),
+        )))
+    }
+
+    /// Get the wrapped async database
+    pub fn inner(&self) -> &T {
+        &self.db
+    }
+}
+
+/// A blocking wrapper around an [api::DbView]
+#[derive(Debug)]
+pub struct DbView<V> {
+    view: Arc<V>,
+    runtime: Arc<Runtime>,
+}
+
+// Implement Clone because V doesn't need to be Clone
+// so an automatically derived Clone won't work
+impl<V> Clone for DbView<V> {
+    fn clone(&self) -> Self {
+        Self {
+            view: self.view.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl<V: api::DbView> DbView<V> {
+    fn new(view: Arc<V>, runtime: Arc<Runtime>) -> Self {
+        Self { view, runtime }
+    }
+
+    /// See [api::DbView::root_hash]
+    pub fn root_hash(&self) -> Result<HashKey, Error> {
+        self.runtime.block_on(self.view.root_hash())
+    }
+
+    /// See [api::DbView::val]
+    pub fn val<K: KeyType>(&self, key: K) -> Result<Option<&[u8]>, Error> {
+        self.runtime.block_on(self.view.val(key))
+    }
+
+    /// See [api::DbView::single_key_proof]
+    pub fn single_key_proof<K: KeyType, N: ValueType>(
+        &self,
+        key: K,
+    ) -> Result<Option<api::Proof<N>>, Error> {
+        self.runtime.block_on(self.view.single_key_proof(key))
+    }
+
+    /// See [api::DbView::range_proof]
+    pub fn range_proof<K: KeyType, V2, N>(
+        &self,
+        first_key: Option<K>,
+        last_key: Option<K>,
+        limit: usize,
+    ) -> Result<Option<api::RangeProof<K, V2, N>>, Error> {
+        self.runtime
+            .block_on(self.view.range_proof(first_key, last_key, limit))
+    }
+
+    /// Get the wrapped async view
+    pub fn inner(&self) -> &Arc<V> {
+        &self.view
+    }
+}
+
+/// A blocking wrapper around an [api::Proposal]. All of the read
+/// operations of [DbView] are available through [Deref].
+#[derive(Debug)]
+pub struct Proposal<P>(DbView<P>);
+
+impl<P> Deref for Proposal<P> {
+    type Target = DbView<P>;
+
+    fn deref(&self) -> &DbView<P> {
+        &self.0
+    }
+}
+
+impl<P: api::DbView> Proposal<P> {
+    /// Propose a new revision on top of this one, see [api::Proposal::propose]
+    pub fn propose<T, K, V>(&self, data: Batch<K, V>) -> Result<Proposal<P::Proposal>, Error>
+    where
+        P: api::Proposal<T>,
+        T: api::DbView,
+        K: KeyType,
+        V: ValueType,
+    {
+        let proposal = self.0.runtime.block_on(self.0.view.clone().propose(data))?;
+        Ok(Proposal(DbView::new(
+            Arc::new(proposal),
+            self.0.runtime.clone(),
+        )))
+    }
+
+    /// Commit this proposal, see [api::Proposal::commit]
+    pub fn commit<T>(self) -> Result<DbView<T>, Error>
+    where
+        P: api::Proposal<T>,
+        T: api::DbView,
+    {
+        let DbView { view, runtime } = self.0;
+        let committed = runtime.block_on(view.commit())?;
+        Ok(DbView::new(committed, runtime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2::{api::BatchOp, emptydb::EmptyDb};
+
+    #[test]
+    fn blocking_proposal() -> Result<(), Error> {
+        let db = Db::new(EmptyDb)?;
+
+        assert_eq!(db.root_hash()?, [0; 32]);
+        let revision = db.revision(db.root_hash()?)?;
+        assert!(revision.val(b"k")?.is_none());
+
+        let proposal = db.propose(vec![
+            BatchOp::Put {
+                key: b"k",
+                value: b"v",
+            },
+            BatchOp::Delete { key: b"z" },
+        ])?;
+        assert_eq!(proposal.val(b"k")?.unwrap(), b"v");
+        assert!(proposal.val(b"z")?.is_none());
+
+        let nested = proposal.propose(vec![BatchOp::Put {
+            key: b"z",
+            value: b"undo",
+        }])?;
+        assert_eq!(nested.val(b"k")?.unwrap(), b"v");
+        assert_eq!(nested.val(b"z")?.unwrap(), b"undo");
+
+        let committed = nested.commit()?;
+        assert_eq!(committed.root_hash()?, [0; 32]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocking_missing_revision() -> Result<(), Error> {
+        let db = Db::new(EmptyDb)?;
+
+        let err = db.revision([1; 32]).unwrap_err();
+        assert!(matches!(err, Error::HashNotFound { provided } if provided == [1; 32]));
+
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 // See the file LICENSE.md for licensing terms.
 
 pub mod api;
+pub mod blocking;
 pub mod db;
 pub mod propose;
 
@@ -2,7 +2,10 @@
 // See the file LICENSE.md for licensing terms.
 
 use firewood::{
-    db::{BatchOp, Db as PersistedDb, DbConfig, DbError, WalConfig},
+    db::{
+        BatchOp, BranchFactor, Db as PersistedDb, DbConfig, DbError, DbRevConfig, TrieEncoding,
+        WalConfig, MAX_METADATA_LEN,
+    },
     merkle::TrieHash,
 };
 
@@ -18,7 +21,7 @@ use std::{
 macro_rules! kv_dump {
     ($e: ident) => {{
         let mut s = Vec::new();
-        $e.kv_root_hash().unwrap();
+        $e.kv_root_hash(None).unwrap();
         $e.kv_dump(&mut s).unwrap();
         String::from_utf8(s).unwrap()
     }};
@@ -124,7 +127,7 @@ fn test_revisions() {
                 dumped.pop_back();
                 hashes.pop_back();
             }
-            let root_hash = db.kv_root_hash().unwrap();
+            let root_hash = db.kv_root_hash(None).unwrap();
             hashes.push_front(root_hash);
             dumped.push_front(kv_dump!(db));
             dumped
@@ -157,6 +160,1193 @@ fn test_revisions() {
     }
 }
 
+#[test]
+fn get_revision_by_unknown_root_hash() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let db = Db::new("test_unknown_root_hash", &cfg.truncate(true).build()).unwrap();
+
+    let mut hashes = Vec::new();
+    for i in 0..5u8 {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        hashes.push(db.kv_root_hash(None).unwrap());
+    }
+
+    assert!(db.get_revision(&TrieHash([0xff; 32])).is_none());
+    // only the last `max_revisions` commits are retained
+    assert!(db.get_revision(&hashes[1]).is_none());
+    for hash in &hashes[2..] {
+        let rev = db.get_revision(hash).unwrap();
+        assert_eq!(&rev.kv_root_hash(None).unwrap(), hash);
+    }
+}
+
+#[test]
+fn pinned_revision_outlives_window() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let db = Db::new("test_pinned_revision", &cfg.truncate(true).build()).unwrap();
+
+    let commit = |i: u8| {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        db.kv_root_hash(None).unwrap()
+    };
+
+    let unpinned = commit(0);
+    let pinned = commit(1);
+    let pin = db.pin_revision(&pinned).unwrap();
+    assert_eq!(pin.root_hash(), &pinned);
+    assert!(db.pin_revision(&TrieHash([0xff; 32])).is_none());
+
+    for i in 2..8 {
+        commit(i);
+    }
+
+    assert!(db.get_revision(&unpinned).is_none());
+    let rev = db.get_revision(&pinned).unwrap();
+    assert_eq!(rev.kv_root_hash(None).unwrap(), pinned);
+    assert_eq!(rev.kv_get([1]).unwrap(), vec![1]);
+    assert!(rev.kv_get([2]).is_none());
+    drop(rev);
+
+    let stats = db.pin_stats();
+    assert_eq!(stats.pins, 1);
+    assert!(stats.retained_revisions > 0);
+    assert!(stats.retained_bytes > 0);
+    assert_eq!(db.metrics().pin_revision.hit_count.get(), 2);
+
+    drop(pin);
+    assert!(db.get_revision(&pinned).is_none());
+    assert_eq!(db.pin_stats(), Default::default());
+}
+
+#[test]
+fn pinned_revisions_are_released_over_the_limit() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .max_pinned_bytes(0)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let db = Db::new("test_pinned_revision_limit", &cfg.truncate(true).build()).unwrap();
+
+    let batch = vec![BatchOp::Put {
+        key: b"k",
+        value: b"v".to_vec(),
+    }];
+    db.new_proposal(batch).unwrap().commit().unwrap();
+    let pinned = db.kv_root_hash(None).unwrap();
+    let _pin = db.pin_revision(&pinned).unwrap();
+
+    for i in 0..5u8 {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+    }
+
+    assert!(db.get_revision(&pinned).is_none());
+    let stats = db.pin_stats();
+    assert_eq!(stats.pins, 1);
+    assert_eq!(stats.retained_bytes, 0);
+    assert_eq!(stats.forced_releases, 1);
+}
+
+#[test]
+fn archival_db_reconstructs_every_revision() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let db = PersistedDb::new(
+        "test_archival_db",
+        &cfg.clone().archival(true).truncate(true).build(),
+    )
+    .unwrap();
+
+    let commit = |db: &PersistedDb, i: u8| {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch)
+            .unwrap()
+            .commit_with_metadata(&[b'h', i])
+            .unwrap();
+        db.kv_root_hash(None).unwrap()
+    };
+    let check = |db: &PersistedDb, hashes: &[TrieHash]| {
+        for (i, hash) in hashes.iter().enumerate() {
+            let rev = db.get_revision(hash).unwrap();
+            assert_eq!(&rev.kv_root_hash(None).unwrap(), hash);
+            assert_eq!(rev.kv_get([i as u8]).unwrap(), vec![i as u8]);
+            assert!(rev.kv_get([i as u8 + 1]).is_none());
+
+            // so is the metadata of every commit
+            assert_eq!(db.revision_metadata(hash).unwrap(), [b'h', i as u8]);
+            let rev = db.get_revision_by_metadata(&[b'h', i as u8]).unwrap();
+            assert_eq!(&rev.kv_root_hash(None).unwrap(), hash);
+            let rev = db
+                .get_revision_by_metadata_prefix(&[b'h', i as u8])
+                .unwrap();
+            assert_eq!(&rev.kv_root_hash(None).unwrap(), hash);
+        }
+    };
+
+    let mut hashes: Vec<_> = (0..10).map(|i| commit(&db, i)).collect();
+    check(&db, &hashes);
+
+    // a reconstructed revision stays valid across later commits
+    let oldest = db.get_revision(&hashes[0]).unwrap();
+    hashes.push(commit(&db, 10));
+    assert_eq!(oldest.kv_get([0]).unwrap(), vec![0]);
+    assert!(oldest.kv_get([1]).is_none());
+    drop(oldest);
+
+    // the history survives reopening the database, which stays archival without asking
+    drop(db);
+    let db = Db::new("test_archival_db", &cfg.truncate(false).build()).unwrap();
+    check(&db, &hashes);
+    hashes.push(commit(&db, 11));
+    check(&db, &hashes);
+}
+
+#[test]
+fn archival_db_reopens_with_a_commit_missing_from_the_wal() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .archival(true)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let commit = |db: &PersistedDb, i: u8| {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        db.kv_root_hash(None).unwrap()
+    };
+
+    // the history of a DB with one commit, put in a DB without any, is what a crash leaves
+    // behind when the first commit reaches the history but not the WAL
+    let ahead = Db::new("test_history_ahead", &cfg.clone().truncate(true).build()).unwrap();
+    let lost = commit(&ahead, 0);
+    let db = PersistedDb::new("test_history_behind", &cfg.clone().truncate(true).build()).unwrap();
+    drop(db);
+    for file in ["index", "log"] {
+        std::fs::copy(
+            Path::new("test_history_ahead/history").join(file),
+            Path::new("test_history_behind/history").join(file),
+        )
+        .unwrap();
+    }
+    drop(ahead);
+
+    let db = Db::new("test_history_behind", &cfg.build()).unwrap();
+    assert!(db.get_revision(&lost).is_none());
+    let hashes: Vec<_> = (1..6).map(|i| commit(&db, i)).collect();
+    let rev = db.get_revision(&hashes[0]).unwrap();
+    assert_eq!(rev.kv_get([1]).unwrap(), vec![1]);
+    assert!(rev.kv_get([0]).is_none());
+}
+
+#[test]
+fn revision_by_commit_metadata() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(3).build());
+    let db = Db::new("test_commit_metadata", &cfg.truncate(true).build()).unwrap();
+
+    let mut hashes = Vec::new();
+    for height in 0..5u64 {
+        let batch = vec![BatchOp::Put {
+            key: height.to_be_bytes(),
+            value: vec![1],
+        }];
+        let metadata = [&height.to_be_bytes()[..], b"block hash"].concat();
+        db.new_proposal(batch)
+            .unwrap()
+            .commit_with_metadata(&metadata)
+            .unwrap();
+        hashes.push(db.kv_root_hash(None).unwrap());
+    }
+
+    let metadata = |height: u64| [&height.to_be_bytes()[..], b"block hash"].concat();
+    let rev = db.get_revision_by_metadata(&metadata(3)).unwrap();
+    assert_eq!(rev.kv_root_hash(None).unwrap(), hashes[3]);
+    assert!(rev.kv_get(4u64.to_be_bytes()).is_none());
+    assert_eq!(db.revision_metadata(&hashes[4]).unwrap(), metadata(4));
+
+    // only the whole metadata matches, unless a prefix such as the height alone is asked for
+    assert!(db.get_revision_by_metadata(&3u64.to_be_bytes()).is_none());
+    assert!(db.get_revision_by_metadata(&[]).is_none());
+    let rev = db
+        .get_revision_by_metadata_prefix(&3u64.to_be_bytes())
+        .unwrap();
+    assert_eq!(rev.kv_root_hash(None).unwrap(), hashes[3]);
+    assert!(db
+        .get_revision_by_metadata_prefix(&5u64.to_be_bytes())
+        .is_none());
+    assert!(db.get_revision_by_metadata_prefix(&[]).is_none());
+
+    // only retained revisions can be found
+    assert!(db.get_revision_by_metadata(&metadata(1)).is_none());
+    assert!(db.revision_metadata(&hashes[1]).is_none());
+
+    let batch = vec![BatchOp::Delete {
+        key: 0u64.to_be_bytes(),
+    }];
+    db.new_proposal(batch).unwrap().commit().unwrap();
+    assert_eq!(
+        db.revision_metadata(&db.kv_root_hash(None).unwrap())
+            .unwrap(),
+        Vec::<u8>::new()
+    );
+
+    let proposal = db.new_proposal::<Vec<u8>>(vec![]).unwrap();
+    assert!(matches!(
+        proposal.commit_with_metadata(&[0; MAX_METADATA_LEN + 1]),
+        Err(DbError::MetadataTooLarge)
+    ));
+}
+
+#[test]
+fn rollback_to_retained_revision() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new("test_rollback", &cfg.clone().truncate(true).build()).unwrap();
+
+    let mut hashes = Vec::new();
+    for i in 0..5u8 {
+        let batch = vec![
+            BatchOp::Put {
+                key: [i],
+                value: vec![i],
+            },
+            BatchOp::Delete { key: [0] },
+        ];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        hashes.push(db.kv_root_hash(None).unwrap());
+    }
+    let stale = db
+        .new_proposal(vec![BatchOp::Put {
+            key: b"k",
+            value: b"v".to_vec(),
+        }])
+        .unwrap();
+
+    db.rollback_to(&hashes[2]).unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), hashes[2]);
+    assert_eq!(db.kv_get([2]).unwrap(), vec![2]);
+    assert!(matches!(db.kv_get([3]), Err(DbError::KeyNotFound)));
+    // the rolled back revisions are still readable
+    let rev = db.get_revision(&hashes[4]).unwrap();
+    assert_eq!(rev.kv_get([4]).unwrap(), vec![4]);
+    drop(rev);
+
+    assert!(matches!(stale.commit(), Err(DbError::InvalidProposal)));
+    drop(stale);
+    assert!(matches!(
+        db.rollback_to(&TrieHash([0xff; 32])),
+        Err(DbError::RevisionNotFound(_))
+    ));
+
+    db.new_proposal(vec![BatchOp::Put {
+        key: [5],
+        value: vec![5],
+    }])
+    .unwrap()
+    .commit()
+    .unwrap();
+    db.rollback_to(&hashes[2]).unwrap();
+
+    // the rollback is durable
+    drop(db);
+    let db = Db::new("test_rollback", &cfg.truncate(false).build()).unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), hashes[2]);
+    assert!(matches!(db.kv_get([5]), Err(DbError::KeyNotFound)));
+
+    // only the last `max_revisions` commits can be rolled back to
+    assert!(matches!(
+        db.rollback_to(&hashes[0]),
+        Err(DbError::RevisionNotFound(hash)) if hash == hashes[0]
+    ));
+}
+
+#[test]
+fn keyspaces_commit_together() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new("test_keyspaces", &cfg.clone().truncate(true).build()).unwrap();
+    let put = |keyspace: &str, key: &'static [u8]| BatchOp::KeyspacePut {
+        keyspace: keyspace.to_string(),
+        key,
+        value: key.to_vec(),
+    };
+
+    db.new_proposal(vec![
+        BatchOp::Put {
+            key: &b"k"[..],
+            value: b"v".to_vec(),
+        },
+        put("state", b"a"),
+        put("receipts", b"b"),
+    ])
+    .unwrap()
+    .commit()
+    .unwrap();
+    let kv_root_hash = db.kv_root_hash(None).unwrap();
+    let first = db.root_hash().unwrap();
+    assert_ne!(first, kv_root_hash);
+    assert_eq!(db.keyspaces().unwrap(), ["receipts", "state"]);
+    assert_eq!(db.kv_get(b"k").unwrap(), b"v");
+    assert_eq!(db.keyspace_get("state", b"a").unwrap(), b"a");
+    assert!(matches!(
+        db.keyspace_get("state", b"b"),
+        Err(DbError::KeyNotFound)
+    ));
+    assert!(matches!(
+        db.kv_root_hash(Some("chain")),
+        Err(DbError::KeyspaceNotFound(_))
+    ));
+
+    // each keyspace has the root hash of a trie holding its keys alone
+    let single =
+        PersistedDb::new("test_keyspaces_single", &cfg.clone().truncate(true).build()).unwrap();
+    single
+        .new_proposal(vec![BatchOp::Put {
+            key: b"a",
+            value: b"a".to_vec(),
+        }])
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(
+        db.kv_root_hash(Some("state")).unwrap(),
+        single.kv_root_hash(None).unwrap()
+    );
+    drop(single);
+    remove_dir_all("test_keyspaces_single").unwrap();
+
+    // a commit to a keyspace alone makes a new revision
+    let stale = db.new_proposal(vec![put("state", b"c")]).unwrap();
+    db.new_proposal(vec![
+        put("state", b"d"),
+        BatchOp::KeyspaceDelete {
+            keyspace: "receipts".to_string(),
+            key: &b"b"[..],
+        },
+    ])
+    .unwrap()
+    .commit()
+    .unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), kv_root_hash);
+    let second = db.root_hash().unwrap();
+    assert_ne!(second, first);
+    assert!(matches!(stale.commit(), Err(DbError::InvalidProposal)));
+    drop(stale);
+
+    let rev = db.get_revision(&first).unwrap();
+    assert_eq!(rev.keyspace_get("receipts", b"b").unwrap(), b"b");
+    assert!(rev.keyspace_get("state", b"d").is_none());
+    drop(rev);
+
+    // the keyspaces are durable
+    drop(db);
+    let db = Db::new("test_keyspaces", &cfg.build()).unwrap();
+    assert_eq!(db.root_hash().unwrap(), second);
+    assert_eq!(db.keyspace_get("state", b"d").unwrap(), b"d");
+    assert!(db.keyspace_get("receipts", b"b").is_err());
+    assert!(db.new_proposal(vec![put("", b"a")]).is_err());
+}
+
+#[test]
+fn batch_is_applied_in_order() {
+    let cfg = DbConfig::builder().wal(WalConfig::builder().max_revisions(5).build());
+    let put = |key: u8, value: u8| BatchOp::Put {
+        key: vec![key],
+        value: vec![value],
+    };
+    let delete = |key: u8| BatchOp::Delete { key: vec![key] };
+    let keyspace_put = |key: u8| BatchOp::KeyspacePut {
+        keyspace: "state".to_string(),
+        key: vec![key],
+        value: vec![key],
+    };
+    let keyspace_delete = |key: u8| BatchOp::KeyspaceDelete {
+        keyspace: "state".to_string(),
+        key: vec![key],
+    };
+    let batch = || {
+        (0..64u8)
+            .map(|i| put(i, i))
+            .chain([
+                // the last change to a key wins
+                put(1, 100),
+                delete(2),
+                delete(3),
+                put(3, 103),
+                delete(200),
+                // a keyspace is created by a put even if its keys are deleted again
+                keyspace_delete(1),
+                keyspace_put(1),
+                keyspace_delete(1),
+            ])
+            .collect::<Vec<_>>()
+    };
+
+    let db = Db::new("test_batch", &cfg.clone().truncate(true).build()).unwrap();
+    db.new_proposal(batch()).unwrap().commit().unwrap();
+    let one_by_one = Db::new("test_batch_one_by_one", &cfg.truncate(true).build()).unwrap();
+    for op in batch() {
+        one_by_one.new_proposal(vec![op]).unwrap().commit().unwrap();
+    }
+
+    assert_eq!(db.root_hash().unwrap(), one_by_one.root_hash().unwrap());
+    assert_eq!(db.kv_get([1]).unwrap(), [100]);
+    assert!(db.kv_get([2]).is_err());
+    assert_eq!(db.kv_get([3]).unwrap(), [103]);
+    assert_eq!(db.keyspaces().unwrap(), ["state"]);
+    assert!(db.keyspace_get("state", [1]).is_err());
+}
+
+#[test]
+fn proposal_from_sorted_replaces_kv_trie() {
+    let cfg = DbConfig::builder().wal(WalConfig::builder().max_revisions(5).build());
+    let items = || (0..1000u32).map(|i| (i.to_be_bytes(), i.to_le_bytes().to_vec()));
+
+    let db = Db::new("test_sorted", &cfg.clone().truncate(true).build()).unwrap();
+    let old = BatchOp::Put {
+        key: 5000u32.to_be_bytes(),
+        value: vec![1],
+    };
+    db.new_proposal(vec![old]).unwrap().commit().unwrap();
+    db.new_proposal_from_sorted(items())
+        .unwrap()
+        .commit()
+        .unwrap();
+    let inserted = Db::new("test_sorted_puts", &cfg.truncate(true).build()).unwrap();
+    let batch = items()
+        .map(|(key, value)| BatchOp::Put { key, value })
+        .collect();
+    inserted.new_proposal(batch).unwrap().commit().unwrap();
+
+    // the trie has the sorted items alone
+    assert_eq!(
+        db.kv_root_hash(None).unwrap(),
+        inserted.kv_root_hash(None).unwrap()
+    );
+    assert_eq!(db.kv_get(7u32.to_be_bytes()).unwrap(), 7u32.to_le_bytes());
+    assert!(db.kv_get(5000u32.to_be_bytes()).is_err());
+
+    let unsorted = [(vec![2], vec![2]), (vec![1], vec![1])];
+    assert!(matches!(
+        db.new_proposal_from_sorted(unsorted),
+        Err(DbError::Merkle(_))
+    ));
+}
+
+#[cfg(feature = "eth")]
+#[test]
+fn accounts_with_storage() {
+    use firewood::account::{code_hash, AccountOp, U256};
+
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new("test_accounts", &cfg.clone().truncate(true).build()).unwrap();
+    let op = |address: &'static [u8], op| BatchOp::Account { address, op };
+    // storage maps words to words, like the storage of Ethereum accounts
+    let word = |byte| vec![byte; 32];
+    let set_state = |address, byte| {
+        op(
+            address,
+            AccountOp::SetState {
+                key: word(byte),
+                value: word(byte),
+            },
+        )
+    };
+
+    db.new_proposal(vec![
+        op(b"alice", AccountOp::SetNonce(3)),
+        op(b"alice", AccountOp::SetBalance(U256::from(100))),
+        op(b"alice", AccountOp::SetCode(b"code".to_vec())),
+        set_state(b"alice", b'x'),
+        set_state(b"alice", b'y'),
+        op(b"bob", AccountOp::SetBalance(U256::from(7))),
+    ])
+    .unwrap()
+    .commit()
+    .unwrap();
+
+    let alice = db.get_account(b"alice").unwrap().unwrap();
+    assert_eq!(alice.nonce, 3);
+    assert_eq!(alice.balance, U256::from(100));
+    assert_eq!(alice.code_hash, code_hash(b"code"));
+    assert_eq!(db.get_code(b"alice").unwrap(), b"code");
+    assert_eq!(
+        db.get_state(b"alice".to_vec(), word(b'x')).unwrap(),
+        word(b'x')
+    );
+    assert!(db.get_code(b"bob").unwrap().is_empty());
+    assert!(db.get_account(b"carol").unwrap().is_none());
+    assert!(matches!(
+        db.get_state(b"bob".to_vec(), word(b'x')),
+        Err(DbError::KeyNotFound)
+    ));
+
+    // the storage root is the root hash of a trie holding the storage alone
+    let single =
+        PersistedDb::new("test_accounts_single", &cfg.clone().truncate(true).build()).unwrap();
+    single
+        .new_proposal(vec![
+            BatchOp::Put {
+                key: word(b'x'),
+                value: word(b'x'),
+            },
+            BatchOp::Put {
+                key: word(b'y'),
+                value: word(b'y'),
+            },
+        ])
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(alice.storage_root, single.kv_root_hash(None).unwrap());
+    drop(single);
+    remove_dir_all("test_accounts_single").unwrap();
+
+    // accounts and storage are proven against the root hash of the generic storage
+    let kv_root_hash = db.kv_root_hash(None).unwrap();
+    let keys = [word(b'x'), word(0xff)];
+    let proof = db.prove_account(b"alice", &keys).unwrap();
+    let (account, storage) = proof.verify(b"alice", &keys, &kv_root_hash).unwrap();
+    assert_eq!(account.unwrap(), alice);
+    assert_eq!(storage, [Some(word(b'x')), None]);
+    let proof = db.prove_account(b"carol", &keys).unwrap();
+    let (account, storage) = proof.verify(b"carol", &keys, &kv_root_hash).unwrap();
+    assert!(account.is_none());
+    assert_eq!(storage, [None, None]);
+
+    // a change to the storage alone changes the storage root
+    db.new_proposal(vec![op(
+        b"alice",
+        AccountOp::DeleteState { key: word(b'y') },
+    )])
+    .unwrap()
+    .commit()
+    .unwrap();
+    let storage_root = db.get_account(b"alice").unwrap().unwrap().storage_root;
+    assert_ne!(storage_root, alice.storage_root);
+    assert_ne!(db.kv_root_hash(None).unwrap(), kv_root_hash);
+
+    // deleting an account deletes its storage, and recreating it starts afresh
+    db.new_proposal(vec![
+        op(b"alice", AccountOp::Delete),
+        op(b"bob", AccountOp::Delete),
+    ])
+    .unwrap()
+    .commit()
+    .unwrap();
+    assert!(db.get_account(b"alice").unwrap().is_none());
+    assert!(db.get_state(b"alice".to_vec(), word(b'x')).is_err());
+    // but not its code, which can still be looked up by its hash
+    assert_eq!(db.get_code_by_hash(&code_hash(b"code")).unwrap(), b"code");
+    assert!(db.get_code_by_hash(&code_hash(b"none")).is_err());
+    db.new_proposal(vec![op(b"alice", AccountOp::SetNonce(1))])
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert_eq!(
+        db.get_account(b"alice").unwrap().unwrap(),
+        firewood::account::Account {
+            nonce: 1,
+            ..Default::default()
+        }
+    );
+
+    // the accounts are durable
+    let root_hash = db.root_hash().unwrap();
+    drop(db);
+    let db = Db::new("test_accounts", &cfg.build()).unwrap();
+    assert_eq!(db.root_hash().unwrap(), root_hash);
+    assert_eq!(db.get_account(b"alice").unwrap().unwrap().nonce, 1);
+
+    // building the generic storage from sorted keys replaces the accounts and their storage
+    db.new_proposal(vec![set_state(b"alice", b'x')])
+        .unwrap()
+        .commit()
+        .unwrap();
+    db.new_proposal_from_sorted([(word(b'k'), word(b'v'))])
+        .unwrap()
+        .commit()
+        .unwrap();
+    assert!(db.get_account(b"alice").unwrap().is_none());
+    assert!(matches!(
+        db.get_state(b"alice".to_vec(), word(b'x')),
+        Err(DbError::KeyNotFound)
+    ));
+}
+
+#[test]
+fn ethereum_trie_encoding_is_recorded() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new(
+        "test_ethereum_encoding",
+        &cfg.clone()
+            .truncate(true)
+            .trie_encoding(TrieEncoding::Ethereum)
+            .build(),
+    )
+    .unwrap();
+    let items = [("do", "verb"), ("dog", "puppy"), ("horse", "stallion")];
+    db.new_proposal(
+        items
+            .iter()
+            .map(|(k, v)| BatchOp::Put {
+                key: k.as_bytes(),
+                value: v.as_bytes().to_vec(),
+            })
+            .collect(),
+    )
+    .unwrap()
+    .commit()
+    .unwrap();
+    let expected = triehash::trie_root::<keccak_hasher::KeccakHasher, Vec<_>, _, _>(items.to_vec());
+    let root_hash = db.kv_root_hash(None).unwrap();
+    assert_eq!(*root_hash, expected);
+    let rev = db.get_revision(&db.root_hash().unwrap()).unwrap();
+    assert!(rev.prove(b"do").is_err());
+    drop(rev);
+
+    // the encoding the DB was created with wins over the config
+    drop(db);
+    let db = Db::new("test_ethereum_encoding", &cfg.build()).unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), root_hash);
+    db.new_proposal(vec![BatchOp::Delete { key: b"dog" }])
+        .unwrap()
+        .commit()
+        .unwrap();
+    let expected = triehash::trie_root::<keccak_hasher::KeccakHasher, Vec<_>, _, _>(vec![
+        ("do", "verb"),
+        ("horse", "stallion"),
+    ]);
+    assert_eq!(*db.kv_root_hash(None).unwrap(), expected);
+}
+
+#[test]
+fn branch_factor_is_recorded() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new(
+        "test_branch_factor",
+        &cfg.clone()
+            .truncate(true)
+            .branch_factor(BranchFactor::TwoFiftySix)
+            .build(),
+    )
+    .unwrap();
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (0..100u8)
+        .map(|i| (vec![i, i.wrapping_mul(7), 0xff], vec![i]))
+        .collect();
+    db.new_proposal(
+        items
+            .iter()
+            .map(|(k, v)| BatchOp::Put {
+                key: k,
+                value: v.clone(),
+            })
+            .collect(),
+    )
+    .unwrap()
+    .commit()
+    .unwrap();
+    let root_hash = db.kv_root_hash(None).unwrap();
+
+    // the branch factor the DB was created with wins over the config
+    drop(db);
+    let db = Db::new("test_branch_factor", &cfg.build()).unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), root_hash);
+    let rev = db.get_revision(&db.root_hash().unwrap()).unwrap();
+    for (key, val) in &items {
+        assert_eq!(&rev.kv_get(key).unwrap(), val);
+    }
+}
+
+#[test]
+fn long_keys_with_small_branch_factors() {
+    // keys of 64 and 128 bytes, in pairs that share all but their last byte, which gives leaf
+    // and extension nodes paths of more than 255 bytes
+    let items: Vec<(Vec<u8>, Vec<u8>)> = [(64, 0xab), (128, 0xcd)]
+        .into_iter()
+        .flat_map(|(len, byte)| {
+            [0, 1].map(|last| ([vec![byte; len - 1], vec![last]].concat(), vec![last; len]))
+        })
+        .collect();
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+
+    for branch_factor in [BranchFactor::Two, BranchFactor::Four] {
+        let db = PersistedDb::new(
+            "test_long_keys",
+            &cfg.clone()
+                .truncate(true)
+                .branch_factor(branch_factor)
+                .build(),
+        )
+        .unwrap();
+        db.new_proposal(
+            items
+                .iter()
+                .map(|(k, v)| BatchOp::Put {
+                    key: k,
+                    value: v.clone(),
+                })
+                .collect(),
+        )
+        .unwrap()
+        .commit()
+        .unwrap();
+        let root_hash = db.kv_root_hash(None).unwrap();
+
+        // the nodes are read back from disk
+        drop(db);
+        let db = Db::new("test_long_keys", &cfg.clone().build()).unwrap();
+        assert_eq!(db.kv_root_hash(None).unwrap(), root_hash);
+        let rev = db.get_revision(&db.root_hash().unwrap()).unwrap();
+        for (key, val) in &items {
+            assert_eq!(&rev.kv_get(key).unwrap(), val);
+        }
+    }
+}
+
+#[test]
+fn hash_threads_give_the_same_root_hashes() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .truncate(true)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let one_thread = Db::new("test_one_hash_thread", &cfg.clone().build()).unwrap();
+    let parallel = Db::new(
+        "test_hash_threads",
+        &cfg.rev(DbRevConfig::builder().hash_threads(4).build())
+            .build(),
+    )
+    .unwrap();
+
+    for round in 0..3u8 {
+        let batch = |n: u8| -> Vec<BatchOp<[u8; 2]>> {
+            (0..=255)
+                .map(|i| BatchOp::Put {
+                    key: [i, n],
+                    value: vec![round; i as usize % 40 + 1],
+                })
+                .collect()
+        };
+        // a proposal on top of another one hashes on the same threads
+        let [expected, found] = [&one_thread, &parallel].map(|db| {
+            let proposal = Arc::new(db.new_proposal(batch(round)).unwrap());
+            proposal
+                .propose(batch(round + 3))
+                .unwrap()
+                .commit()
+                .unwrap();
+            db.kv_root_hash(None).unwrap()
+        });
+        assert_eq!(expected, found);
+    }
+}
+
+#[test]
+fn branch_factor_needs_firewood_encoding() {
+    let cfg = DbConfig::builder()
+        .truncate(true)
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .trie_encoding(TrieEncoding::Ethereum)
+        .branch_factor(BranchFactor::TwoFiftySix)
+        .build();
+    let db = PersistedDb::new("test_branch_factor_ethereum", &cfg);
+    let _ = remove_dir_all("test_branch_factor_ethereum");
+    assert!(matches!(db, Err(DbError::InvalidParams)));
+}
+
+#[test]
+fn secure_keys_are_recorded() {
+    use sha3::{Digest, Keccak256};
+
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new(
+        "test_secure_keys",
+        &cfg.clone().truncate(true).secure_keys(true).build(),
+    )
+    .unwrap();
+    db.new_proposal(vec![
+        BatchOp::Put {
+            key: &b"account/0001"[..],
+            value: b"alice".to_vec(),
+        },
+        BatchOp::Put {
+            key: &b"account/0002"[..],
+            value: b"bob".to_vec(),
+        },
+        BatchOp::KeyspacePut {
+            keyspace: "names".to_string(),
+            key: &b"alice"[..],
+            value: b"0001".to_vec(),
+        },
+    ])
+    .unwrap()
+    .commit()
+    .unwrap();
+    let root_hash = db.root_hash().unwrap();
+
+    // whether keys are hashed is fixed when the DB is created
+    drop(db);
+    let db = Db::new("test_secure_keys", &cfg.build()).unwrap();
+    assert_eq!(db.root_hash().unwrap(), root_hash);
+    let rev = db.get_revision(&root_hash).unwrap();
+    assert_eq!(rev.kv_get(b"account/0001").unwrap(), b"alice");
+    assert_eq!(rev.keyspace_get("names", b"alice").unwrap(), b"0001");
+    assert_eq!(db.keyspaces().unwrap(), vec!["names"]);
+    assert_eq!(
+        rev.preimage(Keccak256::digest(b"account/0002")).unwrap(),
+        Some(b"account/0002".to_vec())
+    );
+
+    // dumps give the original keys next to the hashed paths
+    let mut dump = Vec::new();
+    db.kv_dump(&mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    assert!(dump.contains(&format!("key={}", hex::encode(b"account/0001"))));
+}
+
+#[test]
+fn long_values_are_stored_in_blobs() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let code = |i: u8| vec![i; 300];
+    // every long value is shared by several keys
+    let puts = || {
+        (0..30u8).map(move |i| BatchOp::Put {
+            key: [i],
+            value: if i % 2 == 0 { vec![i; 64] } else { code(i % 3) },
+        })
+    };
+    let db = PersistedDb::new(
+        "test_blobs",
+        &cfg.clone().truncate(true).blob_threshold(64).build(),
+    )
+    .unwrap();
+    let inline = Db::new(
+        "test_blobs_inline",
+        &cfg.clone().truncate(true).blob_threshold(0).build(),
+    )
+    .unwrap();
+    for db in [&db, &inline.0] {
+        db.new_proposal(puts().collect()).unwrap().commit().unwrap();
+    }
+    // root hashes don't depend on where values are stored
+    let root_hash = db.kv_root_hash(None).unwrap();
+    assert_eq!(root_hash, inline.kv_root_hash(None).unwrap());
+
+    // the threshold is fixed when the DB is created
+    drop(db);
+    let db = Db::new("test_blobs", &cfg.clone().build()).unwrap();
+    let rev = db.get_revision(&db.root_hash().unwrap()).unwrap();
+    for op in puts() {
+        let BatchOp::Put { key, value } = op else {
+            unreachable!()
+        };
+        assert_eq!(rev.kv_get(key).unwrap(), value);
+    }
+    assert_eq!(rev.blob_refs(vec![0; 64]).unwrap(), 0);
+    assert_eq!(rev.blob_refs(code(1)).unwrap(), 5);
+    assert_eq!(
+        inline
+            .get_revision(&root_hash)
+            .unwrap()
+            .blob_refs(code(1))
+            .unwrap(),
+        0
+    );
+
+    // a blob is freed along with the last value stored in it
+    for db in [&db.0, &inline.0] {
+        let deletes = (0..30u8)
+            .filter(|i| i % 3 == 1)
+            .map(|i| BatchOp::Delete { key: [i] });
+        db.new_proposal(deletes.collect())
+            .unwrap()
+            .commit()
+            .unwrap();
+    }
+    assert_eq!(
+        db.kv_root_hash(None).unwrap(),
+        inline.kv_root_hash(None).unwrap()
+    );
+    let rev = db.get_revision(&db.root_hash().unwrap()).unwrap();
+    assert_eq!(rev.blob_refs(code(1)).unwrap(), 0);
+    assert_eq!(rev.blob_refs(code(2)).unwrap(), 5);
+    assert!(rev.kv_get([1]).is_none());
+
+    let too_small = PersistedDb::new(
+        "test_blobs_too_small",
+        &cfg.truncate(true).blob_threshold(8).build(),
+    );
+    let _ = remove_dir_all("test_blobs_too_small");
+    assert!(matches!(too_small, Err(DbError::InvalidParams)));
+}
+
+#[test]
+fn checkpoint_and_restore() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        // small WAL files, so the WAL spans several
+        .wal(
+            WalConfig::builder()
+                .file_nbit(12)
+                .block_nbit(8)
+                .max_revisions(5)
+                .build(),
+        );
+    let db = Db::new(
+        "test_checkpoint_db",
+        &cfg.clone().archival(true).truncate(true).build(),
+    )
+    .unwrap();
+    let _ = remove_dir_all("test_checkpoint_backup");
+
+    let commit = |db: &PersistedDb, i: u8| {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        db.kv_root_hash(None).unwrap()
+    };
+
+    // older commits that fill WAL files the copy can do without
+    for i in 0..20 {
+        commit(&db, 100 + i);
+    }
+    let hashes: Vec<_> = (0..3).map(|i| commit(&db, i)).collect();
+    db.checkpoint("test_checkpoint_backup").unwrap();
+    let wal_len = |dir: &str| -> u64 {
+        std::fs::read_dir(Path::new(dir).join("wal"))
+            .unwrap()
+            .map(|entry| entry.unwrap().metadata().unwrap().len())
+            .sum()
+    };
+    assert!(wal_len("test_checkpoint_backup") < wal_len("test_checkpoint_db"));
+    commit(&db, 3);
+    // the archival history is left behind
+    assert!(!std::path::Path::new("test_checkpoint_backup/history").exists());
+    assert!(db.checkpoint("test_checkpoint_backup").is_err());
+
+    // the copy can be opened as is, with its window of past revisions
+    let backup = PersistedDb::new("test_checkpoint_backup", &cfg.clone().build()).unwrap();
+    assert_eq!(backup.kv_root_hash(None).unwrap(), hashes[2]);
+    assert!(backup.kv_get([3]).is_err());
+    let rev = backup.get_revision(&hashes[0]).unwrap();
+    assert_eq!(rev.kv_get([0]).unwrap(), vec![0]);
+    assert!(rev.kv_get([1]).is_none());
+    drop(rev);
+    drop(backup);
+
+    let _ = remove_dir_all("test_checkpoint_restored");
+    assert!(PersistedDb::restore("test_checkpoint_db/merkle", "test_checkpoint_restored").is_err());
+    PersistedDb::restore("test_checkpoint_backup", "test_checkpoint_restored").unwrap();
+    let restored = Db::new("test_checkpoint_restored", &cfg.build()).unwrap();
+    assert_eq!(restored.kv_root_hash(None).unwrap(), hashes[2]);
+    commit(&restored, 4);
+    assert_eq!(restored.kv_get([4]).unwrap(), vec![4]);
+
+    remove_dir_all("test_checkpoint_backup").unwrap();
+}
+
+#[test]
+fn read_only_alongside_writer() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = Db::new("test_read_only_db", &cfg.clone().truncate(true).build()).unwrap();
+    let commit = |i: u8| {
+        let batch = vec![BatchOp::Put {
+            key: [i],
+            value: vec![i],
+        }];
+        db.new_proposal(batch).unwrap().commit().unwrap();
+        db.kv_root_hash(None).unwrap()
+    };
+    let hashes: Vec<_> = (0..3).map(commit).collect();
+
+    // the writer still holds the DB, and its latest commits may only be in the WAL
+    let read_only_cfg = cfg.clone().read_only(true).build();
+    let reader = PersistedDb::new("test_read_only_db", &read_only_cfg).unwrap();
+    assert_eq!(reader.kv_root_hash(None).unwrap(), hashes[2]);
+    assert_eq!(reader.kv_get([2]).unwrap(), vec![2]);
+    let rev = reader.get_revision(&hashes[0]).unwrap();
+    assert_eq!(rev.kv_get([0]).unwrap(), vec![0]);
+    assert!(rev.kv_get([1]).is_none());
+    drop(rev);
+
+    assert!(matches!(
+        reader.new_proposal::<Vec<u8>>(vec![]),
+        Err(DbError::ReadOnly)
+    ));
+    assert!(matches!(
+        reader.rollback_to(&hashes[0]),
+        Err(DbError::ReadOnly)
+    ));
+    drop(reader);
+
+    // the writer carries on unaffected, and a new reader sees its later commits
+    let latest = commit(3);
+    let reader = PersistedDb::new("test_read_only_db", &read_only_cfg).unwrap();
+    assert_eq!(reader.kv_root_hash(None).unwrap(), latest);
+    drop(reader);
+
+    assert!(PersistedDb::new("test_read_only_missing", &read_only_cfg).is_err());
+    assert!(PersistedDb::new(
+        "test_read_only_db",
+        &cfg.read_only(true).truncate(true).build()
+    )
+    .is_err());
+}
+
+#[test]
+fn second_writer_is_locked_out() {
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let db = PersistedDb::new("test_locked_db", &cfg.clone().truncate(true).build()).unwrap();
+    db.new_proposal(vec![BatchOp::Put {
+        key: b"k",
+        value: b"v".to_vec(),
+    }])
+    .unwrap()
+    .commit()
+    .unwrap();
+
+    let pid = std::process::id();
+    for truncate in [false, true] {
+        let err = PersistedDb::new("test_locked_db", &cfg.clone().truncate(truncate).build())
+            .unwrap_err();
+        assert!(matches!(err, DbError::Locked { pid: Some(holder) } if holder == pid));
+        assert!(err.to_string().contains(&pid.to_string()));
+    }
+    // the truncating writer did not touch the data
+    assert_eq!(db.kv_get(b"k").unwrap(), b"v");
+    assert!(PersistedDb::new("test_locked_db", &cfg.clone().read_only(true).build()).is_ok());
+
+    // the lock goes with the handle
+    let root_hash = db.kv_root_hash(None).unwrap();
+    drop(db);
+    let db = Db::new("test_locked_db", &cfg.build()).unwrap();
+    assert_eq!(db.kv_root_hash(None).unwrap(), root_hash);
+}
+
+#[test]
+fn damaged_db_fails_to_open() {
+    use std::os::unix::fs::FileExt;
+
+    let path = Path::new("test_damaged_db");
+    let cfg = DbConfig::builder()
+        .payload_file_nbit(16)
+        .payload_regn_nbit(16)
+        .wal(WalConfig::builder().max_revisions(5).build());
+    let create = || {
+        let db = PersistedDb::new(path, &cfg.clone().truncate(true).build()).unwrap();
+        db.new_proposal(vec![BatchOp::Put {
+            key: b"k",
+            value: b"v".to_vec(),
+        }])
+        .unwrap()
+        .commit()
+        .unwrap();
+    };
+    let open = || PersistedDb::new(path, &cfg.clone().build()).map(|_| ());
+    let write_at = |file: &Path, data: &[u8], offset: u64| {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path.join(file))
+            .unwrap()
+            .write_all_at(data, offset)
+            .unwrap();
+    };
+    let header = Path::new("merkle/meta/00000000.fw");
+
+    create();
+    write_at(header, b"not a database\0\0", 0);
+    assert!(matches!(open(), Err(DbError::BadMagic(magic)) if magic == "not a database"));
+
+    create();
+    write_at(header, b"firewood v9.9\0\0\0", 0);
+    assert!(matches!(open(), Err(DbError::UnsupportedFormat(magic)) if magic == "firewood v9.9"));
+
+    // wal_block_nbit
+    create();
+    write_at(header, &0u64.to_le_bytes(), 64);
+    assert!(matches!(open(), Err(DbError::CorruptedParams(_))));
+
+    create();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path.join("merkle/compact/00000000.fw"))
+        .unwrap()
+        .set_len((1 << 16) + 1)
+        .unwrap();
+    assert!(matches!(
+        open(),
+        Err(DbError::FileNbitMismatch { len, file_nbit: 16, .. }) if len == (1 << 16) + 1
+    ));
+
+    create();
+    write_at(Path::new("wal/00000000.log"), &[0xff; 64], 0);
+    let err = open().unwrap_err();
+    assert!(matches!(&err, DbError::WalReplay { path: wal, .. } if wal == &path.join("wal")));
+    let err = std::error::Error::source(&err).unwrap().to_string();
+    assert!(err.contains("00000000.log"), "{err}");
+
+    remove_dir_all(path).unwrap();
+}
+
 #[test]
 fn create_db_issue_proof() {
     let cfg = DbConfig::builder()
@@ -194,7 +1384,7 @@ fn create_db_issue_proof() {
     let proposal = db.new_proposal(batch).unwrap();
     proposal.commit().unwrap();
 
-    let root_hash = db.kv_root_hash().unwrap();
+    let root_hash = db.kv_root_hash(None).unwrap();
 
     // Add second commit
     let mut batch = Vec::new();
@@ -210,7 +1400,7 @@ fn create_db_issue_proof() {
 
     let rev = db.get_revision(&root_hash).unwrap();
     let key = "doe".as_bytes();
-    let root_hash = rev.kv_root_hash();
+    let root_hash = rev.kv_root_hash(None);
 
     match rev.prove::<&[u8]>(key) {
         Ok(proof) => {
@@ -251,6 +1441,34 @@ macro_rules! assert_val {
     };
 }
 
+#[test]
+fn revision_proves_many_keys() {
+    let cfg = DbConfig::builder().wal(WalConfig::builder().max_revisions(5).build());
+    let db = Db::new("test_db_multi_proof", &cfg.truncate(true).build()).unwrap();
+    let key = |i: u8| [i, b'k', b'e', b'y'];
+    let batch = (0..100u8)
+        .map(|i| BatchOp::Put {
+            key: key(i * 2),
+            value: vec![i],
+        })
+        .collect();
+    db.new_proposal(batch).unwrap().commit().unwrap();
+
+    let root_hash = db.kv_root_hash(None).unwrap();
+    let rev = db.get_revision(&root_hash).unwrap();
+    // the even keys are in the trie, and the odd ones are not
+    let keys: Vec<_> = (0..200u8).map(key).collect();
+    let proof = rev.prove_many(&keys).unwrap();
+    let single = rev.prove(keys[0]).unwrap();
+    // the nodes of the shared paths are in the proof once
+    assert!(proof.0.len() < keys.len() * single.0.len());
+
+    let values = proof.verify_multi_proof(&keys, *root_hash).unwrap();
+    for (i, value) in (0..200u8).zip(values) {
+        assert_eq!(value.into_value(), (i % 2 == 0).then(|| vec![i / 2]));
+    }
+}
+
 #[test]
 fn db_proposal() -> Result<(), DbError> {
     let cfg = DbConfig::builder().wal(WalConfig::builder().max_revisions(10).build());
@@ -2,9 +2,13 @@
 // See the file LICENSE.md for licensing terms.
 
 use firewood::{
-    merkle::Node,
-    merkle_util::{new_merkle, DataStoreError, MerkleSetup},
-    proof::ProofError,
+    merkle::{MerkleError, Node, TrieEncoding},
+    merkle_util::{
+        new_merkle, new_merkle_with_blobs, new_merkle_with_branch_factor, new_merkle_with_encoding,
+        new_merkle_with_hash_threads, new_secure_merkle, DataStoreError, MerkleSetup,
+    },
+    nibbles::BranchFactor,
+    proof::{Divergence, KeyProof, ProofError, ProofResult},
     v2::api::Proof,
 };
 use rand::Rng;
@@ -73,6 +77,99 @@ fn test_root_hash_fuzz_insertions() {
     }
 }
 
+#[test]
+fn test_for_each_kv() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut items = BTreeMap::new();
+    for _ in 0..200 {
+        // short keys over a small alphabet, so some are prefixes of others
+        let len = rng.gen_range(0..4);
+        let key: Vec<u8> = (0..len).map(|_| rng.gen_range(0..3)).collect();
+        items.insert(key, rng.gen::<[u8; 4]>().to_vec());
+    }
+
+    let mut merkle = merkle_build_test(items.clone().into_iter().collect(), 0x10000, 0x10000)?;
+    let root = merkle.get_root();
+    let mut walked = Vec::new();
+    merkle
+        .get_merkle_mut()
+        .for_each_kv(
+            root,
+            |key, value| -> Result<(), firewood::merkle::MerkleError> {
+                walked.push((key.to_vec(), value.to_vec()));
+                Ok(())
+            },
+        )
+        .map_err(|_| DataStoreError::GetError)?;
+
+    assert_eq!(walked, items.into_iter().collect::<Vec<_>>());
+    Ok(())
+}
+
+#[test]
+fn test_other_branch_factors() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+    use std::collections::BTreeMap;
+
+    let mut rng = StdRng::seed_from_u64(42);
+    for branch_factor in [
+        BranchFactor::Two,
+        BranchFactor::Four,
+        BranchFactor::Sixteen,
+        BranchFactor::TwoFiftySix,
+    ] {
+        let mut merkle = new_merkle_with_branch_factor(0x100000, 0x100000, branch_factor);
+        let mut items = BTreeMap::new();
+        for _ in 0..300 {
+            // few distinct bytes make keys share prefixes, in bits as well as in bytes
+            let len = rng.gen_range(0..5);
+            let key: Vec<u8> = (0..len)
+                .map(|_| [0x00, 0x01, 0x80, 0xff][rng.gen_range(0..4)])
+                .collect();
+            let val = rng.gen::<[u8; 4]>().to_vec();
+            merkle.insert(&key, val.clone())?;
+            items.insert(key, val);
+        }
+        let mut keys: Vec<_> = items.keys().cloned().collect();
+        keys.shuffle(&mut rng);
+        for key in &keys[..keys.len() / 2] {
+            assert_eq!(merkle.remove(key)?, items.remove(key));
+        }
+
+        for (key, val) in &items {
+            assert_eq!(merkle.get(key)?.as_deref(), Some(&val[..]));
+        }
+        for key in &keys[..keys.len() / 2] {
+            assert!(merkle.get(key)?.is_none());
+        }
+
+        let root = merkle.get_root();
+        let mut walked = Vec::new();
+        merkle
+            .get_merkle_mut()
+            .for_each_kv(
+                root,
+                |key, value| -> Result<(), firewood::merkle::MerkleError> {
+                    walked.push((key.to_vec(), value.to_vec()));
+                    Ok(())
+                },
+            )
+            .map_err(|_| DataStoreError::GetError)?;
+        assert_eq!(walked, items.clone().into_iter().collect::<Vec<_>>());
+
+        // the trie doesn't depend on how it came to hold its keys
+        let mut rebuilt = new_merkle_with_branch_factor(0x100000, 0x100000, branch_factor);
+        for (key, val) in &items {
+            rebuilt.insert(key, val.clone())?;
+        }
+        assert_eq!(merkle.root_hash()?, rebuilt.root_hash()?);
+    }
+    Ok(())
+}
+
 #[test]
 fn test_root_hash_reversed_deletions() -> Result<(), DataStoreError> {
     use rand::{rngs::StdRng, Rng, SeedableRng};
@@ -201,6 +298,727 @@ fn test_root_hash_random_deletions() -> Result<(), DataStoreError> {
     Ok(())
 }
 
+#[test]
+fn remove_branch_value_keeps_child() -> Result<(), DataStoreError> {
+    let mut merkle = new_merkle(0x10000, 0x10000);
+    merkle.insert([1], vec![1; 26])?;
+    merkle.insert([1, 1], vec![1; 7])?;
+    merkle.insert([0, 2], vec![1; 5])?;
+    // the child of the branch moves up and has to carry the index it was at
+    merkle.remove([1])?;
+    assert_eq!(&*merkle.get([1, 1])?.unwrap(), &[1; 7]);
+    assert_eq!(&*merkle.get([0, 2])?.unwrap(), &[1; 5]);
+    Ok(())
+}
+
+#[test]
+fn overwrite_hashed_value_with_longer_one() -> Result<(), DataStoreError> {
+    let mut merkle = new_merkle(0x10000, 0x10000);
+    merkle.insert([1], vec![1; 49])?;
+    merkle.root_hash()?;
+    // the leaf no longer fits where it was written with its hash
+    merkle.insert([1], vec![2; 51])?;
+    merkle.root_hash()?;
+    assert_eq!(&*merkle.get([1])?.unwrap(), &[2; 51]);
+    Ok(())
+}
+
+fn ethereum_root(items: &HashMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+    triehash::trie_root::<keccak_hasher::KeccakHasher, Vec<_>, _, _>(items.iter().collect())
+}
+
+#[test]
+fn ethereum_root_hash_of_known_tries() -> Result<(), DataStoreError> {
+    let cases: [&[(&str, &str)]; 4] = [
+        &[],
+        // a single node shorter than a hash is still hashed at the root
+        &[("a", "b")],
+        &[
+            ("do", "verb"),
+            ("doe", "reindeer"),
+            ("dog", "puppy"),
+            ("doge", "coin"),
+            ("horse", "stallion"),
+        ],
+        // short nodes are inlined in their parent, and keys end on branches
+        &[
+            ("a", "1"),
+            ("ab", "2"),
+            ("abc", "3"),
+            ("b", "4"),
+            ("ba", "5"),
+        ],
+    ];
+    for items in cases {
+        let mut merkle = new_merkle_with_encoding(0x10000, 0x10000, TrieEncoding::Ethereum);
+        let mut expected = HashMap::new();
+        for (k, v) in items {
+            merkle.insert(k, v.as_bytes().to_vec())?;
+            expected.insert(k.as_bytes().to_vec(), v.as_bytes().to_vec());
+        }
+        assert_eq!(*merkle.root_hash()?, ethereum_root(&expected), "{items:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn ethereum_root_hash_matches_triehash() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..20 {
+        let mut merkle = new_merkle_with_encoding(0x100000, 0x100000, TrieEncoding::Ethereum);
+        let mut items = HashMap::new();
+        for _ in 0..rng.gen_range(1..100) {
+            // few distinct bytes make keys share prefixes and end on branches
+            let key: Vec<u8> = (0..rng.gen_range(1..6))
+                .map(|_| rng.gen_range(0..4))
+                .collect();
+            // short values make nodes short enough to be inlined
+            let val: Vec<u8> = (0..rng.gen_range(1..40)).map(|_| rng.gen()).collect();
+            merkle.insert(&key, val.clone())?;
+            items.insert(key, val);
+            assert_eq!(*merkle.root_hash()?, ethereum_root(&items));
+        }
+
+        let mut keys: Vec<_> = items.keys().cloned().collect();
+        keys.sort();
+        keys.shuffle(&mut rng);
+        for key in keys {
+            merkle.remove(&key)?;
+            items.remove(&key);
+            assert_eq!(*merkle.root_hash()?, ethereum_root(&items));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn ethereum_encoding_is_not_proven() {
+    let mut merkle = new_merkle_with_encoding(0x10000, 0x10000, TrieEncoding::Ethereum);
+    merkle.insert("k", b"v".to_vec()).unwrap();
+    assert!(merkle.prove("k").is_err());
+}
+
+/// A model of the root hash avalanchego's MerkleDB gives `items`, which has a node at the empty
+/// key, at every key with a value, and wherever keys branch. It is written from the same reading
+/// of MerkleDB as the encoding it checks, so it only supplements
+/// [merkledb_root_hash_matches_avalanchego], which is the test of compatibility.
+fn merkledb_root(items: &HashMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+    let mut nodes: Vec<_> = items
+        .iter()
+        .map(|(k, v)| (k.iter().flat_map(|b| [b >> 4, b & 0xf]).collect(), &v[..]))
+        .collect();
+    nodes.sort();
+    merkledb_node(&[], &nodes)
+}
+
+/// The ID of the MerkleDB node at `key`, whose subtrie holds the sorted `items`, keyed by nibbles.
+fn merkledb_node(key: &[u8], items: &[(Vec<u8>, &[u8])]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    // Go's binary.PutVarint, for non-negative numbers
+    let varint = |buf: &mut Vec<u8>, n: usize| {
+        let mut n = (n as u64) << 1;
+        while n >= 0x80 {
+            buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        buf.push(n as u8);
+    };
+
+    let (value, mut rest) = match items.split_first() {
+        Some(((k, v), rest)) if k.len() == key.len() => (Some(*v), rest),
+        _ => (None, items),
+    };
+    let mut children = Vec::new();
+    while let Some((first, _)) = rest.first() {
+        let index = first[key.len()];
+        let len = rest
+            .iter()
+            .take_while(|(k, _)| k[key.len()] == index)
+            .count();
+        let (group, others) = rest.split_at(len);
+        let last = &group[len - 1].0;
+        let common = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+        children.push((index, merkledb_node(&first[..common], group)));
+        rest = others;
+    }
+
+    let mut buf = Vec::new();
+    varint(&mut buf, children.len());
+    for (index, id) in children {
+        varint(&mut buf, index as usize);
+        buf.extend(id);
+    }
+    match value {
+        Some(value) => {
+            let value = if value.len() >= 32 {
+                Sha256::digest(value).to_vec()
+            } else {
+                value.to_vec()
+            };
+            buf.push(1);
+            varint(&mut buf, value.len());
+            buf.extend(value);
+        }
+        None => buf.push(0),
+    }
+    varint(&mut buf, key.len());
+    buf.extend(key.chunks(2).map(|n| n[0] << 4 | n.get(1).unwrap_or(&0)));
+    Sha256::digest(buf).into()
+}
+
+/// Checks many more tries than the avalanchego vectors, against [merkledb_root].
+#[test]
+fn merkledb_root_hash_matches_model() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    for _ in 0..20 {
+        let mut merkle = new_merkle_with_encoding(0x100000, 0x100000, TrieEncoding::MerkleDb);
+        let mut items = HashMap::new();
+        assert_eq!(*merkle.root_hash()?, merkledb_root(&items));
+        for _ in 0..rng.gen_range(1..100) {
+            // few distinct bytes make keys share prefixes and end on branches, and the empty key
+            // puts a value on the root
+            let key: Vec<u8> = (0..rng.gen_range(0..6))
+                .map(|_| rng.gen_range(0..4))
+                .collect();
+            // values at least as long as a hash are hashed
+            let val: Vec<u8> = (0..rng.gen_range(1..40)).map(|_| rng.gen()).collect();
+            merkle.insert(&key, val.clone())?;
+            items.insert(key, val);
+            assert_eq!(*merkle.root_hash()?, merkledb_root(&items));
+        }
+
+        let mut keys: Vec<_> = items.keys().cloned().collect();
+        keys.sort();
+        keys.shuffle(&mut rng);
+        for key in keys {
+            merkle.remove(&key)?;
+            items.remove(&key);
+            assert_eq!(*merkle.root_hash()?, merkledb_root(&items));
+        }
+    }
+    Ok(())
+}
+
+/// Root hashes given by avalanchego's MerkleDB itself, as of v1.10.0, which
+/// `tests/merkledb_vectors` generates. The vectors are not committed yet, as generating them
+/// takes Go and the avalanchego module; until they are, the MerkleDB encoding is only checked
+/// against [merkledb_root], and is not known to match avalanchego.
+#[test]
+#[ignore = "tests/merkledb_vectors.txt is not generated yet, see tests/merkledb_vectors/main.go"]
+fn merkledb_root_hash_matches_avalanchego() -> Result<(), DataStoreError> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/merkledb_vectors.txt");
+    let vectors = std::fs::read_to_string(path).expect("generated vectors");
+    for line in vectors.lines() {
+        let [name, pairs, root_hash] = line.split('\t').collect::<Vec<_>>()[..] else {
+            panic!("malformed vector {line:?}");
+        };
+        let mut merkle = new_merkle_with_encoding(0x10000, 0x10000, TrieEncoding::MerkleDb);
+        for pair in pairs.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once(':').unwrap();
+            merkle.insert(hex::decode(key).unwrap(), hex::decode(value).unwrap())?;
+        }
+        assert_eq!(
+            hex::encode(*merkle.root_hash()?),
+            root_hash,
+            "root hash of the {name} trie"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn merkledb_encoding_is_not_proven() {
+    let mut merkle = new_merkle_with_encoding(0x10000, 0x10000, TrieEncoding::MerkleDb);
+    merkle.insert("k", b"v".to_vec()).unwrap();
+    assert!(merkle.prove("k").is_err());
+}
+
+#[test]
+fn test_proof_with_other_branch_factor() -> Result<(), ProofError> {
+    let mut items = Vec::from_iter(generate_random_data(500));
+    items.sort();
+    let mut merkle = new_merkle_with_branch_factor(0x100000, 0x100000, BranchFactor::TwoFiftySix);
+    for (key, val) in &items {
+        merkle.insert(key, val.to_vec())?;
+    }
+
+    for (key, val) in &items {
+        let proof = merkle.prove(key)?;
+        assert_eq!(merkle.verify_proof(key, &proof)?.as_deref(), Some(&val[..]));
+    }
+
+    let (start, end) = (100, 200);
+    let mut proof = merkle.prove(items[start].0)?;
+    proof.concat_proofs(merkle.prove(items[end].0)?);
+    let keys = items[start..=end].iter().map(|item| item.0).collect();
+    let vals = items[start..=end].iter().map(|item| item.1).collect();
+    merkle.verify_range_proof(&proof, items[start].0, items[end].0, keys, vals)?;
+
+    // a proof isn't valid for a trie of another branch factor
+    let proof = merkle.prove(items[start].0)?;
+    let root_hash: [u8; 32] = *merkle.root_hash()?;
+    assert!(proof.verify_proof(items[start].0, root_hash).is_err());
+    Ok(())
+}
+
+#[test]
+fn secure_merkle_hashes_keys() -> Result<(), DataStoreError> {
+    use sha3::{Digest, Keccak256};
+    use std::collections::BTreeMap;
+
+    // keys with a long shared prefix, which would make a deep trie
+    let items: BTreeMap<Vec<u8>, Vec<u8>> = (0..50u8)
+        .map(|i| ([&[0xaa; 40][..], &[i]].concat(), vec![i]))
+        .collect();
+    let mut merkle = new_secure_merkle(0x100000, 0x100000);
+    let mut hashed = new_merkle(0x100000, 0x100000);
+    for (key, val) in &items {
+        merkle.insert(key, val.clone())?;
+        hashed.insert(Keccak256::digest(key), val.clone())?;
+    }
+    // a secure trie is the trie of the hashes of its keys
+    assert_eq!(merkle.root_hash()?, hashed.root_hash()?);
+    for (key, val) in &items {
+        assert_eq!(merkle.get(key)?.as_deref(), Some(&val[..]));
+    }
+
+    // iteration gives the original keys, in the order of their hashes
+    let root = merkle.get_root();
+    let mut walked = Vec::new();
+    merkle
+        .get_merkle_mut()
+        .for_each_kv(
+            root,
+            |key, value| -> Result<(), firewood::merkle::MerkleError> {
+                walked.push((key.to_vec(), value.to_vec()));
+                Ok(())
+            },
+        )
+        .map_err(|_| DataStoreError::GetError)?;
+    let mut expected: Vec<_> = items.clone().into_iter().collect();
+    expected.sort_by_key(|(key, _)| Keccak256::digest(key));
+    assert_eq!(walked, expected);
+    let (key, _) = items.iter().next().unwrap();
+    assert!(merkle
+        .dump()?
+        .contains(&format!("key={}", hex::encode(key))));
+
+    // proofs are of the hashed keys
+    let proof = merkle.prove(key)?;
+    assert_eq!(merkle.verify_proof(key, &proof)?, Some(vec![0]));
+    let root_hash: [u8; 32] = *merkle.root_hash()?;
+    assert_eq!(
+        proof
+            .verify_proof(Keccak256::digest(key), root_hash)
+            .unwrap(),
+        Some(vec![0])
+    );
+
+    for (key, val) in &items {
+        assert_eq!(merkle.remove(key)?.as_ref(), Some(val));
+        assert!(merkle.get(key)?.is_none());
+    }
+    assert_eq!(
+        merkle.root_hash()?,
+        new_merkle(0x10000, 0x10000).root_hash()?
+    );
+    Ok(())
+}
+
+#[test]
+fn large_values_are_stored_in_blobs() -> Result<(), DataStoreError> {
+    let code = |i: u8| vec![i; 1000];
+    // values around the threshold, with every large value shared by five keys
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (0..100u8)
+        .map(|i| {
+            let val = match i % 4 {
+                0 => vec![i; 64],
+                1 => vec![i; 65],
+                _ => code(i % 20),
+            };
+            (vec![i, i / 3], val)
+        })
+        .collect();
+    let mut merkle = new_merkle_with_blobs(0x100000, 0x100000, 64);
+    let mut inline = new_merkle(0x100000, 0x100000);
+    for (key, val) in &items {
+        merkle.insert(key, val.clone())?;
+        inline.insert(key, val.clone())?;
+    }
+    // hashing doesn't depend on where values are stored
+    assert_eq!(merkle.root_hash()?, inline.root_hash()?);
+    for (key, val) in &items {
+        assert_eq!(merkle.get(key)?.as_deref(), Some(&val[..]));
+    }
+    let blob_refs = |merkle: &mut MerkleSetup<Store>, val: &[u8]| {
+        merkle.get_merkle_mut().blob_refs(val).unwrap()
+    };
+    assert_eq!(blob_refs(&mut merkle, &[0; 64]), 0);
+    assert_eq!(blob_refs(&mut merkle, &[1; 65]), 1);
+    assert_eq!(blob_refs(&mut merkle, &code(2)), 5);
+
+    // a value that changes lets go of its blob
+    let (key, _) = &items[2];
+    merkle.insert(key, code(3))?;
+    inline.insert(key, code(3))?;
+    assert_eq!(blob_refs(&mut merkle, &code(2)), 4);
+    assert_eq!(blob_refs(&mut merkle, &code(3)), 6);
+    merkle
+        .get_mut(key)?
+        .unwrap()
+        .write(|val| val.truncate(10))
+        .unwrap();
+    inline.insert(key, vec![3; 10])?;
+    assert_eq!(blob_refs(&mut merkle, &code(3)), 5);
+    assert_eq!(merkle.get(key)?.as_deref(), Some(&[3; 10][..]));
+    assert_eq!(merkle.root_hash()?, inline.root_hash()?);
+
+    for (key, _) in &items {
+        assert_eq!(merkle.remove(key)?, inline.remove(key)?);
+    }
+    for i in 0..20 {
+        assert_eq!(blob_refs(&mut merkle, &code(i)), 0);
+    }
+    assert_eq!(
+        merkle.root_hash()?,
+        new_merkle(0x10000, 0x10000).root_hash()?
+    );
+    Ok(())
+}
+
+#[test]
+fn batch_matches_one_by_one() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    // short keys over few symbols, so that batches split, merge and empty every kind of node
+    let keygen = |rng: &mut StdRng| -> Vec<u8> {
+        let len = rng.gen_range(0..4);
+        (0..len).map(|_| rng.gen_range(0..3) * 0x11).collect()
+    };
+    let setups: [fn() -> MerkleSetup<Store>; 4] = [
+        || new_merkle(0x100000, 0x100000),
+        || new_merkle_with_branch_factor(0x100000, 0x100000, BranchFactor::Two),
+        || new_secure_merkle(0x100000, 0x100000),
+        || new_merkle_with_blobs(0x100000, 0x100000, 32),
+    ];
+    for setup in setups {
+        let mut batched = setup();
+        let mut one_by_one = setup();
+        for _ in 0..200 {
+            let len = rng.gen_range(0..12);
+            let batch: Vec<(Vec<u8>, Option<Vec<u8>>)> = (0..len)
+                .map(|_| {
+                    let key = keygen(&mut rng);
+                    // some values go to blobs, and some puts are deletes
+                    let val = match rng.gen_range(0..4) {
+                        0 => None,
+                        1 => Some(vec![rng.gen(); 40]),
+                        _ => Some(vec![rng.gen(); rng.gen_range(1..8)]),
+                    };
+                    (key, val)
+                })
+                .collect();
+            for (key, val) in &batch {
+                match val {
+                    Some(val) => one_by_one.insert(key, val.clone())?,
+                    None => {
+                        one_by_one.remove(key)?;
+                    }
+                }
+            }
+            batched.apply_batch(batch)?;
+            assert_eq!(batched.root_hash()?, one_by_one.root_hash()?);
+        }
+        for key in (0..0x1000).map(|_| keygen(&mut rng)) {
+            assert_eq!(
+                batched.get(&key)?.as_deref(),
+                one_by_one.get(&key)?.as_deref()
+            );
+        }
+        for val in (0..=255).map(|i| vec![i; 40]) {
+            let batched = batched.get_merkle_mut().blob_refs(&val).unwrap();
+            assert_eq!(
+                batched,
+                one_by_one.get_merkle_mut().blob_refs(&val).unwrap()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn builder_matches_inserts() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    // short keys over few symbols, so that the trie has every kind of node, and values in branches
+    let keygen = |rng: &mut StdRng| -> Vec<u8> {
+        let len = rng.gen_range(0..6);
+        (0..len).map(|_| rng.gen_range(0..3) * 0x11).collect()
+    };
+    let setups: [fn() -> MerkleSetup<Store>; 6] = [
+        || new_merkle(0x100000, 0x100000),
+        || new_merkle_with_encoding(0x100000, 0x100000, TrieEncoding::Ethereum),
+        || new_merkle_with_encoding(0x100000, 0x100000, TrieEncoding::MerkleDb),
+        || new_merkle_with_branch_factor(0x100000, 0x100000, BranchFactor::Two),
+        || new_secure_merkle(0x100000, 0x100000),
+        || new_merkle_with_blobs(0x100000, 0x100000, 32),
+    ];
+    for setup in setups {
+        for len in [0, 1, 2, 10, 100] {
+            let mut built = setup();
+            let mut inserted = setup();
+            let mut items: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+            for _ in 0..len {
+                // some values go to blobs
+                let val = if rng.gen_bool(0.2) {
+                    vec![rng.gen(); 40]
+                } else {
+                    vec![rng.gen(); rng.gen_range(1..8)]
+                };
+                items.insert(keygen(&mut rng), val);
+            }
+            for (key, val) in &items {
+                inserted.insert(key, val.clone())?;
+            }
+            let merkle = built.get_merkle_mut();
+            let mut items: Vec<_> = items.into_iter().collect();
+            items.sort_by_key(|(key, _)| merkle.trie_key(key).into_owned());
+            built.build_sorted(items)?;
+            assert_eq!(built.root_hash()?, inserted.root_hash()?);
+
+            // the built trie is changed like any other
+            for _ in 0..20 {
+                let key = keygen(&mut rng);
+                if rng.gen() {
+                    let val = vec![rng.gen(); rng.gen_range(1..8)];
+                    built.insert(&key, val.clone())?;
+                    inserted.insert(&key, val)?;
+                } else {
+                    assert_eq!(built.remove(&key)?, inserted.remove(&key)?);
+                }
+                assert_eq!(built.root_hash()?, inserted.root_hash()?);
+            }
+            for key in (0..0x100).map(|_| keygen(&mut rng)) {
+                assert_eq!(built.get(&key)?.as_deref(), inserted.get(&key)?.as_deref());
+            }
+            for val in (0..=255).map(|i| vec![i; 40]) {
+                let built = built.get_merkle_mut().blob_refs(&val).unwrap();
+                assert_eq!(built, inserted.get_merkle_mut().blob_refs(&val).unwrap());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn builder_rejects_unsorted_keys() {
+    let mut merkle = new_merkle(0x10000, 0x10000);
+    let mut builder = merkle.get_merkle_mut().trie_builder();
+    builder.push(b"b", b"1".to_vec()).unwrap();
+    assert!(matches!(
+        builder.push(b"a", b"2".to_vec()),
+        Err(MerkleError::UnsortedKey(key)) if key == b"a"
+    ));
+    assert!(matches!(
+        builder.push(b"b", b"2".to_vec()),
+        Err(MerkleError::UnsortedKey(key)) if key == b"b"
+    ));
+    builder.push(b"ba", b"3".to_vec()).unwrap();
+}
+
+#[test]
+fn parallel_hashing_matches_one_thread() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut parallel = new_merkle_with_hash_threads(0x1000000, 0x1000000, 4);
+    let mut one_thread = new_merkle(0x1000000, 0x1000000);
+    for round in 0..3 {
+        // every subtree changes in the first round, and some of them in the others
+        for _ in 0..if round == 0 { 2000 } else { 50 } {
+            let key: [u8; 8] = rng.gen();
+            let val = vec![rng.gen(); rng.gen_range(1..64)];
+            parallel.insert(key, val.clone())?;
+            one_thread.insert(key, val)?;
+        }
+        assert_eq!(parallel.root_hash()?, one_thread.root_hash()?);
+        // the same hashes are written back to the same nodes
+        assert_eq!(parallel.dump()?, one_thread.dump()?);
+    }
+    Ok(())
+}
+
+#[test]
+fn multi_proof_matches_single_proofs() -> Result<(), DataStoreError> {
+    use rand::{rngs::StdRng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(42);
+    // short keys over few symbols, so that keys end at every kind of node, or go past them
+    let keygen = |rng: &mut StdRng| -> Vec<u8> {
+        let len = rng.gen_range(1..6);
+        (0..len).map(|_| rng.gen_range(0..3) * 0x11).collect()
+    };
+    let setups: [fn() -> MerkleSetup<Store>; 3] = [
+        || new_merkle(0x100000, 0x100000),
+        || new_merkle_with_branch_factor(0x100000, 0x100000, BranchFactor::Two),
+        || new_secure_merkle(0x100000, 0x100000),
+    ];
+    for setup in setups {
+        for _ in 0..20 {
+            let mut merkle = setup();
+            for _ in 0..rng.gen_range(0..50) {
+                merkle.insert(keygen(&mut rng), vec![rng.gen(); rng.gen_range(1..40)])?;
+            }
+            let keys: Vec<_> = (0..rng.gen_range(0..20))
+                .map(|_| keygen(&mut rng))
+                .collect();
+
+            // the proof has the nodes of every single proof, and no others
+            let proof = merkle.prove_many(&keys)?;
+            let mut nodes = HashMap::new();
+            for key in &keys {
+                nodes.extend(merkle.prove(key)?.0);
+            }
+            assert_eq!(proof.0, nodes);
+
+            // the keys are shown to be in the trie with their values, or not in it
+            let results = merkle.verify_multi_proof(&keys, &proof)?;
+            for (key, result) in keys.iter().zip(results) {
+                assert_eq!(result, merkle.verify(key, &merkle.prove(key)?)?);
+                let value = merkle.get(key)?.map(|value| value.to_vec());
+                assert_eq!(result.into_value(), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn multi_proof_without_a_node_is_invalid() -> Result<(), DataStoreError> {
+    let items = vec![
+        ("do", "verb"),
+        ("doe", "reindeer"),
+        ("dog", "puppy"),
+        ("horse", "stallion"),
+    ];
+    let merkle = merkle_build_test(items, 0x10000, 0x10000)?;
+    let keys = ["doe", "dog", "cat", "horse", "do", "d"];
+    let mut proof = merkle.prove_many(&keys)?;
+    let values: Vec<_> = merkle
+        .verify_multi_proof(&keys, &proof)?
+        .into_iter()
+        .map(ProofResult::into_value)
+        .collect();
+    let value = |value: &str| Some(value.as_bytes().to_vec());
+    assert_eq!(
+        values,
+        [
+            value("reindeer"),
+            value("puppy"),
+            None,
+            value("stallion"),
+            value("verb"),
+            None
+        ]
+    );
+
+    // the node every key goes through
+    proof.0.remove(&*merkle.root_hash()?);
+    assert!(merkle.verify_multi_proof(&keys, &proof).is_err());
+    Ok(())
+}
+
+#[test]
+fn absence_is_proven_where_the_key_leaves_the_trie() -> Result<(), DataStoreError> {
+    let items = vec![
+        ("do", "verb"),
+        ("doe", "reindeer"),
+        ("dog", "puppy"),
+        ("horse", "stallion"),
+    ];
+    let merkle = merkle_build_test(items, 0x10000, 0x10000)?;
+    let root_hash: [u8; 32] = *merkle.root_hash()?;
+
+    // every key starts with the symbol 6, and no key goes on with 3 from there
+    let proof = merkle.prove("cat")?;
+    let divergence = proof.verify_absence("cat", root_hash).unwrap();
+    assert_eq!(divergence.depth, 1);
+    assert!(proof.0.contains_key(&divergence.node.unwrap()));
+
+    // a key that ends inside the path of a node, past a node, or at a node without a value
+    for (key, depth) in [("dot", 4), ("does", 6), ("horses", 10), ("d", 2), ("h", 2)] {
+        let proof = merkle.prove(key)?;
+        let divergence = proof.verify_absence(key, root_hash).unwrap();
+        assert_eq!(divergence.depth, depth, "{key}");
+        assert!(proof.0.contains_key(&divergence.node.unwrap()));
+    }
+
+    // a key that is in the trie is not absent, even at a branch node
+    for key in ["do", "doe"] {
+        let proof = merkle.prove(key)?;
+        assert!(matches!(
+            proof.verify_absence(key, root_hash),
+            Err(ProofError::KeyPresent)
+        ));
+        assert!(matches!(
+            proof.verify(key, root_hash),
+            Ok(ProofResult::Present(_))
+        ));
+    }
+
+    // an empty trie has every key absent without any node
+    let empty = merkle_build_test(Vec::<(&str, &str)>::new(), 0x10000, 0x10000)?;
+    let proof = empty.prove("do")?;
+    let divergence = proof.verify_absence("do", *empty.root_hash()?).unwrap();
+    assert_eq!(
+        divergence,
+        Divergence {
+            depth: 0,
+            node: None
+        }
+    );
+    // but another trie does not
+    assert!(proof.verify("do", root_hash).is_err());
+    Ok(())
+}
+
+#[test]
+fn key_proofs_round_trip() -> Result<(), ProofError> {
+    let items = vec![("do", "verb"), ("doe", "reindeer"), ("horse", "stallion")];
+    let merkle = merkle_build_test(items, 0x10000, 0x10000)?;
+    let root_hash: [u8; 32] = *merkle.root_hash()?;
+    for key in ["do", "doe", "dog", "cat"] {
+        let proof = merkle.prove(key)?;
+        let key_proof = KeyProof::new(key.into(), proof, root_hash, BranchFactor::default())?;
+        assert_eq!(
+            key_proof.result.clone().into_value().is_some(),
+            key.starts_with("do") && key != "dog"
+        );
+
+        let bytes = key_proof.to_bytes();
+        let decoded = KeyProof::from_bytes(&bytes)?;
+        assert_eq!(decoded, key_proof);
+        decoded.verify(root_hash, BranchFactor::default())?;
+
+        // a proof doesn't show anything else of its key
+        let mut forged = decoded.clone();
+        forged.result = match forged.result {
+            ProofResult::Present(_) => ProofResult::Absent(Divergence {
+                depth: 0,
+                node: None,
+            }),
+            ProofResult::Absent(_) => ProofResult::Present(b"verb".to_vec()),
+        };
+        assert!(matches!(
+            forged.verify(root_hash, BranchFactor::default()),
+            Err(ProofError::InvalidProof)
+        ));
+        assert!(decoded.verify([0; 32], BranchFactor::default()).is_err());
+    }
+    assert!(KeyProof::from_bytes(&[1, 2, 3]).is_err());
+    Ok(())
+}
+
 #[test]
 fn test_one_element_proof() -> Result<(), DataStoreError> {
     let items = vec![("k", "v")];
@@ -0,0 +1,5 @@
+module github.com/ava-labs/firewood/firewood/tests/merkledb_vectors
+
+go 1.20
+
+require github.com/ava-labs/avalanchego v1.10.0
@@ -0,0 +1,72 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+// Prints the root hashes that avalanchego's MerkleDB gives a few tries, which
+// the MerkleDB encoding of firewood is checked against in tests/merkle.rs.
+// Each line is the name of a trie, its keys and values in hex, and its root
+// hash in hex. To regenerate the vectors:
+//
+//	go mod tidy && go run . > ../merkledb_vectors.txt
+package main
+
+import (
+	"context"
+	"encoding/hex"
+	"fmt"
+	"strings"
+
+	"github.com/ava-labs/avalanchego/database/memdb"
+	"github.com/ava-labs/avalanchego/trace"
+	"github.com/ava-labs/avalanchego/x/merkledb"
+)
+
+type kv struct {
+	key, value string
+}
+
+var tries = []struct {
+	name string
+	kvs  []kv
+}{
+	{"empty", nil},
+	{"single key", []kv{{"k", "v"}}},
+	{"empty key", []kv{{"", "root"}}},
+	{"shared prefixes", []kv{
+		{"do", "verb"},
+		{"doe", "reindeer"},
+		{"dog", "puppy"},
+		{"dogglesworth", "cat"},
+		{"horse", "stallion"},
+	}},
+	{"long values", []kv{
+		{"a", strings.Repeat("x", 31)},
+		{"ab", strings.Repeat("y", 32)},
+		{"abc", strings.Repeat("z", 100)},
+	}},
+}
+
+func main() {
+	ctx := context.Background()
+	for _, trie := range tries {
+		db, err := merkledb.New(ctx, memdb.New(), merkledb.Config{
+			HistoryLength: 1,
+			NodeCacheSize: 1,
+			Tracer:        trace.Noop,
+		})
+		check(err)
+		pairs := make([]string, len(trie.kvs))
+		for i, kv := range trie.kvs {
+			check(db.Put([]byte(kv.key), []byte(kv.value)))
+			pairs[i] = hex.EncodeToString([]byte(kv.key)) + ":" + hex.EncodeToString([]byte(kv.value))
+		}
+		root, err := db.GetMerkleRoot(ctx)
+		check(err)
+		fmt.Printf("%s\t%s\t%s\n", trie.name, strings.Join(pairs, ","), hex.EncodeToString(root[:]))
+	}
+}
+
+func check(err error) {
+	if err != nil {
+		panic(err)
+	}
+}
@@ -3,7 +3,9 @@
 
 use anyhow::{Error, Result};
 use clap::{value_parser, Args};
-use firewood::db::{Db, DbConfig, DbRevConfig, DiskBufferConfig, WalConfig};
+use firewood::db::{
+    BranchFactor, Db, DbConfig, DbRevConfig, DiskBufferConfig, TrieEncoding, WalConfig,
+};
 use log;
 
 #[derive(Args)]
@@ -120,6 +122,53 @@ pub struct Options {
     )]
     pub root_hash_file_nbit: u64,
 
+    #[arg(
+        long,
+        required = false,
+        default_value = "firewood",
+        value_parser = ["firewood", "ethereum", "merkledb"],
+        value_name = "TRIE_ENCODING",
+        help = "How trie nodes are encoded to be hashed. The ethereum encoding gives the root hashes
+    of Ethereum's Merkle Patricia Trie, and the merkledb one those of avalanchego's MerkleDB. It
+    can't be changed once the DB is created."
+    )]
+    pub trie_encoding: String,
+
+    #[arg(
+        long,
+        required = false,
+        default_value = "16",
+        value_parser = ["2", "4", "16", "256"],
+        value_name = "BRANCH_FACTOR",
+        help = "How many children the branch nodes of the tries have. Only the firewood encoding
+    supports factors other than 16. It can't be changed once the DB is created."
+    )]
+    pub branch_factor: String,
+
+    #[arg(
+        long,
+        required = false,
+        value_parser = value_parser!(bool),
+        default_missing_value = "true",
+        default_value_t = false,
+        value_name = "SECURE_KEYS",
+        help = "Whether keys are hashed before they are put in the tries, which keeps them balanced
+    however much keys share prefixes. Proofs are of the hashed keys. It can't be changed once the
+    DB is created. [default: false]"
+    )]
+    pub secure_keys: bool,
+
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 4096,
+        value_name = "BLOB_THRESHOLD",
+        help = "Values longer than this many bytes are stored out of line, in blobs shared by equal
+    values. 0 keeps every value in its node, otherwise it must be at least 32. It can't be changed
+    once the DB is created."
+    )]
+    pub blob_threshold: u64,
+
     #[arg(
         long,
         required = false,
@@ -132,6 +181,18 @@ pub struct Options {
     )]
     pub truncate: bool,
 
+    #[arg(
+        long,
+        required = false,
+        value_parser = value_parser!(bool),
+        default_missing_value = "false",
+        default_value_t = false,
+        value_name = "ARCHIVAL",
+        help = "Whether to keep every past revision in an append-only history store, so that any of
+    them can be read back. [default: false]"
+    )]
+    pub archival: bool,
+
     /// Revision options
     #[arg(
         long,
@@ -150,6 +211,15 @@ pub struct Options {
     )]
     blob_ncached_objs: usize,
 
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 1,
+        value_name = "REV_HASH_THREADS",
+        help = "Threads that the root hash of a trie is computed on."
+    )]
+    hash_threads: usize,
+
     /// Disk Buffer options
     #[arg(
         long,
@@ -251,6 +321,16 @@ pub struct Options {
     of the past N commits to the database."
     )]
     max_revisions: u32,
+
+    #[arg(
+        long,
+        required = false,
+        default_value_t = 1 << 30,
+        value_name = "MAX_PINNED_BYTES",
+        help = "Maximum bytes of past revisions kept in memory for pinned revisions that have left
+    the rolling window."
+    )]
+    max_pinned_bytes: u64,
 }
 
 pub fn initialize_db_config(opts: &Options) -> DbConfig {
@@ -266,9 +346,26 @@ pub fn initialize_db_config(opts: &Options) -> DbConfig {
         root_hash_ncached_pages: opts.payload_ncached_pages,
         root_hash_ncached_files: opts.root_hash_ncached_files,
         root_hash_file_nbit: opts.root_hash_file_nbit,
+        trie_encoding: match opts.trie_encoding.as_str() {
+            "ethereum" => TrieEncoding::Ethereum,
+            "merkledb" => TrieEncoding::MerkleDb,
+            _ => TrieEncoding::Firewood,
+        },
+        branch_factor: match opts.branch_factor.as_str() {
+            "2" => BranchFactor::Two,
+            "4" => BranchFactor::Four,
+            "256" => BranchFactor::TwoFiftySix,
+            _ => BranchFactor::Sixteen,
+        },
+        secure_keys: opts.secure_keys,
+        blob_threshold: opts.blob_threshold,
         truncate: opts.truncate,
+        archival: opts.archival,
+        read_only: false,
+        max_pinned_bytes: opts.max_pinned_bytes,
         rev: DbRevConfig {
             merkle_ncached_objs: opts.merkle_ncached_objs,
+            hash_threads: opts.hash_threads,
         },
         buffer: DiskBufferConfig {
             max_buffered: opts.max_buffered,
@@ -22,6 +22,7 @@ pub fn run(opts: &Options) -> Result<()> {
     log::debug!("dump database {:?}", opts);
     let cfg = DbConfig::builder()
         .truncate(false)
+        .read_only(true)
         .wal(WalConfig::builder().max_revisions(10).build());
 
     let db = Db::new(opts.db.as_str(), &cfg.build()).map_err(Error::msg)?;
@@ -4,11 +4,14 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 
+pub mod backup;
 pub mod create;
 pub mod delete;
 pub mod dump;
 pub mod get;
 pub mod insert;
+pub mod migrate;
+pub mod restore;
 pub mod root;
 
 #[derive(Parser)]
@@ -33,7 +36,7 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new firewood database
-    Create(create::Options),
+    Create(Box<create::Options>),
     /// Insert a key/value pair into the database
     Insert(insert::Options),
     /// Get values associated with a key
@@ -44,6 +47,12 @@ enum Commands {
     Root(root::Options),
     /// Dump contents of key/value store
     Dump(dump::Options),
+    /// Write a consistent backup of a database that is not in use to a new directory
+    Backup(backup::Options),
+    /// Restore a database from a backup
+    Restore(restore::Options),
+    /// Upgrade a database written in an older format
+    Migrate(migrate::Options),
 }
 
 fn main() -> Result<()> {
@@ -61,5 +70,8 @@ fn main() -> Result<()> {
         Commands::Delete(opts) => delete::run(opts),
         Commands::Root(opts) => root::run(opts),
         Commands::Dump(opts) => dump::run(opts),
+        Commands::Backup(opts) => backup::run(opts),
+        Commands::Restore(opts) => restore::run(opts),
+        Commands::Migrate(opts) => migrate::run(opts),
     }
 }
@@ -0,0 +1,66 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use anyhow::{Error, Result};
+use clap::Args;
+use firewood::db::Db;
+use log;
+use std::path::Path;
+
+#[derive(Debug, Args)]
+pub struct Options {
+    /// The database path (if no path is provided, return an error). Defaults to firewood.
+    #[arg(
+        long,
+        required = false,
+        value_name = "DB_NAME",
+        default_value_t = String::from("firewood"),
+        help = "Name of the database"
+    )]
+    pub db: String,
+
+    /// Where to write the upgraded database
+    #[arg(
+        long,
+        required = false,
+        value_name = "DEST_DIR",
+        help = "Directory to write the upgraded database to, which must not exist yet. \
+                The database is upgraded in place if this is not provided."
+    )]
+    pub dest: Option<String>,
+
+    #[arg(
+        long,
+        required = false,
+        help = "Print the steps of the upgrade without changing anything"
+    )]
+    pub dry_run: bool,
+}
+
+pub fn run(opts: &Options) -> Result<()> {
+    log::debug!("migrate database {:?}", opts);
+    let plan = Db::migration_plan(&opts.db).map_err(Error::msg)?;
+    if plan.steps.is_empty() {
+        println!("{:?} is up to date (format version {})", opts.db, plan.to);
+        return Ok(());
+    }
+
+    if opts.dry_run {
+        println!(
+            "{:?} would be upgraded from format version {} to {}:",
+            opts.db, plan.from, plan.to
+        );
+    } else {
+        Db::migrate(&opts.db, opts.dest.as_deref().map(Path::new)).map_err(Error::msg)?;
+        println!(
+            "upgraded {:?} from format version {} to {}:",
+            opts.dest.as_ref().unwrap_or(&opts.db),
+            plan.from,
+            plan.to
+        );
+    }
+    for step in plan.steps {
+        println!("  - {step}");
+    }
+    Ok(())
+}
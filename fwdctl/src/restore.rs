@@ -0,0 +1,35 @@
+// Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
+// See the file LICENSE.md for licensing terms.
+
+use anyhow::{Error, Result};
+use clap::Args;
+use firewood::db::Db;
+use log;
+
+#[derive(Debug, Args)]
+pub struct Options {
+    /// The backup to restore
+    #[arg(
+        required = true,
+        value_name = "BACKUP_DIR",
+        help = "Directory written by the backup command"
+    )]
+    pub src: String,
+
+    /// The database path (if no path is provided, return an error). Defaults to firewood.
+    #[arg(
+        long,
+        required = false,
+        value_name = "DB_NAME",
+        default_value_t = String::from("firewood"),
+        help = "Name of the database to restore to, which must not exist yet"
+    )]
+    pub db: String,
+}
+
+pub fn run(opts: &Options) -> Result<()> {
+    log::debug!("restore database {:?}", opts);
+    Db::restore(&opts.src, &opts.db).map_err(Error::msg)?;
+    println!("restored {:?} from {:?}", opts.db, opts.src);
+    Ok(())
+}
@@ -24,11 +24,12 @@ pub fn run(opts: &Options) -> Result<()> {
     log::debug!("root hash {:?}", opts);
     let cfg = DbConfig::builder()
         .truncate(false)
+        .read_only(true)
         .wal(WalConfig::builder().max_revisions(10).build());
 
     let db = Db::new(opts.db.as_str(), &cfg.build()).map_err(Error::msg)?;
 
-    let root = db.kv_root_hash().map_err(Error::msg)?;
+    let root = db.kv_root_hash(None).map_err(Error::msg)?;
     println!("{:X?}", *root);
     Ok(())
 }
@@ -3,6 +3,7 @@
 
 use anyhow::{anyhow, Result};
 use assert_cmd::Command;
+use firewood::db::{Db, DbConfig};
 use predicates::prelude::*;
 use serial_test::serial;
 use std::{fs::remove_dir_all, path::PathBuf};
@@ -177,6 +178,169 @@ fn fwdctl_root_hash() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn fwdctl_root_hash_in_ethereum_encoding() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .args(["--trie-encoding", "ethereum"])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("insert")
+        .args(["year"])
+        .args(["2023"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    // the root hash Ethereum gives the same trie
+    Command::cargo_bin(PRG)?
+        .arg("root")
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "[74, 33, 3A, DD, A9, DA, 99, 44,",
+        ));
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn fwdctl_create_with_branch_factor() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .args(["--branch-factor", "256"])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("insert")
+        .args(["year"])
+        .args(["2023"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("get")
+        .args(["year"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2023"));
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    // the Ethereum encoding only has 16-way branch nodes
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .args(["--trie-encoding", "ethereum"])
+        .args(["--branch-factor", "256"])
+        .assert()
+        .failure();
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn fwdctl_create_with_secure_keys() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .arg("--secure-keys")
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("insert")
+        .args(["year"])
+        .args(["2023"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("get")
+        .args(["year"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2023"));
+
+    // the dump gives the key next to its hash
+    Command::cargo_bin(PRG)?
+        .arg("dump")
+        .args([tmpdb::path()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("key=79656172"));
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn fwdctl_create_with_blob_threshold() -> Result<()> {
+    // shorter than a hash
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .args(["--blob-threshold", "8"])
+        .assert()
+        .failure();
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .args(["--blob-threshold", "32"])
+        .assert()
+        .success();
+
+    let value = "v".repeat(40);
+    Command::cargo_bin(PRG)?
+        .arg("insert")
+        .args(["code"])
+        .args([&value])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("get")
+        .args(["code"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(value));
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn fwdctl_dump() -> Result<()> {
@@ -209,6 +373,117 @@ fn fwdctl_dump() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn fwdctl_backup_and_restore() -> Result<()> {
+    let backup = tmpdb::path().with_extension("backup");
+    let restored = tmpdb::path().with_extension("restored");
+    let _ = remove_dir_all(&backup);
+    let _ = remove_dir_all(&restored);
+
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("insert")
+        .args(["year"])
+        .args(["2023"])
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("backup")
+        .arg(&backup)
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("restore")
+        .arg(&backup)
+        .args(["--db"])
+        .args([&restored])
+        .assert()
+        .success();
+
+    Command::cargo_bin(PRG)?
+        .arg("get")
+        .args(["year"])
+        .args(["--db"])
+        .args([&restored])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2023"));
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+    remove_dir_all(backup)?;
+    remove_dir_all(restored)?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn fwdctl_backup_refuses_a_database_in_use() -> Result<()> {
+    let backup = tmpdb::path().with_extension("backup");
+    let _ = remove_dir_all(&backup);
+
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .assert()
+        .success();
+
+    // stands in for a live node holding the database open
+    let db = Db::new(tmpdb::path(), &DbConfig::builder().truncate(false).build())?;
+
+    Command::cargo_bin(PRG)?
+        .arg("backup")
+        .arg(&backup)
+        .args(["--db"])
+        .args([tmpdb::path()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already open for writing"));
+    assert!(!backup.exists());
+
+    drop(db);
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn fwdctl_migrate_up_to_date() -> Result<()> {
+    Command::cargo_bin(PRG)?
+        .arg("create")
+        .arg(tmpdb::path())
+        .assert()
+        .success();
+
+    for args in [&["--dry-run"][..], &[]] {
+        Command::cargo_bin(PRG)?
+            .arg("migrate")
+            .args(args)
+            .args(["--db"])
+            .args([tmpdb::path()])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("is up to date"));
+    }
+
+    fwdctl_delete_db().map_err(|e| anyhow!(e))?;
+
+    Ok(())
+}
+
 // A module to create a temporary database name for use in
 // tests. The directory will be one of:
 // - cargo's compile-time CARGO_TARGET_TMPDIR, if that exists
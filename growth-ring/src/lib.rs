@@ -80,6 +80,10 @@ impl RawWalFile {
             .await
             .map(Self)
     }
+
+    pub async fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        OpenOptions::new().read(true).open(path).await.map(Self)
+    }
 }
 
 pub struct WalFileImpl {
@@ -141,6 +145,7 @@ impl WalFile for WalFileImpl {
 
 pub struct WalStoreImpl {
     root_dir: PathBuf,
+    read_only: bool,
 }
 
 impl WalStoreImpl {
@@ -159,6 +164,23 @@ impl WalStoreImpl {
 
         Ok(WalStoreImpl {
             root_dir: wal_dir.as_ref().to_path_buf(),
+            read_only: false,
+        })
+    }
+
+    /// Open an existing Wal dir without ever creating, writing or removing files in it, see
+    /// [WalLoader::peek](wal::WalLoader::peek).
+    pub fn new_read_only<P: AsRef<Path>>(wal_dir: P) -> Result<Self, WalError> {
+        if !wal_dir.as_ref().is_dir() {
+            return Err(WalError::Other(format!(
+                "{:?} is not a Wal dir",
+                wal_dir.as_ref()
+            )));
+        }
+
+        Ok(WalStoreImpl {
+            root_dir: wal_dir.as_ref().to_path_buf(),
+            read_only: true,
         })
     }
 }
@@ -180,12 +202,19 @@ impl WalStore<WalFileImpl> for WalStoreImpl {
     async fn open_file(&self, filename: &str, _touch: bool) -> Result<WalFileImpl, WalError> {
         let path = self.root_dir.join(filename);
 
-        let file = RawWalFile::open(path).await?;
+        let file = if self.read_only {
+            RawWalFile::open_read_only(path).await?
+        } else {
+            RawWalFile::open(path).await?
+        };
 
         Ok(file.into())
     }
 
     async fn remove_file(&self, filename: String) -> Result<(), WalError> {
+        if self.read_only {
+            return Err(WalError::Other("the Wal dir is read-only".to_string()));
+        }
         let file_to_remove = self.root_dir.join(filename);
         fs::remove_file(file_to_remove).map_err(From::from)
     }
@@ -351,6 +380,57 @@ mod tests {
         assert_eq!(result, Some(data.into()));
     }
 
+    #[test]
+    fn peek_reads_records_without_modifying_the_wal() {
+        let wal_dir = get_temp_walfile_path(file!(), line!());
+        let mut loader = wal::WalLoader::new();
+        loader.file_nbit(9).block_nbit(8);
+        // long enough to span several blocks and files
+        let records: Vec<_> = (0..8).map(|i| format!("{i}").repeat(300)).collect();
+        let runtime = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        };
+
+        // a write is done once it is handed to a blocking task, and the runtime waits for
+        // those when it is dropped
+        runtime().block_on(async {
+            let store = WalStoreImpl::new(&wal_dir, true).unwrap();
+            let mut wal = loader.load(store, |_, _| Ok(()), 0).await.unwrap();
+            for ring in wal.grow(records.clone()) {
+                ring.await.unwrap();
+            }
+        });
+
+        let snapshot = || {
+            let mut files: Vec<_> = fs::read_dir(&wal_dir)
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (entry.file_name(), fs::read(entry.path()).unwrap())
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let before = snapshot();
+
+        let mut peeked = Vec::new();
+        runtime()
+            .block_on(
+                loader.peek(WalStoreImpl::new_read_only(&wal_dir).unwrap(), |raw, _| {
+                    peeked.push(String::from_utf8(raw.into()).unwrap());
+                    Ok(())
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(peeked, records);
+        assert_eq!(snapshot(), before);
+    }
+
     fn get_temp_walfile_path(file: &str, line: u32) -> PathBuf {
         let path = option_env!("CARGO_TARGET_TMPDIR")
             .map(PathBuf::from)
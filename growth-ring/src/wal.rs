@@ -259,7 +259,40 @@ impl<F: WalFile + 'static, S: WalStore<F>> WalFilePool<F, S> {
     ) -> Result<Self, WalError> {
         let header_file = store.open_file("HEAD", true).await?;
         header_file.truncate(HEADER_SIZE).await?;
-        Ok(WalFilePool {
+        Ok(Self::with_header(
+            store,
+            header_file,
+            file_nbit,
+            block_nbit,
+            cache_size,
+        ))
+    }
+
+    /// Open the pool without touching any file, so it can only be read from.
+    async fn open(
+        store: S,
+        file_nbit: u64,
+        block_nbit: u64,
+        cache_size: NonZeroUsize,
+    ) -> Result<Self, WalError> {
+        let header_file = store.open_file("HEAD", false).await?;
+        Ok(Self::with_header(
+            store,
+            header_file,
+            file_nbit,
+            block_nbit,
+            cache_size,
+        ))
+    }
+
+    fn with_header(
+        store: S,
+        header_file: F,
+        file_nbit: u64,
+        block_nbit: u64,
+        cache_size: NonZeroUsize,
+    ) -> Self {
+        WalFilePool {
             store,
             header_file,
             handle_cache: RefCell::new(lru::LruCache::new(cache_size)),
@@ -269,11 +302,18 @@ impl<F: WalFile + 'static, S: WalStore<F>> WalFilePool<F, S> {
             file_nbit,
             file_size: 1 << file_nbit,
             block_nbit,
-        })
+        }
     }
 
     async fn read_header(&self) -> Result<Header, WalError> {
-        let bytes = self.header_file.read(0, HEADER_SIZE).await?.unwrap();
+        let bytes = self
+            .header_file
+            .read(0, HEADER_SIZE)
+            .await?
+            .ok_or_else(|| WalError::Corrupted {
+                file: "HEAD".to_string(),
+                offset: 0,
+            })?;
         let bytes: [u8; HEADER_SIZE] = (&*bytes).try_into().unwrap();
         let header: Header = cast_slice(&bytes)[0];
         Ok(header)
@@ -1003,7 +1043,7 @@ impl WalLoader {
         &'a self,
         file: &'a WalFileHandle<'a, F, S>,
         chunks: &'a mut Option<(Vec<WalBytes>, WalPos)>,
-    ) -> impl futures::Stream<Item = Result<(WalBytes, WalRingId, u32), bool>> + 'a {
+    ) -> impl futures::Stream<Item = Result<(WalBytes, WalRingId, u32), Option<WalPos>>> + 'a {
         let fid = file.fid;
         let file_nbit = self.file_nbit;
         let block_size = 1 << self.block_nbit;
@@ -1040,7 +1080,7 @@ impl WalLoader {
                 macro_rules! die {
                     () => {{
                         v.done = true;
-                        return Some((Err(true), ()));
+                        return Some((Err(Some(v.off)), ()));
                     }};
                 }
 
@@ -1155,7 +1195,7 @@ impl WalLoader {
                             RecoverPolicy::Strict => die!(),
                             RecoverPolicy::BestEffort => {
                                 v.done = true;
-                                return Some((Err(false), ()));
+                                return Some((Err(None), ()));
                             }
                         },
                     }
@@ -1165,6 +1205,79 @@ impl WalLoader {
         })
     }
 
+    /// Locate the record that `recover_func` failed on.
+    fn recover_error(file_nbit: u64, ring_id: WalRingId, source: WalError) -> WalError {
+        WalError::Recover {
+            file: get_fname(ring_id.start >> file_nbit),
+            offset: ring_id.start & ((1 << file_nbit) - 1),
+            source: Box::new(source),
+        }
+    }
+
+    /// Read the records that [load](Self::load) would replay, without modifying the Wal files,
+    /// so they can be inspected while a [WalWriter] still appends to them. The records from the
+    /// files the writer has already removed are skipped. Under [RecoverPolicy::BestEffort], a
+    /// record that is still being written ends the Wal instead of failing.
+    pub async fn peek<
+        F: WalFile + 'static,
+        S: WalStore<F>,
+        Func: FnMut(WalBytes, WalRingId) -> Result<(), WalError>,
+    >(
+        &self,
+        store: S,
+        mut recover_func: Func,
+    ) -> Result<(), WalError> {
+        let filename_fmt = regex::Regex::new(FILENAME_FMT).unwrap();
+        let file_pool =
+            WalFilePool::open(store, self.file_nbit, self.block_nbit, self.cache_size).await?;
+        let mut fids: Vec<_> = file_pool
+            .store
+            .enumerate_files()?
+            .filter(|f| filename_fmt.is_match(f))
+            .map(|s| get_fid(&s))
+            .collect();
+
+        let header = file_pool.read_header().await?;
+        // the file to recover from may have been removed by the writer already
+        if !fids.contains(&header.recover_fid) {
+            fids.push(header.recover_fid);
+        }
+        let logfiles = sort_fids(self.file_nbit, fids)
+            .into_iter()
+            .map(|(_, fid)| fid)
+            .skip_while(|fid| *fid != header.recover_fid);
+
+        let mut chunks = None;
+        'outer: for fid in logfiles {
+            let f = match file_pool.get_file(fid, false).await {
+                Ok(f) => f,
+                Err(_) if fid == header.recover_fid => continue,
+                Err(e) => return Err(e),
+            };
+            let stream = self.read_records(&f, &mut chunks);
+            futures::pin_mut!(stream);
+            while let Some(res) = stream.next().await {
+                let (bytes, ring_id, _) = match res {
+                    Ok(t) => t,
+                    Err(_) if matches!(self.recover_policy, RecoverPolicy::BestEffort) => {
+                        break 'outer
+                    }
+                    Err(None) => break 'outer,
+                    Err(Some(offset)) => {
+                        return Err(WalError::Corrupted {
+                            file: get_fname(fid),
+                            offset,
+                        })
+                    }
+                };
+                recover_func(bytes, ring_id)
+                    .map_err(|e| Self::recover_error(self.file_nbit, ring_id, e))?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Recover by reading the Wal files.
     pub async fn load<
         F: WalFile + 'static,
@@ -1215,18 +1328,17 @@ impl WalLoader {
                 futures::pin_mut!(stream);
                 while let Some(res) = stream.next().await {
                     let (bytes, ring_id, _) = match res {
-                        Err(e) => {
-                            if e {
-                                return Err(WalError::Other(
-                                    "error loading from storage".to_string(),
-                                ));
-                            } else {
-                                break 'outer;
-                            }
+                        Err(Some(offset)) => {
+                            return Err(WalError::Corrupted {
+                                file: get_fname(fid),
+                                offset,
+                            })
                         }
+                        Err(None) => break 'outer,
                         Ok(t) => t,
                     };
-                    recover_func(bytes, ring_id)?;
+                    recover_func(bytes, ring_id)
+                        .map_err(|e| Self::recover_error(self.file_nbit, ring_id, e))?;
                 }
             }
             scanned.push((fname, f));
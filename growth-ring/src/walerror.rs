@@ -18,6 +18,15 @@ pub enum WalError {
     IOError(Arc<std::io::Error>),
     #[error("Wal directory already exists")]
     WalDirExists,
+    #[error("the Wal file {file} is corrupted at offset {offset}")]
+    Corrupted { file: String, offset: u64 },
+    #[error("failed to recover the record in Wal file {file} at offset {offset}: {source}")]
+    Recover {
+        file: String,
+        offset: u64,
+        #[source]
+        source: Box<WalError>,
+    },
 }
 
 impl From<i32> for WalError {
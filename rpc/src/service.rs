@@ -1,9 +1,13 @@
 // Copyright (C) 2023, Ava Labs, Inc. All rights reserved.
 // See the file LICENSE.md for licensing terms.
 
-use firewood::v2::{
-    api::{Db, Error},
-    emptydb::{EmptyDb, HistoricalImpl},
+use firewood::{
+    db::DbError,
+    proof::ProofError,
+    v2::{
+        api::{Db, Error},
+        emptydb::{EmptyDb, HistoricalImpl},
+    },
 };
 use std::{
     collections::HashMap,
@@ -13,7 +17,7 @@ use std::{
     },
 };
 use tokio::sync::Mutex;
-use tonic::Status;
+use tonic::{Code, Status};
 
 pub mod database;
 pub mod db;
@@ -24,18 +28,40 @@ trait IntoStatusResultExt<T> {
 
 impl<T> IntoStatusResultExt<T> for Result<T, Error> {
     fn into_status_result(self) -> Result<T, Status> {
-        self.map_err(|err| match err {
-            Error::HashNotFound { provided: _ } => todo!(),
-            Error::IncorrectRootHash {
-                provided: _,
-                current: _,
-            } => todo!(),
-            Error::IO(_) => todo!(),
-            Error::InvalidProposal => todo!(),
-            _ => todo!(),
-        })
+        self.map_err(into_status)
     }
 }
+
+/// Convert an API error into a [Status]. The message carries the full error,
+/// while the code is chosen by [status_code].
+pub fn into_status(err: Error) -> Status {
+    Status::new(status_code(&err), err.to_string())
+}
+
+/// The stable mapping from API errors to gRPC status codes. Clients should
+/// branch on these codes rather than on the message text:
+///
+/// | code                  | meaning                                          |
+/// |-----------------------|--------------------------------------------------|
+/// | `NOT_FOUND`           | the revision or key does not exist               |
+/// | `FAILED_PRECONDITION` | the proposal or root hash is stale, or the       |
+/// |                       | database is open read-only                       |
+/// | `INVALID_ARGUMENT`    | the request or a supplied proof is malformed     |
+/// | `DATA_LOSS`           | the stored trie is inconsistent                  |
+/// | `INTERNAL`            | an I/O, WAL or other server-side failure         |
+pub fn status_code(err: &Error) -> Code {
+    match err {
+        Error::HashNotFound { .. } | Error::Db(DbError::KeyNotFound) => Code::NotFound,
+        Error::IncorrectRootHash { .. } | Error::InvalidProposal | Error::Db(DbError::ReadOnly) => {
+            Code::FailedPrecondition
+        }
+        Error::Proof(ProofError::Shale(_) | ProofError::SystemError(_)) => Code::Internal,
+        Error::InvalidParams | Error::Proof(_) => Code::InvalidArgument,
+        Error::Merkle(_) | Error::Shale(_) => Code::DataLoss,
+        _ => Code::Internal,
+    }
+}
+
 pub struct Database {
     db: EmptyDb,
     iterators: Arc<Mutex<Iterators>>,
@@ -81,3 +107,50 @@ impl Iterators {
         self.map.remove(&id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use firewood::merkle::MerkleError;
+
+    #[test]
+    fn status_codes_are_stable() {
+        let cases = [
+            (Error::HashNotFound { provided: [0; 32] }, Code::NotFound),
+            (DbError::KeyNotFound.into(), Code::NotFound),
+            (Error::InvalidProposal, Code::FailedPrecondition),
+            (DbError::InvalidProposal.into(), Code::FailedPrecondition),
+            (DbError::ReadOnly.into(), Code::FailedPrecondition),
+            (
+                Error::IncorrectRootHash {
+                    provided: [0; 32],
+                    current: [1; 32],
+                },
+                Code::FailedPrecondition,
+            ),
+            (DbError::InvalidParams.into(), Code::InvalidArgument),
+            (ProofError::InvalidProof.into(), Code::InvalidArgument),
+            (MerkleError::NotBranchNode.into(), Code::DataLoss),
+            (
+                std::io::Error::from(std::io::ErrorKind::Other).into(),
+                Code::Internal,
+            ),
+        ];
+
+        for (err, code) in cases {
+            assert_eq!(into_status(err).code(), code);
+        }
+    }
+
+    #[test]
+    fn source_is_preserved() {
+        use std::error::Error as _;
+
+        let err: Error = ProofError::Db(DbError::KeyNotFound).into();
+        assert!(matches!(err, Error::Db(DbError::KeyNotFound)));
+
+        let err: Error = DbError::IO(std::io::ErrorKind::NotFound.into()).into();
+        let source = err.source().expect("io error should be the source");
+        assert!(source.downcast_ref::<std::io::Error>().is_some());
+    }
+}
@@ -141,10 +141,14 @@ impl<T: ?Sized + Send + Sync> Obj<T> {
     pub fn write(&mut self, modify: impl FnOnce(&mut T)) -> Result<(), ObjWriteError> {
         modify(self.value.write());
 
-        // if `estimate_mem_image` gives overflow, the object will not be written
+        // if `estimate_mem_image` gives overflow, the object will not be written, not even the
+        // changes made before, since it no longer fits where it is and has to be moved
         self.dirty = match self.value.estimate_mem_image() {
             Some(len) => Some(len),
-            None => return Err(ObjWriteError),
+            None => {
+                self.dirty = None;
+                return Err(ObjWriteError);
+            }
         };
 
         Ok(())